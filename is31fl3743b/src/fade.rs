@@ -0,0 +1,121 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Minimal fade engine for kll-core PixelFadeControl capabilities
+//!
+//! Each profile stores a target global brightness and a step size.
+//! The brightness of the most recently commanded profile is approached one step per tick
+//! and sent to the ISSI chips using the brightness_set function queue.
+
+use crate::{Is31fl3743bAtsam4Dma, IssiError};
+use kll_core::pixel::FadeCommand;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct FadeProfile {
+    /// Target global brightness
+    pub brightness: u8,
+    /// Brightness change per tick (0 applies the target immediately)
+    pub step: u8,
+}
+
+impl FadeProfile {
+    pub const fn new(brightness: u8, step: u8) -> Self {
+        Self { brightness, step }
+    }
+}
+
+/// PROFILES: Number of fade profiles
+pub struct FadeControl<const PROFILES: usize> {
+    /// Default profile settings, used for resets
+    defaults: [FadeProfile; PROFILES],
+    /// Current profile settings
+    profiles: [FadeProfile; PROFILES],
+    /// Most recently commanded profile
+    active: usize,
+    /// Brightness last sent to the ISSI chips
+    current: u8,
+}
+
+impl<const PROFILES: usize> FadeControl<PROFILES> {
+    /// current: Brightness currently set on the ISSI chips
+    pub fn new(defaults: [FadeProfile; PROFILES], current: u8) -> Self {
+        Self {
+            defaults,
+            profiles: defaults,
+            active: 0,
+            current,
+        }
+    }
+
+    /// Current settings for a profile
+    pub fn profile(&self, profile: u8) -> Option<&FadeProfile> {
+        self.profiles.get(profile as usize)
+    }
+
+    /// Brightness last sent to the ISSI chips
+    pub fn brightness(&self) -> u8 {
+        self.current
+    }
+
+    /// Applies a kll-core PixelFadeControl command to a profile
+    /// The profile becomes the active profile (except for ResetAll)
+    pub fn command(&mut self, profile: u8, command: FadeCommand, arg: u8) -> Result<(), IssiError> {
+        let index = profile as usize;
+        if index >= PROFILES {
+            return Err(IssiError::ProfileOutOfRange(profile));
+        }
+
+        let settings = &mut self.profiles[index];
+        match command {
+            FadeCommand::Reset => {
+                *settings = self.defaults[index];
+            }
+            FadeCommand::ResetAll => {
+                self.profiles = self.defaults;
+                return Ok(());
+            }
+            FadeCommand::BrightnessSet => {
+                settings.brightness = arg;
+            }
+            FadeCommand::BrightnessIncrement => {
+                settings.brightness = settings.brightness.saturating_add(arg);
+            }
+            FadeCommand::BrightnessDecrement => {
+                settings.brightness = settings.brightness.saturating_sub(arg);
+            }
+            FadeCommand::BrightnessDefault => {
+                settings.brightness = self.defaults[index].brightness;
+            }
+        }
+        self.active = index;
+        Ok(())
+    }
+
+    /// Steps the brightness towards the active profile
+    /// Call once per fade period (e.g. every scan loop)
+    /// Returns the new brightness if an update was queued
+    pub fn tick<const CHIPS: usize, const QUEUE_SIZE: usize>(
+        &mut self,
+        issi: &mut Is31fl3743bAtsam4Dma<CHIPS, QUEUE_SIZE>,
+    ) -> Result<Option<u8>, IssiError> {
+        let FadeProfile { brightness, step } = self.profiles[self.active];
+        if brightness == self.current {
+            return Ok(None);
+        }
+
+        let next = if step == 0 {
+            brightness
+        } else if brightness > self.current {
+            self.current.saturating_add(step).min(brightness)
+        } else {
+            self.current.saturating_sub(step).max(brightness)
+        };
+        issi.brightness_set(next)?;
+        self.current = next;
+        Ok(Some(next))
+    }
+}
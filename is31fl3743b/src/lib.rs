@@ -9,6 +9,10 @@
 
 #![no_std]
 
+#[cfg(feature = "kll-core")]
+pub mod fade;
+mod test;
+
 use heapless::spsc::Queue;
 
 const ISSI_CONFIG_PAGE: u8 = 0x52;
@@ -25,7 +29,11 @@ const ISSI_OPEN_REG_START: u8 = 0x03;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
 pub enum IssiError {
+    ChannelOutOfRange(usize),
+    ChipOutOfRange(usize),
     OpenDetectNotReady,
+    PixelOutOfRange(usize),
+    ProfileOutOfRange(u8),
     PdcBufferTooSmall(usize, usize),
     FuncQueueEmpty,
     FuncQueueFull,
@@ -53,6 +61,19 @@ impl<const CHIPS: usize> Default for IssiBuf<CHIPS> {
     }
 }
 
+/// Maps a logical RGB pixel to physical PWM channels
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+pub struct RgbMap {
+    /// Index into the logical frame
+    pub pixel: usize,
+    /// Chip index (not the cs)
+    pub chip: usize,
+    /// PWM channels (index into the PWM page)
+    pub r: usize,
+    pub g: usize,
+    pub b: usize,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
 pub enum Function {
     /// Set Brightness
@@ -71,8 +92,8 @@ pub enum Function {
     ShortCircuitDetectRead,
     /// Detect channel short circuits (setup)
     ShortCircuitDetectSetup,
-    /// Software Shutdown
-    SoftwareShutdown,
+    /// Software Shutdown (enable state at the time the function was queued)
+    SoftwareShutdown(bool),
     /// Unknown function
     Unknown,
 }
@@ -93,6 +114,41 @@ const fn atsam4_var_spi(data: u8, cs: u8, lastxfer: bool) -> u32 {
     (data as u32) | ((atsam4_cs_to_pcs(cs) as u32) << 16) | (if lastxfer { 1 } else { 0 } << 24)
 }
 
+/// Size of a register sync transaction (page, register, value) for each chip
+const fn reg_sync_tx_len(chips: usize, regs: usize) -> usize {
+    3 * chips * regs
+}
+
+/// Size of a full page (PWM or Scaling) transaction
+/// Page select + start register + page data, per chip
+const fn page_tx_len(chips: usize) -> usize {
+    chips * (2 + ISSI_PAGE_LEN)
+}
+
+/// Size of an open/short detect read transaction
+/// Page select + start register + detect registers, per chip
+const fn detect_read_len(chips: usize) -> usize {
+    chips * (2 + ISSI_OPEN_REG_LEN)
+}
+
+/// Worst-case tx transaction size (SPI_TX_BUF_SIZE) for the given number of chips
+pub const fn required_tx_len(chips: usize) -> usize {
+    let mut len = page_tx_len(chips);
+    if detect_read_len(chips) > len {
+        len = detect_read_len(chips);
+    }
+    // Reset is the largest register sync transaction
+    if reg_sync_tx_len(chips, 6) > len {
+        len = reg_sync_tx_len(chips, 6);
+    }
+    len
+}
+
+/// Worst-case rx transaction size (SPI_RX_BUF_SIZE) for the given number of chips
+pub const fn required_rx_len(chips: usize) -> usize {
+    detect_read_len(chips)
+}
+
 /// Copies the sync expression to the buffer and returns the new position
 /// atsam4_reg_sync!(tx_buf, position, [cs0, cs1,...], page, register, value)
 macro_rules! atsam4_reg_sync {
@@ -117,17 +173,17 @@ macro_rules! atsam4_reg_sync {
 /// atsam4 specific implementation of Is31fl3743b (variable cs mode)
 ///
 /// ```ignore
-/// use is31fl3743b::Is31fl3743bAtsam4Dma;
+/// use is31fl3743b::{required_rx_len, required_tx_len, Is31fl3743bAtsam4Dma};
 ///
 /// const ISSI_DRIVER_CHIPS: usize = 2;
 /// const ISSI_DRIVER_QUEUE_SIZE: usize = 5;
 /// const ISSI_DRIVER_CS_LAYOUT: [u8; ISSI_DRIVER_CHIPS] = [0, 1];
 /// // Must be 256 or less, or a power of 2; e.g. 512 due limitations with embedded-dma
-/// // Actual value should be -> ISSI_DRIVER_CHIPS * 198 (e.g. 396);
+/// // Actual value should be -> required_tx_len(ISSI_DRIVER_CHIPS) (e.g. 400);
 /// // Size is determined by the largest SPI tx transaction
-/// const SPI_TX_BUF_SIZE: usize = 512;
+/// const SPI_TX_BUF_SIZE: usize = required_tx_len(ISSI_DRIVER_CHIPS).next_power_of_two();
 /// // Size is determined by the largest SPI rx transaction
-/// const SPI_RX_BUF_SIZE: usize = (32 + 2) * ISSI_DRIVER_CHIPS;
+/// const SPI_RX_BUF_SIZE: usize = required_rx_len(ISSI_DRIVER_CHIPS);
 ///
 /// #[init(local = [spi_tx_buf: [u32; SPI_TX_BUF_SIZE] = [0; SPI_TX_BUF_SIZE], spi_rx_buf: [u32; SPI_RX_BUF_SIZE] = [0; SPI_RX_BUF_SIZE],])]
 /// fn init(mut cx: init::Context) -> (Shared, Local, init::Monotonics) {
@@ -183,9 +239,7 @@ macro_rules! atsam4_reg_sync {
 ///     }
 ///
 ///     // Start ISSI LED Driver initialization
-///     issi.reset().unwrap(); // Queue reset DMA transaction
-///     issi.scaling().unwrap(); // Queue scaling default
-///     issi.pwm().unwrap(); // Queue pwm default
+///     issi.init_sequence().unwrap(); // Queue reset, scaling default and pwm default
 ///     let (rx_len, tx_len) = issi.tx_function(cx.local.spi_tx_buf).unwrap();
 ///     let spi_rxtx = spi.read_write_len(cx.local.spi_rx_buf, rx_len, cx.local.spi_tx_buf, tx_len);
 ///
@@ -249,6 +303,8 @@ pub struct Is31fl3743bAtsam4Dma<const CHIPS: usize, const QUEUE_SIZE: usize> {
     open_detect: [[u8; ISSI_OPEN_REG_LEN]; CHIPS],
     /// Holds most recent rx_len
     last_rx_len: usize,
+    /// Holds most recent tx_len
+    last_tx_len: usize,
 }
 
 impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QUEUE_SIZE> {
@@ -265,6 +321,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
             open_detect_ready: false,
             open_detect: [[0; ISSI_OPEN_REG_LEN]; CHIPS],
             last_rx_len: 0,
+            last_tx_len: 0,
         }
     }
 
@@ -278,6 +335,34 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         &mut self.page_buf.scaling
     }
 
+    /// Copies a logical RGB frame into the PWM page buffer using the wiring map
+    /// Queues a PWM update once the buffer has been updated
+    /// The map is validated before modifying the buffer
+    pub fn apply_rgb(&mut self, frame: &[[u8; 3]], map: &[RgbMap]) -> Result<(), IssiError> {
+        for entry in map {
+            if entry.pixel >= frame.len() {
+                return Err(IssiError::PixelOutOfRange(entry.pixel));
+            }
+            if entry.chip >= CHIPS {
+                return Err(IssiError::ChipOutOfRange(entry.chip));
+            }
+            for ch in [entry.r, entry.g, entry.b] {
+                if ch >= ISSI_PAGE_LEN {
+                    return Err(IssiError::ChannelOutOfRange(ch));
+                }
+            }
+        }
+
+        for entry in map {
+            let [r, g, b] = frame[entry.pixel];
+            let chip_buf = &mut self.page_buf.pwm[entry.chip];
+            chip_buf[entry.r] = r;
+            chip_buf[entry.g] = g;
+            chip_buf[entry.b] = b;
+        }
+        self.pwm()
+    }
+
     /// Called to process DMA data buffer (after interrupt)
     pub fn rx_function(&mut self, rx_buf: &[u32]) -> Result<(), IssiError> {
         // Dequeue function as we're finished with it
@@ -296,7 +381,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
             Function::Scaling => self.scaling_rx(rx_buf),
             Function::ShortCircuitDetectRead => self.short_circuit_detect_read_rx(rx_buf),
             Function::ShortCircuitDetectSetup => self.short_circuit_detect_setup_rx(rx_buf),
-            Function::SoftwareShutdown => self.software_shutdown_rx(rx_buf),
+            Function::SoftwareShutdown(_) => self.software_shutdown_rx(rx_buf),
             _ => Err(IssiError::UnhandledFunction(func)),
         }
     }
@@ -311,7 +396,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
             return Err(IssiError::FuncQueueEmpty);
         };
 
-        match func {
+        let lens = match func {
             Function::Brightness => self.brightness_set_tx(tx_buf),
             Function::OpenCircuitDetectRead => self.openshort_circuit_detect_read_tx(tx_buf),
             Function::OpenCircuitDetectSetup => self.open_circuit_detect_setup_tx(tx_buf),
@@ -320,11 +405,53 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
             Function::Scaling => self.scaling_tx(tx_buf),
             Function::ShortCircuitDetectRead => self.openshort_circuit_detect_read_tx(tx_buf),
             Function::ShortCircuitDetectSetup => self.short_circuit_detect_setup_tx(tx_buf),
-            Function::SoftwareShutdown => self.software_shutdown_tx(tx_buf),
+            Function::SoftwareShutdown(enable) => {
+                let enable = *enable;
+                self.software_shutdown_tx(tx_buf, enable)
+            }
             _ => Err(IssiError::UnhandledFunction(*func)),
+        }?;
+        self.last_tx_len = lens.1;
+        Ok(lens)
+    }
+
+    /// rx_len of the most recently prepared DMA transaction
+    pub fn last_rx_len(&self) -> usize {
+        self.last_rx_len
+    }
+
+    /// tx_len of the most recently prepared DMA transaction
+    /// Useful for sizing SPI_TX_BUF_SIZE
+    pub fn last_tx_len(&self) -> usize {
+        self.last_tx_len
+    }
+
+    /// Number of functions that can still be queued before FuncQueueFull
+    /// Note: a queue of QUEUE_SIZE holds at most QUEUE_SIZE - 1 functions
+    pub fn queue_space(&self) -> usize {
+        self.func_queue.capacity() - self.func_queue.len()
+    }
+
+    /// Makes sure the tx buffer can hold the transaction
+    fn check_tx_len(tx_buf: &[u32], needed: usize) -> Result<(), IssiError> {
+        if tx_buf.len() < needed {
+            Err(IssiError::PdcBufferTooSmall(needed, tx_buf.len()))
+        } else {
+            Ok(())
         }
     }
 
+    /// Queues the chip initialization sequence (Reset, Scaling then Pwm)
+    /// Nothing is queued if there isn't room for all three functions
+    pub fn init_sequence(&mut self) -> Result<(), IssiError> {
+        if self.queue_space() < 3 {
+            return Err(IssiError::FuncQueueFull);
+        }
+        self.reset()?;
+        self.scaling()?;
+        self.pwm()
+    }
+
     /// Triggers chip reset sequence
     pub fn reset(&mut self) -> Result<(), IssiError> {
         if self.func_queue.enqueue(Function::Reset).is_ok() {
@@ -339,6 +466,11 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     }
 
     fn reset_tx(&mut self, tx_buf: &mut [u32]) -> Result<(usize, usize), IssiError> {
+        // 4 registers for all chips, sync for all chips and (optionally) software shutdown
+        Self::check_tx_len(
+            tx_buf,
+            reg_sync_tx_len(CHIPS, 5 + if self.enable { 1 } else { 0 }),
+        )?;
         let chips = &self.cs;
         let (last, chips_except_last) = self.cs.split_last().unwrap();
         let last = [last];
@@ -409,6 +541,8 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     }
 
     fn scaling_tx(&mut self, tx_buf: &mut [u32]) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, page_tx_len(CHIPS))?;
+
         // Copy each byte from the shared buffer into the DMA/PDC buffer
         // The DMA format encodes the CS and last byte in a transaction
         let mut pos = 0;
@@ -452,6 +586,8 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     }
 
     fn pwm_tx(&mut self, tx_buf: &mut [u32]) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, page_tx_len(CHIPS))?;
+
         // Copy each byte from the shared buffer into the DMA/PDC buffer
         // The DMA format encodes the CS and last byte in a transaction
         let mut pos = 0;
@@ -486,21 +622,21 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     /// (Software Shutdown)
     pub fn enable(&mut self) -> Result<(), IssiError> {
         self.enable = true;
-        self.software_shutdown()
+        self.software_shutdown(true)
     }
 
     /// Disable LEDs on next process loop
     /// (Software Shutdown)
     pub fn disable(&mut self) -> Result<(), IssiError> {
         self.enable = false;
-        self.software_shutdown()
+        self.software_shutdown(false)
     }
 
     /// Toggle LEDs on next process loop
     /// (Software Shutdown)
     pub fn toggle(&mut self) -> Result<bool, IssiError> {
         self.enable = !self.enable;
-        self.software_shutdown()?;
+        self.software_shutdown(self.enable)?;
         Ok(self.enable)
     }
 
@@ -509,8 +645,12 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         self.enable
     }
 
-    fn software_shutdown(&mut self) -> Result<(), IssiError> {
-        if self.func_queue.enqueue(Function::SoftwareShutdown).is_ok() {
+    fn software_shutdown(&mut self, enable: bool) -> Result<(), IssiError> {
+        if self
+            .func_queue
+            .enqueue(Function::SoftwareShutdown(enable))
+            .is_ok()
+        {
             Ok(())
         } else {
             Err(IssiError::FuncQueueFull)
@@ -521,8 +661,13 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         Ok(())
     }
 
-    fn software_shutdown_tx(&mut self, tx_buf: &mut [u32]) -> Result<(usize, usize), IssiError> {
-        let pos = if self.enable {
+    fn software_shutdown_tx(
+        &mut self,
+        tx_buf: &mut [u32],
+        enable: bool,
+    ) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, reg_sync_tx_len(CHIPS, 1))?;
+        let pos = if enable {
             // Disable software shutdown
             atsam4_reg_sync!(tx_buf, 0, &self.cs, ISSI_CONFIG_PAGE, 0x00, 0x01)
         } else {
@@ -537,11 +682,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     /// Minimum value: 0x00
     /// Maximum value: 0xFF
     pub fn brightness_increase(&mut self, inc: u8) -> Result<u8, IssiError> {
-        let val = if inc as u16 + self.current_global_brightness as u16 > 0xFF {
-            0xFF
-        } else {
-            self.current_global_brightness + inc
-        };
+        let val = self.current_global_brightness.saturating_add(inc);
         self.brightness_set(val)?;
         Ok(val)
     }
@@ -550,11 +691,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     /// Minimum value: 0x00
     /// Maximum value: 0xFF
     pub fn brightness_decrease(&mut self, dec: u8) -> Result<u8, IssiError> {
-        let val = if self.current_global_brightness as i16 - (dec as i16) < 0 {
-            0x0
-        } else {
-            self.current_global_brightness - dec
-        };
+        let val = self.current_global_brightness.saturating_sub(dec);
         self.brightness_set(val)?;
         Ok(val)
     }
@@ -574,6 +711,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     }
 
     fn brightness_set_tx(&mut self, tx_buf: &mut [u32]) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, reg_sync_tx_len(CHIPS, 1))?;
         let pos = atsam4_reg_sync!(
             tx_buf,
             0,
@@ -631,6 +769,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         &mut self,
         tx_buf: &mut [u32],
     ) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, reg_sync_tx_len(CHIPS, 3))?;
         let chips = &self.cs;
         let pos = 0;
 
@@ -660,6 +799,8 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         &mut self,
         tx_buf: &mut [u32],
     ) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, detect_read_len(CHIPS))?;
+
         // The DMA format encodes the CS and last byte in a transaction
         let mut pos = 0;
         for cs in self.cs {
@@ -720,6 +861,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         &mut self,
         tx_buf: &mut [u32],
     ) -> Result<(usize, usize), IssiError> {
+        Self::check_tx_len(tx_buf, reg_sync_tx_len(CHIPS, 3))?;
         let chips = &self.cs;
         let pos = 0;
 
@@ -745,8 +887,20 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
         Ok(())
     }
 
+    /// Validates chip and channel indices for the open/short detect buffers
+    fn detect_index(chip: usize, ch: usize) -> Result<(), IssiError> {
+        if chip >= CHIPS {
+            Err(IssiError::ChipOutOfRange(chip))
+        } else if ch / 8 >= ISSI_OPEN_REG_LEN {
+            Err(IssiError::ChannelOutOfRange(ch))
+        } else {
+            Ok(())
+        }
+    }
+
     /// Can used to find open circuit channel positions after calling open_detect()
     pub fn open_circuit_lookup(&self, chip: usize, ch: usize) -> Result<bool, IssiError> {
+        Self::detect_index(chip, ch)?;
         if self.open_detect_ready {
             Ok((self.open_detect[chip][ch / 8] >> (ch % 8)) & 0x01 == 0x01)
         } else {
@@ -755,6 +909,7 @@ impl<const CHIPS: usize, const QUEUE_SIZE: usize> Is31fl3743bAtsam4Dma<CHIPS, QU
     }
 
     pub fn short_circuit_lookup(&self, chip: usize, ch: usize) -> Result<bool, IssiError> {
+        Self::detect_index(chip, ch)?;
         if self.short_detect_ready {
             Ok((self.short_detect[chip][ch / 8] >> (ch % 8)) & 0x01 == 0x01)
         } else {
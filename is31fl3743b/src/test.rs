@@ -0,0 +1,351 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg(test)]
+
+// ----- Crates -----
+
+use super::*;
+
+// ----- Types -----
+
+type TestIssi = Is31fl3743bAtsam4Dma<2, 8>;
+
+// ----- Tests -----
+
+#[test]
+fn detect_lookup_chip_out_of_range() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    issi.open_detect_ready = true;
+    issi.short_detect_ready = true;
+
+    assert_eq!(
+        issi.open_circuit_lookup(2, 0),
+        Err(IssiError::ChipOutOfRange(2))
+    );
+    assert_eq!(
+        issi.short_circuit_lookup(2, 0),
+        Err(IssiError::ChipOutOfRange(2))
+    );
+}
+
+#[test]
+fn detect_lookup_channel_out_of_range() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    issi.open_detect_ready = true;
+    issi.short_detect_ready = true;
+    issi.open_detect[1][ISSI_OPEN_REG_LEN - 1] = 0x80;
+
+    // Last valid channel
+    let last = ISSI_OPEN_REG_LEN * 8 - 1;
+    assert_eq!(issi.open_circuit_lookup(1, last), Ok(true));
+    assert_eq!(issi.short_circuit_lookup(1, last), Ok(false));
+
+    assert_eq!(
+        issi.open_circuit_lookup(1, last + 1),
+        Err(IssiError::ChannelOutOfRange(last + 1))
+    );
+    assert_eq!(
+        issi.short_circuit_lookup(1, last + 1),
+        Err(IssiError::ChannelOutOfRange(last + 1))
+    );
+}
+
+#[test]
+fn last_transaction_len() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    let mut tx_buf = [0u32; 512];
+    assert_eq!(issi.last_tx_len(), 0);
+
+    issi.scaling().unwrap();
+    let (rx_len, tx_len) = issi.tx_function(&mut tx_buf).unwrap();
+
+    // Page select + start register + page data, per chip
+    assert_eq!(tx_len, 2 * (2 + ISSI_PAGE_LEN));
+    assert_eq!(issi.last_tx_len(), tx_len);
+    assert_eq!(issi.last_rx_len(), rx_len);
+
+    // Still available after the transaction completes
+    issi.rx_function(&[]).unwrap();
+    assert_eq!(issi.last_tx_len(), tx_len);
+}
+
+#[test]
+fn tx_buffer_too_small() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    let mut tx_buf = [0u32; 100];
+
+    assert_eq!(
+        issi.pwm_tx(&mut tx_buf),
+        Err(IssiError::PdcBufferTooSmall(2 * (2 + ISSI_PAGE_LEN), 100))
+    );
+    assert_eq!(
+        issi.scaling_tx(&mut tx_buf),
+        Err(IssiError::PdcBufferTooSmall(2 * (2 + ISSI_PAGE_LEN), 100))
+    );
+    assert_eq!(
+        issi.brightness_set_tx(&mut tx_buf[..5]),
+        Err(IssiError::PdcBufferTooSmall(6, 5))
+    );
+
+    // Queued functions report the error through tx_function
+    issi.pwm().unwrap();
+    assert_eq!(
+        issi.tx_function(&mut tx_buf),
+        Err(IssiError::PdcBufferTooSmall(2 * (2 + ISSI_PAGE_LEN), 100))
+    );
+}
+
+/// Runs a pwm transaction with a buffer of exactly required_tx_len
+fn pwm_tx_len<const CHIPS: usize>(cs: [u8; CHIPS]) -> usize {
+    let mut issi = Is31fl3743bAtsam4Dma::<CHIPS, 8>::new(cs, 0xFF, true);
+    let mut tx_buf = [0u32; 1024];
+    let (_, tx_len) = issi.pwm_tx(&mut tx_buf[..required_tx_len(CHIPS)]).unwrap();
+    tx_len
+}
+
+#[test]
+fn required_buffer_len() {
+    assert_eq!(pwm_tx_len([0]), required_tx_len(1));
+    assert_eq!(pwm_tx_len([0, 1]), required_tx_len(2));
+    assert_eq!(pwm_tx_len([0, 1, 2, 3]), required_tx_len(4));
+
+    // Usable at compile time
+    const TX_LEN: usize = required_tx_len(2);
+    const RX_LEN: usize = required_rx_len(2);
+    assert_eq!(TX_LEN, 400);
+    assert_eq!(RX_LEN, 2 * (2 + ISSI_OPEN_REG_LEN));
+}
+
+#[test]
+fn apply_rgb() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    let frame = [[0x10, 0x20, 0x30], [0x40, 0x50, 0x60]];
+    let map = [
+        RgbMap {
+            pixel: 0,
+            chip: 0,
+            r: 0,
+            g: 1,
+            b: 2,
+        },
+        RgbMap {
+            pixel: 1,
+            chip: 1,
+            r: 20,
+            g: 10,
+            b: ISSI_PAGE_LEN - 1,
+        },
+    ];
+
+    issi.apply_rgb(&frame, &map).unwrap();
+    assert_eq!(issi.func_queue.dequeue(), Some(Function::Pwm));
+
+    let pwm = issi.pwm_page_buf();
+    assert_eq!(pwm[0][..4], [0x10, 0x20, 0x30, 0x00]);
+    assert_eq!(pwm[1][20], 0x40);
+    assert_eq!(pwm[1][10], 0x50);
+    assert_eq!(pwm[1][ISSI_PAGE_LEN - 1], 0x60);
+    assert_eq!(pwm[1][0], 0x00);
+
+    // Invalid map entries leave the buffer untouched
+    let frame = [[0xFF; 3]];
+    let mut bad = map;
+    assert_eq!(
+        issi.apply_rgb(&frame, &bad),
+        Err(IssiError::PixelOutOfRange(1))
+    );
+    bad[1].pixel = 0;
+    bad[1].chip = 2;
+    assert_eq!(
+        issi.apply_rgb(&frame, &bad),
+        Err(IssiError::ChipOutOfRange(2))
+    );
+    bad[1].chip = 1;
+    bad[1].b = ISSI_PAGE_LEN;
+    assert_eq!(
+        issi.apply_rgb(&frame, &bad),
+        Err(IssiError::ChannelOutOfRange(ISSI_PAGE_LEN))
+    );
+    assert_eq!(issi.pwm_page_buf()[0][0], 0x10);
+    assert_eq!(issi.func_queue.dequeue(), None);
+}
+
+#[test]
+fn brightness_saturates() {
+    let mut issi = TestIssi::new([0, 1], 0xF0, true);
+
+    assert_eq!(issi.brightness_increase(0x0F), Ok(0xFF));
+    assert_eq!(issi.brightness_increase(0x01), Ok(0xFF));
+    assert_eq!(issi.brightness_increase(0xFF), Ok(0xFF));
+    assert_eq!(issi.brightness(), 0xFF);
+    while issi.func_queue.dequeue().is_some() {}
+
+    issi.brightness_set(0x10).unwrap();
+    assert_eq!(issi.brightness_decrease(0x10), Ok(0x00));
+    assert_eq!(issi.brightness_decrease(0x01), Ok(0x00));
+    assert_eq!(issi.brightness_decrease(0xFF), Ok(0x00));
+    assert_eq!(issi.brightness(), 0x00);
+}
+
+#[test]
+fn software_shutdown_queued_state() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, false);
+    let mut tx_buf = [0u32; 512];
+
+    // Both queued before the first DMA transaction
+    issi.enable().unwrap();
+    issi.disable().unwrap();
+    assert!(!issi.enabled());
+
+    let (_, tx_len) = issi.tx_function(&mut tx_buf).unwrap();
+    assert_eq!(tx_len, reg_sync_tx_len(2, 1));
+    assert_eq!(tx_buf[2], atsam4_var_spi(0x01, 0, true));
+    assert_eq!(tx_buf[5], atsam4_var_spi(0x01, 1, true));
+    issi.rx_function(&[]).unwrap();
+
+    issi.tx_function(&mut tx_buf).unwrap();
+    assert_eq!(tx_buf[2], atsam4_var_spi(0x00, 0, true));
+    assert_eq!(tx_buf[5], atsam4_var_spi(0x00, 1, true));
+    issi.rx_function(&[]).unwrap();
+
+    // Toggle reports the state that was queued
+    assert_eq!(issi.toggle(), Ok(true));
+    assert_eq!(issi.toggle(), Ok(false));
+    assert_eq!(
+        issi.func_queue.dequeue(),
+        Some(Function::SoftwareShutdown(true))
+    );
+    assert_eq!(
+        issi.func_queue.dequeue(),
+        Some(Function::SoftwareShutdown(false))
+    );
+}
+
+#[test]
+fn queue_backpressure() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    assert_eq!(issi.queue_space(), 7);
+
+    for space in (0..7).rev() {
+        issi.pwm().unwrap();
+        assert_eq!(issi.queue_space(), space);
+    }
+
+    assert_eq!(issi.pwm(), Err(IssiError::FuncQueueFull));
+    assert_eq!(issi.reset(), Err(IssiError::FuncQueueFull));
+    assert_eq!(issi.queue_space(), 0);
+
+    // Space frees up once a transaction completes
+    issi.rx_function(&[]).unwrap();
+    assert_eq!(issi.queue_space(), 1);
+}
+
+#[test]
+fn init_sequence() {
+    let mut issi = TestIssi::new([0, 1], 0xFF, true);
+    issi.init_sequence().unwrap();
+    assert_eq!(issi.func_queue.dequeue(), Some(Function::Reset));
+    assert_eq!(issi.func_queue.dequeue(), Some(Function::Scaling));
+    assert_eq!(issi.func_queue.dequeue(), Some(Function::Pwm));
+    assert_eq!(issi.func_queue.dequeue(), None);
+
+    // Not enough room, nothing is queued
+    for _ in 0..5 {
+        issi.brightness_set(0x10).unwrap();
+    }
+    assert_eq!(issi.init_sequence(), Err(IssiError::FuncQueueFull));
+    assert_eq!(issi.queue_space(), 2);
+}
+
+#[cfg(feature = "kll-core")]
+mod fade {
+    use super::*;
+    use crate::fade::{FadeControl, FadeProfile};
+    use kll_core::pixel::FadeCommand;
+
+    const DEFAULTS: [FadeProfile; 2] = [FadeProfile::new(0x80, 0), FadeProfile::new(0x40, 0x20)];
+
+    /// Runs a fade tick and removes the queued brightness function
+    fn tick(fade: &mut FadeControl<2>, issi: &mut TestIssi) -> Option<u8> {
+        let val = fade.tick(issi).unwrap();
+        if val.is_some() {
+            assert_eq!(issi.func_queue.dequeue(), Some(Function::Brightness));
+            assert_eq!(issi.brightness(), val.unwrap());
+        }
+        assert_eq!(issi.func_queue.dequeue(), None);
+        val
+    }
+
+    #[test]
+    fn brightness_set() {
+        let mut issi = TestIssi::new([0, 1], 0x80, true);
+        let mut fade = FadeControl::new(DEFAULTS, 0x80);
+
+        // Already at the target brightness
+        assert_eq!(tick(&mut fade, &mut issi), None);
+
+        // Immediate
+        fade.command(0, FadeCommand::BrightnessSet, 0x10).unwrap();
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x10));
+        assert_eq!(tick(&mut fade, &mut issi), None);
+
+        // Stepped
+        fade.command(1, FadeCommand::BrightnessSet, 0x50).unwrap();
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x30));
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x50));
+        assert_eq!(tick(&mut fade, &mut issi), None);
+
+        assert_eq!(
+            fade.command(2, FadeCommand::BrightnessSet, 0x10),
+            Err(IssiError::ProfileOutOfRange(2))
+        );
+    }
+
+    #[test]
+    fn brightness_increment() {
+        let mut issi = TestIssi::new([0, 1], 0x80, true);
+        let mut fade = FadeControl::new(DEFAULTS, 0x80);
+
+        fade.command(0, FadeCommand::BrightnessIncrement, 0x10)
+            .unwrap();
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x90));
+
+        // Saturates
+        fade.command(0, FadeCommand::BrightnessIncrement, 0xFF)
+            .unwrap();
+        assert_eq!(tick(&mut fade, &mut issi), Some(0xFF));
+
+        fade.command(0, FadeCommand::BrightnessDecrement, 0x0F)
+            .unwrap();
+        assert_eq!(tick(&mut fade, &mut issi), Some(0xF0));
+    }
+
+    #[test]
+    fn reset() {
+        let mut issi = TestIssi::new([0, 1], 0x80, true);
+        let mut fade = FadeControl::new(DEFAULTS, 0x80);
+
+        fade.command(0, FadeCommand::BrightnessSet, 0x10).unwrap();
+        fade.command(1, FadeCommand::BrightnessSet, 0x10).unwrap();
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x60));
+
+        // Reset a single profile
+        fade.command(1, FadeCommand::Reset, 0).unwrap();
+        assert_eq!(fade.profile(0), Some(&FadeProfile::new(0x10, 0)));
+        assert_eq!(fade.profile(1), Some(&DEFAULTS[1]));
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x40));
+        assert_eq!(tick(&mut fade, &mut issi), None);
+
+        // Reset all profiles
+        fade.command(0, FadeCommand::BrightnessSet, 0x10).unwrap();
+        fade.command(0, FadeCommand::ResetAll, 0).unwrap();
+        assert_eq!(fade.profile(0), Some(&DEFAULTS[0]));
+        assert_eq!(fade.profile(1), Some(&DEFAULTS[1]));
+        assert_eq!(tick(&mut fade, &mut issi), Some(0x80));
+    }
+}
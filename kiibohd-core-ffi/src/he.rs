@@ -94,13 +94,13 @@ pub unsafe extern "C" fn he_scan_event(
     };
 
     match intf.add::<SENSE_ACCUMULATION>(index as usize, val) {
-        Ok(Some(data)) => {
+        Ok((Some(data), _)) => {
             *analysis = data.clone();
             HeStatus::AnalysisReady
         }
-        Ok(None) => HeStatus::Success,
+        Ok((None, _)) => HeStatus::Success,
         Err(err) => match err {
-            SensorError::CalibrationError(data) => match data.cal {
+            SensorError::CalibrationError(status, _) => match status {
                 CalibrationStatus::NotReady => HeStatus::ErrorSensorNotReady,
                 _ => HeStatus::ErrorUnknown,
             },
@@ -135,13 +135,13 @@ pub unsafe extern "C" fn he_test_event(
             index as usize,
             val,
         ) {
-        Ok(Some(data)) => {
+        Ok((Some(data), _)) => {
             *analysis = data.clone();
             HeStatus::AnalysisReady
         }
-        Ok(None) => HeStatus::Success,
+        Ok((None, _)) => HeStatus::Success,
         Err(err) => match err {
-            SensorError::CalibrationError(data) => match data.cal {
+            SensorError::CalibrationError(status, _) => match status {
                 CalibrationStatus::MagnetWrongPoleOrMissing => {
                     HeStatus::ErrorMagnetWrongPoleOrMissing
                 }
@@ -168,7 +168,7 @@ pub extern "C" fn he_calibration(index: u16) -> CalibrationStatus {
         };
 
         match intf.get_data(index as usize) {
-            Ok(results) => results.cal.clone(),
+            Ok(results) => results.cal,
             Err(_) => CalibrationStatus::InvalidIndex,
         }
     }
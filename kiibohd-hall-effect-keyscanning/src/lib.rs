@@ -7,8 +7,10 @@
 
 #![no_std]
 
+mod test;
+
 use embedded_hal::digital::v2::OutputPin;
-use kiibohd_hall_effect::{SenseAnalysis, SensorError, Sensors};
+use kiibohd_hall_effect::{CalibrationStatus, SenseAnalysis, SensorError, Sensors};
 
 /// Handles strobing the Hall Effect sensor matrix
 /// ADC reading is handled separately as the current embedded-hal doesn't work
@@ -46,6 +48,8 @@ use kiibohd_hall_effect::{SenseAnalysis, SensorError, Sensors};
 pub struct Matrix<C: OutputPin, const CSIZE: usize, const MSIZE: usize, const INVERT_STROBE: bool> {
     cols: [C; CSIZE],
     cur_strobe: usize,
+    strobe_order: [usize; CSIZE],
+    strobe_pos: usize,
     sensors: Sensors<MSIZE>,
 }
 
@@ -57,13 +61,15 @@ impl<C: OutputPin, const CSIZE: usize, const MSIZE: usize, const INVERT_STROBE:
         let res = Self {
             cols,
             cur_strobe: CSIZE - 1,
+            strobe_order: core::array::from_fn(|i| i),
+            strobe_pos: CSIZE - 1,
             sensors,
         };
         Ok(res)
     }
 
     /// Clears strobes
-    /// Resets strobe counter to the last element (so next_strobe starts at 0)
+    /// Resets strobe counter to the last element (so next_strobe starts at the first strobe)
     pub fn clear<'a, E: 'a>(&'a mut self) -> Result<(), E>
     where
         C: OutputPin<Error = E>,
@@ -77,10 +83,32 @@ impl<C: OutputPin, const CSIZE: usize, const MSIZE: usize, const INVERT_STROBE:
             }
         }
         // Reset strobe position
-        self.cur_strobe = CSIZE - 1;
+        self.strobe_pos = CSIZE - 1;
+        self.cur_strobe = self.strobe_order[self.strobe_pos];
         Ok(())
     }
 
+    /// Set the order in which columns are strobed (defaults to 0..CSIZE)
+    /// e.g. an interleaved order to spread EMI
+    /// The next call to next_strobe starts at the beginning of the new order
+    ///
+    /// Returns false (and the order is unchanged) if the order is not a permutation of the columns
+    pub fn set_strobe_order(&mut self, order: [usize; CSIZE]) -> bool {
+        // Each column must be strobed exactly once
+        let mut seen = [false; CSIZE];
+        for &col in order.iter() {
+            if col >= CSIZE || seen[col] {
+                return false;
+            }
+            seen[col] = true;
+        }
+
+        // Current strobe is left as-is so next_strobe unsets the correct column
+        self.strobe_order = order;
+        self.strobe_pos = CSIZE - 1;
+        true
+    }
+
     /// Next strobe
     pub fn next_strobe<'a, E: 'a>(&'a mut self) -> Result<usize, E>
     where
@@ -94,11 +122,12 @@ impl<C: OutputPin, const CSIZE: usize, const MSIZE: usize, const INVERT_STROBE:
         }
 
         // Check for roll-over condition
-        if self.cur_strobe >= CSIZE - 1 {
-            self.cur_strobe = 0;
+        if self.strobe_pos >= CSIZE - 1 {
+            self.strobe_pos = 0;
         } else {
-            self.cur_strobe += 1;
+            self.strobe_pos += 1;
         }
+        self.cur_strobe = self.strobe_order[self.strobe_pos];
 
         // Set new strobe
         if INVERT_STROBE {
@@ -118,11 +147,46 @@ impl<C: OutputPin, const CSIZE: usize, const MSIZE: usize, const INVERT_STROBE:
     /// Record ADC Hall Effect reading for the given the current row/sense index
     /// The sense index is usually 0-5, though it depends on the typical setup
     /// SC: Sample Count - How many samples before computing an analysis for a given index
+    /// Also returns the new calibration status of the index, if it changed
     pub fn record<const SC: usize>(
         &mut self,
         index: usize,
         value: u16,
-    ) -> Result<Option<&SenseAnalysis>, SensorError> {
+    ) -> Result<(Option<&SenseAnalysis>, Option<CalibrationStatus>), SensorError> {
         self.sensors.add::<SC>(index, value)
     }
+
+    /// Calibration progress of the matrix
+    /// Returns (calibrated sensors, total sensors)
+    pub fn calibration_progress(&self) -> (usize, usize) {
+        let ready = (0..MSIZE)
+            .filter(|&index| match self.sensors.get_data(index) {
+                Ok(data) => data.cal == CalibrationStatus::MagnetDetected,
+                Err(_) => false,
+            })
+            .count();
+        (ready, MSIZE)
+    }
+
+    /// Returns true once every sensor in the matrix has been calibrated
+    pub fn all_calibrated(&self) -> bool {
+        let (ready, total) = self.calibration_progress();
+        ready == total
+    }
+
+    /// Record ADC Hall Effect readings for each of the sense lines of the current strobe
+    /// Readings are ordered by sense index (usually 0-5)
+    /// SC: Sample Count - How many samples before computing an analysis for a given index
+    pub fn record_column<const SC: usize>(&mut self, readings: &[u16]) -> Result<(), SensorError> {
+        let rsize = MSIZE / CSIZE;
+        for (sense, value) in readings.iter().enumerate() {
+            // Determine matrix index
+            let index = self.cur_strobe * rsize + sense;
+            if sense >= rsize {
+                return Err(SensorError::InvalidSensor(index));
+            }
+            self.sensors.add::<SC>(index, *value)?;
+        }
+        Ok(())
+    }
 }
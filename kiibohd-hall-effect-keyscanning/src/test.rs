@@ -0,0 +1,124 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg(test)]
+
+// ----- Crates -----
+
+use super::*;
+use core::convert::Infallible;
+
+// ----- Types -----
+
+const CSIZE: usize = 2;
+const RSIZE: usize = 3;
+const MSIZE: usize = RSIZE * CSIZE;
+
+type TestMatrix = Matrix<MockPin, CSIZE, MSIZE, false>;
+
+// ----- Structs -----
+
+/// Mock strobe GPIO
+struct MockPin {
+    high: bool,
+}
+
+impl OutputPin for MockPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.high = true;
+        Ok(())
+    }
+}
+
+// ----- Functions -----
+
+fn test_matrix() -> TestMatrix {
+    TestMatrix::new([MockPin { high: false }, MockPin { high: false }]).unwrap()
+}
+
+// ----- Tests -----
+
+#[test]
+fn record_column() {
+    let mut matrix = test_matrix();
+
+    // Strobe the second column
+    assert_eq!(matrix.next_strobe::<Infallible>().unwrap(), 0);
+    assert_eq!(matrix.next_strobe::<Infallible>().unwrap(), 1);
+    assert!(!matrix.cols[0].high);
+    assert!(matrix.cols[1].high);
+
+    let readings = [1500, 1600, 1700];
+    assert!(matrix.record_column::<1>(&readings).is_ok());
+
+    // First column has no data
+    for index in 0..RSIZE {
+        assert!(matrix.sensors.get_data(index).is_err());
+    }
+
+    // Second column
+    for (sense, reading) in readings.iter().enumerate() {
+        let data = matrix.sensors.get_data(RSIZE + sense).unwrap();
+        assert_eq!(data.stats.min, *reading);
+        assert_eq!(data.stats.samples, 1);
+    }
+}
+
+#[test]
+fn record_column_too_many_readings() {
+    let mut matrix = test_matrix();
+    assert_eq!(matrix.next_strobe::<Infallible>().unwrap(), 0);
+
+    assert!(matches!(
+        matrix.record_column::<1>(&[1500, 1600, 1700, 1800]),
+        Err(SensorError::InvalidSensor(3))
+    ));
+}
+
+#[test]
+fn calibration_progress() {
+    let mut matrix = test_matrix();
+    assert_eq!(matrix.calibration_progress(), (0, MSIZE));
+    assert!(!matrix.all_calibrated());
+
+    // First column
+    assert_eq!(matrix.next_strobe::<Infallible>().unwrap(), 0);
+    assert!(matrix.record_column::<1>(&[1500, 1600, 1700]).is_ok());
+    assert_eq!(matrix.calibration_progress(), (RSIZE, MSIZE));
+    assert!(!matrix.all_calibrated());
+
+    // Second column
+    assert_eq!(matrix.next_strobe::<Infallible>().unwrap(), 1);
+    assert!(matrix.record_column::<1>(&[1500, 1600, 1700]).is_ok());
+    assert_eq!(matrix.calibration_progress(), (MSIZE, MSIZE));
+    assert!(matrix.all_calibrated());
+}
+
+#[test]
+fn strobe_order_reversed() {
+    let mut matrix = test_matrix();
+
+    // Not a permutation of the columns
+    assert!(!matrix.set_strobe_order([1, 1]));
+    assert!(!matrix.set_strobe_order([0, 2]));
+
+    assert!(matrix.set_strobe_order([1, 0]));
+    for _ in 0..2 {
+        for strobe in [1, 0] {
+            assert_eq!(matrix.next_strobe::<Infallible>().unwrap(), strobe);
+            assert!(matrix.cols[strobe].high);
+            assert!(!matrix.cols[1 - strobe].high);
+        }
+    }
+}
@@ -37,12 +37,25 @@ use log::*;
 // TODO Use features to determine which lookup table to use
 use rawlookup::MODEL;
 
+/// ADC resolution of the lookup table
+const MODEL_BITS: usize = MODEL.len().trailing_zeros() as usize;
+
+/// Normalizes a raw ADC_BITS resolution reading into the lookup table index range
+fn model_index<const ADC_BITS: usize>(raw: u16) -> usize {
+    if ADC_BITS < MODEL_BITS {
+        (raw as usize) << (MODEL_BITS - ADC_BITS)
+    } else {
+        (raw as usize) >> (ADC_BITS - MODEL_BITS)
+    }
+}
+
 // ----- Sense Data -----
 
 /// Calibration status indicates if a sensor position is ready to send
 /// analysis for a particular key.
 #[repr(C)]
-#[derive(Clone, Debug, PartialEq, defmt::Format)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum CalibrationStatus {
     NotReady = 0,                 // Still trying to determine status (from power-on)
     SensorMissing = 1,            // ADC value at 0
@@ -52,13 +65,27 @@ pub enum CalibrationStatus {
     InvalidIndex = 5, // Invalid index
 }
 
-#[derive(Clone, Debug, defmt::Format)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub enum SensorError {
-    CalibrationError(SenseData),
+    /// Calibration status and sensor index
+    CalibrationError(CalibrationStatus, usize),
     FailedToResize(usize),
     InvalidSensor(usize),
 }
 
+impl SensorError {
+    /// Sets the sensor index of a CalibrationError
+    fn with_index(self, index: usize) -> Self {
+        match self {
+            SensorError::CalibrationError(status, _) => {
+                SensorError::CalibrationError(status, index)
+            }
+            err => err,
+        }
+    }
+}
+
 /// Calculations:
 ///  d = linearized(adc sample) --> distance
 ///  v = (d - d_prev) / 1       --> velocity
@@ -67,7 +94,8 @@ pub enum SensorError {
 ///
 /// These calculations assume constant time delta of 1
 #[repr(C)]
-#[derive(Clone, Debug, defmt::Format)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub struct SenseAnalysis {
     raw: u16,          // Raw ADC reading
     distance: i16,     // Distance value (lookup + min/max alignment)
@@ -79,9 +107,26 @@ pub struct SenseAnalysis {
 impl SenseAnalysis {
     /// Using the raw value do calculations
     /// Requires the previous analysis
-    pub fn new(raw: u16, data: &SenseData) -> SenseAnalysis {
-        // Do raw lookup (we've already checked the bounds)
-        let initial_distance = MODEL[raw as usize];
+    /// ADC_BITS: ADC resolution of the raw value, normalized to the lookup table resolution
+    /// Returns a null analysis if the raw (or min) value is outside of the lookup table
+    pub fn new<const ADC_BITS: usize>(raw: u16, data: &SenseData) -> SenseAnalysis {
+        SenseAnalysis::analyze::<ADC_BITS>(raw, &data.analysis, &data.stats)
+    }
+
+    /// Same as new(), using the previous analysis and stats of the sensor
+    fn analyze<const ADC_BITS: usize>(
+        raw: u16,
+        previous: &SenseAnalysis,
+        stats: &SenseStats,
+    ) -> SenseAnalysis {
+        // Do raw lookup
+        let initial_distance = match MODEL.get(model_index::<ADC_BITS>(raw)) {
+            Some(distance) => *distance,
+            None => {
+                warn!("Raw value out of range: {} ({} bits)", raw, ADC_BITS);
+                return SenseAnalysis::null();
+            }
+        };
 
         /*
         // Min/max adjustment
@@ -98,13 +143,19 @@ impl SenseAnalysis {
             }
         };
         */
-        let distance_offset = MODEL[data.stats.min as usize];
+        let distance_offset = match MODEL.get(model_index::<ADC_BITS>(stats.min)) {
+            Some(distance) => *distance,
+            None => {
+                warn!("Min value out of range: {} ({} bits)", stats.min, ADC_BITS);
+                return SenseAnalysis::null();
+            }
+        };
         let distance = initial_distance - distance_offset;
-        let velocity = distance - data.analysis.distance; // / 1
-        let acceleration = (velocity - data.analysis.velocity) / 2;
+        let velocity = distance - previous.distance; // / 1
+        let acceleration = (velocity - previous.velocity) / 2;
         // NOTE: To use jerk, the compile-time thresholds will need to be
         //       multiplied by 3 (to account for the missing / 3)
-        let jerk = acceleration - data.analysis.acceleration;
+        let jerk = acceleration - previous.acceleration;
         SenseAnalysis {
             raw,
             distance,
@@ -124,11 +175,67 @@ impl SenseAnalysis {
             jerk: 0,
         }
     }
+
+    /// Builds an analysis from already computed values
+    /// Useful for testing downstream logic without ADC samples
+    pub fn from_parts(
+        raw: u16,
+        distance: i16,
+        velocity: i16,
+        acceleration: i16,
+        jerk: i16,
+    ) -> SenseAnalysis {
+        SenseAnalysis {
+            raw,
+            distance,
+            velocity,
+            acceleration,
+            jerk,
+        }
+    }
+
+    /// Raw ADC reading
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
+    /// Distance value (lookup + min/max alignment)
+    pub fn distance(&self) -> i16 {
+        self.distance
+    }
+
+    /// Velocity calculation
+    pub fn velocity(&self) -> i16 {
+        self.velocity
+    }
+
+    /// Acceleration calculation (missing / 2)
+    pub fn acceleration(&self) -> i16 {
+        self.acceleration
+    }
+
+    /// Jerk calculation (missing / 3)
+    pub fn jerk(&self) -> i16 {
+        self.jerk
+    }
+}
+
+/// Compile-time validation of the sample count (SC)
+struct SampleCount<const SC: usize>;
+
+impl<const SC: usize> SampleCount<SC> {
+    /// SC must be a power of two that fits in the u8 sample counter
+    /// This also guarantees scratch accumulation can't overflow (128 * 0xFFFF * 2 < u32::MAX)
+    const VALID: () = assert!(
+        SC.is_power_of_two() && SC <= 128,
+        "SC must be a power of two between 1 and 128"
+    );
 }
 
 /// Stores incoming raw samples
 #[repr(C)]
-#[derive(Clone, Debug, defmt::Format)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub struct RawData {
     scratch_samples: u8,
     scratch: u32,
@@ -147,10 +254,15 @@ impl RawData {
     /// Adds to the internal scratch location
     /// Designed to accumulate until a set number of readings added
     /// SC: specifies the number of scratch samples until ready to average
-    ///     Should be a power of two (1, 2, 4, 8, 16...) for the compiler to
-    ///     optimize.
+    ///     Must be a power of two (1, 2, 4, 8, 16...128), enforced at compile time
+    ///
+    /// Smoothing is a moving average of the last two windows (2 * SC samples).
+    /// The first window (or first window after a reset) is averaged on its own.
     fn add<const SC: usize>(&mut self, reading: u16) -> Option<u16> {
-        self.scratch += reading as u32;
+        #[allow(clippy::let_unit_value)]
+        let _ = SampleCount::<SC>::VALID;
+
+        self.scratch = self.scratch.saturating_add(reading as u32);
         self.scratch_samples += 1;
         trace!(
             "Reading: {}  Sample: {}/{}",
@@ -163,8 +275,8 @@ impl RawData {
             let val = if self.prev_scratch == 0 {
                 self.scratch / SC as u32
             } else {
-                // Average previous value if non-zero
-                (self.scratch + self.prev_scratch) / SC as u32 / SC as u32
+                // Average with the previous window if non-zero
+                self.scratch.saturating_add(self.prev_scratch) / (2 * SC as u32)
             };
             self.prev_scratch = self.scratch;
             self.scratch = 0;
@@ -185,11 +297,13 @@ impl RawData {
 
 /// Sense stats include statistically information about the sensor data
 #[repr(C)]
-#[derive(Clone, Debug, defmt::Format)]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
 pub struct SenseStats {
-    pub min: u16,     // Minimum raw value (reset when out of calibration)
-    pub max: u16,     // Maximum raw value (reset when out of calibration)
-    pub samples: u32, // Total number of samples (does not reset)
+    pub min: u16,                       // Minimum raw value (reset when out of calibration)
+    pub max: u16,                       // Maximum raw value (reset when out of calibration)
+    pub samples: u32,                   // Total number of samples (does not reset)
+    pub samples_since_calibration: u32, // Samples since calibration (reset when out of calibration)
 }
 
 impl SenseStats {
@@ -198,16 +312,121 @@ impl SenseStats {
             min: 0xFFFF,
             max: 0x0000,
             samples: 0,
+            samples_since_calibration: 0,
         }
     }
 
-    /// Reset, resettable stats (e.g. min, max, but not samples)
+    /// Reset, resettable stats (e.g. min, max, samples_since_calibration, but not samples)
     fn reset(&mut self) {
         self.min = 0xFFFF;
         self.max = 0x0000;
+        self.samples_since_calibration = 0;
+    }
+
+    /// Update stats with a new averaged reading
+    fn add(&mut self, data: u16) {
+        if data > self.max {
+            self.max = data;
+        }
+        if data < self.min {
+            self.min = data;
+        }
+        self.samples = self.samples.wrapping_add(1);
+        self.samples_since_calibration = self.samples_since_calibration.saturating_add(1);
+    }
+}
+
+/// Buffer of recent raw readings, oldest first
+#[cfg(feature = "diagnostics")]
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+struct ReadingHistory<const H: usize> {
+    readings: [u16; H],
+    len: usize,
+}
+
+#[cfg(feature = "diagnostics")]
+impl<const H: usize> ReadingHistory<H> {
+    fn new() -> ReadingHistory<H> {
+        ReadingHistory {
+            readings: [0; H],
+            len: 0,
+        }
+    }
+
+    /// Adds a reading, dropping the oldest reading once full
+    /// The readings are shifted so they stay in chronological order
+    fn push(&mut self, reading: u16) {
+        if H == 0 {
+            return;
+        }
+        if self.len < H {
+            self.len += 1;
+        } else {
+            self.readings.copy_within(1.., 0);
+        }
+        self.readings[self.len - 1] = reading;
+    }
+
+    fn readings(&self) -> &[u16] {
+        &self.readings[..self.len]
     }
 }
 
+/// Distribution of a set of raw readings
+#[cfg(feature = "diagnostics")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct ReadingStats {
+    pub count: usize,
+    pub mean: u16,   // Rounded down
+    pub stddev: u16, // Population standard deviation, rounded down
+}
+
+#[cfg(feature = "diagnostics")]
+impl ReadingStats {
+    /// Computes the mean and standard deviation of the readings
+    /// Returns None if there are no readings
+    pub fn new(readings: &[u16]) -> Option<ReadingStats> {
+        if readings.is_empty() {
+            return None;
+        }
+        let count = readings.len() as u64;
+        let sum: u64 = readings.iter().map(|&r| r as u64).sum();
+        let mean = sum / count;
+        let variance = readings
+            .iter()
+            .map(|&r| {
+                let diff = (r as i64 - mean as i64).unsigned_abs();
+                diff * diff
+            })
+            .sum::<u64>()
+            / count;
+
+        Some(ReadingStats {
+            count: readings.len(),
+            mean: mean as u16,
+            stddev: isqrt(variance) as u16,
+        })
+    }
+}
+
+/// Integer square root (rounded down)
+#[cfg(feature = "diagnostics")]
+fn isqrt(val: u64) -> u64 {
+    if val < 2 {
+        return val;
+    }
+    // Newton's method
+    let mut x = val;
+    let mut y = x.div_ceil(2);
+    while y < x {
+        x = y;
+        y = (x + val / x) / 2;
+    }
+    x
+}
+
 /// Sense data is store per ADC source element (e.g. per key)
 /// The analysis is stored in a queue, where old values expire out
 /// min/max is used to handle offsets from the distance lookups
@@ -251,52 +470,93 @@ impl SenseStats {
 /// * MNOK: Min valid calibration (Wrong magnet direction; wrong pole, less than a specific value)
 /// * MXOK: Max valid calibration (Bad Sensor threshold; sensor is bad if reading is higher than this value)
 /// * NS: No sensor detected (less than a specific value)
-#[derive(Clone, Debug, defmt::Format)]
-pub struct SenseData {
+///
+/// H: Number of recent raw readings kept for diagnostics (ignored without the diagnostics feature)
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct SenseData<const H: usize = 0> {
     pub analysis: SenseAnalysis,
     pub cal: CalibrationStatus,
     pub data: RawData,
     pub stats: SenseStats,
+    #[cfg(feature = "diagnostics")]
+    history: ReadingHistory<H>,
+}
+
+/// Small copyable view of a sensor
+/// Useful for passing the latest results between an ISR and a task
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub struct SenseSnapshot {
+    pub raw: u16,
+    pub distance: i16,
+    pub velocity: i16,
+    pub cal: CalibrationStatus,
 }
 
-impl SenseData {
-    pub fn new() -> SenseData {
+impl<const H: usize> SenseData<H> {
+    pub fn new() -> Self {
         SenseData {
             analysis: SenseAnalysis::null(),
             cal: CalibrationStatus::NotReady,
             data: RawData::new(),
             stats: SenseStats::new(),
+            #[cfg(feature = "diagnostics")]
+            history: ReadingHistory::new(),
         }
     }
 
+    /// Most recent raw readings (up to H), oldest first
+    #[cfg(feature = "diagnostics")]
+    pub fn recent_readings(&self) -> &[u16] {
+        self.history.readings()
+    }
+
+    /// Mean and standard deviation of the most recent raw readings
+    #[cfg(feature = "diagnostics")]
+    pub fn reading_stats(&self) -> Option<ReadingStats> {
+        ReadingStats::new(self.recent_readings())
+    }
+
     /// Acculumate a new sensor reading
     /// Once the required number of samples is retrieved, do analysis
     /// Analysis does a few more addition, subtraction and comparisions
     /// so it's a more expensive operation.
     /// Normal mode
-    fn add<const SC: usize>(
+    /// MXOK: Readings above this value mark the sensor as broken (usize::MAX to disable)
+    fn add<const SC: usize, const MXOK: usize, const ADC_BITS: usize>(
         &mut self,
         reading: u16,
-    ) -> Result<Option<&SenseAnalysis>, SensorError> {
+    ) -> Result<(Option<&SenseAnalysis>, Option<CalibrationStatus>), SensorError> {
+        #[cfg(feature = "diagnostics")]
+        self.history.push(reading);
+
         // Add value to accumulator
         if let Some(data) = self.data.add::<SC>(reading) {
-            // Check min/max values
-            if data > self.stats.max {
-                self.stats.max = data;
-            }
-            if data < self.stats.min {
-                self.stats.min = data;
-            }
+            // Update min/max values and sample counts
+            self.stats.add(data);
             trace!("Reading: {}  Stats: {:?}", reading, self.stats);
 
+            // Saturated reading (shorted or broken sensor)
+            if data as usize > MXOK {
+                warn!("Saturated reading: {}", data);
+                self.update_cal(CalibrationStatus::SensorBroken);
+                self.stats.reset();
+                self.data.reset();
+                self.analysis = SenseAnalysis::null();
+                self.analysis.raw = data;
+                return Err(SensorError::CalibrationError(self.cal, 0));
+            }
+
             // As soon as we have enough values accumulated, set magnet as detected in normal mode
-            self.cal = CalibrationStatus::MagnetDetected;
+            let transition = self.update_cal(CalibrationStatus::MagnetDetected);
 
             // Calculate new analysis (requires previous results + min/max)
-            self.analysis = SenseAnalysis::new(data, self);
-            Ok(Some(&self.analysis))
+            self.analysis = SenseAnalysis::analyze::<ADC_BITS>(data, &self.analysis, &self.stats);
+            Ok((Some(&self.analysis), transition))
         } else {
-            Ok(None)
+            Ok((None, None))
         }
     }
 
@@ -305,22 +565,26 @@ impl SenseData {
     /// Analysis does a few more addition, subtraction and comparisions
     /// so it's a more expensive operation.
     /// Test mode
-    fn add_test<const SC: usize, const MNOK: usize, const MXOK: usize, const NS: usize>(
+    fn add_test<
+        const SC: usize,
+        const MNOK: usize,
+        const MXOK: usize,
+        const NS: usize,
+        const ADC_BITS: usize,
+    >(
         &mut self,
         reading: u16,
-    ) -> Result<Option<&SenseAnalysis>, SensorError> {
+    ) -> Result<(Option<&SenseAnalysis>, Option<CalibrationStatus>), SensorError> {
+        #[cfg(feature = "diagnostics")]
+        self.history.push(reading);
+
         // Add value to accumulator
         if let Some(data) = self.data.add::<SC>(reading) {
-            // Check min/max values
-            if data > self.stats.max {
-                self.stats.max = data;
-            }
-            if data < self.stats.min {
-                self.stats.min = data;
-            }
+            // Update min/max values and sample counts
+            self.stats.add(data);
 
             // Check calibration
-            self.cal = self.check_calibration::<MNOK, MXOK, NS>(data);
+            let transition = self.update_cal(self.check_calibration::<MNOK, MXOK, NS>(data));
             trace!(
                 "Reading: {}  Cal: {:?}  Stats: {:?}",
                 reading,
@@ -338,15 +602,26 @@ impl SenseData {
                     // Clear analysis, only set raw
                     self.analysis = SenseAnalysis::null();
                     self.analysis.raw = data;
-                    return Err(SensorError::CalibrationError(self.clone()));
+                    return Err(SensorError::CalibrationError(self.cal, 0));
                 }
             }
 
             // Calculate new analysis (requires previous results + min/max)
-            self.analysis = SenseAnalysis::new(data, self);
-            Ok(Some(&self.analysis))
+            self.analysis = SenseAnalysis::analyze::<ADC_BITS>(data, &self.analysis, &self.stats);
+            Ok((Some(&self.analysis), transition))
         } else {
-            Ok(None)
+            Ok((None, None))
+        }
+    }
+
+    /// Sets the calibration status
+    /// Returns the new status only if it changed
+    fn update_cal(&mut self, cal: CalibrationStatus) -> Option<CalibrationStatus> {
+        if self.cal == cal {
+            None
+        } else {
+            self.cal = cal;
+            Some(cal)
         }
     }
 
@@ -379,22 +654,25 @@ impl SenseData {
     }
 }
 
-impl Default for SenseData {
+impl<const H: usize> Default for SenseData<H> {
     fn default() -> Self {
-        SenseData::new()
+        Self::new()
     }
 }
 
 // ----- Hall Effect Interface ------
 
-pub struct Sensors<const S: usize> {
-    sensors: Vec<SenseData, S>,
+/// S: Number of sensors
+/// ADC_BITS: ADC resolution of the sensor readings (defaults to 12 bits)
+/// H: Number of recent raw readings kept per sensor (ignored without the diagnostics feature)
+pub struct Sensors<const S: usize, const ADC_BITS: usize = 12, const H: usize = 0> {
+    sensors: Vec<SenseData<H>, S>,
 }
 
-impl<const S: usize> Sensors<S> {
+impl<const S: usize, const ADC_BITS: usize, const H: usize> Sensors<S, ADC_BITS, H> {
     /// Initializes full Sensor array
     /// Only fails if static allocation fails (very unlikely)
-    pub fn new() -> Result<Sensors<S>, SensorError> {
+    pub fn new() -> Result<Self, SensorError> {
         let mut sensors = Vec::new();
         if sensors.resize_default(S).is_err() {
             Err(SensorError::FailedToResize(S))
@@ -404,38 +682,67 @@ impl<const S: usize> Sensors<S> {
     }
 
     /// Add sense data for a specific sensor
+    /// Returns the analysis (once enough samples are accumulated) and the new calibration status
+    /// (only when the status changed)
     pub fn add<const SC: usize>(
         &mut self,
         index: usize,
         reading: u16,
-    ) -> Result<Option<&SenseAnalysis>, SensorError> {
+    ) -> Result<(Option<&SenseAnalysis>, Option<CalibrationStatus>), SensorError> {
         trace!("Index: {}  Reading: {}", index, reading);
         if index < self.sensors.len() {
-            self.sensors[index].add::<SC>(reading)
+            self.sensors[index]
+                .add::<SC, { usize::MAX }, ADC_BITS>(reading)
+                .map_err(|err| err.with_index(index))
         } else {
             Err(SensorError::InvalidSensor(index))
         }
     }
 
     /// Add sense data for a specific sensor
+    /// Same as add, but readings above MXOK mark the sensor as broken and return a
+    /// CalibrationError (the sensor recovers once readings are valid again)
+    pub fn add_guarded<const SC: usize, const MXOK: usize>(
+        &mut self,
+        index: usize,
+        reading: u16,
+    ) -> Result<(Option<&SenseAnalysis>, Option<CalibrationStatus>), SensorError> {
+        trace!("Index: {}  Reading: {}", index, reading);
+        if index < self.sensors.len() {
+            self.sensors[index]
+                .add::<SC, MXOK, ADC_BITS>(reading)
+                .map_err(|err| err.with_index(index))
+        } else {
+            Err(SensorError::InvalidSensor(index))
+        }
+    }
+
+    /// Add sense data for a specific sensor
+    /// Returns the analysis (once enough samples are accumulated) and the new calibration status
+    /// (only when the status changed)
     /// Test mode
     pub fn add_test<const SC: usize, const MNOK: usize, const MXOK: usize, const NS: usize>(
         &mut self,
         index: usize,
         reading: u16,
-    ) -> Result<Option<&SenseAnalysis>, SensorError> {
+    ) -> Result<(Option<&SenseAnalysis>, Option<CalibrationStatus>), SensorError> {
         trace!("Index: {}  Reading: {}", index, reading);
         if index < self.sensors.len() {
-            self.sensors[index].add_test::<SC, MNOK, MXOK, NS>(reading)
+            self.sensors[index]
+                .add_test::<SC, MNOK, MXOK, NS, ADC_BITS>(reading)
+                .map_err(|err| err.with_index(index))
         } else {
             Err(SensorError::InvalidSensor(index))
         }
     }
 
-    pub fn get_data(&self, index: usize) -> Result<&SenseData, SensorError> {
+    pub fn get_data(&self, index: usize) -> Result<&SenseData<H>, SensorError> {
         if index < self.sensors.len() {
             if self.sensors[index].cal == CalibrationStatus::NotReady {
-                Err(SensorError::CalibrationError(self.sensors[index].clone()))
+                Err(SensorError::CalibrationError(
+                    self.sensors[index].cal,
+                    index,
+                ))
             } else {
                 Ok(&self.sensors[index])
             }
@@ -443,4 +750,30 @@ impl<const S: usize> Sensors<S> {
             Err(SensorError::InvalidSensor(index))
         }
     }
+
+    /// Copy of the latest analysis and calibration status for a specific sensor
+    pub fn snapshot(&self, index: usize) -> Result<SenseSnapshot, SensorError> {
+        if index < self.sensors.len() {
+            let sensor = &self.sensors[index];
+            Ok(SenseSnapshot {
+                raw: sensor.analysis.raw,
+                distance: sensor.analysis.distance,
+                velocity: sensor.analysis.velocity,
+                cal: sensor.cal,
+            })
+        } else {
+            Err(SensorError::InvalidSensor(index))
+        }
+    }
+
+    /// Most recent raw readings for a specific sensor
+    /// Available regardless of calibration status
+    #[cfg(feature = "diagnostics")]
+    pub fn recent_readings(&self, index: usize) -> Result<&[u16], SensorError> {
+        if index < self.sensors.len() {
+            Ok(self.sensors[index].recent_readings())
+        } else {
+            Err(SensorError::InvalidSensor(index))
+        }
+    }
 }
@@ -78,7 +78,7 @@ fn not_ready() {
     // Retrieve before sending any data
     let state = sensors.get_data(0);
     match state.clone() {
-        Err(SensorError::CalibrationError(data)) => match data.cal {
+        Err(SensorError::CalibrationError(status, _)) => match status {
             CalibrationStatus::NotReady => {
                 return;
             }
@@ -111,7 +111,7 @@ fn sensor_missing() {
     );
 
     match state.clone() {
-        Err(SensorError::CalibrationError(data)) => match data.cal {
+        Err(SensorError::CalibrationError(status, _)) => match status {
             CalibrationStatus::SensorMissing => {
                 return;
             }
@@ -139,7 +139,7 @@ fn sensor_broken() {
         sensors.add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, 0xFFFF);
 
     match state.clone() {
-        Err(SensorError::CalibrationError(data)) => match data.cal {
+        Err(SensorError::CalibrationError(status, _)) => match status {
             CalibrationStatus::SensorBroken => {
                 return;
             }
@@ -172,7 +172,7 @@ fn magnet_missing() {
     );
 
     match state.clone() {
-        Err(SensorError::CalibrationError(data)) => match data.cal {
+        Err(SensorError::CalibrationError(status, _)) => match status {
             CalibrationStatus::MagnetWrongPoleOrMissing => {
                 return;
             }
@@ -196,7 +196,7 @@ fn magnet_check_calibration<const U: usize>(sensors: &mut Sensors<U>) {
 
     let mut test = false;
     match state.clone() {
-        Ok(rval) => {
+        Ok((rval, _)) => {
             if let Some(rval) = rval {
                 if rval.raw == val {
                     test = true;
@@ -234,7 +234,7 @@ fn magnet_check_normal<const U: usize>(sensors: &mut Sensors<U>) {
 
     let mut test = false;
     match state.clone() {
-        Ok(rval) => {
+        Ok((rval, _)) => {
             if let Some(rval) = rval {
                 if rval.raw == val {
                     test = true;
@@ -301,7 +301,7 @@ fn sensor_min_adjust() {
         sensors.add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, val);
     let mut test = false;
     match state.clone() {
-        Ok(rval) => {
+        Ok((rval, _)) => {
             if let Some(rval) = rval {
                 if rval.raw == val {
                     test = true;
@@ -316,3 +316,345 @@ fn sensor_min_adjust() {
     let new_min = sensors.get_data(0).unwrap().stats.min;
     assert!(val == new_min);
 }
+
+#[test]
+fn calibration_transition() {
+    setup_logging_lite().ok();
+
+    // Allocate a single sensor
+    let mut sensors = Sensors::<1>::new().unwrap();
+    let val = MIN_OK_THRESHOLD as u16 + 2;
+
+    // No transition until averaging is complete
+    let state =
+        sensors.add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, val);
+    assert!(
+        matches!(state, Ok((None, None))),
+        "Unexpected state: {:?}",
+        state
+    );
+
+    // NotReady -> MagnetDetected
+    let state =
+        sensors.add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, val);
+    assert!(
+        matches!(
+            state,
+            Ok((Some(_), Some(CalibrationStatus::MagnetDetected)))
+        ),
+        "Unexpected state: {:?}",
+        state
+    );
+
+    // Transition is only reported once
+    for _ in 0..4 {
+        let state =
+            sensors.add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, val);
+        assert!(
+            matches!(state, Ok((_, None))),
+            "Unexpected state: {:?}",
+            state
+        );
+    }
+}
+
+#[test]
+fn raw_data_max_average() {
+    setup_logging_lite().ok();
+
+    let mut data = RawData::new();
+
+    // Worst-case readings, averaging completes on the last sample
+    for _ in 0..15 {
+        assert_eq!(data.add::<16>(0xFFFF), None);
+    }
+    assert_eq!(data.add::<16>(0xFFFF), Some(0xFFFF));
+
+    // Second window is combined with the first (32 worst-case samples)
+    for _ in 0..15 {
+        assert_eq!(data.add::<16>(0xFFFF), None);
+    }
+    assert_eq!(data.add::<16>(0xFFFF), Some(0xFFFF));
+
+    // Combined with a window of zero readings, the average is halved
+    for _ in 0..15 {
+        assert_eq!(data.add::<16>(0), None);
+    }
+    assert_eq!(data.add::<16>(0), Some(0x7FFF));
+}
+
+#[test]
+fn raw_data_ramp_average() {
+    setup_logging_lite().ok();
+
+    let mut data = RawData::new();
+    let mut reading = 0;
+    let mut averages = [0; 3];
+    for average in averages.iter_mut() {
+        for _ in 0..4 {
+            reading += 10;
+            if let Some(val) = data.add::<4>(reading) {
+                *average = val;
+            }
+        }
+    }
+
+    // First window: (10 + 20 + 30 + 40) / 4
+    // Then the moving average of the last two windows (8 samples)
+    assert_eq!(averages, [25, 45, 85]);
+}
+
+#[test]
+fn sample_counts() {
+    setup_logging_lite().ok();
+
+    // Allocate a single sensor
+    let mut sensors = Sensors::<1>::new().unwrap();
+
+    // Two averaging windows
+    magnet_calibrate::<1>(&mut sensors);
+    let stats = &sensors.get_data(0).unwrap().stats;
+    assert_eq!(stats.samples, 2);
+    assert_eq!(stats.samples_since_calibration, 2);
+
+    // Lose calibration, only samples_since_calibration is reset
+    for _ in 0..2 {
+        sensors
+            .add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(
+                0,
+                NO_SENSOR_THRESHOLD as u16 - 1,
+            )
+            .ok();
+    }
+    let stats = &sensors.get_data(0).unwrap().stats;
+    assert_eq!(stats.samples, 3);
+    assert_eq!(stats.samples_since_calibration, 0);
+}
+
+#[test]
+fn adc_resolution() {
+    setup_logging_lite().ok();
+
+    let mut data = SenseData::new();
+    let expected = MODEL[2048] - MODEL[1024];
+
+    // 12-bit
+    data.stats.min = 1024;
+    let analysis = SenseAnalysis::new::<12>(2048, &data);
+    assert_eq!(analysis.raw, 2048);
+    assert_eq!(analysis.distance, expected);
+
+    // 10-bit
+    data.stats.min = 256;
+    let analysis = SenseAnalysis::new::<10>(512, &data);
+    assert_eq!(analysis.raw, 512);
+    assert_eq!(analysis.distance, expected);
+
+    // Out of range 10-bit value
+    let analysis = SenseAnalysis::new::<10>(1024, &data);
+    assert_eq!(analysis.raw, 0);
+    assert_eq!(analysis.distance, 0);
+}
+
+#[test]
+fn raw_out_of_range() {
+    setup_logging_lite().ok();
+
+    // Allocate a single sensor
+    let mut sensors = Sensors::<1>::new().unwrap();
+
+    // Glitched reading larger than the lookup table
+    assert!(sensors.add::<2>(0, 0xFFFF).is_ok());
+    let state = sensors.add::<2>(0, 0xFFFF);
+    match state {
+        Ok((Some(analysis), _)) => {
+            assert_eq!(analysis.raw, 0);
+            assert_eq!(analysis.distance, 0);
+        }
+        _ => {
+            panic!("Unexpected state: {:?}", state);
+        }
+    }
+
+    // Min value larger than the lookup table
+    let mut data = SenseData::new();
+    data.stats.min = MODEL.len() as u16;
+    let analysis = SenseAnalysis::new::<12>(100, &data);
+    assert_eq!(analysis.raw, 0);
+}
+
+#[test]
+fn calibration_error_index() {
+    setup_logging_lite().ok();
+
+    let mut sensors = Sensors::<3>::new().unwrap();
+
+    // Sensor 2 reads 0, which is a missing sensor
+    assert!(sensors
+        .add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(2, 0)
+        .is_ok());
+    let state =
+        sensors.add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(2, 0);
+    assert_eq!(
+        state.err(),
+        Some(SensorError::CalibrationError(
+            CalibrationStatus::SensorMissing,
+            2
+        ))
+    );
+
+    // Sensor 1 hasn't received any data yet
+    assert_eq!(
+        sensors.get_data(1).err(),
+        Some(SensorError::CalibrationError(
+            CalibrationStatus::NotReady,
+            1
+        ))
+    );
+
+    // Errors only carry the status and index, not the sensor data
+    assert!(core::mem::size_of::<SensorError>() < core::mem::size_of::<SenseData>());
+}
+
+#[test]
+fn snapshot() {
+    setup_logging_lite().ok();
+
+    let mut sensors = Sensors::<2>::new().unwrap();
+
+    // Snapshots are available before calibration
+    let snapshot = sensors.snapshot(0).unwrap();
+    assert_eq!(snapshot.cal, CalibrationStatus::NotReady);
+    assert_eq!(snapshot.raw, 0);
+
+    magnet_calibrate::<2>(&mut sensors);
+
+    // Move the magnet closer
+    let val = MIN_OK_THRESHOLD as u16 + 200;
+    assert!(sensors
+        .add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, val)
+        .is_ok());
+    assert!(sensors
+        .add_test::<2, MIN_OK_THRESHOLD, MAX_OK_THRESHOLD, NO_SENSOR_THRESHOLD>(0, val)
+        .is_ok());
+
+    // Snapshot is a copy, so the sensors may be updated while it's held
+    let snapshot = sensors.snapshot(0).unwrap();
+    let data = sensors.get_data(0).unwrap();
+    assert_eq!(snapshot.raw, data.analysis.raw);
+    assert_eq!(snapshot.distance, data.analysis.distance);
+    assert_eq!(snapshot.velocity, data.analysis.velocity);
+    assert_eq!(snapshot.cal, data.cal);
+    assert_eq!(snapshot.cal, CalibrationStatus::MagnetDetected);
+
+    assert_eq!(sensors.snapshot(2), Err(SensorError::InvalidSensor(2)));
+}
+
+#[test]
+fn analysis_from_parts() {
+    let analysis = SenseAnalysis::from_parts(2000, 120, -15, 4, -1);
+    assert_eq!(analysis.raw(), 2000);
+    assert_eq!(analysis.distance(), 120);
+    assert_eq!(analysis.velocity(), -15);
+    assert_eq!(analysis.acceleration(), 4);
+    assert_eq!(analysis.jerk(), -1);
+
+    let null = SenseAnalysis::null();
+    assert_eq!(null.raw(), 0);
+    assert_eq!(null.distance(), 0);
+}
+
+#[test]
+fn saturation_guard() {
+    setup_logging_lite().ok();
+
+    let mut sensors = Sensors::<1>::new().unwrap();
+    let val = MIN_OK_THRESHOLD as u16 + 2;
+
+    // Normal readings
+    assert!(sensors.add_guarded::<2, MAX_OK_THRESHOLD>(0, val).is_ok());
+    let state = sensors.add_guarded::<2, MAX_OK_THRESHOLD>(0, val);
+    assert!(matches!(
+        state,
+        Ok((Some(_), Some(CalibrationStatus::MagnetDetected)))
+    ));
+
+    // Saturated reading
+    assert!(sensors.add_guarded::<2, MAX_OK_THRESHOLD>(0, 0xFFF).is_ok());
+    assert_eq!(
+        sensors.add_guarded::<2, MAX_OK_THRESHOLD>(0, 0xFFF).err(),
+        Some(SensorError::CalibrationError(
+            CalibrationStatus::SensorBroken,
+            0
+        ))
+    );
+    let snapshot = sensors.snapshot(0).unwrap();
+    assert_eq!(snapshot.cal, CalibrationStatus::SensorBroken);
+    assert_eq!(snapshot.distance, 0);
+
+    // Recovers once readings are valid again
+    assert!(sensors.add_guarded::<2, MAX_OK_THRESHOLD>(0, val).is_ok());
+    let state = sensors.add_guarded::<2, MAX_OK_THRESHOLD>(0, val);
+    assert!(matches!(
+        state,
+        Ok((Some(_), Some(CalibrationStatus::MagnetDetected)))
+    ));
+
+    // Without the guard, saturated readings are still analyzed
+    assert!(sensors.add::<2>(0, 0xFFF).is_ok());
+    assert!(sensors.add::<2>(0, 0xFFF).is_ok());
+    assert_eq!(
+        sensors.snapshot(0).unwrap().cal,
+        CalibrationStatus::MagnetDetected
+    );
+}
+
+#[test]
+#[cfg(feature = "diagnostics")]
+fn recent_readings() {
+    setup_logging_lite().ok();
+
+    // Keep the last 8 readings
+    let mut sensors = Sensors::<1, 12, 8>::new().unwrap();
+    assert_eq!(sensors.recent_readings(0).unwrap(), &[]);
+
+    let readings = [1020, 1040, 1040, 1040, 1050, 1050, 1070, 1090];
+    for reading in readings {
+        assert!(sensors.add::<2>(0, reading).is_ok());
+    }
+    assert_eq!(sensors.recent_readings(0).unwrap(), &readings);
+    assert_eq!(
+        sensors.get_data(0).unwrap().reading_stats(),
+        Some(ReadingStats {
+            count: 8,
+            mean: 1050,
+            stddev: 20,
+        })
+    );
+
+    // Oldest readings are dropped, order stays oldest first
+    assert!(sensors.add::<2>(0, 1100).is_ok());
+    assert!(sensors.add::<2>(0, 1110).is_ok());
+    assert_eq!(
+        sensors.recent_readings(0).unwrap(),
+        &[1040, 1040, 1050, 1050, 1070, 1090, 1100, 1110]
+    );
+
+    assert_eq!(ReadingStats::new(&[]), None);
+    assert_eq!(
+        sensors.recent_readings(1),
+        Err(SensorError::InvalidSensor(1))
+    );
+}
+
+#[test]
+#[cfg(feature = "defmt-impl")]
+fn defmt_format() {
+    fn assert_format<T: defmt::Format>() {}
+    assert_format::<CalibrationStatus>();
+    assert_format::<SenseAnalysis>();
+    assert_format::<SenseData>();
+    assert_format::<SenseSnapshot>();
+    assert_format::<SenseStats>();
+    assert_format::<SensorError>();
+}
@@ -9,18 +9,23 @@
 
 #![no_std]
 
+mod test;
+
 // ----- Crates -----
 
 use heapless::{String, Vec};
 pub use hid_io_protocol::commands::*;
 pub use hid_io_protocol::*;
-use kll_core::TriggerEvent;
+use kll_core::{layer, CapabilityEvent, CapabilityRun, TriggerEvent};
 use pkg_version::*;
 
 // ----- Sizes -----
 
 pub const MESSAGE_LEN: usize = 256;
 
+/// Max number of active layers sent in response to a LayerStateCommand
+pub const LAYER_STATE_LEN: usize = 16;
+
 // ----- General Structs -----
 
 pub struct HidIoHostInfo {
@@ -38,8 +43,123 @@ pub enum HidIoEvent {
     TriggerEvent(TriggerEvent),
 }
 
+/// Status of the last test packet sent using send_test_packet
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TestPacketStatus {
+    /// No test packet has been sent
+    None,
+    /// Waiting for the host to echo the test packet
+    Pending,
+    /// Host echoed the test packet data
+    Verified,
+    /// Host echoed different data
+    Mismatch,
+}
+
+/// Dynamic keymap override commands
+/// hid-io-protocol does not define a keymap command (yet), these are sent using h0050
+/// (manufacturing) command ids (see LedDetectCommand), argument is unused.
+///
+/// Data (after the h0050 command and argument): layer (u8), ttype (u8), index (u16 le)
+/// Set also sends the override: trigger guide (u16 le), result guide (u16 le)
+/// A Nak is sent if the data is invalid or the override could not be set, otherwise the Ack is
+/// followed by a h0051 result (same command and argument).
+/// Get sends the override in the h0051 data (empty if not set)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum KeymapCommand {
+    /// Read the override of the key
+    Get = 0x0140,
+    /// Set the override of the key
+    Set = 0x0141,
+    /// Clear the override of the key
+    Clear = 0x0142,
+}
+
+impl KeymapCommand {
+    pub fn from_command(command: u16) -> Option<Self> {
+        match command {
+            0x0140 => Some(KeymapCommand::Get),
+            0x0141 => Some(KeymapCommand::Set),
+            0x0142 => Some(KeymapCommand::Clear),
+            _ => None,
+        }
+    }
+}
+
+/// Layer state commands
+/// hid-io-protocol does not define a layer state command (yet), these are sent using h0050
+/// (manufacturing) command ids (see LedDetectCommand), argument is unused.
+///
+/// Data (after the h0050 command and argument): empty to read the whole active layer stack, or
+/// layer (u8) to read a single layer
+/// A Nak is sent if the data is invalid or layer state is not supported, otherwise the Ack is
+/// followed by a h0051 result (same command and argument).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum LayerStateCommand {
+    /// Read the layer state
+    /// h0051 data: layer, layer::State (u8) pairs in evaluation order
+    /// (a single layer not in the active stack is sent back as layer::State::Off)
+    Get = 0x0150,
+}
+
+impl LayerStateCommand {
+    pub fn from_command(command: u16) -> Option<Self> {
+        match command {
+            0x0150 => Some(LayerStateCommand::Get),
+            _ => None,
+        }
+    }
+}
+
+/// LED driver open/short detection commands
+/// Sent using h0050 (manufacturing) command ids, argument is the command specific value
+/// Detection is asynchronous (e.g. is31fl3743b must wait at least 750 us after the detection
+/// setup before the results can be read). Once the results are ready the firmware sends them
+/// back using CommandInterface::led_detect_result (h0051, one response per chip).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum LedDetectCommand {
+    /// Start open circuit detection
+    /// argument: unused
+    Open = 0x0130,
+    /// Start short circuit detection
+    /// argument: unused
+    Short = 0x0131,
+}
+
+impl LedDetectCommand {
+    pub fn from_command(command: u16) -> Option<Self> {
+        match command {
+            0x0130 => Some(LedDetectCommand::Open),
+            0x0131 => Some(LedDetectCommand::Short),
+            _ => None,
+        }
+    }
+}
+
+// ----- Functions -----
+
+/// Returns true if the h0050 (manufacturing) data is a kiibohd command
+/// (KeymapCommand or LayerStateCommand)
+/// These are handled by CommandInterface instead of the h0050_manufacturing_cmd callback
+fn is_vendor_command(data: &[u8]) -> bool {
+    match data {
+        [low, high, _, _, ..] => {
+            let command = u16::from_le_bytes([*low, *high]);
+            KeymapCommand::from_command(command).is_some()
+                || LayerStateCommand::from_command(command).is_some()
+        }
+        _ => false,
+    }
+}
+
 // ----- Command Interface -----
 
+/// HID-IO CommandInterface
+/// See the Commands impl for a description of each of the const generics
+/// MSUB1 and MSUB4 default to a MESSAGE_LEN message length
 pub struct CommandInterface<
     KINTF: KiibohdCommandInterface<H>,
     const TX: usize,
@@ -48,6 +168,8 @@ pub struct CommandInterface<
     const H: usize,
     const S: usize,
     const ID: usize,
+    const MSUB1: usize = { MESSAGE_LEN - 1 },
+    const MSUB4: usize = { MESSAGE_LEN - 4 },
 > {
     ids: Vec<HidIoCommandId, ID>,
     pub rx_bytebuf: buffer::Buffer<RX, N>,
@@ -56,6 +178,10 @@ pub struct CommandInterface<
     serial_buf: Vec<u8, S>,
     hostinfo: HidIoHostInfo,
     term_out_buffer: String<H>,
+    /// Data of the last test packet sent to the host
+    test_packet: Vec<u8, H>,
+    /// Echo status of the last test packet sent to the host
+    test_packet_status: TestPacketStatus,
     interface: KINTF,
 }
 
@@ -67,12 +193,22 @@ impl<
         const H: usize,
         const S: usize,
         const ID: usize,
-    > CommandInterface<KINTF, TX, RX, N, H, S, ID>
+        const MSUB1: usize,
+        const MSUB4: usize,
+    > CommandInterface<KINTF, TX, RX, N, H, S, ID, MSUB1, MSUB4>
 {
     pub fn new(
         ids: &[HidIoCommandId],
         interface: KINTF,
-    ) -> Result<CommandInterface<KINTF, TX, RX, N, H, S, ID>, CommandError> {
+    ) -> Result<CommandInterface<KINTF, TX, RX, N, H, S, ID, MSUB1, MSUB4>, CommandError> {
+        // MSUB1 and MSUB4 must describe the same message length
+        const {
+            assert!(
+                MSUB1 == MSUB4 + 3,
+                "MSUB1 and MSUB4 must be derived from the same message length"
+            )
+        };
+
         // Make sure we have a large enough id vec
         let ids = match Vec::from_slice(ids) {
             Ok(ids) => ids,
@@ -103,6 +239,8 @@ impl<
             serial_buf,
             hostinfo,
             term_out_buffer,
+            test_packet: Vec::new(),
+            test_packet_status: TestPacketStatus::None,
             interface,
         })
     }
@@ -111,6 +249,39 @@ impl<
         &self.hostinfo
     }
 
+    /// Reset the HID-IO decode/encode state
+    /// Clears all buffers, including any partially decoded packet
+    /// Use after a USB reset or when the link has desynced
+    pub fn reset(&mut self) {
+        self.rx_bytebuf = buffer::Buffer::new();
+        self.tx_bytebuf = buffer::Buffer::new();
+        self.rx_packetbuf.clear();
+        self.serial_buf.clear();
+        self.term_out_buffer.clear();
+        self.test_packet_status = TestPacketStatus::None;
+    }
+
+    /// Send a h0002 test packet to the host
+    /// The host echoes the data back, use test_packet_status() to check the result
+    pub fn send_test_packet(&mut self, data: &[u8]) -> Result<(), CommandError> {
+        let data: Vec<u8, H> = match Vec::from_slice(data) {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(CommandError::DataVecTooSmall);
+            }
+        };
+
+        self.h0002_test(h0002::Cmd { data: data.clone() }, false)?;
+        self.test_packet = data;
+        self.test_packet_status = TestPacketStatus::Pending;
+        Ok(())
+    }
+
+    /// Echo status of the last test packet sent using send_test_packet()
+    pub fn test_packet_status(&self) -> TestPacketStatus {
+        self.test_packet_status
+    }
+
     /// Decode rx_bytebuf into a HidIoPacketBuffer
     /// Returns true if buffer ready, false if not
     pub fn rx_packetbuffer_decode(&mut self) -> Result<bool, CommandError> {
@@ -152,7 +323,10 @@ impl<
         let mut cur = 0;
         while (count == 0 || cur < count) && self.rx_packetbuffer_decode()? {
             // Process rx buffer
-            self.rx_message_handling(self.rx_packetbuf.clone())?;
+            let buf = self.rx_packetbuf.clone();
+            if !self.kiibohd_message_handling(&buf)? {
+                self.rx_message_handling(buf)?;
+            }
 
             // Clear buffer
             self.rx_packetbuf.clear();
@@ -173,6 +347,232 @@ impl<
         Ok(())
     }
 
+    /// Handles the HID-IO commands that Commands::rx_message_handling does not dispatch
+    /// Returns false if the packet was not handled
+    fn kiibohd_message_handling(
+        &mut self,
+        buf: &HidIoPacketBuffer<H>,
+    ) -> Result<bool, CommandError> {
+        match buf.id {
+            HidIoCommandId::PixelSet3c8b => {}
+            // Other manufacturing commands are handled by h0050_manufacturing_cmd
+            HidIoCommandId::ManufacturingTest if is_vendor_command(&buf.data) => {}
+            _ => {
+                return Ok(false);
+            }
+        }
+
+        // Make sure we're processing a supported id
+        if !self.supported_id(buf.id) {
+            return Err(CommandError::IdNotSupported(buf.id));
+        }
+
+        // Only commands from the host are handled (the device does not send these)
+        match buf.ptype {
+            HidIoPacketType::Data => {}
+            HidIoPacketType::NaData => {
+                return Err(CommandError::InvalidPacketBufferType(buf.ptype));
+            }
+            HidIoPacketType::Ack | HidIoPacketType::Nak => {
+                return Ok(true);
+            }
+            _ => {
+                return Err(CommandError::InvalidRxMessage(buf.ptype));
+            }
+        }
+
+        let ack = match buf.id {
+            HidIoCommandId::ManufacturingTest => {
+                self.h0050_vendor_cmd(&buf.data)?;
+                return Ok(true);
+            }
+            HidIoCommandId::PixelSet3c8b => self.h0023_pixelset_cmd(&buf.data),
+            _ => None,
+        };
+        match ack {
+            Some(data) => self.data_ack(buf.id, data)?,
+            None => self.empty_nak(buf.id)?,
+        }
+        Ok(true)
+    }
+
+    /// Ack with a data payload
+    fn data_ack(&mut self, id: HidIoCommandId, data: Vec<u8, H>) -> Result<(), CommandError> {
+        self.tx_packetbuffer_send(&mut HidIoPacketBuffer {
+            ptype: HidIoPacketType::Ack,
+            id,
+            max_len: self.default_packet_chunk(),
+            data,
+            done: true,
+        })
+    }
+
+    /// Handle the kiibohd h0050 (manufacturing) commands (see is_vendor_command)
+    /// Data: command (u16 le), argument (u16 le), command specific data
+    /// Acks and sends the result using h0051 (same command and argument), or Naks on failure
+    fn h0050_vendor_cmd(&mut self, data: &[u8]) -> Result<(), CommandError> {
+        let command = u16::from_le_bytes([data[0], data[1]]);
+        let argument = u16::from_le_bytes([data[2], data[3]]);
+
+        let result = if let Some(keymap) = KeymapCommand::from_command(command) {
+            self.h0050_keymap_cmd(keymap, &data[4..])
+        } else if let Some(LayerStateCommand::Get) = LayerStateCommand::from_command(command) {
+            self.h0050_layerstate_cmd(&data[4..])
+        } else {
+            None
+        };
+
+        let id = HidIoCommandId::ManufacturingTest;
+        let result = match result {
+            Some(result) => result,
+            None => {
+                return self.empty_nak(id);
+            }
+        };
+        let data = match Vec::from_slice(&result) {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(CommandError::DataVecTooSmall);
+            }
+        };
+        self.empty_ack(id)?;
+        self.h0051_manufacturingres(h0051::Cmd {
+            command,
+            argument,
+            data,
+        })
+    }
+
+    /// Handle dynamic keymap override commands (see KeymapCommand)
+    /// Returns the h0051 result data, None to Nak
+    fn h0050_keymap_cmd(&mut self, command: KeymapCommand, data: &[u8]) -> Option<Vec<u8, H>> {
+        if data.len() < 4 {
+            return None;
+        }
+        let key = (data[0], data[1], u16::from_le_bytes([data[2], data[3]]));
+
+        let mut ack = Vec::new();
+        match command {
+            KeymapCommand::Get => {
+                if let Some((trigger_guide, result_guide)) = self.interface.get_keymap_override(key)
+                {
+                    ack.extend_from_slice(&trigger_guide.to_le_bytes()).ok()?;
+                    ack.extend_from_slice(&result_guide.to_le_bytes()).ok()?;
+                }
+            }
+            KeymapCommand::Set => {
+                if data.len() < 8 {
+                    return None;
+                }
+                let trigger_guide = u16::from_le_bytes([data[4], data[5]]);
+                let result_guide = u16::from_le_bytes([data[6], data[7]]);
+                if !self
+                    .interface
+                    .set_keymap_override(key, Some((trigger_guide, result_guide)))
+                {
+                    return None;
+                }
+            }
+            KeymapCommand::Clear => {
+                if !self.interface.set_keymap_override(key, None) {
+                    return None;
+                }
+            }
+        }
+        Some(ack)
+    }
+
+    /// Handle pixel direct-set commands
+    /// Data: start pixel index (u16 le), then red, green, blue (u8) for each consecutive pixel
+    /// Returns the Ack data (empty), None to Nak
+    fn h0023_pixelset_cmd(&mut self, data: &[u8]) -> Option<Vec<u8, H>> {
+        if data.len() < 5 {
+            return None;
+        }
+        let start = u16::from_le_bytes([data[0], data[1]]);
+        let colors = data[2..].chunks_exact(3);
+        if !colors.remainder().is_empty() {
+            return None;
+        }
+
+        for (pos, rgb) in colors.enumerate() {
+            let index = start.checked_add(pos as u16)?;
+            if !self.interface.pixel_set(index, [rgb[0], rgb[1], rgb[2]]) {
+                return None;
+            }
+        }
+        Some(Vec::new())
+    }
+
+    /// Handle layer state requests (see LayerStateCommand)
+    /// Returns the h0051 result data, None to Nak
+    fn h0050_layerstate_cmd(&mut self, data: &[u8]) -> Option<Vec<u8, H>> {
+        let layers = self.interface.layer_state()?;
+
+        let mut ack = Vec::new();
+        match data {
+            [] => {
+                for (layer, state) in &layers {
+                    ack.extend_from_slice(&[*layer, *state as u8]).ok()?;
+                }
+            }
+            [layer] => {
+                let state = layers
+                    .iter()
+                    .find(|(l, _)| l == layer)
+                    .map_or(layer::State::Off, |(_, state)| *state);
+                ack.extend_from_slice(&[*layer, state as u8]).ok()?;
+            }
+            _ => {
+                return None;
+            }
+        }
+        Some(ack)
+    }
+
+    /// Send open/short detection results to the host
+    /// Call once the results of a LedDetectCommand are ready (see LedDetectCommand)
+    /// Sent using h0051 (argument: chip, data: channel bitmask, 1 bit per channel)
+    pub fn led_detect_result(
+        &mut self,
+        command: LedDetectCommand,
+        chip: u16,
+        channels: &[u8],
+    ) -> Result<(), CommandError> {
+        let data = match Vec::from_slice(channels) {
+            Ok(data) => data,
+            Err(_) => {
+                return Err(CommandError::DataVecTooSmall);
+            }
+        };
+        self.h0051_manufacturingres(h0051::Cmd {
+            command: command as u16,
+            argument: chip,
+            data,
+        })
+    }
+
+    /// Handles a kll-core McuFlashMode CapabilityRun (e.g. a key mapped to enter the bootloader)
+    /// Calls the same h0016_flashmode_cmd callback used by the HID-IO flash mode command.
+    ///
+    /// Only the Last (release) event enters flash mode. Push the USB buffers (e.g. the key
+    /// release) before jumping to the bootloader so the host does not see a stuck key; just like
+    /// h0016_flashmode_cmd, the callback should ideally schedule the jump rather than jump
+    /// immediately.
+    ///
+    /// Returns None if the CapabilityRun is not a McuFlashMode release
+    pub fn mcu_flash_mode(
+        &mut self,
+        cap_run: CapabilityRun,
+    ) -> Option<Result<h0016::Ack, h0016::Nak>> {
+        match cap_run {
+            CapabilityRun::McuFlashMode {
+                state: CapabilityEvent::Last,
+            } => Some(self.interface.h0016_flashmode_cmd(h0016::Cmd {})),
+            _ => None,
+        }
+    }
+
     /// Process incoming events through HID-IO
     /// This is the preferred mechanism to interact with HID-IO (if possible for your situation)
     pub fn process_event(&mut self, event: HidIoEvent) -> Result<(), CommandError> {
@@ -191,6 +591,8 @@ impl<
 /// H - Max data payload length (HidIoPacketBuffer)
 /// S - Serialization buffer size
 /// ID - Max number of HidIoCommandIds
+/// MSUB1 - Max message length - 1
+/// MSUB4 - Max message length - 4
 impl<
         KINTF: KiibohdCommandInterface<H>,
         const TX: usize,
@@ -199,8 +601,9 @@ impl<
         const H: usize,
         const S: usize,
         const ID: usize,
-    > Commands<H, { MESSAGE_LEN - 1 }, { MESSAGE_LEN - 4 }, ID>
-    for CommandInterface<KINTF, TX, RX, N, H, S, ID>
+        const MSUB1: usize,
+        const MSUB4: usize,
+    > Commands<H, MSUB1, MSUB4, ID> for CommandInterface<KINTF, TX, RX, N, H, S, ID, MSUB1, MSUB4>
 {
     fn default_packet_chunk(&self) -> u32 {
         N as u32
@@ -252,10 +655,7 @@ impl<
     }
 
     /// Uses the CommandInterface to send data directly
-    fn h0001_info_cmd(
-        &mut self,
-        data: h0001::Cmd,
-    ) -> Result<h0001::Ack<{ MESSAGE_LEN - 1 }>, h0001::Nak> {
+    fn h0001_info_cmd(&mut self, data: h0001::Cmd) -> Result<h0001::Ack<MSUB1>, h0001::Nak> {
         use h0001::*;
 
         let property = data.property;
@@ -343,10 +743,7 @@ impl<
     }
     /// Uses the CommandInterface to store data rather than issue
     /// a callback
-    fn h0001_info_ack(
-        &mut self,
-        data: h0001::Ack<{ MESSAGE_LEN - 1 }>,
-    ) -> Result<(), CommandError> {
+    fn h0001_info_ack(&mut self, data: h0001::Ack<MSUB1>) -> Result<(), CommandError> {
         use h0001::*;
 
         match data.property {
@@ -380,6 +777,18 @@ impl<
         Ok(h0002::Ack { data: data.data })
     }
 
+    /// Validates the echo of a test packet sent using send_test_packet()
+    fn h0002_test_ack(&mut self, data: h0002::Ack<H>) -> Result<(), CommandError> {
+        if self.test_packet_status == TestPacketStatus::Pending {
+            self.test_packet_status = if data.data == self.test_packet {
+                TestPacketStatus::Verified
+            } else {
+                TestPacketStatus::Mismatch
+            };
+        }
+        Ok(())
+    }
+
     fn h0016_flashmode_cmd(&mut self, data: h0016::Cmd) -> Result<h0016::Ack, h0016::Nak> {
         self.interface.h0016_flashmode_cmd(data)
     }
@@ -404,6 +813,15 @@ impl<
     }
 
     fn h0050_manufacturing_cmd(&mut self, data: h0050::Cmd) -> Result<h0050::Ack, h0050::Nak> {
+        // LED open/short detection commands
+        if let Some(command) = LedDetectCommand::from_command(data.command) {
+            return if self.interface.led_detect(command) {
+                Ok(h0050::Ack {})
+            } else {
+                Err(h0050::Nak {})
+            };
+        }
+
         self.interface.h0050_manufacturing_cmd(data)
     }
 
@@ -498,4 +916,46 @@ pub trait KiibohdCommandInterface<const H: usize> {
     fn h0050_manufacturing_cmd(&mut self, _data: h0050::Cmd) -> Result<h0050::Ack, h0050::Nak> {
         Err(h0050::Nak {})
     }
+
+    /// Returns the dynamic keymap override for the given (layer, ttype, index)
+    /// Usually implemented using kll_core::layout::LayerState
+    /// Callback
+    fn get_keymap_override(&self, _key: (u8, u8, u16)) -> Option<(u16, u16)> {
+        None
+    }
+
+    /// Sets (or clears if None) the dynamic keymap override (trigger_guide, result_guide) for the
+    /// given (layer, ttype, index)
+    /// Usually implemented using kll_core::layout::LayerState
+    /// Return false if the override could not be set
+    /// Callback
+    fn set_keymap_override(&mut self, _key: (u8, u8, u16), _guide: Option<(u16, u16)>) -> bool {
+        false
+    }
+
+    /// Returns the active layer stack (in evaluation order) and the state of each layer
+    /// Usually implemented using kll_core::layout::LayerState::active_layers and layer_state
+    /// Return None if not supported
+    /// Callback
+    fn layer_state(&self) -> Option<Vec<(u8, layer::State), LAYER_STATE_LEN>> {
+        None
+    }
+
+    /// Starts LED driver open or short circuit detection
+    /// Usually implemented using is31fl3743b open_circuit_detect / short_circuit_detect
+    /// Results are sent later using CommandInterface::led_detect_result
+    /// Return false if not supported or detection could not be started
+    /// Callback
+    fn led_detect(&mut self, _command: LedDetectCommand) -> bool {
+        false
+    }
+
+    /// Sets the color of a single pixel (e.g. for host-driven lighting effects)
+    /// The pixel index is the logical (KLL) pixel index, the implementation is responsible for
+    /// mapping it to the LED driver channels (e.g. is31fl3743b PWM buffer).
+    /// Return false if not supported or the pixel index is invalid
+    /// Callback
+    fn pixel_set(&mut self, _index: u16, _rgb: [u8; 3]) -> bool {
+        false
+    }
 }
@@ -0,0 +1,416 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg(test)]
+
+// ----- Crates -----
+
+use super::*;
+
+// ----- Types -----
+
+const BUF_CHUNK: usize = 64;
+const ID_LEN: usize = 10;
+const RX_BUF: usize = 8;
+const SERIALIZATION_LEN: usize = 277;
+const TX_BUF: usize = 8;
+
+type TestCommandInterface = CommandInterface<
+    TestInterface,
+    TX_BUF,
+    RX_BUF,
+    BUF_CHUNK,
+    MESSAGE_LEN,
+    SERIALIZATION_LEN,
+    ID_LEN,
+>;
+
+const SMALL_MESSAGE_LEN: usize = 64;
+
+type SmallTestCommandInterface = CommandInterface<
+    TestInterface,
+    TX_BUF,
+    RX_BUF,
+    BUF_CHUNK,
+    SMALL_MESSAGE_LEN,
+    SERIALIZATION_LEN,
+    ID_LEN,
+    { SMALL_MESSAGE_LEN - 1 },
+    { SMALL_MESSAGE_LEN - 4 },
+>;
+
+/// Last keymap override set, (key, guide)
+type KeymapOverride = ((u8, u8, u16), Option<(u16, u16)>);
+
+#[derive(Default)]
+struct TestInterface {
+    keymap_override: Option<KeymapOverride>,
+    pixel: Option<(u16, [u8; 3])>,
+    layers: Vec<(u8, layer::State), LAYER_STATE_LEN>,
+    led_detect: Option<LedDetectCommand>,
+    flash_mode: bool,
+}
+
+impl<const H: usize> KiibohdCommandInterface<H> for TestInterface {
+    fn h0001_device_name(&self) -> Option<&str> {
+        Some("Test Device")
+    }
+
+    fn h0001_firmware_name(&self) -> Option<&str> {
+        Some("kiibohd-hid-io-test")
+    }
+
+    fn get_keymap_override(&self, key: (u8, u8, u16)) -> Option<(u16, u16)> {
+        match self.keymap_override {
+            Some((k, guide)) if k == key => guide,
+            _ => None,
+        }
+    }
+
+    fn set_keymap_override(&mut self, key: (u8, u8, u16), guide: Option<(u16, u16)>) -> bool {
+        self.keymap_override = Some((key, guide));
+        true
+    }
+
+    fn pixel_set(&mut self, index: u16, rgb: [u8; 3]) -> bool {
+        self.pixel = Some((index, rgb));
+        true
+    }
+
+    fn layer_state(&self) -> Option<Vec<(u8, layer::State), LAYER_STATE_LEN>> {
+        Some(self.layers.clone())
+    }
+
+    fn led_detect(&mut self, command: LedDetectCommand) -> bool {
+        self.led_detect = Some(command);
+        true
+    }
+
+    fn h0016_flashmode_cmd(&mut self, _data: h0016::Cmd) -> Result<h0016::Ack, h0016::Nak> {
+        self.flash_mode = true;
+        Ok(h0016::Ack { scancode: 0 })
+    }
+}
+
+// ----- Functions -----
+
+fn manufacturing_cmd(intf: &mut TestCommandInterface, command: u16, argument: u16) {
+    assert!(intf
+        .h0050_manufacturing_cmd(h0050::Cmd { command, argument })
+        .is_ok());
+}
+
+/// Serializes a packet and moves the given number of chunks from tx to rx
+fn loopback_packet(intf: &mut TestCommandInterface, data: &[u8], chunks: usize) {
+    let mut buf = HidIoPacketBuffer {
+        ptype: HidIoPacketType::Data,
+        id: HidIoCommandId::TestPacket,
+        max_len: BUF_CHUNK as u32,
+        data: Vec::from_slice(data).unwrap(),
+        done: true,
+    };
+    assert!(intf.tx_packetbuffer_send(&mut buf).is_ok());
+    for _ in 0..chunks {
+        let chunk = intf.tx_bytebuf.dequeue().unwrap();
+        assert!(intf.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+}
+
+/// Sends a command packet to the interface and returns the decoded response
+fn command_packet(
+    intf: &mut TestCommandInterface,
+    id: HidIoCommandId,
+    data: &[u8],
+) -> HidIoPacketBuffer<MESSAGE_LEN> {
+    let mut buf = HidIoPacketBuffer {
+        ptype: HidIoPacketType::Data,
+        id,
+        max_len: BUF_CHUNK as u32,
+        data: Vec::from_slice(data).unwrap(),
+        done: true,
+    };
+    assert!(intf.tx_packetbuffer_send(&mut buf).is_ok());
+    while let Some(chunk) = intf.tx_bytebuf.dequeue() {
+        assert!(intf.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+    assert_eq!(intf.process_rx(0).unwrap(), 1);
+
+    // Loop the response back to decode it
+    while let Some(chunk) = intf.tx_bytebuf.dequeue() {
+        assert!(intf.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+    assert!(intf.rx_packetbuffer_decode().unwrap());
+    let res = intf.rx_packetbuf.clone();
+    intf.rx_packetbuf.clear();
+    res
+}
+
+/// Sends a kiibohd h0050 command (argument 0) to the interface
+/// Returns the response type and the data of the h0051 result that follows an Ack
+fn vendor_cmd(
+    intf: &mut TestCommandInterface,
+    command: u16,
+    data: &[u8],
+) -> (HidIoPacketType, Vec<u8, MESSAGE_LEN>) {
+    let mut packet = Vec::<u8, MESSAGE_LEN>::new();
+    packet.extend_from_slice(&command.to_le_bytes()).unwrap();
+    packet.extend_from_slice(&0u16.to_le_bytes()).unwrap();
+    packet.extend_from_slice(data).unwrap();
+    let res = command_packet(intf, HidIoCommandId::ManufacturingTest, &packet);
+    if res.ptype != HidIoPacketType::Ack {
+        return (res.ptype, Vec::new());
+    }
+
+    // Decode the h0051 result
+    assert!(intf.rx_packetbuffer_decode().unwrap());
+    assert_eq!(intf.rx_packetbuf.id, HidIoCommandId::ManufacturingResult);
+    assert_eq!(&intf.rx_packetbuf.data[..4], &packet[..4]);
+    let result = Vec::from_slice(&intf.rx_packetbuf.data[4..]).unwrap();
+    intf.rx_packetbuf.clear();
+    (res.ptype, result)
+}
+
+// ----- Tests -----
+
+#[test]
+fn set_keymap_override() {
+    let ids = [
+        HidIoCommandId::ManufacturingTest,
+        HidIoCommandId::ManufacturingResult,
+    ];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+
+    // Layer 1, Switch Type (1), Index 42 -> (8, 10)
+    let set = [1, 1, 42, 0, 8, 0, 10, 0];
+    let res = vendor_cmd(&mut intf, KeymapCommand::Set as u16, &set);
+    assert_eq!(res, (HidIoPacketType::Ack, Vec::new()));
+    assert_eq!(
+        intf.interface.keymap_override,
+        Some(((1, 1, 42), Some((8, 10))))
+    );
+
+    // Read the override back
+    let key = [1, 1, 42, 0];
+    let (ptype, data) = vendor_cmd(&mut intf, KeymapCommand::Get as u16, &key);
+    assert_eq!(ptype, HidIoPacketType::Ack);
+    assert_eq!(&data[..], &[8, 0, 10, 0]);
+
+    // Set without the guides is rejected
+    let res = vendor_cmd(&mut intf, KeymapCommand::Set as u16, &set[..4]);
+    assert_eq!(res.0, HidIoPacketType::Nak);
+    assert_eq!(
+        intf.interface.keymap_override,
+        Some(((1, 1, 42), Some((8, 10))))
+    );
+
+    // Clear override
+    let res = vendor_cmd(&mut intf, KeymapCommand::Clear as u16, &key);
+    assert_eq!(res.0, HidIoPacketType::Ack);
+    assert_eq!(intf.interface.keymap_override, Some(((1, 1, 42), None)));
+    let (ptype, data) = vendor_cmd(&mut intf, KeymapCommand::Get as u16, &key);
+    assert_eq!(ptype, HidIoPacketType::Ack);
+    assert!(data.is_empty());
+
+    // Other manufacturing commands are still handled by h0050_manufacturing_cmd
+    let open = (LedDetectCommand::Open as u16).to_le_bytes();
+    let res = command_packet(
+        &mut intf,
+        HidIoCommandId::ManufacturingTest,
+        &[open[0], open[1], 0, 0],
+    );
+    assert_eq!(res.ptype, HidIoPacketType::Ack);
+    assert_eq!(intf.interface.led_detect, Some(LedDetectCommand::Open));
+}
+
+#[test]
+fn pixel_set() {
+    let ids = [HidIoCommandId::PixelSet3c8b];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+
+    // Pixel 300 -> (0x12, 0x34, 0x56)
+    let data = [0x2C, 0x01, 0x12, 0x34, 0x56];
+    let res = command_packet(&mut intf, HidIoCommandId::PixelSet3c8b, &data);
+    assert_eq!(res.ptype, HidIoPacketType::Ack);
+    assert_eq!(intf.interface.pixel, Some((300, [0x12, 0x34, 0x56])));
+
+    // Pixels 300 and 301, the last pixel set is recorded
+    let data = [0x2C, 0x01, 0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC];
+    let res = command_packet(&mut intf, HidIoCommandId::PixelSet3c8b, &data);
+    assert_eq!(res.ptype, HidIoPacketType::Ack);
+    assert_eq!(intf.interface.pixel, Some((301, [0x78, 0x9A, 0xBC])));
+
+    // Partial color is rejected
+    let data = [0x00, 0x00, 0x12, 0x34];
+    let res = command_packet(&mut intf, HidIoCommandId::PixelSet3c8b, &data);
+    assert_eq!(res.ptype, HidIoPacketType::Nak);
+    assert_eq!(intf.interface.pixel, Some((301, [0x78, 0x9A, 0xBC])));
+}
+
+#[test]
+fn layer_state() {
+    let ids = [
+        HidIoCommandId::ManufacturingTest,
+        HidIoCommandId::ManufacturingResult,
+    ];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+    intf.interface.layers =
+        Vec::from_slice(&[(2, layer::State::Lock), (1, layer::State::Shift)]).unwrap();
+    let get = LayerStateCommand::Get as u16;
+
+    // Active layer stack
+    let (ptype, data) = vendor_cmd(&mut intf, get, &[]);
+    assert_eq!(ptype, HidIoPacketType::Ack);
+    assert_eq!(
+        &data[..],
+        &[2, layer::State::Lock as u8, 1, layer::State::Shift as u8]
+    );
+
+    // Single layer
+    let (ptype, data) = vendor_cmd(&mut intf, get, &[1]);
+    assert_eq!(ptype, HidIoPacketType::Ack);
+    assert_eq!(&data[..], &[1, layer::State::Shift as u8]);
+
+    // Inactive layer
+    let (ptype, data) = vendor_cmd(&mut intf, get, &[3]);
+    assert_eq!(ptype, HidIoPacketType::Ack);
+    assert_eq!(&data[..], &[3, layer::State::Off as u8]);
+
+    // Invalid argument
+    let res = vendor_cmd(&mut intf, get, &[1, 2]);
+    assert_eq!(res.0, HidIoPacketType::Nak);
+}
+
+#[test]
+fn led_detect() {
+    let ids = [
+        HidIoCommandId::ManufacturingTest,
+        HidIoCommandId::ManufacturingResult,
+    ];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+
+    manufacturing_cmd(&mut intf, LedDetectCommand::Short as u16, 0);
+    assert_eq!(intf.interface.led_detect, Some(LedDetectCommand::Short));
+    manufacturing_cmd(&mut intf, LedDetectCommand::Open as u16, 0);
+    assert_eq!(intf.interface.led_detect, Some(LedDetectCommand::Open));
+
+    // Results are ready, chip 1 with channels 0 and 9 open
+    assert!(intf
+        .led_detect_result(LedDetectCommand::Open, 1, &[0x01, 0x02, 0x00])
+        .is_ok());
+
+    // Loop the h0051 response back to decode it
+    while let Some(chunk) = intf.tx_bytebuf.dequeue() {
+        assert!(intf.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+    assert!(intf.rx_packetbuffer_decode().unwrap());
+    assert_eq!(intf.rx_packetbuf.id, HidIoCommandId::ManufacturingResult);
+
+    let mut expected = Vec::<u8, 8>::new();
+    expected
+        .extend_from_slice(&(LedDetectCommand::Open as u16).to_le_bytes())
+        .unwrap();
+    expected.extend_from_slice(&1u16.to_le_bytes()).unwrap();
+    expected.extend_from_slice(&[0x01, 0x02, 0x00]).unwrap();
+    assert_eq!(&intf.rx_packetbuf.data[..], &expected[..]);
+}
+
+#[test]
+fn mcu_flash_mode() {
+    let ids = [HidIoCommandId::FlashMode];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+
+    // Press does not enter flash mode
+    let press = CapabilityRun::McuFlashMode {
+        state: CapabilityEvent::Initial,
+    };
+    assert!(intf.mcu_flash_mode(press).is_none());
+    assert!(!intf.interface.flash_mode);
+
+    // Other capabilities are ignored
+    let noop = CapabilityRun::NoOp {
+        state: CapabilityEvent::Last,
+    };
+    assert!(intf.mcu_flash_mode(noop).is_none());
+    assert!(!intf.interface.flash_mode);
+
+    // Release enters flash mode
+    let release = CapabilityRun::McuFlashMode {
+        state: CapabilityEvent::Last,
+    };
+    assert!(matches!(intf.mcu_flash_mode(release), Some(Ok(_))));
+    assert!(intf.interface.flash_mode);
+}
+
+#[test]
+fn reset() {
+    let ids = [HidIoCommandId::TestPacket];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+
+    // Only decode the first chunk of a multi-chunk packet
+    loopback_packet(&mut intf, &[0xAA; 100], 1);
+    assert!(!intf.rx_packetbuffer_decode().unwrap());
+    assert!(!intf.tx_bytebuf.is_empty());
+
+    // Reset and make sure all of the decode state is cleared
+    intf.reset();
+    assert!(intf.rx_bytebuf.is_empty());
+    assert!(intf.tx_bytebuf.is_empty());
+    assert!(intf.rx_packetbuf.data.is_empty());
+    assert!(intf.serial_buf.is_empty());
+
+    // A fresh packet parses
+    loopback_packet(&mut intf, &[0x55; 10], 1);
+    assert!(intf.rx_packetbuffer_decode().unwrap());
+    assert_eq!(intf.rx_packetbuf.id, HidIoCommandId::TestPacket);
+    assert_eq!(&intf.rx_packetbuf.data[..], &[0x55; 10]);
+}
+
+#[test]
+fn small_message_len() {
+    let ids = [HidIoCommandId::TestPacket];
+    let mut intf = SmallTestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+
+    // Send a test packet and loop it back
+    let data = Vec::from_slice(&[0x12; 32]).unwrap();
+    assert!(intf.h0002_test(h0002::Cmd { data }, false).is_ok());
+    while let Some(chunk) = intf.tx_bytebuf.dequeue() {
+        assert!(intf.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+
+    // Process the test packet, an ack is sent in response
+    assert_eq!(intf.process_rx(0).unwrap(), 1);
+    assert!(!intf.tx_bytebuf.is_empty());
+}
+
+#[test]
+fn send_test_packet() {
+    let ids = [HidIoCommandId::TestPacket];
+    let mut intf = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+    let mut host = TestCommandInterface::new(&ids, TestInterface::default()).unwrap();
+    assert_eq!(intf.test_packet_status(), TestPacketStatus::None);
+
+    // Send test packet to the host
+    assert!(intf.send_test_packet(&[0x01, 0x02, 0x03, 0x04]).is_ok());
+    assert_eq!(intf.test_packet_status(), TestPacketStatus::Pending);
+    while let Some(chunk) = intf.tx_bytebuf.dequeue() {
+        assert!(host.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+
+    // Host echoes the test packet
+    assert_eq!(host.process_rx(0).unwrap(), 1);
+    while let Some(chunk) = host.tx_bytebuf.dequeue() {
+        assert!(intf.rx_bytebuf.enqueue(chunk).is_ok());
+    }
+    assert_eq!(intf.process_rx(0).unwrap(), 1);
+    assert_eq!(intf.test_packet_status(), TestPacketStatus::Verified);
+
+    // Echo with different data
+    assert!(intf.send_test_packet(&[0x01, 0x02]).is_ok());
+    let data = Vec::from_slice(&[0x02, 0x01]).unwrap();
+    assert!(intf.h0002_test_ack(h0002::Ack { data }).is_ok());
+    assert_eq!(intf.test_packet_status(), TestPacketStatus::Mismatch);
+}
@@ -0,0 +1,298 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Scan loop driver
+//!
+//! Ties the keyscanning Matrix to a kll-core LayerState.
+//! Each call to scan_once() strobes every column, converts the KeyEvents into TriggerEvents,
+//! handles off-state lookups and returns the resulting CapabilityRuns.
+//!
+//! Layer CapabilityRuns (e.g. LayerState) are applied to the LayerState by scan_once(), they must
+//! not be applied again by the caller. The generated layer TriggerEvents are processed at the
+//! start of the next scan_once() call, before any switch events, so that layer activation
+//! triggers fire.
+//!
+//! ```ignore
+//! let mut engine = ScanEngine::new(matrix, layer_state);
+//!
+//! // Every scan loop
+//! for cap_run in engine.scan_once::<Infallible, LSIZE>().unwrap() {
+//!     kiibohd_usb::enqueue_keyboard_event(cap_run, &mut kbd_producer).ok();
+//! }
+//! ```
+
+use crate::{Matrix, NoPull, PullMode, DEBOUNCE_DEFAULT};
+use embedded_hal::digital::v2::{InputPin, IoPin, OutputPin};
+use kll_core::layout::{LayerState, ProcessError};
+use kll_core::{trigger::Phro, CapabilityRun, TriggerEvent};
+
+#[derive(Copy, Clone, Debug, PartialEq, defmt::Format)]
+pub enum ScanError<E> {
+    /// GPIO error
+    Gpio(E),
+    /// LayerState could not process a TriggerEvent
+    Process(ProcessError),
+    /// Too many layer TriggerEvents generated in a single scan loop (MAX_ACTIVE_LAYERS)
+    LayerEventOverflow,
+}
+
+pub struct ScanEngine<
+    'a,
+    C: OutputPin,
+    R: InputPin,
+    const CSIZE: usize,
+    const RSIZE: usize,
+    const MSIZE: usize,
+    const SCAN_PERIOD_US: u32,
+    const DEBOUNCE_US: u32,
+    const IDLE_MS: u32,
+    const LAYOUT_SIZE: usize,
+    const STATE_SIZE: usize,
+    const MAX_LAYERS: usize,
+    const MAX_ACTIVE_LAYERS: usize,
+    const MAX_ACTIVE_TRIGGERS: usize,
+    const MAX_LAYER_STACK_CACHE: usize,
+    const MAX_OFF_STATE_LOOKUP: usize,
+    const MAX_OVERRIDES: usize,
+    const MAX_ONE_SHOT: usize,
+    const MAX_LAYER_TAPS: usize,
+    const MAX_AUTOREPEAT: usize,
+    const MAX_COALESCED: usize,
+    const MAKE_US: u32 = DEBOUNCE_DEFAULT,
+    const BREAK_US: u32 = DEBOUNCE_DEFAULT,
+    P = NoPull,
+> {
+    matrix: Matrix<
+        C,
+        R,
+        CSIZE,
+        RSIZE,
+        MSIZE,
+        SCAN_PERIOD_US,
+        DEBOUNCE_US,
+        IDLE_MS,
+        MAKE_US,
+        BREAK_US,
+        P,
+    >,
+    layer_state: LayerState<
+        'a,
+        LAYOUT_SIZE,
+        STATE_SIZE,
+        MAX_LAYERS,
+        MAX_ACTIVE_LAYERS,
+        MAX_ACTIVE_TRIGGERS,
+        MAX_LAYER_STACK_CACHE,
+        MAX_OFF_STATE_LOOKUP,
+        MAX_OVERRIDES,
+        MAX_ONE_SHOT,
+        MAX_LAYER_TAPS,
+        MAX_AUTOREPEAT,
+        MAX_COALESCED,
+    >,
+    /// Layer TriggerEvents to process during the next scan loop
+    layer_events: heapless::Vec<TriggerEvent, MAX_ACTIVE_LAYERS>,
+}
+
+impl<
+        'a,
+        C: OutputPin,
+        R: InputPin,
+        const CSIZE: usize,
+        const RSIZE: usize,
+        const MSIZE: usize,
+        const SCAN_PERIOD_US: u32,
+        const DEBOUNCE_US: u32,
+        const IDLE_MS: u32,
+        const LAYOUT_SIZE: usize,
+        const STATE_SIZE: usize,
+        const MAX_LAYERS: usize,
+        const MAX_ACTIVE_LAYERS: usize,
+        const MAX_ACTIVE_TRIGGERS: usize,
+        const MAX_LAYER_STACK_CACHE: usize,
+        const MAX_OFF_STATE_LOOKUP: usize,
+        const MAX_OVERRIDES: usize,
+        const MAX_ONE_SHOT: usize,
+        const MAX_LAYER_TAPS: usize,
+        const MAX_AUTOREPEAT: usize,
+        const MAX_COALESCED: usize,
+        const MAKE_US: u32,
+        const BREAK_US: u32,
+        P,
+    >
+    ScanEngine<
+        'a,
+        C,
+        R,
+        CSIZE,
+        RSIZE,
+        MSIZE,
+        SCAN_PERIOD_US,
+        DEBOUNCE_US,
+        IDLE_MS,
+        LAYOUT_SIZE,
+        STATE_SIZE,
+        MAX_LAYERS,
+        MAX_ACTIVE_LAYERS,
+        MAX_ACTIVE_TRIGGERS,
+        MAX_LAYER_STACK_CACHE,
+        MAX_OFF_STATE_LOOKUP,
+        MAX_OVERRIDES,
+        MAX_ONE_SHOT,
+        MAX_LAYER_TAPS,
+        MAX_AUTOREPEAT,
+        MAX_COALESCED,
+        MAKE_US,
+        BREAK_US,
+        P,
+    >
+{
+    pub fn new(
+        matrix: Matrix<
+            C,
+            R,
+            CSIZE,
+            RSIZE,
+            MSIZE,
+            SCAN_PERIOD_US,
+            DEBOUNCE_US,
+            IDLE_MS,
+            MAKE_US,
+            BREAK_US,
+            P,
+        >,
+        layer_state: LayerState<
+            'a,
+            LAYOUT_SIZE,
+            STATE_SIZE,
+            MAX_LAYERS,
+            MAX_ACTIVE_LAYERS,
+            MAX_ACTIVE_TRIGGERS,
+            MAX_LAYER_STACK_CACHE,
+            MAX_OFF_STATE_LOOKUP,
+            MAX_OVERRIDES,
+            MAX_ONE_SHOT,
+            MAX_LAYER_TAPS,
+            MAX_AUTOREPEAT,
+            MAX_COALESCED,
+        >,
+    ) -> Self {
+        Self {
+            matrix,
+            layer_state,
+            layer_events: heapless::Vec::new(),
+        }
+    }
+
+    /// Access the Matrix (e.g. idle detection)
+    pub fn matrix(
+        &mut self,
+    ) -> &mut Matrix<
+        C,
+        R,
+        CSIZE,
+        RSIZE,
+        MSIZE,
+        SCAN_PERIOD_US,
+        DEBOUNCE_US,
+        IDLE_MS,
+        MAKE_US,
+        BREAK_US,
+        P,
+    > {
+        &mut self.matrix
+    }
+
+    /// Access the LayerState (e.g. layer changes or overrides)
+    pub fn layer_state(
+        &mut self,
+    ) -> &mut LayerState<
+        'a,
+        LAYOUT_SIZE,
+        STATE_SIZE,
+        MAX_LAYERS,
+        MAX_ACTIVE_LAYERS,
+        MAX_ACTIVE_TRIGGERS,
+        MAX_LAYER_STACK_CACHE,
+        MAX_OFF_STATE_LOOKUP,
+        MAX_OVERRIDES,
+        MAX_ONE_SHOT,
+        MAX_LAYER_TAPS,
+        MAX_AUTOREPEAT,
+        MAX_COALESCED,
+    > {
+        &mut self.layer_state
+    }
+
+    /// Runs a full scan loop
+    /// Every column is sensed once, so this must be called every SCAN_PERIOD_US * CSIZE
+    ///
+    /// LSIZE: Maximum number of guides per lookup and CapabilityRuns per scan loop
+    pub fn scan_once<E, const LSIZE: usize>(
+        &mut self,
+    ) -> Result<heapless::Vec<CapabilityRun, LSIZE>, ScanError<E>>
+    where
+        C: OutputPin<Error = E> + IoPin<R, C>,
+        R: InputPin<Error = E> + IoPin<R, C>,
+        E: core::convert::From<<R as IoPin<R, C>>::Error>
+            + core::convert::From<<C as IoPin<R, C>>::Error>,
+        P: PullMode<R>,
+    {
+        // Each scan loop is a single time instance
+        self.layer_state.increment_time();
+
+        // Layer events from the previous scan loop are processed first
+        for event in core::mem::take(&mut self.layer_events) {
+            self.layer_state
+                .process_trigger::<LSIZE>(event)
+                .map_err(ScanError::Process)?;
+        }
+
+        for _ in 0..CSIZE {
+            let (events, strobe) = self.matrix.sense::<E>().map_err(ScanError::Gpio)?;
+            for (i, event) in events.iter().enumerate() {
+                let index = strobe * RSIZE + i;
+                let event = event.trigger_event(index);
+
+                // Steady off states are only needed for off-state lookups
+                if let TriggerEvent::Switch {
+                    state: Phro::Off, ..
+                } = event
+                {
+                    continue;
+                }
+                self.layer_state
+                    .process_trigger::<LSIZE>(event)
+                    .map_err(ScanError::Process)?;
+            }
+            self.matrix.next_strobe::<E>().map_err(ScanError::Gpio)?;
+        }
+
+        // Evaluate any triggers that depend on keys not being pressed
+        // Only switch (ttype 1) off states are generated by the matrix
+        let matrix = &self.matrix;
+        self.layer_state
+            .process_off_state_lookups::<LSIZE>(|ttype, index| match (ttype, usize::from(index)) {
+                // Indices outside of the matrix are skipped
+                (1, index) if index < MSIZE => matrix.generate_event(index).trigger_event(index),
+                _ => TriggerEvent::None,
+            });
+
+        let results = self.layer_state.finalize_triggers::<LSIZE>();
+
+        // Apply layer changes, the generated events are processed during the next scan loop
+        for cap_run in &results {
+            for event in self.layer_state.apply_capability(*cap_run) {
+                self.layer_events
+                    .push(event)
+                    .map_err(|_| ScanError::LayerEventOverflow)?;
+            }
+        }
+
+        Ok(results)
+    }
+}
@@ -8,9 +8,14 @@
 
 #![no_std]
 
+#[cfg(feature = "kll-core")]
+pub mod engine;
 pub mod state;
+mod test;
 
-pub use self::state::{KeyState, State};
+pub use self::state::{KeyState, State, DEBOUNCE_DEFAULT};
+use core::marker::PhantomData;
+use embedded_hal::blocking::delay::DelayUs;
 use embedded_hal::digital::v2::{InputPin, IoPin, OutputPin, PinState};
 
 /// Records momentary push button events
@@ -30,6 +35,43 @@ pub enum KeyEvent {
     },
 }
 
+/// Sense GPIOs that support configuring an internal pull-down
+pub trait SensePull: InputPin {
+    /// Enable the internal pull-down of the sense GPIO
+    fn set_pull_down(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Sense line pull mode, applied each time the sense lines are drained by next_strobe()
+pub trait PullMode<R: InputPin> {
+    fn apply(row: &mut R) -> Result<(), R::Error>;
+}
+
+/// Sense line pull configuration is left unchanged
+///
+/// Use with external pull-down resistors on each of the sense lines.
+/// Without any pull-down, sense lines will float after being drained which may cause ghost
+/// readings.
+pub struct NoPull;
+
+impl<R: InputPin> PullMode<R> for NoPull {
+    fn apply(_row: &mut R) -> Result<(), R::Error> {
+        Ok(())
+    }
+}
+
+/// Sense lines use the internal pull-down of the GPIO
+///
+/// For matrices without external pull-down resistors.
+/// Internal pull-downs are weak (usually 30k-100k) compared to typical external resistors so the
+/// sense lines will take longer to settle after a strobe (see Matrix::next_strobe_settle()).
+pub struct PullDown;
+
+impl<R: SensePull> PullMode<R> for PullDown {
+    fn apply(row: &mut R) -> Result<(), R::Error> {
+        row.set_pull_down()
+    }
+}
+
 /// This struct handles scanning and strobing of the key matrix.
 ///
 /// It also handles the debouncing of key input to ensure acurate keypresses are being read.
@@ -46,6 +88,9 @@ pub enum KeyEvent {
 /// // Debounce timer in us. Can only be as precise as a multiple of SCAN_PERIOD_US.
 /// // Per-key timer is reset if the raw gpio reading changes for any reason.
 /// const DEBOUNCE_US = 5000; // 5 ms
+/// // Optional make (press) and break (release) debounce windows in us, DEBOUNCE_US by default.
+/// const MAKE_US = 5000; // 5 ms
+/// const BREAK_US = 2000; // 2 ms
 /// // Idle timer in ms. Only valid if the switch is in the off state.
 /// const IDLE_MS = 600_0000; // 600 seconds or 10 minutes
 ///
@@ -80,7 +125,7 @@ pub enum KeyEvent {
 /// ];
 ///
 /// let mut matrix = Matrix::<OutputPin, InputPin, CSIZE, RSIZE, MSIZE, SCAN_PERIOD_US, DEBOUNCE_US,
-/// IDLE_MS>::new(cols, rows);
+/// IDLE_MS, MAKE_US, BREAK_US>::new(cols, rows);
 ///
 /// // Prepare first strobe
 /// matrix.next_strobe().unwrap();
@@ -88,7 +133,13 @@ pub enum KeyEvent {
 /// // --> This next part must be done in constant time (SCAN_PERIOD_US) <--
 /// let state = matrix.sense().unwrap();
 /// matrix.next_strobe().unwrap();
+///
+/// // Or, for matrices that need time for the sense lines to settle after strobing
+/// let state = matrix.sense().unwrap();
+/// matrix.next_strobe_settle(&mut delay, SETTLE_US).unwrap();
 /// ```
+///
+/// P selects the sense line pull mode (NoPull by default, see PullDown)
 pub struct Matrix<
     C: OutputPin,
     R: InputPin,
@@ -98,6 +149,9 @@ pub struct Matrix<
     const SCAN_PERIOD_US: u32,
     const DEBOUNCE_US: u32,
     const IDLE_MS: u32,
+    const MAKE_US: u32 = DEBOUNCE_DEFAULT,
+    const BREAK_US: u32 = DEBOUNCE_DEFAULT,
+    P = NoPull,
 > {
     /// Strobe GPIOs (columns)
     cols: [C; CSIZE],
@@ -105,8 +159,16 @@ pub struct Matrix<
     rows: [R; RSIZE],
     /// Current GPIO column being strobed
     cur_strobe: usize,
+    /// Order in which columns are strobed
+    strobe_order: [usize; CSIZE],
+    /// Position in strobe_order of the current strobe
+    strobe_pos: usize,
     /// Recorded state of the entire matrix
-    state_matrix: [KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>; MSIZE],
+    state_matrix: [KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US>; MSIZE],
+    /// Keys that are never considered idle (e.g. layer-lock keys)
+    idle_exempt: [bool; MSIZE],
+    /// Sense line pull mode
+    pull: PhantomData<P>,
 }
 
 impl<
@@ -118,19 +180,29 @@ impl<
         const SCAN_PERIOD_US: u32,
         const DEBOUNCE_US: u32,
         const IDLE_MS: u32,
-    > Matrix<C, R, CSIZE, RSIZE, MSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>
+        const MAKE_US: u32,
+        const BREAK_US: u32,
+        P,
+    >
+    Matrix<C, R, CSIZE, RSIZE, MSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US, P>
 {
     pub fn new<'a, E: 'a>(cols: [C; CSIZE], rows: [R; RSIZE]) -> Result<Self, E>
     where
         C: OutputPin<Error = E>,
         E: core::convert::From<<C as OutputPin>::Error>,
     {
-        let state_matrix = [KeyState::<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>::new(); MSIZE];
+        let state_matrix =
+            [KeyState::<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US>::new();
+                MSIZE];
         let mut res = Self {
             cols,
             rows,
             cur_strobe: CSIZE - 1,
+            strobe_order: core::array::from_fn(|i| i),
+            strobe_pos: CSIZE - 1,
             state_matrix,
+            idle_exempt: [false; MSIZE],
+            pull: PhantomData,
         };
 
         // Reset strobe position and make sure all strobes are off
@@ -139,7 +211,7 @@ impl<
     }
 
     /// Clears strobes
-    /// Resets strobe counter to the last element (so next_strobe starts at 0)
+    /// Resets strobe counter to the last element (so next_strobe starts at the first strobe)
     pub fn clear<'a, E: 'a>(&'a mut self) -> Result<(), E>
     where
         C: OutputPin<Error = E>,
@@ -150,10 +222,32 @@ impl<
         }
 
         // Reset strobe position
-        self.cur_strobe = CSIZE - 1;
+        self.strobe_pos = CSIZE - 1;
+        self.cur_strobe = self.strobe_order[self.strobe_pos];
         Ok(())
     }
 
+    /// Set the order in which columns are strobed (defaults to 0..CSIZE)
+    /// e.g. an interleaved order to spread EMI
+    /// The next call to next_strobe starts at the beginning of the new order
+    ///
+    /// Returns false (and the order is unchanged) if the order is not a permutation of the columns
+    pub fn set_strobe_order(&mut self, order: [usize; CSIZE]) -> bool {
+        // Each column must be strobed exactly once
+        let mut seen = [false; CSIZE];
+        for &col in order.iter() {
+            if col >= CSIZE || seen[col] {
+                return false;
+            }
+            seen[col] = true;
+        }
+
+        // Current strobe is left as-is so next_strobe unsets the correct column
+        self.strobe_order = order;
+        self.strobe_pos = CSIZE - 1;
+        true
+    }
+
     /// Next strobe
     pub fn next_strobe<'a, E: 'a>(&'a mut self) -> Result<usize, E>
     where
@@ -161,6 +255,7 @@ impl<
         R: InputPin<Error = E> + IoPin<R, C>,
         E: core::convert::From<<R as IoPin<R, C>>::Error>
             + core::convert::From<<C as IoPin<R, C>>::Error>,
+        P: PullMode<R>,
     {
         // Unset current strobe
         self.cols[self.cur_strobe].set_low()?;
@@ -175,16 +270,19 @@ impl<
             unsafe {
                 let row = core::ptr::read(ptr);
                 // Temporarily sink sense gpios and reset to sense/read gpio
-                row.into_output_pin(PinState::Low)?.into_input_pin()?;
+                let mut row = row.into_output_pin(PinState::Low)?.into_input_pin()?;
+                // Apply sense line pull configuration
+                P::apply(&mut row)?;
             }
         }
 
         // Check for roll-over condition
-        if self.cur_strobe >= CSIZE - 1 {
-            self.cur_strobe = 0;
+        if self.strobe_pos >= CSIZE - 1 {
+            self.strobe_pos = 0;
         } else {
-            self.cur_strobe += 1;
+            self.strobe_pos += 1;
         }
+        self.cur_strobe = self.strobe_order[self.strobe_pos];
 
         // Set new strobe
         self.cols[self.cur_strobe].set_high()?;
@@ -192,6 +290,35 @@ impl<
         Ok(self.cur_strobe)
     }
 
+    /// Next strobe, then wait for the sense lines to settle
+    ///
+    /// On matrices with high capacitance (e.g. large matrices or long traces) the sense lines
+    /// may not have settled by the time sense() is called directly after next_strobe().
+    /// The delay is called after the new strobe is set so sense() can safely be called once this
+    /// function returns.
+    ///
+    /// NOTE: The settle delay is part of the SCAN_PERIOD_US timing budget.
+    ///       settle_us + sense + next_strobe must all fit within SCAN_PERIOD_US.
+    pub fn next_strobe_settle<'a, E: 'a, D: DelayUs<u32>>(
+        &'a mut self,
+        delay: &mut D,
+        settle_us: u32,
+    ) -> Result<usize, E>
+    where
+        C: OutputPin<Error = E> + IoPin<R, C>,
+        R: InputPin<Error = E> + IoPin<R, C>,
+        E: core::convert::From<<R as IoPin<R, C>>::Error>
+            + core::convert::From<<C as IoPin<R, C>>::Error>,
+        P: PullMode<R>,
+    {
+        let strobe = self.next_strobe()?;
+
+        // Wait for the sense lines to settle before allowing a sense
+        delay.delay_us(settle_us);
+
+        Ok(strobe)
+    }
+
     /// Current strobe
     pub fn strobe(&self) -> usize {
         self.cur_strobe
@@ -233,11 +360,53 @@ impl<
         Ok((res, self.cur_strobe))
     }
 
+    /// Read the raw sense GPIOs of the currently strobed column
+    ///
+    /// No debouncing is done and the recorded matrix state is not updated.
+    /// Useful for hardware diagnostics (e.g. checking test-points over HID-IO).
+    pub fn sense_raw<'a, E: 'a>(&'a self) -> Result<[bool; RSIZE], E>
+    where
+        E: core::convert::From<<R as InputPin>::Error>,
+    {
+        let mut res = [false; RSIZE];
+
+        for (i, r) in self.rows.iter().enumerate() {
+            res[i] = r.is_high()?;
+        }
+
+        Ok(res)
+    }
+
     /// Return the KeyState for a given index
-    pub fn state(&self, index: usize) -> KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS> {
+    pub fn state(
+        &self,
+        index: usize,
+    ) -> KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US> {
         self.state_matrix[index]
     }
 
+    /// Mark a key as exempt from idle detection
+    ///
+    /// Exempt keys are ignored by all_idle() (counted as idle whether held or not), so holding an
+    /// exempt key (e.g. a layer-lock) does not prevent the matrix from being idle.
+    pub fn set_idle_exempt(&mut self, index: usize, exempt: bool) {
+        self.idle_exempt[index] = exempt;
+    }
+
+    /// Check if the key is exempt from idle detection
+    pub fn idle_exempt(&self, index: usize) -> bool {
+        self.idle_exempt[index]
+    }
+
+    /// Determine if the entire matrix is idle
+    /// Useful to determine when the firmware can enter power-save
+    pub fn all_idle(&self) -> bool {
+        self.state_matrix
+            .iter()
+            .zip(self.idle_exempt.iter())
+            .all(|(state, exempt)| *exempt || state.idle())
+    }
+
     /// Generate event from KeyState
     /// Useful when trying to determine if a key has not been pressed
     pub fn generate_event(&self, index: usize) -> KeyEvent {
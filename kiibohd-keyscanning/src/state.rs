@@ -25,13 +25,21 @@ impl Not for State {
     }
 }
 
+/// Use DEBOUNCE_US for the make (MAKE_US) or break (BREAK_US) debounce window
+pub const DEBOUNCE_DEFAULT: u32 = u32::MAX;
+
 /// The KeyState handles all of the decision making and state changes based on a high or low signal from a GPIO pin
+///
+/// MAKE_US and BREAK_US are the debounce windows used for Off -> On (make) and On -> Off (break)
+/// transitions. Both default to DEBOUNCE_US.
 #[derive(Copy, Clone)]
 pub struct KeyState<
     const CSIZE: usize,
     const SCAN_PERIOD_US: u32,
     const DEBOUNCE_US: u32,
     const IDLE_MS: u32,
+    const MAKE_US: u32 = DEBOUNCE_DEFAULT,
+    const BREAK_US: u32 = DEBOUNCE_DEFAULT,
 > {
     /// Most recently GPIO reading (not debounced)
     raw_state: State,
@@ -58,13 +66,36 @@ pub struct KeyState<
 
     /// This is used to track the list GPIO read bounce
     ///
-    /// If cycles * scan_period > DEBOUNCE_US then raw_state is assigned to state.
+    /// If cycles * scan_period > debounce window then raw_state is assigned to state.
     cycles_since_last_bounce: u32,
+
+    /// Used to track the number of cycles since the raw GPIO reading has changed (not debounced)
+    cycles_since_raw_change: u32,
 }
 
-impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, const IDLE_MS: u32>
-    KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>
+impl<
+        const CSIZE: usize,
+        const SCAN_PERIOD_US: u32,
+        const DEBOUNCE_US: u32,
+        const IDLE_MS: u32,
+        const MAKE_US: u32,
+        const BREAK_US: u32,
+    > KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US>
 {
+    /// Debounce window for Off -> On transitions
+    const MAKE_WINDOW_US: u32 = if MAKE_US == DEBOUNCE_DEFAULT {
+        DEBOUNCE_US
+    } else {
+        MAKE_US
+    };
+
+    /// Debounce window for On -> Off transitions
+    const BREAK_WINDOW_US: u32 = if BREAK_US == DEBOUNCE_DEFAULT {
+        DEBOUNCE_US
+    } else {
+        BREAK_US
+    };
+
     pub fn new() -> Self {
         Self {
             raw_state: State::Off,
@@ -74,6 +105,7 @@ impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, cons
             raw_state_average: 0,
             cycles_since_state_change: 0,
             cycles_since_last_bounce: 0,
+            cycles_since_raw_change: 0,
         }
     }
 
@@ -98,8 +130,9 @@ impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, cons
             // Update raw state
             self.raw_state = if on { State::On } else { State::Off };
 
-            // Reset bounce cycle counter
+            // Reset bounce and raw transition cycle counters
             self.cycles_since_last_bounce = 0;
+            self.cycles_since_raw_change = 0;
 
             // Start debounce tracking (if we haven't already started)
             self.debounce_tracking = true;
@@ -109,12 +142,19 @@ impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, cons
             return self.state();
         }
 
-        // Increment debounce cycle counter
+        // Increment debounce and raw transition cycle counters
         self.cycles_since_last_bounce += 1;
+        self.cycles_since_raw_change = self.cycles_since_raw_change.saturating_add(1);
+
+        // Determine the debounce window from the transition direction
+        let debounce_us = match self.state {
+            State::Off => Self::MAKE_WINDOW_US,
+            State::On => Self::BREAK_WINDOW_US,
+        };
 
         // Update the debounced state if it has changed and exceeded the debounce timer
         // (debounce timer resets if there is any bouncing during the debounce interval).
-        if self.cycles_since_last_bounce * SCAN_PERIOD_US * CSIZE as u32 >= DEBOUNCE_US {
+        if self.cycles_since_last_bounce * SCAN_PERIOD_US * CSIZE as u32 >= debounce_us {
             // Since we have hit the cycles_since_last_bounce threshold, we can keep it here
             self.cycles_since_last_bounce -= 1;
 
@@ -171,6 +211,13 @@ impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, cons
         self.cycles_since_state_change
     }
 
+    /// Number of cycles since the last raw GPIO change (not debounced)
+    /// 0 when the GPIO reading first changes
+    /// Unlike cycles_since_state_change, bouncing resets this counter
+    pub fn last_transition_cycles(&self) -> u32 {
+        self.cycles_since_raw_change
+    }
+
     /// True if the switch is idle
     /// idle indicates the switch is off and there have been no events
     pub fn idle(&self) -> bool {
@@ -178,8 +225,14 @@ impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, cons
     }
 }
 
-impl<const CSIZE: usize, const SCAN_PERIOD_US: u32, const DEBOUNCE_US: u32, const IDLE_MS: u32>
-    Default for KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>
+impl<
+        const CSIZE: usize,
+        const SCAN_PERIOD_US: u32,
+        const DEBOUNCE_US: u32,
+        const IDLE_MS: u32,
+        const MAKE_US: u32,
+        const BREAK_US: u32,
+    > Default for KeyState<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US>
 {
     fn default() -> Self {
         Self::new()
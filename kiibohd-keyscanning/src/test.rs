@@ -0,0 +1,682 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg(test)]
+
+// ----- Crates -----
+
+use super::*;
+use core::cell::Cell;
+use core::convert::Infallible;
+
+// ----- Types -----
+
+const CSIZE: usize = 1;
+const RSIZE: usize = 2;
+const MSIZE: usize = RSIZE * CSIZE;
+const SCAN_PERIOD_US: u32 = 1000;
+const DEBOUNCE_US: u32 = 5000;
+const IDLE_MS: u32 = 10;
+
+type TestMatrix<'a> =
+    Matrix<MockPin<'a>, MockPin<'a>, CSIZE, RSIZE, MSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>;
+
+// ----- Structs -----
+
+/// Mock GPIO, the level is shared with the test so it can be changed while owned by the Matrix
+struct MockPin<'a> {
+    level: &'a Cell<bool>,
+}
+
+impl<'a> OutputPin for MockPin<'a> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.level.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.level.set(true);
+        Ok(())
+    }
+}
+
+impl<'a> InputPin for MockPin<'a> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.level.get())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.level.get())
+    }
+}
+
+impl<'a> IoPin<MockPin<'a>, MockPin<'a>> for MockPin<'a> {
+    type Error = Infallible;
+
+    fn into_input_pin(self) -> Result<MockPin<'a>, Self::Error> {
+        Ok(self)
+    }
+
+    fn into_output_pin(self, state: PinState) -> Result<MockPin<'a>, Self::Error> {
+        self.level.set(state == PinState::High);
+        Ok(self)
+    }
+}
+
+// ----- Tests -----
+
+#[test]
+fn sense_raw_debounce_divergence() {
+    let col = Cell::new(false);
+    let rows = [Cell::new(false), Cell::new(false)];
+    let mut matrix = TestMatrix::new::<Infallible>(
+        [MockPin { level: &col }],
+        [MockPin { level: &rows[0] }, MockPin { level: &rows[1] }],
+    )
+    .unwrap();
+
+    // Press the first switch
+    rows[0].set(true);
+
+    // Raw reading is immediate, debounced reading stays off during the debounce window
+    let debounce_cycles = DEBOUNCE_US / SCAN_PERIOD_US / CSIZE as u32;
+    for _ in 0..debounce_cycles {
+        let raw = matrix.sense_raw::<Infallible>().unwrap();
+        assert_eq!(raw, [true, false]);
+
+        let (events, _) = matrix.sense::<Infallible>().unwrap();
+        assert!(
+            matches!(events[0], KeyEvent::Off { .. }),
+            "Unexpected event: {:?}",
+            events[0]
+        );
+    }
+
+    // Once the debounce window has passed both readings agree
+    let (events, _) = matrix.sense::<Infallible>().unwrap();
+    assert!(
+        matches!(events[0], KeyEvent::On { .. }),
+        "Unexpected event: {:?}",
+        events[0]
+    );
+    assert_eq!(matrix.sense_raw::<Infallible>().unwrap(), [true, false]);
+}
+
+/// Mock delay, simulates the sense line settling once the delay has been called
+struct MockSettleDelay<'a> {
+    strobe: &'a Cell<bool>,
+    sense: &'a Cell<bool>,
+    delayed_us: u32,
+}
+
+impl<'a> DelayUs<u32> for MockSettleDelay<'a> {
+    fn delay_us(&mut self, us: u32) {
+        // Strobe must already be set before waiting
+        assert!(self.strobe.get(), "Strobe not set before settle delay");
+        self.delayed_us += us;
+        self.sense.set(true);
+    }
+}
+
+#[test]
+fn strobe_settle_delay() {
+    let col = Cell::new(false);
+    let rows = [Cell::new(false), Cell::new(false)];
+    let mut matrix = TestMatrix::new::<Infallible>(
+        [MockPin { level: &col }],
+        [MockPin { level: &rows[0] }, MockPin { level: &rows[1] }],
+    )
+    .unwrap();
+
+    let mut delay = MockSettleDelay {
+        strobe: &col,
+        sense: &rows[1],
+        delayed_us: 0,
+    };
+
+    // Sense line hasn't settled yet
+    assert_eq!(matrix.sense_raw::<Infallible>().unwrap(), [false, false]);
+
+    // Sense line is settled once next_strobe_settle returns
+    assert_eq!(
+        matrix.next_strobe_settle::<Infallible, _>(&mut delay, 20),
+        Ok(0)
+    );
+    assert_eq!(delay.delayed_us, 20);
+    assert_eq!(matrix.sense_raw::<Infallible>().unwrap(), [false, true]);
+}
+
+#[test]
+fn idle_exempt_held() {
+    let col = Cell::new(false);
+    let rows = [Cell::new(false), Cell::new(false)];
+    let mut matrix = TestMatrix::new::<Infallible>(
+        [MockPin { level: &col }],
+        [MockPin { level: &rows[0] }, MockPin { level: &rows[1] }],
+    )
+    .unwrap();
+    matrix.set_idle_exempt(0, true);
+    assert!(matrix.idle_exempt(0));
+    assert!(!matrix.idle_exempt(1));
+
+    // Hold the exempt key past IDLE_MS
+    rows[0].set(true);
+    let idle_cycles = IDLE_MS * 1000 / SCAN_PERIOD_US / CSIZE as u32;
+    let debounce_cycles = DEBOUNCE_US / SCAN_PERIOD_US / CSIZE as u32;
+    for _ in 0..(idle_cycles + debounce_cycles) * 2 {
+        matrix.sense::<Infallible>().unwrap();
+    }
+    assert!(matrix.state(1).idle());
+    assert!(!matrix.state(0).idle());
+    assert!(matrix.all_idle());
+
+    // The same held key blocks idle once it is no longer exempt
+    matrix.set_idle_exempt(0, false);
+    assert!(!matrix.all_idle());
+
+    // Released exempt keys are ignored as well, even before IDLE_MS
+    matrix.set_idle_exempt(0, true);
+    rows[0].set(false);
+    for _ in 0..=debounce_cycles {
+        matrix.sense::<Infallible>().unwrap();
+    }
+    assert!(!matrix.state(0).idle());
+    assert!(matrix.all_idle());
+}
+
+#[test]
+fn strobe_order_reversed() {
+    let cols = [Cell::new(false), Cell::new(false), Cell::new(false)];
+    let rows = [Cell::new(false), Cell::new(false)];
+    let mut matrix = Matrix::<
+        MockPin,
+        MockPin,
+        3,
+        RSIZE,
+        { 3 * RSIZE },
+        SCAN_PERIOD_US,
+        DEBOUNCE_US,
+        IDLE_MS,
+    >::new::<Infallible>(
+        [
+            MockPin { level: &cols[0] },
+            MockPin { level: &cols[1] },
+            MockPin { level: &cols[2] },
+        ],
+        [MockPin { level: &rows[0] }, MockPin { level: &rows[1] }],
+    )
+    .unwrap();
+
+    // Not a permutation of the columns
+    assert!(!matrix.set_strobe_order([0, 0, 1]));
+    assert!(!matrix.set_strobe_order([0, 1, 3]));
+
+    assert!(matrix.set_strobe_order([2, 1, 0]));
+    for _ in 0..2 {
+        for strobe in [2, 1, 0] {
+            assert_eq!(matrix.next_strobe::<Infallible>(), Ok(strobe));
+            assert!(cols[strobe].get());
+            assert_eq!(cols.iter().filter(|c| c.get()).count(), 1);
+        }
+    }
+}
+
+/// Mock GPIO that records when the internal pull-down is enabled
+struct MockPullPin<'a> {
+    level: &'a Cell<bool>,
+    pull_down: &'a Cell<u32>,
+}
+
+impl<'a> OutputPin for MockPullPin<'a> {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.level.set(false);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.level.set(true);
+        Ok(())
+    }
+}
+
+impl<'a> InputPin for MockPullPin<'a> {
+    type Error = Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(self.level.get())
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(!self.level.get())
+    }
+}
+
+impl<'a> IoPin<MockPullPin<'a>, MockPullPin<'a>> for MockPullPin<'a> {
+    type Error = Infallible;
+
+    fn into_input_pin(self) -> Result<MockPullPin<'a>, Self::Error> {
+        Ok(self)
+    }
+
+    fn into_output_pin(self, state: PinState) -> Result<MockPullPin<'a>, Self::Error> {
+        self.level.set(state == PinState::High);
+        Ok(self)
+    }
+}
+
+impl<'a> SensePull for MockPullPin<'a> {
+    fn set_pull_down(&mut self) -> Result<(), Self::Error> {
+        self.pull_down.set(self.pull_down.get() + 1);
+        Ok(())
+    }
+}
+
+#[test]
+fn sense_pull_down() {
+    let col = Cell::new(false);
+    let rows = [Cell::new(false), Cell::new(false)];
+    let pull_downs = [Cell::new(0), Cell::new(0), Cell::new(0)];
+    let mut matrix = Matrix::<
+        MockPullPin,
+        MockPullPin,
+        CSIZE,
+        RSIZE,
+        MSIZE,
+        SCAN_PERIOD_US,
+        DEBOUNCE_US,
+        IDLE_MS,
+        DEBOUNCE_DEFAULT,
+        DEBOUNCE_DEFAULT,
+        PullDown,
+    >::new::<Infallible>(
+        [MockPullPin {
+            level: &col,
+            pull_down: &pull_downs[0],
+        }],
+        [
+            MockPullPin {
+                level: &rows[0],
+                pull_down: &pull_downs[1],
+            },
+            MockPullPin {
+                level: &rows[1],
+                pull_down: &pull_downs[2],
+            },
+        ],
+    )
+    .unwrap();
+
+    // Pull-down is applied to each sense line every strobe
+    for strobes in 1..=3 {
+        assert_eq!(matrix.next_strobe::<Infallible>(), Ok(0));
+        assert_eq!(pull_downs[0].get(), 0);
+        assert_eq!(pull_downs[1].get(), strobes);
+        assert_eq!(pull_downs[2].get(), strobes);
+    }
+}
+
+#[test]
+fn raw_and_debounced_transition_cycles() {
+    let mut state = KeyState::<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>::new();
+    let debounce_cycles = DEBOUNCE_US / SCAN_PERIOD_US / CSIZE as u32;
+
+    // Raw reading changes, debounced state does not
+    state.record(true);
+    assert_eq!(state.last_transition_cycles(), 0);
+    assert_eq!(state.state().0, State::Off);
+
+    // Bounce resets the raw counter only
+    state.record(false);
+    state.record(true);
+    assert_eq!(state.last_transition_cycles(), 0);
+
+    // Debounced state changes once the debounce window has passed
+    for _ in 0..debounce_cycles {
+        state.record(true);
+    }
+    assert_eq!(state.state().0, State::On);
+    assert_eq!(state.cycles_since_state_change(), 0);
+    assert_eq!(state.last_transition_cycles(), debounce_cycles);
+
+    // Both counters advance while held
+    state.record(true);
+    assert_eq!(state.cycles_since_state_change(), 1);
+    assert_eq!(state.last_transition_cycles(), debounce_cycles + 1);
+    assert!(!state.idle());
+}
+
+#[test]
+fn asymmetric_debounce() {
+    const MAKE_US: u32 = 5000;
+    const BREAK_US: u32 = 2000;
+    let mut state =
+        KeyState::<CSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS, MAKE_US, BREAK_US>::new();
+    let make_cycles = MAKE_US / SCAN_PERIOD_US / CSIZE as u32;
+    let break_cycles = BREAK_US / SCAN_PERIOD_US / CSIZE as u32;
+
+    // Bouncy make, uses the make window after the last bounce
+    state.record(true);
+    state.record(false);
+    state.record(true);
+    for _ in 0..make_cycles - 1 {
+        assert_eq!(state.record(true).0, State::Off);
+    }
+    assert_eq!(state.record(true).0, State::On);
+
+    // Clean break, uses the shorter break window
+    state.record(false);
+    for _ in 0..break_cycles - 1 {
+        assert_eq!(state.record(false).0, State::On);
+    }
+    assert_eq!(state.record(false).0, State::Off);
+}
+
+#[cfg(feature = "kll-core")]
+mod engine {
+    use super::*;
+    use crate::engine::ScanEngine;
+    use kll_core::guide::GuideBuilder;
+    use kll_core::layout::{LayerLookup, LayerState};
+    use kll_core::{
+        kll_hid, layer, trigger, Capability, CapabilityEvent, CapabilityRun, CapabilityState,
+        TriggerCondition, TriggerEvent,
+    };
+
+    /// Mock switch matrix GPIO
+    /// Sense lines are only high while the strobe is set and the switch is pressed
+    struct KeyPin<'a> {
+        strobe: &'a Cell<bool>,
+        pressed: Option<&'a Cell<bool>>,
+    }
+
+    impl<'a> OutputPin for KeyPin<'a> {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.strobe.set(false);
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            self.strobe.set(true);
+            Ok(())
+        }
+    }
+
+    impl<'a> InputPin for KeyPin<'a> {
+        type Error = Infallible;
+
+        fn is_high(&self) -> Result<bool, Self::Error> {
+            Ok(self.strobe.get() && self.pressed.is_some_and(|p| p.get()))
+        }
+
+        fn is_low(&self) -> Result<bool, Self::Error> {
+            Ok(!self.is_high()?)
+        }
+    }
+
+    impl<'a> IoPin<KeyPin<'a>, KeyPin<'a>> for KeyPin<'a> {
+        type Error = Infallible;
+
+        fn into_input_pin(self) -> Result<KeyPin<'a>, Self::Error> {
+            Ok(self)
+        }
+
+        fn into_output_pin(self, _state: PinState) -> Result<KeyPin<'a>, Self::Error> {
+            Ok(self)
+        }
+    }
+
+    #[rustfmt::skip]
+    const LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+        // Layer 0, Switch Type (1), Index 1, 1 trigger index: 0
+        0, 1, 1, [0],
+    );
+
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[
+        // index: TriggerGuideIndex => ResultGuideIndex
+        0, 0, // 0: 0 => 0
+    ];
+
+    const TRIGGER_GUIDES: &[u8] = kll_macros::trigger_guide!([[TriggerCondition::Switch {
+        state: trigger::Phro::Press,
+        index: 1,
+        loop_condition_index: 0,
+    },]]);
+
+    const RESULT_GUIDES: &[u8] = kll_macros::result_guide!([[Capability::HidKeyboard {
+        state: CapabilityState::Initial,
+        loop_condition_index: 0,
+        id: kll_hid::Keyboard::A,
+    },]]);
+
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    /// Single column, two switch (index 0 and 1) matrix
+    fn key_matrix<'a>(
+        col: &'a Cell<bool>,
+        pressed: &'a [Cell<bool>; 2],
+    ) -> Matrix<KeyPin<'a>, KeyPin<'a>, CSIZE, RSIZE, MSIZE, SCAN_PERIOD_US, DEBOUNCE_US, IDLE_MS>
+    {
+        Matrix::new::<Infallible>(
+            [KeyPin {
+                strobe: col,
+                pressed: None,
+            }],
+            [
+                KeyPin {
+                    strobe: col,
+                    pressed: Some(&pressed[0]),
+                },
+                KeyPin {
+                    strobe: col,
+                    pressed: Some(&pressed[1]),
+                },
+            ],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn scan_press_to_keyboard() {
+        let col = Cell::new(false);
+        let pressed = [Cell::new(false), Cell::new(false)];
+        let matrix = key_matrix(&col, &pressed);
+
+        let lookup = LayerLookup::<256>::new(
+            LAYER_LOOKUP,
+            TRIGGER_GUIDES,
+            RESULT_GUIDES,
+            TRIGGER_RESULT_MAPPING,
+            LOOP_CONDITION_LOOKUP,
+        )
+        .unwrap();
+        let layer_state = LayerState::<256, 64, 2, 2, 2, 4, 2, 2>::new(lookup, 0);
+        let mut engine = ScanEngine::new(matrix, layer_state);
+
+        // Nothing pressed
+        for _ in 0..3 {
+            assert!(engine.scan_once::<Infallible, 4>().unwrap().is_empty());
+        }
+
+        // Press the second switch (index 1), result is sent once debouncing has finished
+        pressed[1].set(true);
+        let mut results = heapless::Vec::<CapabilityRun, 4>::new();
+        for _ in 0..(DEBOUNCE_US / SCAN_PERIOD_US) * 2 {
+            results = engine.scan_once::<Infallible, 4>().unwrap();
+            if !results.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(
+            results,
+            [CapabilityRun::HidKeyboard {
+                state: CapabilityEvent::Initial,
+                id: kll_hid::Keyboard::A,
+            }],
+            "Unexpected result: {:?}",
+            results
+        );
+    }
+
+    #[test]
+    fn scan_layer_activation() {
+        #[rustfmt::skip]
+        const LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+            // Layer 0, Switch Type (1), Index 1, 1 trigger index: 0
+            0, 1, 1, [0],
+            // Layer 0, Layer Type (7), Index 1, 1 trigger index: 2
+            0, 7, 1, [2],
+            // Layer 1, Switch Type (1), Index 0, No Triggers
+            1, 1, 0, [],
+        );
+
+        // trigger_guide!/result_guide! encode a single guide per invocation
+        // Build each guide separately and concatenate them
+        let mut trigger_guides = GuideBuilder::new();
+        trigger_guides.push_trigger_combo(&[TriggerCondition::Switch {
+            state: trigger::Phro::Press,
+            index: 1,
+            loop_condition_index: 0,
+        }]);
+        let mut trigger_guides = trigger_guides.finish();
+        let layer_trigger = trigger_guides.len() as u16;
+        let mut guide = GuideBuilder::new();
+        guide.push_trigger_combo(&[TriggerCondition::Layer {
+            state: trigger::LayerState::LockActivate,
+            loop_condition_index: 0,
+            layer: 1,
+        }]);
+        trigger_guides.extend(guide.finish());
+
+        let mut result_guides = GuideBuilder::new();
+        result_guides.push_result_combo(&[Capability::LayerState {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            layer: 1,
+            layer_state: layer::State::Lock,
+        }]);
+        let mut result_guides = result_guides.finish();
+        let layer_result = result_guides.len() as u16;
+        let mut guide = GuideBuilder::new();
+        guide.push_result_combo(&[Capability::HidKeyboard {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            id: kll_hid::Keyboard::B,
+        }]);
+        result_guides.extend(guide.finish());
+
+        #[rustfmt::skip]
+        let trigger_result_mapping = [
+            // index: TriggerGuideIndex => ResultGuideIndex
+            0, 0, // 0: Switch 1 => Lock layer 1
+            layer_trigger, layer_result, // 2: Layer 1 LockActivate => Keyboard B
+        ];
+
+        let col = Cell::new(false);
+        let pressed = [Cell::new(false), Cell::new(false)];
+        let matrix = key_matrix(&col, &pressed);
+
+        let lookup = LayerLookup::<256>::new(
+            LAYER_LOOKUP,
+            &trigger_guides,
+            &result_guides,
+            &trigger_result_mapping,
+            LOOP_CONDITION_LOOKUP,
+        )
+        .unwrap();
+        let layer_state = LayerState::<256, 64, 2, 2, 2, 4, 2, 2>::new(lookup, 0);
+        let mut engine = ScanEngine::new(matrix, layer_state);
+
+        // Press the second switch (index 1) to lock layer 1
+        pressed[1].set(true);
+        let mut results = heapless::Vec::<CapabilityRun, 4>::new();
+        for _ in 0..(DEBOUNCE_US / SCAN_PERIOD_US) * 2 {
+            results = engine.scan_once::<Infallible, 4>().unwrap();
+            if !results.is_empty() {
+                break;
+            }
+        }
+        assert_eq!(
+            results,
+            [CapabilityRun::LayerState {
+                state: CapabilityEvent::Initial,
+                layer: 1,
+                layer_state: layer::State::Lock,
+            }],
+            "Unexpected result: {:?}",
+            results
+        );
+        assert_eq!(
+            engine.layer_state().layer_state(1),
+            Some(layer::State::Lock)
+        );
+
+        // Layer activation macro fires on the next scan loop
+        let results = engine.scan_once::<Infallible, 4>().unwrap();
+        assert_eq!(
+            results,
+            [CapabilityRun::HidKeyboard {
+                state: CapabilityEvent::Initial,
+                id: kll_hid::Keyboard::B,
+            }],
+            "Unexpected result: {:?}",
+            results
+        );
+    }
+
+    #[test]
+    fn scan_off_state_outside_matrix() {
+        #[rustfmt::skip]
+        const LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+            // Layer 0, Switch Type (1), Index 5, 1 trigger index: 0
+            0, 1, 5, [0],
+        );
+
+        const TRIGGER_GUIDES: &[u8] = kll_macros::trigger_guide!([[TriggerCondition::Switch {
+            state: trigger::Phro::Off,
+            index: 5,
+            loop_condition_index: 0,
+        },]]);
+
+        let col = Cell::new(false);
+        let pressed = [Cell::new(false), Cell::new(false)];
+        let matrix = key_matrix(&col, &pressed);
+
+        let lookup = LayerLookup::<256>::new(
+            LAYER_LOOKUP,
+            TRIGGER_GUIDES,
+            RESULT_GUIDES,
+            TRIGGER_RESULT_MAPPING,
+            LOOP_CONDITION_LOOKUP,
+        )
+        .unwrap();
+        let layer_state = LayerState::<256, 64, 2, 2, 2, 4, 2, 2>::new(lookup, 0);
+        let mut engine = ScanEngine::new(matrix, layer_state);
+
+        // Index 5 is not part of the matrix (e.g. a switch from another scan source)
+        engine
+            .layer_state()
+            .process_trigger::<4>(TriggerEvent::Switch {
+                state: trigger::Phro::Press,
+                index: 5,
+                last_state: 0,
+            })
+            .unwrap();
+        assert_eq!(engine.layer_state().off_state_lookups().len(), 1);
+
+        // The off state lookup is skipped instead of reading past the end of the matrix
+        assert!(engine.scan_once::<Infallible, 4>().unwrap().is_empty());
+        assert!(engine.layer_state().off_state_lookups().is_empty());
+    }
+}
@@ -56,6 +56,53 @@ pub struct KeyboardNkroReport {
     pub keybitmap: [u8; 29],
 }
 
+/// NKRO Keyboard - HID Bitmap with a configurable bitmap length
+///
+/// Identical to KeyboardNkroReport, but supports N byte bitmaps (29 to 32 bytes).
+/// A 32 byte bitmap covers every 8-bit key code (1-255), including the reserved 0xE8..0xFF range.
+/// The last bit of the bitmap is padding (0 is not a key).
+/// Only the keybitmap is sent as the input report (use push_raw_input).
+pub struct KeyboardNkroDescriptor<const N: usize>;
+
+impl<const N: usize> KeyboardNkroDescriptor<N> {
+    /// HID descriptor for an N byte NKRO bitmap
+    pub const DESC: [u8; 41] = keyboard_nkro_descriptor(N);
+}
+
+/// Generates the NKRO keyboard HID descriptor for an N byte bitmap
+/// Matches KeyboardNkroReport::desc() when N is 29
+pub const fn keyboard_nkro_descriptor(bytes: usize) -> [u8; 41] {
+    assert!(
+        bytes >= 29 && bytes <= 32,
+        "NKRO bitmap must be between 29 and 32 bytes"
+    );
+    // Keys 1 to (bits - 1), last bit is padding
+    let keys = (bytes * 8 - 1) as u8;
+    [
+        0x05, 0x01, // Usage Page (Generic Desktop Ctrls)
+        0x09, 0x06, // Usage (Keyboard)
+        0xA1, 0x01, // Collection (Application)
+        0x05, 0x08, //   Usage Page (LEDs)
+        0x19, 0x01, //   Usage Minimum (Num Lock)
+        0x29, 0x05, //   Usage Maximum (Kana)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x25, 0x01, //   Logical Maximum (1)
+        0x75, 0x01, //   Report Size (1)
+        0x95, 0x05, //   Report Count (5)
+        0x91, 0x02, //   Output (Data,Var,Abs)
+        0x95, 0x03, //   Report Count (3)
+        0x91, 0x03, //   Output (Const,Var,Abs)
+        0x05, 0x07, //   Usage Page (Kbrd/Keypad)
+        0x19, 0x01, //   Usage Minimum (0x01)
+        0x29, keys, //   Usage Maximum
+        0x95, keys, //   Report Count
+        0x81, 0x00, //   Input (Data,Array,Abs)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x03, //   Input (Const,Var,Abs)
+        0xC0, // End Collection
+    ]
+}
+
 /// System Control and Consumer Control
 ///
 /// System Control 0x81 through 0xB7
@@ -93,6 +140,55 @@ pub struct SysCtrlConsumerCtrlReport {
     pub system_ctrl: u8,
 }
 
+/// Maximum number of simultaneous Consumer Control keys (SysCtrlConsumerCtrlDescriptor)
+pub const CONSUMER_CTRL_MAX: usize = 16;
+
+/// System Control and Consumer Control - Consumer Control array of N keys
+///
+/// Identical to SysCtrlConsumerCtrlReport, but the Consumer Control array holds N (1 to 16)
+/// simultaneous keys.
+/// The input report is N little-endian 16-bit Consumer Control codes followed by the
+/// 8-bit System Control code (use push_raw_input).
+pub struct SysCtrlConsumerCtrlDescriptor<const N: usize>;
+
+impl<const N: usize> SysCtrlConsumerCtrlDescriptor<N> {
+    /// HID descriptor for an N key Consumer Control array
+    pub const DESC: [u8; 44] = sysctrl_consumerctrl_descriptor(N);
+}
+
+/// Generates the System Control and Consumer Control HID descriptor for an N key array
+/// Matches SysCtrlConsumerCtrlReport::desc() when N is 1, apart from the explicit
+/// System Control Report Count
+pub const fn sysctrl_consumerctrl_descriptor(keys: usize) -> [u8; 44] {
+    assert!(
+        keys >= 1 && keys <= CONSUMER_CTRL_MAX,
+        "Consumer Control array must be between 1 and 16 keys"
+    );
+    let keys = keys as u8;
+    [
+        0x05, 0x0C, // Usage Page (Consumer)
+        0x09, 0x01, // Usage (Consumer Control)
+        0xA1, 0x01, // Collection (Application)
+        0x05, 0x0C, //   Usage Page (Consumer)
+        0x19, 0x00, //   Usage Minimum (Unassigned)
+        0x2A, 0x9D, 0x02, //   Usage Maximum (0x029D)
+        0x15, 0x00, //   Logical Minimum (0)
+        0x27, 0xFF, 0xFF, 0x00, 0x00, //   Logical Maximum (65535)
+        0x75, 0x10, //   Report Size (16)
+        0x95, keys, //   Report Count
+        0x81, 0x00, //   Input (Data,Array,Abs)
+        0x05, 0x01, //   Usage Page (Generic Desktop Ctrls)
+        0x19, 0x81, //   Usage Minimum (Sys Power Down)
+        0x29, 0xB7, //   Usage Maximum (Sys Display LCD Autoscale)
+        0x15, 0x01, //   Logical Minimum (1)
+        0x26, 0xFF, 0x00, //   Logical Maximum (255)
+        0x75, 0x08, //   Report Size (8)
+        0x95, 0x01, //   Report Count (1)
+        0x81, 0x00, //   Input (Data,Array,Abs)
+        0xC0, // End Collection
+    ]
+}
+
 /// Mouse Interface
 /// MouseReport describes a report and its companion descriptor that can be used
 /// to send mouse movements and button presses to a host.
@@ -30,11 +30,14 @@ use defmt::{error, trace, warn};
 use log::{error, trace, warn};
 
 pub use crate::descriptor::{
-    HidioReport, KeyboardNkroReport, MouseReport, SysCtrlConsumerCtrlReport,
+    keyboard_nkro_descriptor, sysctrl_consumerctrl_descriptor, HidioReport, KeyboardNkroDescriptor,
+    KeyboardNkroReport, MouseReport, SysCtrlConsumerCtrlDescriptor, SysCtrlConsumerCtrlReport,
+    CONSUMER_CTRL_MAX,
 };
 use heapless::spsc::Consumer;
 use usb_device::bus::{UsbBus, UsbBusAllocator};
-use usb_device::class::UsbClass;
+use usb_device::class::{ControlIn, ControlOut, UsbClass};
+use usb_device::control::{Recipient, Request, RequestType};
 use usbd_hid::descriptor::generator_prelude::*;
 use usbd_hid::descriptor::KeyboardReport;
 use usbd_hid::hid_class::{HIDClass, HidClassSettings, HidProtocol, HidSubClass};
@@ -98,11 +101,161 @@ pub enum CtrlState {
     Unknown,
 }
 
+/// Modifies an NKRO bitmask
+/// The bitmap covers keys 1 to (N * 8 - 1), any other keys are ignored
+fn nkro_bit<const N: usize>(keybitmap: &mut [u8; N], key: u8, press: bool) {
+    // NOTE: The indexing actually starts from 1 (not 0), so position 0 represents 1
+    //       0 in USB HID represents no keys pressed, so it's meaningless in a bitmask
+    //       Ignore any keys past the end of the bitmap (e.g. 231/0xE7 for 29 bytes)
+    if key == 0 || usize::from(key) >= N * 8 {
+        warn!("Invalid key for nkro_bit({}, {}), ignored.", key, press);
+        return;
+    }
+
+    let key = key - 1;
+
+    // Determine position
+    let byte: usize = (key / 8).into();
+    let bit: usize = (key % 8).into();
+
+    // Set/Unset
+    if press {
+        keybitmap[byte] |= 1 << bit;
+    } else {
+        keybitmap[byte] &= !(1 << bit);
+    }
+}
+
+#[cfg(feature = "mouse")]
+impl MouseReport {
+    fn button_bit(&mut self, button: u8, press: bool) {
+        // Ignore keys outside of 1 to 8
+        if let 1..=8 = button {
+            let button = button - 1;
+            // Determine position
+            let bit: usize = (button % 8).into();
+
+            // Set/Unset
+            if press {
+                self.buttons |= 1 << bit;
+            } else {
+                self.buttons &= !(1 << bit);
+            }
+        }
+    }
+
+    /// Applies a MouseState to the report
+    /// Position and wheel updates are relative and accumulate (saturating) until the report is
+    /// sent
+    fn update(&mut self, state: MouseState) {
+        match state {
+            MouseState::Press(key) => {
+                self.button_bit(key, true);
+            }
+            MouseState::Release(key) => {
+                self.button_bit(key, false);
+            }
+            MouseState::Position { x, y } => {
+                self.x = self.x.saturating_add(x);
+                self.y = self.y.saturating_add(y);
+            }
+            MouseState::VertWheel(pos) => {
+                self.vert_wheel = self.vert_wheel.saturating_add(pos);
+            }
+            MouseState::HorzWheel(pos) => {
+                self.horz_wheel = self.horz_wheel.saturating_add(pos);
+            }
+            MouseState::Clear => {
+                self.buttons = 0;
+            }
+            MouseState::Unknown => {}
+        }
+    }
+}
+
+/// HID class GET_IDLE request
+const HID_REQ_GET_IDLE: u8 = 0x02;
+/// HID class SET_IDLE request
+const HID_REQ_SET_IDLE: u8 = 0x0a;
+
+/// Max number of interfaces (interface number) with a tracked idle rate
+const IDLE_INTERFACES: usize = 8;
+
+/// Captures the host SET_IDLE requests from the HID class request path
+/// usbd-hid accepts SET_IDLE without keeping the idle rate, so this class observes the request
+/// (it never accepts or rejects it), stores the rate of each interface and answers GET_IDLE with
+/// it. Must be polled before the HID classes.
+/// The rate of the keyboard interface is applied to the keyboard by push().
+#[derive(Default)]
+struct IdleRequest {
+    /// Idle rate of each interface (index is the interface number), None until set by the host
+    rates: [Option<u8>; IDLE_INTERFACES],
+    /// Interface number of the (boot) keyboard
+    kbd_interface: u8,
+    /// Keyboard idle rate requested by the host, not yet applied
+    kbd_rate: Option<u8>,
+}
+
+impl IdleRequest {
+    /// Returns the interface number if the request is the given HID class request
+    fn interface(req: &Request, request: u8) -> Option<usize> {
+        if req.request_type == RequestType::Class
+            && req.recipient == Recipient::Interface
+            && req.request == request
+        {
+            Some(usize::from(req.index as u8))
+        } else {
+            None
+        }
+    }
+
+    /// Records the idle rate of a HID SET_IDLE request
+    fn request(&mut self, req: &Request) {
+        if let Some(interface) = Self::interface(req, HID_REQ_SET_IDLE) {
+            // Upper byte is the duration (units of 4 ms), lower byte is the report id
+            let rate = (req.value >> 8) as u8;
+            match self.rates.get_mut(interface) {
+                Some(entry) => *entry = Some(rate),
+                None => warn!("SET_IDLE for untracked interface: {}", interface),
+            }
+            if interface == usize::from(self.kbd_interface) {
+                self.kbd_rate = Some(rate);
+            }
+        }
+    }
+
+    /// Idle rate of the interface for a HID GET_IDLE request
+    /// None if not a GET_IDLE request or the host has not set the rate of the interface
+    fn rate(&self, req: &Request) -> Option<u8> {
+        let interface = Self::interface(req, HID_REQ_GET_IDLE)?;
+        self.rates.get(interface).copied().flatten()
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for IdleRequest {
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        if let Some(rate) = self.rate(xfer.request()) {
+            if let Err(e) = xfer.accept_with(&[rate]) {
+                error!("GET_IDLE response failed: {:?}", e);
+            }
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        self.request(xfer.request());
+    }
+}
+
 /// USB HID Combination Interface
 ///
 /// Handles creation and management of multiple USB HID interfaces through SPSC queues.
 /// Maintains state for you so you only need to send state changes and poll events.
 ///
+/// NKRO_SIZE sets the NKRO keybitmap length in bytes (29 to 32, defaults to 29).
+/// 32 bytes is needed to send keys in the reserved 0xE8..0xFF range.
+/// CONSUMER_SIZE sets the number of simultaneous Consumer Control keys (1 to 16, defaults to 1).
+/// Each additional key adds 2 bytes to the System/Consumer Control report.
+///
 /// Typical Usage
 /// - Queue up changes using SPSC queues
 /// - Call push to send the current states of all the queues
@@ -203,15 +356,25 @@ pub struct HidInterface<
     const KBD_SIZE: usize,
     const MOUSE_SIZE: usize,
     const CTRL_SIZE: usize,
+    const NKRO_SIZE: usize = 29,
+    const CONSUMER_SIZE: usize = 1,
 > {
     kbd_6kro: HIDClass<'a, B>,
     kbd_6kro_report: KeyboardReport,
     kbd_nkro: HIDClass<'a, B>,
-    kbd_nkro_report: KeyboardNkroReport,
+    kbd_nkro_keybitmap: [u8; NKRO_SIZE],
     kbd_consumer: Consumer<'a, KeyState, KBD_SIZE>,
     ctrl: HIDClass<'a, B>,
     ctrl_consumer: Consumer<'a, CtrlState, CTRL_SIZE>,
-    ctrl_report: SysCtrlConsumerCtrlReport,
+    ctrl_consumer_keys: [u16; CONSUMER_SIZE],
+    ctrl_system_key: u8,
+    kbd_protocol_mode: HidProtocolMode,
+    kbd_protocol_config: ProtocolModeConfig,
+    kbd_auto_kro: bool,
+    kbd_auto_nkro: bool,
+    kbd_idle_rate: u8,
+    kbd_idle_elapsed: u32,
+    kbd_idle_request: IdleRequest,
     #[cfg(feature = "mouse")]
     mouse: HIDClass<'a, B>,
     #[cfg(feature = "mouse")]
@@ -222,8 +385,14 @@ pub struct HidInterface<
     hidio: HIDClass<'a, B>,
 }
 
-impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE: usize>
-    HidInterface<'_, B, KBD_SIZE, MOUSE_SIZE, CTRL_SIZE>
+impl<
+        B: UsbBus,
+        const KBD_SIZE: usize,
+        const MOUSE_SIZE: usize,
+        const CTRL_SIZE: usize,
+        const NKRO_SIZE: usize,
+        const CONSUMER_SIZE: usize,
+    > HidInterface<'_, B, KBD_SIZE, MOUSE_SIZE, CTRL_SIZE, NKRO_SIZE, CONSUMER_SIZE>
 {
     pub fn new<'a>(
         alloc: &'a UsbBusAllocator<B>,
@@ -231,7 +400,7 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         kbd_consumer: Consumer<'a, KeyState, KBD_SIZE>,
         #[cfg(feature = "mouse")] mouse_consumer: Consumer<'a, MouseState, MOUSE_SIZE>,
         ctrl_consumer: Consumer<'a, CtrlState, CTRL_SIZE>,
-    ) -> HidInterface<'a, B, KBD_SIZE, MOUSE_SIZE, CTRL_SIZE> {
+    ) -> HidInterface<'a, B, KBD_SIZE, MOUSE_SIZE, CTRL_SIZE, NKRO_SIZE, CONSUMER_SIZE> {
         let kbd_6kro = HIDClass::new_ep_in_with_settings(
             alloc,
             KeyboardReport::desc(),
@@ -245,7 +414,7 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         );
         let kbd_nkro = HIDClass::new_ep_in_with_settings(
             alloc,
-            KeyboardNkroReport::desc(),
+            &KeyboardNkroDescriptor::<NKRO_SIZE>::DESC,
             10,
             HidClassSettings {
                 subclass: HidSubClass::NoSubClass,
@@ -254,11 +423,18 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
                 locale,
             },
         );
-        let ctrl = HIDClass::new_ep_in(alloc, SysCtrlConsumerCtrlReport::desc(), 10);
+        // A single Consumer Control key uses the original descriptor
+        let ctrl_desc: &[u8] = if CONSUMER_SIZE == 1 {
+            SysCtrlConsumerCtrlReport::desc()
+        } else {
+            &SysCtrlConsumerCtrlDescriptor::<CONSUMER_SIZE>::DESC
+        };
+        let ctrl = HIDClass::new_ep_in(alloc, ctrl_desc, 10);
         #[cfg(feature = "mouse")]
         let mouse = HIDClass::new_ep_in(alloc, MouseReport::desc(), 10);
         #[cfg(feature = "hidio")]
         let hidio = HIDClass::new(alloc, HidioReport::desc(), 10);
+        let kbd_protocol_mode = kbd_6kro.get_protocol_mode().unwrap();
 
         HidInterface {
             kbd_6kro,
@@ -269,17 +445,19 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
                 keycodes: [0; 6],
             },
             kbd_nkro,
-            kbd_nkro_report: KeyboardNkroReport {
-                leds: 0,
-                keybitmap: [0; 29],
-            },
+            kbd_nkro_keybitmap: [0; NKRO_SIZE],
             kbd_consumer,
             ctrl,
             ctrl_consumer,
-            ctrl_report: SysCtrlConsumerCtrlReport {
-                consumer_ctrl: 0,
-                system_ctrl: 0,
-            },
+            ctrl_consumer_keys: [0; CONSUMER_SIZE],
+            ctrl_system_key: 0,
+            kbd_protocol_mode,
+            kbd_protocol_config: ProtocolModeConfig::DefaultBehavior,
+            kbd_auto_kro: false,
+            kbd_auto_nkro: false,
+            kbd_idle_rate: 0,
+            kbd_idle_elapsed: 0,
+            kbd_idle_request: IdleRequest::default(),
             #[cfg(feature = "mouse")]
             mouse,
             #[cfg(feature = "mouse")]
@@ -307,6 +485,59 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         );
         self.kbd_6kro.set_protocol_mode(mode, config).ok();
         self.kbd_nkro.set_protocol_mode(mode, config).ok();
+        self.kbd_protocol_config = config;
+    }
+
+    /// Enables automatic NKRO vs. 6KRO report selection
+    /// The 6KRO (boot) report is used while 6 or fewer keys (excluding modifiers) are pressed
+    /// and the NKRO report otherwise, regardless of the host protocol mode.
+    /// Ignored while the protocol mode is forced (see set_kbd_protocol_mode)
+    pub fn set_kbd_auto_kro(&mut self, enable: bool) {
+        trace!("HidInterface::set_kbd_auto_kro({})", enable);
+        self.kbd_auto_kro = enable;
+    }
+
+    /// Returns true if automatic NKRO vs. 6KRO report selection is enabled
+    pub fn get_kbd_auto_kro(&self) -> bool {
+        self.kbd_auto_kro
+    }
+
+    /// Currently pressed keys (excluding modifiers), in ascending order
+    /// Derived from the NKRO bitmap, only updated by push_keyboard()
+    /// Keys beyond the first 32 are not returned
+    pub fn pressed_keys(&self) -> heapless::Vec<u8, 32> {
+        let mut keys = heapless::Vec::new();
+        for key in 1..NKRO_SIZE * 8 {
+            // HID keyboard codes are 8 bit
+            let key = match u8::try_from(key) {
+                Ok(key) => key,
+                Err(_) => break,
+            };
+            if key & 0xE0 == 0xE0 || !self.kbd_key_bit(key) {
+                continue;
+            }
+            if keys.push(key).is_err() {
+                break;
+            }
+        }
+        keys
+    }
+
+    /// Currently pressed modifiers as a bitmask (bit 0 is 0xE0 LeftControl)
+    /// Derived from the NKRO bitmap, only updated by push_keyboard()
+    pub fn pressed_modifiers(&self) -> u8 {
+        (0..8u8)
+            .filter(|bit| self.kbd_key_bit(0xE0 | bit))
+            .fold(0, |mask, bit| mask | 1 << bit)
+    }
+
+    /// Reads a key from the NKRO bitmap (see nkro_bit)
+    fn kbd_key_bit(&self, key: u8) -> bool {
+        if key == 0 {
+            return false;
+        }
+        let key = usize::from(key - 1);
+        key / 8 < NKRO_SIZE && self.kbd_nkro_keybitmap[key / 8] & (1 << (key % 8)) != 0
     }
 
     /// Retrieves the current protocol mode
@@ -315,10 +546,75 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         self.kbd_6kro.get_protocol_mode().unwrap()
     }
 
+    /// Applies a kll-core HidProtocol CapabilityRun to the keyboard protocol mode
+    /// Only the Initial (press) event changes the mode, Toggle switches from the current mode.
+    /// The current ProtocolModeConfig is kept.
+    ///
+    /// Returns true if the protocol mode was updated
+    #[cfg(feature = "kll-core")]
+    pub fn apply_protocol(&mut self, cap_run: kll_core::CapabilityRun) -> bool {
+        match cap_run {
+            kll_core::CapabilityRun::HidProtocol {
+                state: kll_core::CapabilityEvent::Initial,
+                mode,
+            } => {
+                let mode = protocol_mode(mode, self.get_kbd_protocol_mode());
+                self.set_kbd_protocol_mode(mode, self.kbd_protocol_config);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets the keyboard idle rate
+    /// Units of 4 ms, 0 disables periodic resends (indefinite idle)
+    /// The rate requested by the host (SET_IDLE) is applied by push()
+    pub fn set_kbd_idle_rate(&mut self, rate: u8) {
+        trace!("HidInterface::set_kbd_idle_rate({})", rate);
+        self.kbd_idle_rate = rate;
+        self.kbd_idle_elapsed = 0;
+    }
+
+    /// Retrieves the current keyboard idle rate (units of 4 ms)
+    pub fn get_kbd_idle_rate(&self) -> u8 {
+        self.kbd_idle_rate
+    }
+
+    /// Sets the interface number of the (boot) keyboard, only host SET_IDLE requests for this
+    /// interface change the keyboard idle rate
+    /// Defaults to 0 (HidInterface allocated before any other USB class)
+    pub fn set_kbd_interface(&mut self, interface: u8) {
+        self.kbd_idle_request.kbd_interface = interface;
+    }
+
+    /// Advances the keyboard idle timer by the given number of milliseconds
+    /// The keyboard report is resent by push() once the idle interval elapses
+    pub fn idle_tick(&mut self, elapsed_ms: u32) {
+        self.kbd_idle_elapsed = self.kbd_idle_elapsed.saturating_add(elapsed_ms);
+    }
+
+    /// Returns the new keyboard protocol mode if it changed since the last call
+    /// Use this to re-push the current keyboard state after a host SET_PROTOCOL
+    pub fn protocol_mode_changed(&mut self) -> Option<HidProtocolMode> {
+        let mode = self.get_kbd_protocol_mode();
+        if mode == self.kbd_protocol_mode {
+            return None;
+        }
+
+        trace!(
+            "HidInterface::protocol_mode_changed({:?} -> {:?})",
+            self.kbd_protocol_mode,
+            mode
+        );
+        self.kbd_protocol_mode = mode;
+        Some(mode)
+    }
+
     /// Used to pass all of the interfaces to usb_dev.poll()
     #[cfg(all(feature = "mouse", feature = "hidio"))]
-    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 5] {
+    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 6] {
         [
+            &mut self.kbd_idle_request,
             &mut self.kbd_6kro,
             &mut self.kbd_nkro,
             &mut self.ctrl,
@@ -329,8 +625,9 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
 
     /// Used to pass all of the interfaces to usb_dev.poll()
     #[cfg(all(feature = "mouse", not(feature = "hidio")))]
-    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 4] {
+    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 5] {
         [
+            &mut self.kbd_idle_request,
             &mut self.kbd_6kro,
             &mut self.kbd_nkro,
             &mut self.ctrl,
@@ -340,8 +637,9 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
 
     /// Used to pass all of the interfaces to usb_dev.poll()
     #[cfg(all(not(feature = "mouse"), feature = "hidio"))]
-    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 4] {
+    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 5] {
         [
+            &mut self.kbd_idle_request,
             &mut self.kbd_6kro,
             &mut self.kbd_nkro,
             &mut self.ctrl,
@@ -351,32 +649,18 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
 
     /// Used to pass all of the interfaces to usb_dev.poll()
     #[cfg(all(not(feature = "mouse"), not(feature = "hidio")))]
-    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 3] {
-        [&mut self.kbd_6kro, &mut self.kbd_nkro, &mut self.ctrl]
+    pub fn interfaces(&mut self) -> [&'_ mut dyn UsbClass<B>; 4] {
+        [
+            &mut self.kbd_idle_request,
+            &mut self.kbd_6kro,
+            &mut self.kbd_nkro,
+            &mut self.ctrl,
+        ]
     }
 
     /// Modifies the nkro report bitmask
     fn nkro_bit(&mut self, key: u8, press: bool) {
-        // NOTE: The indexing actually starts from 1 (not 0), so position 0 represents 1
-        //       0 in USB HID represents no keys pressed, so it's meaningless in a bitmask
-        //       Ignore any keys over 231/0xE7
-        if key == 0 || key > 0xE7 {
-            warn!("Invalid key for nkro_bit({}, {}), ignored.", key, press);
-            return;
-        }
-
-        let key = key - 1;
-
-        // Determine position
-        let byte: usize = (key / 8).into();
-        let bit: usize = (key % 8).into();
-
-        // Set/Unset
-        if press {
-            self.kbd_nkro_report.keybitmap[byte] |= 1 << bit;
-        } else {
-            self.kbd_nkro_report.keybitmap[byte] &= !(1 << bit);
-        }
+        nkro_bit(&mut self.kbd_nkro_keybitmap, key, press);
     }
 
     fn update_kbd(&mut self) -> bool {
@@ -388,14 +672,20 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
                 Some(state) => {
                     updated = true;
                     match state {
+                        // Modifier-only fast path
+                        // Modifiers only live in the modifier byte of the 6KRO report
+                        KeyState::Press(key) if key & 0xE0 == 0xE0 => {
+                            // Left shift 1 by key XOR 0xE0
+                            self.kbd_6kro_report.modifier |= 1 << (key ^ 0xE0);
+                            self.nkro_bit(key, true);
+                        }
+                        KeyState::Release(key) if key & 0xE0 == 0xE0 => {
+                            self.kbd_6kro_report.modifier &= !(1 << (key ^ 0xE0));
+                            self.nkro_bit(key, false);
+                        }
                         KeyState::Press(key) => {
                             // Ignore 0
                             // - 6KRO -
-                            // Modifiers
-                            if key & 0xE0 == 0xE0 {
-                                self.kbd_6kro_report.modifier |= 1 << (key ^ 0xE0);
-                                // Left shift 1 by key XOR 0xE0
-                            }
                             // Keys
                             for pos in self.kbd_6kro_report.keycodes.iter_mut() {
                                 // Check to see if key is already presed
@@ -414,11 +704,6 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
                         }
                         KeyState::Release(key) => {
                             // - 6KRO -
-                            // Modifiers
-                            if key & 0xE0 == 0xE0 {
-                                self.kbd_6kro_report.modifier |= 1 << (key ^ 0xE0);
-                                // Left shift 1 by key XOR 0xE0
-                            }
                             // Keys
                             if key != 0 {
                                 // Check to see if key is pressed
@@ -444,7 +729,7 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
                             self.kbd_6kro_report.keycodes = [0; 6];
 
                             // - NKRO -
-                            self.kbd_nkro_report.keybitmap = [0; 29];
+                            self.kbd_nkro_keybitmap = [0; NKRO_SIZE];
                         }
                         KeyState::Unknown => {}
                     }
@@ -456,64 +741,92 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         }
     }
 
+    /// Fills free 6KRO slots with held keys that did not fit while the report was full
+    /// Keys already in the report keep their position
+    fn fill_6kro_keys(&mut self) {
+        if !self.kbd_6kro_report.keycodes.contains(&0) {
+            return;
+        }
+
+        for key in self.pressed_keys() {
+            let keycodes = &mut self.kbd_6kro_report.keycodes;
+            if keycodes.contains(&key) {
+                continue;
+            }
+            match keycodes.iter().position(|&k| k == 0) {
+                Some(pos) => keycodes[pos] = key,
+                None => break,
+            }
+        }
+    }
+
     fn push_6kro_kbd(&mut self) {
+        self.fill_6kro_keys();
         if let Err(val) = self.kbd_6kro.push_input(&self.kbd_6kro_report) {
             error!("6KRO Buffer Overflow: {:?}", val);
         }
     }
 
     fn push_nkro_kbd(&mut self) {
-        if let Err(val) = self.kbd_nkro.push_input(&self.kbd_nkro_report) {
+        // Only the keybitmap is part of the input report
+        if let Err(val) = self.kbd_nkro.push_raw_input(&self.kbd_nkro_keybitmap) {
             error!("NKRO Buffer Overflow: {:?}", val);
         }
     }
 
-    #[cfg(feature = "mouse")]
-    fn mouse_button_bit(&mut self, button: u8, press: bool) {
-        // Ignore keys outside of 1 to 8
-        if let 1..=8 = button {
-            let button = button - 1;
-            // Determine position
-            let bit: usize = (button % 8).into();
+    /// Number of pressed keys, modifiers are not counted
+    fn kbd_pressed_count(&self) -> u32 {
+        let mut count: u32 = self
+            .kbd_nkro_keybitmap
+            .iter()
+            .map(|byte| byte.count_ones())
+            .sum();
+        for key in 0xE0..=0xE7u8 {
+            if self.kbd_key_bit(key) {
+                count -= 1;
+            }
+        }
+        count
+    }
 
-            // Set/Unset
-            if press {
-                self.mouse_report.buttons |= 1 << bit;
-            } else {
-                self.mouse_report.buttons &= !(1 << bit);
+    /// Automatic NKRO vs. 6KRO report selection
+    /// When switching, the report of the previous format is cleared so no keys are left pressed
+    fn push_auto_kbd(&mut self) {
+        let nkro = self.kbd_pressed_count() > 6;
+        if nkro != self.kbd_auto_nkro {
+            trace!("HidInterface::push_auto_kbd(nkro: {})", nkro);
+            if nkro {
+                let empty = KeyboardReport {
+                    modifier: 0,
+                    reserved: 0,
+                    leds: 0,
+                    keycodes: [0; 6],
+                };
+                if let Err(val) = self.kbd_6kro.push_input(&empty) {
+                    error!("6KRO Buffer Overflow: {:?}", val);
+                }
+            } else if let Err(val) = self.kbd_nkro.push_raw_input(&[0; NKRO_SIZE]) {
+                error!("NKRO Buffer Overflow: {:?}", val);
             }
+            self.kbd_auto_nkro = nkro;
+        }
+
+        if nkro {
+            self.push_nkro_kbd();
+        } else {
+            self.push_6kro_kbd();
         }
     }
 
+    /// Processes the mouse spsc queue and pushes the mouse report over USB
     #[cfg(feature = "mouse")]
-    fn push_mouse(&mut self) {
+    pub fn push_mouse(&mut self) {
         let mut updated = false;
 
         // Empty mouse queue
         while let Some(state) = self.mouse_consumer.dequeue() {
             updated = true;
-            match state {
-                MouseState::Press(key) => {
-                    self.mouse_button_bit(key, true);
-                }
-                MouseState::Release(key) => {
-                    self.mouse_button_bit(key, false);
-                }
-                MouseState::Position { x, y } => {
-                    self.mouse_report.x = x;
-                    self.mouse_report.y = y;
-                }
-                MouseState::VertWheel(pos) => {
-                    self.mouse_report.vert_wheel = pos;
-                }
-                MouseState::HorzWheel(pos) => {
-                    self.mouse_report.horz_wheel = pos;
-                }
-                MouseState::Clear => {
-                    self.mouse_report.buttons = 0;
-                }
-                MouseState::Unknown => {}
-            }
+            self.mouse_report.update(state);
         }
 
         // Push report
@@ -530,7 +843,8 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         self.mouse_report.horz_wheel = 0;
     }
 
-    fn push_ctrl(&mut self) {
+    /// Processes the ctrl spsc queue and pushes the system and consumer control report over USB
+    pub fn push_ctrl(&mut self) {
         let mut updated = false;
 
         // Empty ctrl queue
@@ -538,20 +852,36 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
             updated = true;
             match state {
                 CtrlState::SystemCtrlPress(key) => {
-                    self.ctrl_report.system_ctrl = key;
+                    self.ctrl_system_key = key;
                 }
                 CtrlState::SystemCtrlRelease(_key) => {
-                    self.ctrl_report.system_ctrl = 0;
+                    self.ctrl_system_key = 0;
                 }
                 CtrlState::ConsumerCtrlPress(key) => {
-                    self.ctrl_report.consumer_ctrl = key;
+                    let keys = &mut self.ctrl_consumer_keys;
+                    if !keys.contains(&key) {
+                        // Use the first free slot, or replace the last key if the array is full
+                        let pos = keys
+                            .iter()
+                            .position(|&k| k == 0)
+                            .unwrap_or(CONSUMER_SIZE - 1);
+                        keys[pos] = key;
+                    }
                 }
-                CtrlState::ConsumerCtrlRelease(_key) => {
-                    self.ctrl_report.consumer_ctrl = 0;
+                CtrlState::ConsumerCtrlRelease(key) => {
+                    let keys = &mut self.ctrl_consumer_keys;
+                    if CONSUMER_SIZE == 1 {
+                        // 1KRO, any release clears the key
+                        keys[0] = 0;
+                    } else if let Some(index) = keys.iter().position(|&k| k == key) {
+                        // Keep the array packed
+                        keys[index..].rotate_left(1);
+                        keys[CONSUMER_SIZE - 1] = 0;
+                    }
                 }
                 CtrlState::Clear => {
-                    self.ctrl_report.consumer_ctrl = 0;
-                    self.ctrl_report.system_ctrl = 0;
+                    self.ctrl_consumer_keys = [0; CONSUMER_SIZE];
+                    self.ctrl_system_key = 0;
                 }
                 CtrlState::Unknown => {}
             }
@@ -559,7 +889,13 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
 
         // Push report
         if updated {
-            if let Err(val) = self.ctrl.push_input(&self.ctrl_report) {
+            // Consumer Control codes (little-endian) followed by the System Control code
+            let mut report = [0; CONSUMER_CTRL_MAX * 2 + 1];
+            for (bytes, key) in report.chunks_exact_mut(2).zip(self.ctrl_consumer_keys) {
+                bytes.copy_from_slice(&key.to_le_bytes());
+            }
+            report[CONSUMER_SIZE * 2] = self.ctrl_system_key;
+            if let Err(val) = self.ctrl.push_raw_input(&report[..CONSUMER_SIZE * 2 + 1]) {
                 error!("Ctrl Buffer Overflow: {:?}", val);
             }
         }
@@ -569,8 +905,47 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
     /// This is primarily for keyboard, mouse and ctrl interfaces.
     /// HID-IO is handled with poll()
     pub fn push(&mut self) {
+        // Push keyboard reports
+        self.push_keyboard();
+
+        // Push consumer and system control reports
+        self.push_ctrl();
+
+        // Push mouse reports
+        #[cfg(feature = "mouse")]
+        self.push_mouse();
+    }
+
+    /// Processes the keyboard spsc queue and pushes the keyboard report over USB
+    /// Uses the NKRO or 6KRO (boot) report depending on the protocol mode
+    /// (or the number of pressed keys, see set_kbd_auto_kro)
+    /// The report is also resent, even if unchanged, once the idle interval has elapsed
+    pub fn push_keyboard(&mut self) {
+        // Apply the idle rate requested by the host
+        if let Some(rate) = self.kbd_idle_request.kbd_rate.take() {
+            self.set_kbd_idle_rate(rate);
+        }
+
+        let updated = self.update_kbd();
+
+        // Check if the host requested idle interval has elapsed
+        let idle_expired =
+            self.kbd_idle_rate != 0 && self.kbd_idle_elapsed >= u32::from(self.kbd_idle_rate) * 4;
+
         // Update keyboard if necessary
-        if self.update_kbd() {
+        if updated || idle_expired {
+            self.kbd_idle_elapsed = 0;
+
+            // Automatic selection, unless the protocol mode is forced
+            let forced = !matches!(
+                self.kbd_protocol_config,
+                ProtocolModeConfig::DefaultBehavior
+            );
+            if self.kbd_auto_kro && !forced {
+                self.push_auto_kbd();
+                return;
+            }
+
             // Check protocol mode to decide nkro vs. 6kro (boot)
             match self.get_kbd_protocol_mode() {
                 HidProtocolMode::Report => {
@@ -581,13 +956,6 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
                 }
             }
         }
-
-        // Push consumer and system control reports
-        self.push_ctrl();
-
-        // Push mouse reports
-        #[cfg(feature = "mouse")]
-        self.push_mouse();
     }
 
     /// Poll the HID-IO interface
@@ -600,9 +968,11 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
         const H: usize,
         const S: usize,
         const ID: usize,
+        const MSUB1: usize,
+        const MSUB4: usize,
     >(
         &mut self,
-        interface: &mut CommandInterface<KINTF, TX, RX, N, H, S, ID>,
+        interface: &mut CommandInterface<KINTF, TX, RX, N, H, S, ID, MSUB1, MSUB4>,
     ) {
         // Check for any incoming packets
         while !interface.rx_bytebuf.is_full() {
@@ -658,6 +1028,23 @@ impl<B: UsbBus, const KBD_SIZE: usize, const MOUSE_SIZE: usize, const CTRL_SIZE:
     }
 }
 
+/// Converts a kll-core HID protocol into a usbd-hid protocol mode
+/// Toggle switches between Boot and Report depending on the current mode
+#[cfg(feature = "kll-core")]
+pub fn protocol_mode(
+    protocol: kll_core::hid::Protocol,
+    current: HidProtocolMode,
+) -> HidProtocolMode {
+    match protocol {
+        kll_core::hid::Protocol::Boot => HidProtocolMode::Boot,
+        kll_core::hid::Protocol::Application => HidProtocolMode::Report,
+        kll_core::hid::Protocol::Toggle => match current {
+            HidProtocolMode::Boot => HidProtocolMode::Report,
+            HidProtocolMode::Report => HidProtocolMode::Boot,
+        },
+    }
+}
+
 #[cfg(feature = "kll-core")]
 pub fn enqueue_keyboard_event<const KBD_SIZE: usize>(
     cap_run: kll_core::CapabilityRun,
@@ -723,9 +1110,82 @@ pub fn enqueue_ctrl_event<const CTRL_SIZE: usize>(
 
 #[cfg(feature = "kll-core")]
 pub fn enqueue_mouse_event<const MOUSE_SIZE: usize>(
-    _cap_run: kll_core::CapabilityRun,
-    _mouse_producer: &mut Producer<MouseState, MOUSE_SIZE>,
+    cap_run: kll_core::CapabilityRun,
+    mouse_producer: &mut Producer<MouseState, MOUSE_SIZE>,
 ) -> Result<(), MouseState> {
-    // TODO
-    Err(MouseState::Unknown)
+    match cap_run {
+        kll_core::CapabilityRun::HidMouseButton { state, button } => match state {
+            kll_core::CapabilityEvent::Initial => mouse_producer.enqueue(MouseState::Press(button)),
+            kll_core::CapabilityEvent::Last => mouse_producer.enqueue(MouseState::Release(button)),
+            _ => Ok(()),
+        },
+        kll_core::CapabilityRun::HidMouseMove { state, x, y } => match state {
+            kll_core::CapabilityEvent::Initial => {
+                mouse_producer.enqueue(MouseState::Position { x, y })
+            }
+            _ => Ok(()),
+        },
+        kll_core::CapabilityRun::HidMouseWheel { state, vert, horz } => match state {
+            kll_core::CapabilityEvent::Initial => {
+                if vert != 0 {
+                    mouse_producer.enqueue(MouseState::VertWheel(vert))?;
+                }
+                if horz != 0 {
+                    mouse_producer.enqueue(MouseState::HorzWheel(horz))?;
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        },
+        _ => {
+            error!("Unknown CapabilityRun for Mouse: {:?}", cap_run);
+            Err(MouseState::Unknown)
+        }
+    }
+}
+
+/// USB HID queue a CapabilityRun was dispatched to
+#[cfg(feature = "kll-core")]
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt-impl", derive(defmt::Format))]
+pub enum HidQueue {
+    Keyboard,
+    Ctrl,
+    Mouse,
+}
+
+/// Enqueues a CapabilityRun to the matching USB HID queue
+///
+/// Returns the queue that handled the CapabilityRun, or None if it isn't a USB HID capability
+/// Returns Err with the queue if it could not be enqueued (e.g. the queue is full)
+#[cfg(feature = "kll-core")]
+pub fn dispatch_capability<
+    const KBD_SIZE: usize,
+    const CTRL_SIZE: usize,
+    const MOUSE_SIZE: usize,
+>(
+    cap_run: kll_core::CapabilityRun,
+    kbd_producer: &mut Producer<KeyState, KBD_SIZE>,
+    ctrl_producer: &mut Producer<CtrlState, CTRL_SIZE>,
+    mouse_producer: &mut Producer<MouseState, MOUSE_SIZE>,
+) -> Result<Option<HidQueue>, HidQueue> {
+    match cap_run {
+        kll_core::CapabilityRun::HidKeyboard { .. }
+        | kll_core::CapabilityRun::HidKeyboardState { .. } => {
+            enqueue_keyboard_event(cap_run, kbd_producer).map_err(|_| HidQueue::Keyboard)?;
+            Ok(Some(HidQueue::Keyboard))
+        }
+        kll_core::CapabilityRun::HidConsumerControl { .. }
+        | kll_core::CapabilityRun::HidSystemControl { .. } => {
+            enqueue_ctrl_event(cap_run, ctrl_producer).map_err(|_| HidQueue::Ctrl)?;
+            Ok(Some(HidQueue::Ctrl))
+        }
+        kll_core::CapabilityRun::HidMouseButton { .. }
+        | kll_core::CapabilityRun::HidMouseMove { .. }
+        | kll_core::CapabilityRun::HidMouseWheel { .. } => {
+            enqueue_mouse_event(cap_run, mouse_producer).map_err(|_| HidQueue::Mouse)?;
+            Ok(Some(HidQueue::Mouse))
+        }
+        _ => Ok(None),
+    }
 }
@@ -7,9 +7,74 @@
 
 #![cfg(test)]
 
-use crate::descriptor::{HidioReport, KeyboardNkroReport, MouseReport, SysCtrlConsumerCtrlReport};
+use crate::descriptor::{
+    keyboard_nkro_descriptor, sysctrl_consumerctrl_descriptor, HidioReport, KeyboardNkroDescriptor,
+    KeyboardNkroReport, MouseReport, SysCtrlConsumerCtrlDescriptor, SysCtrlConsumerCtrlReport,
+};
+use crate::nkro_bit;
+#[cfg(feature = "mouse")]
+use crate::{
+    CtrlState, HidCountryCode, HidInterface, HidProtocolMode, KeyState, MouseState,
+    ProtocolModeConfig,
+};
+#[cfg(feature = "mouse")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(feature = "mouse")]
+use heapless::spsc::Queue;
+#[cfg(feature = "mouse")]
+use usb_device::bus::{PollResult, UsbBus, UsbBusAllocator};
+#[cfg(feature = "mouse")]
+use usb_device::control::{Recipient, Request, RequestType};
+#[cfg(feature = "mouse")]
+use usb_device::endpoint::{EndpointAddress, EndpointType};
+#[cfg(feature = "mouse")]
+use usb_device::{UsbDirection, UsbError};
 use usbd_hid::descriptor::generator_prelude::*;
 
+/// Minimal UsbBus that accepts every endpoint write
+#[cfg(feature = "mouse")]
+struct TestBus {
+    next_ep: usize,
+    writes: &'static AtomicUsize,
+    /// Length of the last endpoint write
+    last_write: &'static AtomicUsize,
+}
+
+#[cfg(feature = "mouse")]
+impl UsbBus for TestBus {
+    fn alloc_ep(
+        &mut self,
+        ep_dir: UsbDirection,
+        _ep_addr: Option<EndpointAddress>,
+        _ep_type: EndpointType,
+        _max_packet_size: u16,
+        _interval: u8,
+    ) -> usb_device::Result<EndpointAddress> {
+        self.next_ep += 1;
+        Ok(EndpointAddress::from_parts(self.next_ep, ep_dir))
+    }
+    fn enable(&mut self) {}
+    fn reset(&self) {}
+    fn set_device_address(&self, _addr: u8) {}
+    fn write(&self, _ep_addr: EndpointAddress, buf: &[u8]) -> usb_device::Result<usize> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        self.last_write.store(buf.len(), Ordering::SeqCst);
+        Ok(buf.len())
+    }
+    fn read(&self, _ep_addr: EndpointAddress, _buf: &mut [u8]) -> usb_device::Result<usize> {
+        Err(UsbError::WouldBlock)
+    }
+    fn set_stalled(&self, _ep_addr: EndpointAddress, _stalled: bool) {}
+    fn is_stalled(&self, _ep_addr: EndpointAddress) -> bool {
+        false
+    }
+    fn suspend(&self) {}
+    fn resume(&self) {}
+    fn poll(&self) -> PollResult {
+        PollResult::None
+    }
+}
+
 #[test]
 fn test_hidio_descriptor() {
     let expected = &[
@@ -61,6 +126,36 @@ fn test_keyboard_nkro_descriptor() {
     assert_eq!(KeyboardNkroReport::desc(), expected);
 }
 
+#[test]
+fn test_keyboard_nkro_descriptor_sized() {
+    // Default size must match the original descriptor
+    assert_eq!(
+        &KeyboardNkroDescriptor::<29>::DESC[..],
+        KeyboardNkroReport::desc()
+    );
+
+    // 32 bytes covers keys 0x01 to 0xFF
+    let desc = keyboard_nkro_descriptor(32);
+    assert_eq!(&desc[30..34], &[0x29, 0xFF, 0x95, 0xFF]);
+}
+
+#[test]
+fn test_nkro_bit_extended() {
+    // Default bitmap ignores keys past 0xE7
+    let mut keybitmap = [0u8; 29];
+    nkro_bit(&mut keybitmap, 0xF0, true);
+    assert_eq!(keybitmap, [0u8; 29]);
+
+    // Larger bitmap supports the high key range
+    let mut keybitmap = [0u8; 32];
+    nkro_bit(&mut keybitmap, 0xF0, true);
+    assert_eq!(keybitmap[29], 0x80);
+    nkro_bit(&mut keybitmap, 0xFF, true);
+    assert_eq!(keybitmap[31], 0x40);
+    nkro_bit(&mut keybitmap, 0xF0, false);
+    assert_eq!(keybitmap[29], 0x00);
+}
+
 #[test]
 fn test_sysctrlconsumerctrl_descriptor() {
     let expected = &[
@@ -90,6 +185,20 @@ fn test_sysctrlconsumerctrl_descriptor() {
     assert_eq!(SysCtrlConsumerCtrlReport::desc(), expected);
 }
 
+#[test]
+fn test_sysctrlconsumerctrl_descriptor_sized() {
+    // 1 key only differs by the explicit System Control Report Count
+    let desc = SysCtrlConsumerCtrlReport::desc();
+    let sized = sysctrl_consumerctrl_descriptor(1);
+    assert_eq!(&sized[..39], &desc[..39]);
+    assert_eq!(&sized[39..41], &[0x95, 0x01]);
+    assert_eq!(&sized[41..], &desc[39..]);
+
+    let desc = SysCtrlConsumerCtrlDescriptor::<4>::DESC;
+    assert_eq!(&desc[22..24], &[0x95, 0x04]);
+    assert_eq!(&desc[39..41], &[0x95, 0x01]);
+}
+
 #[test]
 fn test_mouse_descriptor() {
     let expected = &[
@@ -134,3 +243,840 @@ fn test_mouse_descriptor() {
     //libc_print::libc_println!("Mouse: {:02X?}", MouseReport::desc());
     assert_eq!(MouseReport::desc(), expected);
 }
+
+#[cfg(feature = "mouse")]
+fn empty_mouse_report() -> MouseReport {
+    MouseReport {
+        buttons: 0,
+        x: 0,
+        y: 0,
+        vert_wheel: 0,
+        horz_wheel: 0,
+    }
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_mouse_position_accumulate() {
+    let mut report = empty_mouse_report();
+    report.update(MouseState::Position { x: 10, y: -5 });
+    report.update(MouseState::Press(1));
+    report.update(MouseState::Position { x: 3, y: -2 });
+    assert_eq!(report.x, 13);
+    assert_eq!(report.y, -7);
+    assert_eq!(report.buttons, 0x01);
+
+    // Saturate instead of overflowing
+    report.update(MouseState::Position {
+        x: i16::MAX,
+        y: i16::MIN,
+    });
+    assert_eq!(report.x, i16::MAX);
+    assert_eq!(report.y, i16::MIN);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_mouse_wheel_accumulate() {
+    let mut report = empty_mouse_report();
+    report.update(MouseState::VertWheel(3));
+    report.update(MouseState::VertWheel(4));
+    report.update(MouseState::HorzWheel(-2));
+    report.update(MouseState::HorzWheel(-1));
+    assert_eq!(report.vert_wheel, 7);
+    assert_eq!(report.horz_wheel, -3);
+
+    // Fast scrolling clamps to i8 bounds
+    for _ in 0..4 {
+        report.update(MouseState::VertWheel(100));
+        report.update(MouseState::HorzWheel(-100));
+    }
+    assert_eq!(report.vert_wheel, i8::MAX);
+    assert_eq!(report.horz_wheel, i8::MIN);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_push_keyboard_only() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (mut mouse_producer, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (mut ctrl_producer, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    kbd_producer.enqueue(KeyState::Press(0x04)).unwrap();
+    mouse_producer.enqueue(MouseState::Press(1)).unwrap();
+    ctrl_producer
+        .enqueue(CtrlState::ConsumerCtrlPress(0xE9))
+        .unwrap();
+
+    usb_hid.push_keyboard();
+
+    // Only the keyboard queue is processed
+    assert_eq!(usb_hid.kbd_nkro_keybitmap[0], 0x08);
+    assert_eq!(usb_hid.kbd_6kro_report.keycodes[0], 0x04);
+    assert_eq!(usb_hid.kbd_consumer.len(), 0);
+    assert_eq!(usb_hid.mouse_consumer.len(), 1);
+    assert_eq!(usb_hid.ctrl_consumer.len(), 1);
+    assert_eq!(usb_hid.mouse_report.buttons, 0);
+    assert_eq!(usb_hid.ctrl_consumer_keys[0], 0);
+
+    // push processes the remaining queues
+    usb_hid.push();
+    assert_eq!(usb_hid.mouse_consumer.len(), 0);
+    assert_eq!(usb_hid.ctrl_consumer.len(), 0);
+    assert_eq!(usb_hid.ctrl_consumer_keys[0], 0xE9);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_protocol_mode_changed() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (_, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // No change since initialization
+    assert_eq!(usb_hid.protocol_mode_changed(), None);
+
+    // Host switches to boot protocol
+    usb_hid.set_kbd_protocol_mode(HidProtocolMode::Boot, ProtocolModeConfig::DefaultBehavior);
+    assert_eq!(usb_hid.protocol_mode_changed(), Some(HidProtocolMode::Boot));
+    assert_eq!(usb_hid.protocol_mode_changed(), None);
+
+    // And back to report protocol
+    usb_hid.set_kbd_protocol_mode(HidProtocolMode::Report, ProtocolModeConfig::DefaultBehavior);
+    assert_eq!(
+        usb_hid.protocol_mode_changed(),
+        Some(HidProtocolMode::Report)
+    );
+    assert_eq!(usb_hid.protocol_mode_changed(), None);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_kbd_idle_resend() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // Initial key press is sent
+    kbd_producer.enqueue(KeyState::Press(0x04)).unwrap();
+    usb_hid.push();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+
+    // No idle rate, unchanged report is not resent
+    usb_hid.idle_tick(1000);
+    usb_hid.push();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+
+    // 8 ms idle rate
+    usb_hid.set_kbd_idle_rate(2);
+    assert_eq!(usb_hid.get_kbd_idle_rate(), 2);
+    usb_hid.idle_tick(4);
+    usb_hid.push();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+
+    // Idle interval elapsed, report is resent
+    usb_hid.idle_tick(4);
+    usb_hid.push();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+
+    // Timer restarts after the resend
+    usb_hid.idle_tick(4);
+    usb_hid.push();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_kbd_set_idle_request() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // Other class requests are ignored (SET_PROTOCOL)
+    let mut req = Request {
+        direction: UsbDirection::Out,
+        request_type: RequestType::Class,
+        recipient: Recipient::Interface,
+        request: 0x0b,
+        value: 0x0200,
+        index: 0,
+        length: 0,
+    };
+    usb_hid.kbd_idle_request.request(&req);
+    usb_hid.push();
+    assert_eq!(usb_hid.get_kbd_idle_rate(), 0);
+
+    // Host SET_IDLE 8 ms is applied on the next push
+    req.request = 0x0a;
+    usb_hid.kbd_idle_request.request(&req);
+    assert_eq!(usb_hid.get_kbd_idle_rate(), 0);
+    kbd_producer.enqueue(KeyState::Press(0x04)).unwrap();
+    usb_hid.push();
+    assert_eq!(usb_hid.get_kbd_idle_rate(), 2);
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+
+    // Report is resent once the idle interval elapses
+    usb_hid.idle_tick(8);
+    usb_hid.push();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+
+    // Host SET_IDLE 0 (indefinite) stops the resends
+    req.value = 0;
+    usb_hid.kbd_idle_request.request(&req);
+    usb_hid.idle_tick(8);
+    usb_hid.push();
+    assert_eq!(usb_hid.get_kbd_idle_rate(), 0);
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_idle_rate_per_interface() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (_, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // SET_IDLE 8 ms on the keyboard (interface 0) and 20 ms on the NKRO keyboard (interface 1)
+    let mut set_idle = Request {
+        direction: UsbDirection::Out,
+        request_type: RequestType::Class,
+        recipient: Recipient::Interface,
+        request: 0x0a,
+        value: 0x0200,
+        index: 0,
+        length: 0,
+    };
+    usb_hid.kbd_idle_request.request(&set_idle);
+    set_idle.value = 0x0500;
+    set_idle.index = 1;
+    usb_hid.kbd_idle_request.request(&set_idle);
+
+    // GET_IDLE returns the rate of each interface
+    let mut get_idle = Request {
+        direction: UsbDirection::In,
+        request_type: RequestType::Class,
+        recipient: Recipient::Interface,
+        request: 0x02,
+        value: 0,
+        index: 0,
+        length: 1,
+    };
+    assert_eq!(usb_hid.kbd_idle_request.rate(&get_idle), Some(2));
+    get_idle.index = 1;
+    assert_eq!(usb_hid.kbd_idle_request.rate(&get_idle), Some(5));
+    get_idle.index = 2;
+    assert_eq!(usb_hid.kbd_idle_request.rate(&get_idle), None);
+
+    // Only the keyboard interface rate is applied to the keyboard
+    usb_hid.push();
+    assert_eq!(usb_hid.get_kbd_idle_rate(), 2);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_kbd_auto_kro() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 8> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 8, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+    usb_hid.set_kbd_auto_kro(true);
+    assert!(usb_hid.get_kbd_auto_kro());
+
+    // 6 keys + modifier, 6KRO report (8 bytes)
+    for key in 0x04..0x0A {
+        kbd_producer.enqueue(KeyState::Press(key)).unwrap();
+    }
+    kbd_producer.enqueue(KeyState::Press(0xE1)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+    assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 8);
+
+    // 7 keys, 6KRO report is cleared then the NKRO report (29 bytes) is sent
+    kbd_producer.enqueue(KeyState::Press(0x0A)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 3);
+    assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 29);
+
+    // Back to 6 keys, NKRO report is cleared then the 6KRO report is sent
+    kbd_producer.enqueue(KeyState::Release(0x04)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 5);
+    assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 8);
+
+    // Forced mode takes priority
+    usb_hid.set_kbd_protocol_mode(HidProtocolMode::Report, ProtocolModeConfig::ForceReport);
+    kbd_producer.enqueue(KeyState::Release(0x05)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 6);
+    assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 29);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_kbd_6kro_refill() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 8> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 8, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    for (auto_kro, mode, config) in [
+        (false, HidProtocolMode::Boot, ProtocolModeConfig::ForceBoot),
+        (
+            true,
+            HidProtocolMode::Report,
+            ProtocolModeConfig::DefaultBehavior,
+        ),
+    ] {
+        usb_hid.set_kbd_auto_kro(auto_kro);
+        usb_hid.set_kbd_protocol_mode(mode, config);
+
+        // 7 keys, the 7th does not fit in the 6KRO report
+        for key in 0x04..0x0B {
+            kbd_producer.enqueue(KeyState::Press(key)).unwrap();
+        }
+        usb_hid.push_keyboard();
+
+        // Releasing a key makes room for the 7th key
+        kbd_producer.enqueue(KeyState::Release(0x04)).unwrap();
+        usb_hid.push_keyboard();
+        assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 8);
+        assert_eq!(
+            usb_hid.kbd_6kro_report.keycodes,
+            [0x05, 0x06, 0x07, 0x08, 0x09, 0x0A]
+        );
+
+        kbd_producer.enqueue(KeyState::Clear).unwrap();
+        usb_hid.push_keyboard();
+    }
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_pressed_keys() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 8> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 8, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+    assert!(usb_hid.pressed_keys().is_empty());
+    assert_eq!(usb_hid.pressed_modifiers(), 0);
+
+    // Three keys + LeftShift
+    for key in [0x1D, 0x04, 0x2C, 0xE1] {
+        kbd_producer.enqueue(KeyState::Press(key)).unwrap();
+    }
+    usb_hid.push_keyboard();
+    assert_eq!(usb_hid.pressed_keys(), [0x04, 0x1D, 0x2C]);
+    assert_eq!(usb_hid.pressed_modifiers(), 0x02);
+
+    kbd_producer.enqueue(KeyState::Release(0x04)).unwrap();
+    kbd_producer.enqueue(KeyState::Release(0xE1)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(usb_hid.pressed_keys(), [0x1D, 0x2C]);
+    assert_eq!(usb_hid.pressed_modifiers(), 0);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_pressed_keys_full_bitmap() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    // 32 bytes covers every 8 bit key code
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4, 32> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    for key in [0x04, 0xE7] {
+        kbd_producer.enqueue(KeyState::Press(key)).unwrap();
+    }
+    usb_hid.push_keyboard();
+    assert_eq!(usb_hid.pressed_keys(), [0x04]);
+    assert_eq!(usb_hid.pressed_modifiers(), 0x80);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_modifier_only() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 8> = Queue::new();
+    let (mut kbd_producer, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 8, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // Modifiers are not added to the keycode array
+    kbd_producer.enqueue(KeyState::Press(0xE1)).unwrap();
+    kbd_producer.enqueue(KeyState::Press(0xE4)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+    assert_eq!(usb_hid.kbd_6kro_report.modifier, 0x12);
+    assert_eq!(usb_hid.kbd_6kro_report.keycodes, [0; 6]);
+
+    // Releasing a modifier clears only its bit
+    kbd_producer.enqueue(KeyState::Press(0x04)).unwrap();
+    kbd_producer.enqueue(KeyState::Release(0xE1)).unwrap();
+    usb_hid.push_keyboard();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+    assert_eq!(usb_hid.kbd_6kro_report.modifier, 0x10);
+    assert_eq!(usb_hid.kbd_6kro_report.keycodes, [0x04, 0, 0, 0, 0, 0]);
+    assert_eq!(usb_hid.pressed_modifiers(), 0x10);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_consumer_ctrl_single() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (_, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 8> = Queue::new();
+    let (mut ctrl_producer, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 8> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // A second key replaces the first
+    ctrl_producer
+        .enqueue(CtrlState::ConsumerCtrlPress(0xE9))
+        .unwrap();
+    ctrl_producer
+        .enqueue(CtrlState::ConsumerCtrlPress(0xEA))
+        .unwrap();
+    usb_hid.push_ctrl();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+    assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 3);
+    assert_eq!(usb_hid.ctrl_consumer_keys, [0xEA]);
+
+    // Any release clears the key
+    ctrl_producer
+        .enqueue(CtrlState::ConsumerCtrlRelease(0xE9))
+        .unwrap();
+    usb_hid.push_ctrl();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+    assert_eq!(usb_hid.ctrl_consumer_keys, [0]);
+}
+
+#[test]
+#[cfg(feature = "mouse")]
+fn test_consumer_ctrl_array() {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (_, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 8> = Queue::new();
+    let (mut ctrl_producer, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 8, 29, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+
+    // Four simultaneous media keys (4 * 2 bytes + System Control byte)
+    for key in [0xE9, 0xEA, 0xE2, 0xCD] {
+        ctrl_producer
+            .enqueue(CtrlState::ConsumerCtrlPress(key))
+            .unwrap();
+    }
+    usb_hid.push_ctrl();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 1);
+    assert_eq!(LAST_WRITE.load(Ordering::SeqCst), 9);
+    assert_eq!(usb_hid.ctrl_consumer_keys, [0xE9, 0xEA, 0xE2, 0xCD]);
+
+    // Releases keep the array packed
+    ctrl_producer
+        .enqueue(CtrlState::ConsumerCtrlRelease(0xEA))
+        .unwrap();
+    usb_hid.push_ctrl();
+    assert_eq!(WRITES.load(Ordering::SeqCst), 2);
+    assert_eq!(usb_hid.ctrl_consumer_keys, [0xE9, 0xE2, 0xCD, 0]);
+}
+
+#[test]
+#[cfg(all(feature = "mouse", feature = "kll-core"))]
+fn test_apply_protocol() {
+    use kll_core::{hid::Protocol, CapabilityEvent, CapabilityRun};
+
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    static LAST_WRITE: AtomicUsize = AtomicUsize::new(0);
+    let alloc = UsbBusAllocator::new(TestBus {
+        next_ep: 0,
+        writes: &WRITES,
+        last_write: &LAST_WRITE,
+    });
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let (_, kbd_consumer) = kbd_queue.split();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (_, mouse_consumer) = mouse_queue.split();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let (_, ctrl_consumer) = ctrl_queue.split();
+
+    let mut usb_hid: HidInterface<TestBus, 4, 4, 4> = HidInterface::new(
+        &alloc,
+        HidCountryCode::NotSupported,
+        kbd_consumer,
+        mouse_consumer,
+        ctrl_consumer,
+    );
+    usb_hid.set_kbd_protocol_mode(HidProtocolMode::Boot, ProtocolModeConfig::DefaultBehavior);
+
+    let toggle = |state| CapabilityRun::HidProtocol {
+        state,
+        mode: Protocol::Toggle,
+    };
+
+    // Boot -> Application (Report)
+    assert!(usb_hid.apply_protocol(toggle(CapabilityEvent::Initial)));
+    assert_eq!(usb_hid.get_kbd_protocol_mode(), HidProtocolMode::Report);
+
+    // Release is ignored
+    assert!(!usb_hid.apply_protocol(toggle(CapabilityEvent::Last)));
+    assert_eq!(usb_hid.get_kbd_protocol_mode(), HidProtocolMode::Report);
+
+    // Explicit mode
+    assert!(usb_hid.apply_protocol(CapabilityRun::HidProtocol {
+        state: CapabilityEvent::Initial,
+        mode: Protocol::Boot,
+    }));
+    assert_eq!(usb_hid.get_kbd_protocol_mode(), HidProtocolMode::Boot);
+}
+
+#[cfg(feature = "kll-core")]
+#[test]
+fn test_dispatch_capability() {
+    use crate::{dispatch_capability, CtrlState, HidQueue, KeyState, MouseState};
+    use heapless::spsc::Queue;
+    use kll_core::{kll_hid, CapabilityEvent, CapabilityRun};
+
+    let mut kbd_queue: Queue<KeyState, 4> = Queue::new();
+    let mut ctrl_queue: Queue<CtrlState, 4> = Queue::new();
+    let mut mouse_queue: Queue<MouseState, 4> = Queue::new();
+    let (mut kbd_producer, mut kbd_consumer) = kbd_queue.split();
+    let (mut ctrl_producer, mut ctrl_consumer) = ctrl_queue.split();
+    let (mut mouse_producer, mut mouse_consumer) = mouse_queue.split();
+
+    // Keyboard
+    let cap_run = CapabilityRun::HidKeyboard {
+        state: CapabilityEvent::Initial,
+        id: kll_hid::Keyboard::A,
+    };
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Ok(Some(HidQueue::Keyboard))
+    );
+    assert_eq!(
+        kbd_consumer.dequeue(),
+        Some(KeyState::Press(kll_hid::Keyboard::A as u8))
+    );
+
+    // Consumer Control
+    let cap_run = CapabilityRun::HidConsumerControl {
+        state: CapabilityEvent::Last,
+        id: kll_hid::ConsumerControl::Mute,
+    };
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Ok(Some(HidQueue::Ctrl))
+    );
+    assert_eq!(
+        ctrl_consumer.dequeue(),
+        Some(CtrlState::ConsumerCtrlRelease(
+            kll_hid::ConsumerControl::Mute as u16
+        ))
+    );
+
+    // Mouse
+    let cap_run = CapabilityRun::HidMouseButton {
+        state: CapabilityEvent::Initial,
+        button: 2,
+    };
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Ok(Some(HidQueue::Mouse))
+    );
+    assert_eq!(mouse_consumer.dequeue(), Some(MouseState::Press(2)));
+
+    let cap_run = CapabilityRun::HidMouseMove {
+        state: CapabilityEvent::Initial,
+        x: -5,
+        y: 10,
+    };
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Ok(Some(HidQueue::Mouse))
+    );
+    assert_eq!(
+        mouse_consumer.dequeue(),
+        Some(MouseState::Position { x: -5, y: 10 })
+    );
+
+    let cap_run = CapabilityRun::HidMouseWheel {
+        state: CapabilityEvent::Initial,
+        vert: -1,
+        horz: 0,
+    };
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Ok(Some(HidQueue::Mouse))
+    );
+    assert_eq!(mouse_consumer.dequeue(), Some(MouseState::VertWheel(-1)));
+    assert_eq!(mouse_consumer.dequeue(), None);
+
+    // Non-HID capabilities are not dispatched
+    let cap_run = CapabilityRun::NoOp {
+        state: CapabilityEvent::Initial,
+    };
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Ok(None)
+    );
+    assert_eq!(kbd_consumer.dequeue(), None);
+    assert_eq!(ctrl_consumer.dequeue(), None);
+    assert_eq!(mouse_consumer.dequeue(), None);
+
+    // Full queue
+    let cap_run = CapabilityRun::HidKeyboard {
+        state: CapabilityEvent::Initial,
+        id: kll_hid::Keyboard::B,
+    };
+    for _ in 0..3 {
+        assert!(dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        )
+        .is_ok());
+    }
+    assert_eq!(
+        dispatch_capability(
+            cap_run,
+            &mut kbd_producer,
+            &mut ctrl_producer,
+            &mut mouse_producer
+        ),
+        Err(HidQueue::Keyboard)
+    );
+}
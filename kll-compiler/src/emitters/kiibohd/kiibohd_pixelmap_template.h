@@ -0,0 +1,87 @@
+/* Copyright (C) 2015-2019 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+{information}
+
+
+#pragma once
+
+// ----- Includes -----
+
+// KLL Include
+#include <kll.h>
+
+
+
+// ----- Pixel Buffer Setup -----
+
+// Total number of LED channels (highest channel index + 1)
+#define Pixel_TotalChannels_KLL {total_channels}
+
+// Total number of pixels (highest pixel index + 1)
+#define Pixel_TotalPixels_KLL {total_pixels}
+
+
+
+// ----- Pixel Mapping -----
+
+// -- Pixel Channel Mapping
+//
+// Index n: Pixel n
+// {{ <channel width>, <number of channels>, {{ <channel indices> }} }}
+// Undefined pixels have no channels
+const PixelElement Pixel_Mapping[] = {{
+{pixel_mapping}
+}};
+
+
+// -- Pixel Positions
+//
+// Index n: Pixel n
+// Each pixel has 6 dimensions
+// x,y,z and rx,ry,rz (rotation)
+// Units are in mm
+const Position Pixel_Positions[] = {{
+{pixel_positions}
+}};
+
+
+// -- ScanCode to Pixel Mapping
+//
+// Index n: ScanCode n
+// Value: Pixel index + 1 (0 if there is no pixel for the scan code)
+const uint16_t Pixel_ScanCodeToPixel[] = {{
+{scancode_pixel_mapping}
+}};
+
+
+
+// ----- Animations -----
+
+// -- Animation Frames
+//
+// Each pixel modification is: <address>, <number of channels>, (<change>, <value>) per channel
+// Frames are terminated with PixelAddressType_End
+// Each animation has a NULL terminated list of frames
+{animation_frames}
+
+
+// -- Animation List
+//
+// Index n: Animation n (sorted by name)
+const uint16_t **Pixel_Animations[] = {{
+{animation_list}
+}};
@@ -0,0 +1,40 @@
+/* Copyright (C) 2015-2019 by Jacob Alexander
+ *
+ * This file is free software: you can redistribute it and/or modify
+ * it under the terms of the GNU Lesser General Public License as published by
+ * the Free Software Foundation, either version 3 of the License, or
+ * (at your option) any later version.
+ *
+ * This file is distributed in the hope that it will be useful,
+ * but WITHOUT ANY WARRANTY; without even the implied warranty of
+ * MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ * GNU Lesser General Public License for more details.
+ *
+ * You should have received a copy of the GNU Lesser General Public License
+ * along with this file.  If not, see <http://www.gnu.org/licenses/>.
+ */
+
+{information}
+
+
+#pragma once
+
+// ----- Includes -----
+
+#include <stdint.h>
+
+
+
+// ----- USB HID Keyboard Codes -----
+
+// Only the codes used by the layout are defined
+{keyboard_codes}
+
+
+// Number of keyboard codes used by the layout
+#define USBKeyboardCodesUsed {keyboard_code_count}
+
+// Sorted list of keyboard codes used by the layout
+const uint8_t USBKeyboardCodesUsedList[] = {{
+{keyboard_code_list}
+}};
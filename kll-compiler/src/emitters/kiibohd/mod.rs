@@ -1,7 +1,11 @@
+use layouts_rs::Layouts;
+use object_merge::Merge;
+use std::collections::BTreeSet;
 use std::fs;
 use std::path::Path;
 
-use crate::KllGroups;
+use crate::types::{Key, Pixel, PixelAddr, PixelColor, PixelDef, ResultType};
+use crate::{KllDatastore, KllGroups, KllState, Value};
 
 const SAFE_CAPABILITIES: &[&str] = &[
     // PartialMap
@@ -112,6 +116,109 @@ impl PositionEntry {
     }
 }
 
+/// Channel width and channel indices of a pixel
+pub struct PixelElement(usize, Vec<usize>);
+
+impl PixelElement {
+    /// Channels are ordered by channel index
+    fn new(def: &PixelDef) -> Self {
+        let mut channels = def.channels.clone();
+        channels.sort_unstable();
+        let width = channels.iter().map(|(_, width)| *width).max().unwrap_or(0);
+        PixelElement(width, channels.iter().map(|(index, _)| *index).collect())
+    }
+    fn define_str(&self, i: usize) -> String {
+        let channels = if self.1.is_empty() {
+            "0".to_string()
+        } else {
+            self.1
+                .iter()
+                .map(|x| x.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        format!(
+            "\t/* {:3} */ {{ {}, {}, {{ {} }} }},",
+            i,
+            self.0,
+            self.1.len(),
+            channels
+        )
+    }
+}
+
+/// Pixel address of an animation frame pixel modification
+fn pixel_addr_str(pixel: &Pixel) -> String {
+    let range = &pixel.range;
+    match &range.key {
+        Some(Key::Scancode(scancode)) => {
+            return format!("PixelAddressType_ScanCode, {}", scancode);
+        }
+        Some(Key::Usb(name)) => {
+            return format!("PixelAddressType_USBCode, KEY_{}", name);
+        }
+        _ => {}
+    }
+
+    let addrs = [
+        ("Index", &range.index),
+        ("Row", &range.row),
+        ("Column", &range.col),
+    ]
+    .iter()
+    .filter_map(|(kind, addr)| {
+        addr.as_ref().map(|addr| match addr {
+            PixelAddr::Absolute(v) => format!("PixelAddressType_{}, {}", kind, v),
+            PixelAddr::RelativeInt(v) => format!("PixelAddressType_{}Relative, {}", kind, v),
+            PixelAddr::RelativePercent(v) => format!("PixelAddressType_{}Percent, {}", kind, v),
+        })
+    })
+    .collect::<Vec<_>>();
+    if addrs.is_empty() {
+        "PixelAddressType_Index, 0".to_string()
+    } else {
+        addrs.join(", ")
+    }
+}
+
+/// Channel change of an animation frame pixel modification
+fn pixel_change_str(color: &PixelColor) -> String {
+    match color {
+        PixelColor::Rgb(v) => format!("PixelChange_Set, {}", v),
+        PixelColor::Relative(v) if *v < 0 => format!("PixelChange_Subtract, {}", -v),
+        PixelColor::Relative(v) => format!("PixelChange_Add, {}", v),
+        PixelColor::RelativeNoRoll(v) if *v < 0 => format!("PixelChange_NoRoll_Subtract, {}", -v),
+        PixelColor::RelativeNoRoll(v) => format!("PixelChange_NoRoll_Add, {}", v),
+        PixelColor::Shift(v) if *v < 0 => format!("PixelChange_RightShift, {}", -v),
+        PixelColor::Shift(v) => format!("PixelChange_LeftShift, {}", v),
+    }
+}
+
+/// Animation frame (list of pixel modifications)
+pub struct AnimationFrame<'a>(&'a str, Vec<Pixel<'a>>);
+
+impl<'a> AnimationFrame<'a> {
+    fn define_str(&self, i: usize) -> String {
+        let mut lines = vec![format!("const uint16_t {}_frame{}[] = {{", self.0, i)];
+        for pixel in &self.1 {
+            lines.push(format!(
+                "\t{}, {}, {},",
+                pixel_addr_str(pixel),
+                pixel.channel_values.len(),
+                pixel
+                    .channel_values
+                    .iter()
+                    .map(pixel_change_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        lines.push("\tPixelAddressType_End".to_string());
+        lines.push("};".to_string());
+        lines.join("\n")
+    }
+}
+
 pub struct KiibohdData<'a> {
     //capabilities: &'a [Capability<'a>],
     results: &'a [ResultMacro<'a>],
@@ -126,6 +233,8 @@ pub struct KiibohdData<'a> {
 
 include!("test_data.rs");
 
+mod test;
+
 pub fn write(file: &Path, groups: &KllGroups) {
     let config = groups.config();
     let mut capabilities = config
@@ -224,3 +333,238 @@ pub fn write(file: &Path, groups: &KllGroups) {
     );
     fs::write(file, content).unwrap();
 }
+
+/// Generates the pixelmap header (generatedPixelmap.h)
+/// Pixel positions share the position map with scancode positions (S1 <= ... and P1 <= ...)
+pub fn pixelmap(state: &KllState) -> String {
+    let total_pixels = state.pixelmap.keys().max().map_or(0, |max| max + 1);
+    let pixels = (0..total_pixels)
+        .map(|i| match state.pixelmap.get(&i) {
+            Some(def) => PixelElement::new(def),
+            None => PixelElement(0, vec![]),
+        })
+        .collect::<Vec<_>>();
+    let total_channels = pixels
+        .iter()
+        .flat_map(|pixel| pixel.1.iter())
+        .max()
+        .map_or(0, |max| max + 1);
+
+    // Scancode -> pixel index + 1
+    let max_scan_code = state.pixelmap.values().filter_map(|def| def.scancode).max();
+    let mut scancode_pixels = vec![0; max_scan_code.map_or(0, |max| max + 1)];
+    for (i, def) in &state.pixelmap {
+        if let Some(scancode) = def.scancode {
+            scancode_pixels[scancode] = i + 1;
+        }
+    }
+
+    let mut names = state.animations.keys().collect::<Vec<_>>();
+    names.sort();
+    let animations = names
+        .iter()
+        .map(|name| {
+            state.animations[*name]
+                .interpolated_frames()
+                .into_iter()
+                .map(|frame| AnimationFrame(name, frame))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    format!(
+        include_str!("kiibohd_pixelmap_template.h"),
+        information = "// This file was generated by the kll compiler, DO NOT EDIT",
+        total_channels = total_channels,
+        total_pixels = total_pixels,
+        pixel_mapping = pixels
+            .iter()
+            .enumerate()
+            .map(|(i, pixel)| pixel.define_str(i))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        pixel_positions = (0..total_pixels)
+            .map(|i| {
+                let pos = state.positions.get(&i).cloned().unwrap_or_default();
+                PositionEntry(pos.x, pos.y, pos.z, pos.rx, pos.ry, pos.rz).define_str()
+            })
+            .collect::<Vec<_>>()
+            .join(",\n"),
+        scancode_pixel_mapping = scancode_pixels
+            .iter()
+            .enumerate()
+            .map(|(scancode, pixel)| format!("\t/* {:#04X} */ {},", scancode, pixel))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        animation_frames = names
+            .iter()
+            .zip(&animations)
+            .map(|(name, frames)| {
+                let mut defines = frames
+                    .iter()
+                    .enumerate()
+                    .map(|(i, frame)| frame.define_str(i))
+                    .collect::<Vec<_>>();
+                defines.push(format!(
+                    "const uint16_t *{}_frames[] = {{ {}0 }};",
+                    name,
+                    (0..frames.len())
+                        .map(|i| format!("{}_frame{}, ", name, i))
+                        .collect::<String>()
+                ));
+                defines.join("\n\n")
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n"),
+        animation_list = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| format!("\t/* {:2} */ {}_frames,", i, name))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+pub fn write_pixelmap(file: &Path, groups: &KllGroups) {
+    let mut state = groups.config();
+    state.merge(&groups.defaultmap());
+    fs::write(file, pixelmap(&state)).unwrap();
+}
+
+/// USB HID keyboard codes used by the keymap (U"A" results), sorted by code
+/// Names that don't match a keyboard code in the layout are ignored
+pub fn usb_keyboard_codes(state: &KllState, mut layouts: Layouts) -> Vec<kll_hid::Keyboard> {
+    let layout = layouts.get_layout("base/base.json");
+    state
+        .actions()
+        .filter_map(|action| match &action.result {
+            ResultType::Output(key @ Key::Usb(_)) => key.value(&layout),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(|code| kll_hid::Keyboard::from(code as u16))
+        .collect()
+}
+
+/// Generates the USB HID id header (usb_id.h)
+pub fn usb_ids(state: &KllState, layouts: Layouts) -> String {
+    let codes = usb_keyboard_codes(state, layouts);
+    format!(
+        include_str!("kiibohd_usb_id_template.h"),
+        information = "// This file was generated by the kll compiler, DO NOT EDIT",
+        keyboard_codes = codes
+            .iter()
+            .map(|code| format!("#define KEY_{} {:#04X}", code.name(), *code as u8))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        keyboard_code_count = codes.len(),
+        keyboard_code_list = codes
+            .iter()
+            .map(|code| format!("\tKEY_{},", code.name()))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+pub fn write_usb_ids(file: &Path, groups: &KllGroups, layouts: Layouts) {
+    let mut state = groups.config();
+    state.merge(&groups.defaultmap());
+    for partial in groups.partialmaps() {
+        state.merge(&partial);
+    }
+    fs::write(file, usb_ids(&state, layouts)).unwrap();
+}
+
+/// Formats a KLL variable value as a C define value
+/// "text" becomes an escaped C string, 'text' is used verbatim (without the quotes)
+fn define_value_str(value: &str) -> String {
+    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        let escaped = value[1..value.len() - 1]
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n");
+        format!("\"{}\"", escaped)
+    } else if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Generates the KLL define header (kll_defs.h)
+/// Only variables with a define (myVariable => myCDefine;) are emitted, sorted by define name
+/// Defines without a variable value are emitted without a value
+pub fn defines(state: &KllState) -> String {
+    let mut defines = state.defines.iter().collect::<Vec<_>>();
+    defines.sort_by_key(|(_, cname)| **cname);
+
+    format!(
+        include_str!("kiibohd_defs_template.h"),
+        information = "// This file was generated by the kll compiler, DO NOT EDIT",
+        defines = defines
+            .iter()
+            .map(|(name, cname)| match state.variables.get(*name) {
+                Some(Value::Single(value)) => {
+                    format!("#define {} {}", cname, define_value_str(value))
+                }
+                Some(Value::List(values)) => format!(
+                    "#define {} {{ {} }}",
+                    cname,
+                    values
+                        .iter()
+                        .map(|value| define_value_str(value))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                None => format!("#define {}", cname),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// merge() keeps existing values, so earlier config files take precedence over later ones and
+/// the config takes precedence over the default map (same as KllGroups::config())
+pub fn write_defines(file: &Path, groups: &KllGroups) {
+    let mut state = groups.config();
+    state.merge(&groups.defaultmap());
+    fs::write(file, defines(&state)).unwrap();
+}
+
+/// Generates the settings dictionary (kll.json)
+/// scancode_range is inclusive (start and end are the lowest and highest scancodes)
+pub fn settings_json(state: &KllState, kll_data: &KllDatastore) -> String {
+    let variables = state
+        .variables
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::Single(value) => serde_json::json!(value),
+                Value::List(values) => serde_json::json!(values),
+            };
+            (name.to_string(), value)
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let mut unicode_strings = kll_data.unicode_strings.iter().collect::<Vec<_>>();
+    unicode_strings.sort();
+
+    let settings = serde_json::json!({
+        "defines": state.defines,
+        "variables": variables,
+        "scancode_range": {
+            "start": kll_data.scancode_range.start,
+            "end": kll_data.scancode_range.end,
+        },
+        "unicode_strings": unicode_strings,
+        "mapping_count": kll_data.mapping_count,
+    });
+    serde_json::to_string_pretty(&settings).unwrap()
+}
+
+pub fn write_json(file: &Path, groups: &KllGroups) {
+    let mut state = groups.config();
+    state.merge(&groups.defaultmap());
+    let kll_data = KllDatastore::new(&state);
+    fs::write(file, settings_json(&state, &kll_data)).unwrap();
+}
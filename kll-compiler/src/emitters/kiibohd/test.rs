@@ -0,0 +1,107 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#![cfg(test)]
+
+use crate::emitters::kiibohd::{defines, pixelmap, settings_json, usb_ids, usb_keyboard_codes};
+use crate::types::KllFile;
+use crate::KllDatastore;
+use layouts_rs::Layouts;
+use object_merge::Merge;
+use std::path::PathBuf;
+
+#[test]
+fn pixelmap_channels() {
+    let test = "P[0](0:8, 1:8, 2:8) : S1;\nP[2](6:8, 4:8, 5:8) : None;\nP[2] <= x:20;\nA[Glow] <= loops:1;\nA[Glow, 0] <= P[2](+32);\nA[Glow, 1] <= S1(255, 0, 0);\n";
+    let result = KllFile::from_str(test);
+    let state = result.unwrap().into_struct();
+    let header = pixelmap(&state);
+
+    assert!(header.contains("#define Pixel_TotalChannels_KLL 7"));
+    assert!(header.contains("#define Pixel_TotalPixels_KLL 3"));
+
+    // Channels are ordered by channel index, undefined pixels have no channels
+    assert!(header.contains("\t/*   0 */ { 8, 3, { 0, 1, 2 } },"));
+    assert!(header.contains("\t/*   1 */ { 0, 0, { 0 } },"));
+    assert!(header.contains("\t/*   2 */ { 8, 3, { 4, 5, 6 } },"));
+
+    // Pixel 2 position
+    assert!(header.contains("PositionEntry(   20.00,    0.00"));
+
+    // Scancode 1 -> pixel 0
+    assert!(header.contains("\t/* 0x00 */ 0,\n\t/* 0x01 */ 1,"));
+
+    // Animation frames
+    assert!(header.contains("const uint16_t Glow_frame0[] = {\n\tPixelAddressType_Index, 2, 1, PixelChange_Add, 32,\n\tPixelAddressType_End\n};"));
+    assert!(header.contains("\tPixelAddressType_ScanCode, 1, 3, PixelChange_Set, 255, PixelChange_Set, 0, PixelChange_Set, 0,"));
+    assert!(header.contains("const uint16_t *Glow_frames[] = { Glow_frame0, Glow_frame1, 0 };"));
+    assert!(header.contains("\t/*  0 */ Glow_frames,"));
+}
+
+#[test]
+fn usb_id_codes() {
+    let test = "S1 : U\"B\";\nS2 : U\"A\";\nS3 : U\"A\";\nS4 : U\"Esc\";\nS5 : CONS\"Mute\";\n";
+    let result = KllFile::from_str(test);
+    let state = result.unwrap().into_struct();
+
+    // Sorted and deduplicated, consumer codes are not keyboard codes
+    let codes = usb_keyboard_codes(&state, Layouts::from_dir(PathBuf::from("layouts")));
+    assert_eq!(
+        codes,
+        vec![
+            kll_hid::Keyboard::A,
+            kll_hid::Keyboard::B,
+            kll_hid::Keyboard::Esc
+        ]
+    );
+
+    let header = usb_ids(&state, Layouts::from_dir(PathBuf::from("layouts")));
+    assert!(header.contains("#define KEY_A 0x04\n#define KEY_B 0x05\n#define KEY_Esc 0x29\n"));
+    assert!(header.contains("#define USBKeyboardCodesUsed 3\n"));
+    assert!(header.contains("{\n\tKEY_A,\n\tKEY_B,\n\tKEY_Esc,\n};"));
+    assert!(!header.contains("KEY_C "));
+}
+
+#[test]
+fn kll_defines() {
+    let mut state = KllFile::from_str(
+        "myNumber = 414;\nmyNumber => MY_NUMBER;\nmyString = \"stuff \\ here\";\nmyString => MY_STRING;\nmyRaw = '\"raw\"';\nmyRaw => MY_RAW;\nmyArray[0] = 1;\nmyArray[1] = 2;\nmyArray => MY_ARRAY;\nmyFlag => MY_FLAG;\nhidden = 1;\n",
+    )
+    .unwrap()
+    .into_struct();
+
+    // Merged files don't override existing values
+    let other = KllFile::from_str("myNumber = 415;\nmyOther = 1;\nmyOther => MY_OTHER;\n")
+        .unwrap()
+        .into_struct();
+    state.merge(&other);
+
+    let header = defines(&state);
+    assert!(header.contains(
+        "#define MY_ARRAY { 1, 2 }\n#define MY_FLAG\n#define MY_NUMBER 414\n#define MY_OTHER 1\n#define MY_RAW \"raw\"\n#define MY_STRING \"stuff \\\\ here\"\n"
+    ));
+    assert!(!header.contains("hidden"));
+}
+
+#[test]
+fn json_settings() {
+    let test = "Name = test;\nmyNumber = 414;\nmyNumber => MY_NUMBER;\nS0x10 : u\"Hello\";\nS0x22 : U\"A\";\nS0x05 : u\"World\";\n";
+    let state = KllFile::from_str(test).unwrap().into_struct();
+    let kll_data = KllDatastore::new(&state);
+
+    let json = settings_json(&state, &kll_data);
+    let settings: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(settings["scancode_range"]["start"], 0x05);
+    assert_eq!(settings["scancode_range"]["end"], 0x22);
+    assert_eq!(
+        settings["unicode_strings"],
+        serde_json::json!(["Hello", "World"])
+    );
+    assert_eq!(settings["defines"]["myNumber"], "MY_NUMBER");
+    assert_eq!(settings["variables"]["Name"], "test");
+    assert_eq!(settings["mapping_count"], 3);
+}
@@ -5,11 +5,11 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use crate::types::{Key, TriggerType};
+use crate::types::{Key, TriggerType, UnicodeStringLookup};
 use crate::{KllGroups, KllState};
 use layouts_rs::Layouts;
 use log::*;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -32,11 +32,14 @@ pub struct KllCoreData<'a> {
     pub result_guides: Vec<u8>,
     pub trigger_result_map: Vec<u16>,
     pub raw_layer_lookup: Vec<u8>,
+    pub unicode_string_lookup: UnicodeStringLookup,
+    pub unicode_strings: Vec<u8>,
 }
 
 impl<'a> KllCoreData<'a> {
     /// Given KllState layers, generate datastructures for kll-core
-    pub fn new(layers: &mut [KllState<'a>], layouts: Layouts) -> Self {
+    /// Returns an error if the generated datastructures are inconsistent (see validate())
+    pub fn new(layers: &mut [KllState<'a>], layouts: Layouts) -> Result<Self, Error> {
         // Trigger and Result deduplication hashmaps
         let mut trigger_hash = HashMap::new();
         let mut result_hash = HashMap::new();
@@ -53,6 +56,23 @@ impl<'a> KllCoreData<'a> {
         let mut trigger_result_map: Vec<u16> = Vec::new();
         let mut raw_layer_lookup: Vec<u8> = Vec::new();
 
+        // Build deduplicated unicode string table
+        // Each string is assigned an index (in sorted order) and is NULL terminated in the table
+        let mut unicode_string_lookup = UnicodeStringLookup::new();
+        let mut unicode_strings: Vec<u8> = Vec::new();
+        for (index, text) in layers
+            .iter()
+            .flat_map(|layer| layer.unicode_strings())
+            .collect::<BTreeSet<String>>()
+            .into_iter()
+            .enumerate()
+        {
+            unicode_strings.extend_from_slice(text.as_bytes());
+            unicode_strings.push(0);
+            unicode_string_lookup.insert(text, index.try_into().unwrap());
+        }
+        trace!("unicode_string_lookup: {:?}", unicode_string_lookup);
+
         for (layer_index, layer) in layers.iter_mut().enumerate() {
             // Generate explicit state in layer
             layer.generate_state_scheduling();
@@ -69,7 +89,8 @@ impl<'a> KllCoreData<'a> {
                         Err(err) => *err.entry.get(),
                     };
 
-                let result_guide = result_list.kll_core_guide(layouts.clone());
+                let result_guide =
+                    result_list.kll_core_guide(layouts.clone(), &unicode_string_lookup);
                 // Determine if result guide has already been added
                 let result_pos =
                     match result_hash.try_insert(result_guide.clone(), result_guides.len()) {
@@ -98,7 +119,8 @@ impl<'a> KllCoreData<'a> {
             // Iterate again to build the necessary layer lookup
             for (trigger_list, result_list) in layer.trigger_result_lists() {
                 let trigger_guide = trigger_list.kll_core_guide();
-                let result_guide = result_list.kll_core_guide(layouts.clone());
+                let result_guide =
+                    result_list.kll_core_guide(layouts.clone(), &unicode_string_lookup);
 
                 // Lookup position in trigger:result lookup
                 let (_, _, trigger_result_pos) =
@@ -119,6 +141,10 @@ impl<'a> KllCoreData<'a> {
                             };
                             (index_type, index)
                         }
+                        TriggerType::Analog { index, .. } | TriggerType::Rotation { index, .. } => {
+                            // Condition type is the lookup type
+                            (u8::from(trigger.kll_core_condition()), *index as u16)
+                        }
                         _ => {
                             panic!("{} Not implemented yet", trigger.trigger);
                         }
@@ -144,7 +170,7 @@ impl<'a> KllCoreData<'a> {
             }
         }
 
-        Self {
+        let kdata = Self {
             layers: layers.to_vec(),
             trigger_hash,
             result_hash,
@@ -154,7 +180,75 @@ impl<'a> KllCoreData<'a> {
             result_guides,
             trigger_result_map,
             raw_layer_lookup,
+            unicode_string_lookup,
+            unicode_strings,
+        };
+
+        // Make sure all the emitted indices are valid
+        kdata.validate()?;
+
+        Ok(kdata)
+    }
+
+    /// Validates that every index in trigger_result_map and raw_layer_lookup points to the start
+    /// of a guide (or trigger:result mapping)
+    /// An invalid index would read garbage at runtime
+    pub fn validate(&self) -> Result<(), Error> {
+        let trigger_starts = guide_starts(
+            &self.trigger_guides,
+            std::mem::size_of::<kll_core::TriggerCondition>(),
+        );
+        let result_starts = guide_starts(
+            &self.result_guides,
+            std::mem::size_of::<kll_core::Capability>(),
+        );
+
+        // trigger_result_map is a list of (trigger guide, result guide) pairs
+        if self.trigger_result_map.len() % 2 != 0 {
+            return Err(Error::InvalidTriggerResultMapIndex(
+                self.trigger_result_map.len(),
+            ));
         }
+        for (pos, pair) in self.trigger_result_map.chunks(2).enumerate() {
+            if !trigger_starts.contains(&(pair[0] as usize)) {
+                error!("Invalid trigger guide index: {} ({})", pair[0], pos * 2);
+                return Err(Error::InvalidTriggerGuideIndex(pos * 2));
+            }
+            if !result_starts.contains(&(pair[1] as usize)) {
+                error!("Invalid result guide index: {} ({})", pair[1], pos * 2 + 1);
+                return Err(Error::InvalidResultGuideIndex(pos * 2 + 1));
+            }
+        }
+
+        // raw_layer_lookup entries: layer, ttype, index (u16), count, trigger_result_map indices
+        let mut pos = 0;
+        while pos < self.raw_layer_lookup.len() {
+            let count = match self.raw_layer_lookup.get(pos + 4) {
+                Some(count) => *count as usize,
+                None => {
+                    return Err(Error::InvalidLayerLookup(pos));
+                }
+            };
+            for n in 0..count {
+                let offset = pos + 5 + n * 2;
+                let index = match self.raw_layer_lookup.get(offset..offset + 2) {
+                    Some(bytes) => u16::from_le_bytes([bytes[0], bytes[1]]) as usize,
+                    None => {
+                        return Err(Error::InvalidLayerLookup(offset));
+                    }
+                };
+                if index % 2 != 0 || index >= self.trigger_result_map.len() {
+                    error!(
+                        "Invalid trigger:result mapping index: {} ({})",
+                        index, offset
+                    );
+                    return Err(Error::InvalidTriggerResultMapIndex(offset));
+                }
+            }
+            pos += 5 + count * 2;
+        }
+
+        Ok(())
     }
 
     /// Generate rust form of kll-core datastructures
@@ -177,6 +271,10 @@ impl<'a> KllCoreData<'a> {
         for elem in &self.raw_layer_lookup {
             raw_layer_lookup += &format!("{}, ", elem).to_string();
         }
+        let mut unicode_strings = String::new();
+        for elem in &self.unicode_strings {
+            unicode_strings += &format!("{}, ", elem).to_string();
+        }
 
         file.write_all(
             &format!(
@@ -198,8 +296,16 @@ pub const TRIGGER_RESULT_MAPPING: &[u16] = &[{}];
 
 /// Raw Layer Lookup Table
 pub const LAYER_LOOKUP: &[u8] = &[{}];
+
+/// Unicode String Table
+/// NULL terminated UTF-8 strings, looked up by index
+pub const UNICODE_STRINGS: &[u8] = &[{}];
 ",
-                trigger_guides, result_guides, trigger_result_mapping, raw_layer_lookup
+                trigger_guides,
+                result_guides,
+                trigger_result_mapping,
+                raw_layer_lookup,
+                unicode_strings
             )
             .into_bytes(),
         )?;
@@ -217,13 +323,87 @@ pub const LAYER_LOOKUP: &[u8] = &[{}];
     */
 }
 
+/// Determine the starting position of each guide in a list of guides
+/// Each guide is a sequence of combos (combo size followed by the elements), terminated by a 0
+fn guide_starts(guides: &[u8], elem_size: usize) -> HashSet<usize> {
+    let mut starts = HashSet::new();
+    let mut pos = 0;
+    while pos < guides.len() {
+        starts.insert(pos);
+        while let Some(count) = guides.get(pos) {
+            let count = *count as usize;
+            pos += 1;
+            if count == 0 {
+                break;
+            }
+            pos += count * elem_size;
+        }
+    }
+    starts
+}
+
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Error {
     ParsingError,
     UnknownError,
+    /// Trigger guide index (trigger_result_map position) does not point to a trigger guide
+    InvalidTriggerGuideIndex(usize),
+    /// Result guide index (trigger_result_map position) does not point to a result guide
+    InvalidResultGuideIndex(usize),
+    /// trigger_result_map index (raw_layer_lookup position) does not point to a mapping
+    InvalidTriggerResultMapIndex(usize),
+    /// raw_layer_lookup entry is truncated (raw_layer_lookup position)
+    InvalidLayerLookup(usize),
+    /// Scancode is bound more than once on the same layer
+    DuplicateScancode {
+        layer: usize,
+        scancode: usize,
+    },
+    /// Capability result uses an undefined capability (keymap index)
+    UnknownCapability {
+        layer: usize,
+        index: usize,
+    },
+    /// Capability result has the wrong number of arguments (keymap index)
+    CapabilityArity {
+        layer: usize,
+        index: usize,
+        expected: usize,
+        found: usize,
+    },
 }
 
-pub fn verify(_groups: &KllGroups) -> Result<(), Error> {
+pub fn verify(groups: &KllGroups) -> Result<(), Error> {
+    // Check for duplicate scancode bindings
+    for (layer, state) in groups.layers().iter().enumerate() {
+        if let Some(scancode) = state.duplicate_scancodes().first() {
+            error!("Duplicate binding on layer {}: S{}", layer, scancode);
+            return Err(Error::DuplicateScancode {
+                layer,
+                scancode: *scancode,
+            });
+        }
+    }
+
+    // Check capability results against the capability definitions
+    for (layer, state) in groups.layers().iter().enumerate() {
+        if let Some(mismatch) = state.capability_mismatches().first() {
+            error!("Invalid capability on layer {}: {}", layer, mismatch);
+            return Err(match mismatch.expected {
+                Some(expected) => Error::CapabilityArity {
+                    layer,
+                    index: mismatch.index,
+                    expected,
+                    found: mismatch.found,
+                },
+                None => Error::UnknownCapability {
+                    layer,
+                    index: mismatch.index,
+                },
+            });
+        }
+    }
+
     Ok(())
 }
 
@@ -233,7 +413,7 @@ pub fn write(file: &Path, groups: &KllGroups, layouts: Layouts) {
     //let layers = &groups.default;
 
     // Generate kll-core datastructures
-    let kdata = KllCoreData::new(&mut layers, layouts);
+    let kdata = KllCoreData::new(&mut layers, layouts).unwrap();
 
     // Write rust file
     kdata.rust(file).unwrap();
@@ -244,6 +424,7 @@ pub fn write(file: &Path, groups: &KllGroups, layouts: Layouts) {
 pub struct KllCoreValidation<'a, const LAYOUT_SIZE: usize> {
     layers: Vec<KllState<'a>>,
     layouts: Layouts,
+    unicode_string_lookup: UnicodeStringLookup,
     lookup: kll_core::layout::LayerLookup<'a, LAYOUT_SIZE>,
 }
 
@@ -259,11 +440,13 @@ impl<'a, const LAYOUT_SIZE: usize> KllCoreValidation<'a, LAYOUT_SIZE> {
             &kdata.result_guides,
             &kdata.trigger_result_map,
             loop_condition_lookup,
-        );
+        )
+        .unwrap();
 
         Self {
             layers,
             layouts,
+            unicode_string_lookup: kdata.unicode_string_lookup.clone(),
             lookup,
         }
     }
@@ -372,7 +555,8 @@ impl<'a, const LAYOUT_SIZE: usize> KllCoreValidation<'a, LAYOUT_SIZE> {
                         trace!("r elem: {:?}", elem);
 
                         // Convert to Capability
-                        let cap = elem.kll_core_condition(self.layouts.clone());
+                        let cap = elem
+                            .kll_core_condition(self.layouts.clone(), &self.unicode_string_lookup);
                         trace!("r cap: {:?}", cap);
 
                         // Convert to CapabilityRun
@@ -7,9 +7,10 @@
 
 #![cfg(test)]
 
-use crate::emitters::kllcore::{KllCoreData, KllCoreValidation};
-use crate::types::KllFile;
+use crate::emitters::kllcore::{Error, KllCoreData, KllCoreValidation};
+use crate::types::{KllFile, UnicodeStringLookup};
 use flexi_logger::Logger;
+use kll_core::TriggerCondition;
 use layouts_rs::Layouts;
 use log::*;
 use std::collections::HashMap;
@@ -49,6 +50,84 @@ fn trigger() {
     // TODO Validate
 }
 
+#[test]
+fn analog_trigger() {
+    setup_logging_lite().ok();
+
+    let test = "A[Distance, 0x12, 50] : U\"A\"(P);\nA[Velocity, 3, -20] : U\"B\"(P);\n";
+    let result = KllFile::from_str(test);
+    let state = result.unwrap().into_struct();
+
+    let conditions: Vec<TriggerCondition> = state
+        .triggers()
+        .map(|trigger| trigger.kll_core_condition())
+        .collect();
+    assert_eq!(
+        conditions,
+        vec![
+            TriggerCondition::AnalogDistance {
+                reserved: 0,
+                index: 0x12,
+                val: 50,
+            },
+            TriggerCondition::AnalogVelocity {
+                reserved: 0,
+                index: 3,
+                val: -20,
+            },
+        ]
+    );
+
+    // Analog triggers are added to the layer lookup using the condition type
+    let mut layers = vec![state];
+    let layouts = Layouts::from_dir(PathBuf::from("layouts"));
+    let kdata = KllCoreData::new(&mut layers, layouts).unwrap();
+    let lookup = kll_core::layout::LayerLookup::<4>::new(
+        &kdata.raw_layer_lookup,
+        &kdata.trigger_guides,
+        &kdata.result_guides,
+        &kdata.trigger_result_map,
+        &[0],
+    )
+    .unwrap();
+    assert!(lookup.layer_lookup().contains_key(&(0, 3, 0x12)));
+    assert!(lookup.layer_lookup().contains_key(&(0, 4, 3)));
+}
+
+#[test]
+fn rotation_trigger() {
+    setup_logging_lite().ok();
+
+    let test = "R[0, CW] : U\"VolumeUp\"(P);\n";
+    let result = KllFile::from_str(test);
+    let state = result.unwrap().into_struct();
+    let mut layers = vec![state];
+    let layouts = Layouts::from_dir(PathBuf::from("layouts"));
+    let kdata = KllCoreData::new(&mut layers, layouts).unwrap();
+
+    // Trigger guide: single combo with a single CW rotation condition
+    let condition = TriggerCondition::Rotation {
+        index: 0,
+        loop_condition_index: 0,
+        position: 1,
+    };
+    let mut trigger_guide = vec![1];
+    trigger_guide.extend_from_slice(unsafe { condition.bytes() });
+    trigger_guide.push(0);
+    assert_eq!(kdata.trigger_guides, trigger_guide);
+
+    // Result guide: single combo pressing Volume Up
+    let capability = kll_core::Capability::HidKeyboard {
+        state: kll_core::CapabilityState::Initial,
+        loop_condition_index: 0,
+        id: kll_core::kll_hid::Keyboard::VolumeUp,
+    };
+    let mut result_guide = vec![1];
+    result_guide.extend_from_slice(unsafe { capability.bytes() });
+    result_guide.push(0);
+    assert_eq!(kdata.result_guides, result_guide);
+}
+
 #[test]
 fn result() {
     let test = fs::read_to_string("examples/kllcoretest.kll").unwrap();
@@ -59,7 +138,7 @@ fn result() {
     // Generate result guides
     let mut result_guides = Vec::new();
     for result_list in state.result_lists() {
-        let mut guide = result_list.kll_core_guide(layouts.clone());
+        let mut guide = result_list.kll_core_guide(layouts.clone(), &UnicodeStringLookup::new());
         result_guides.append(&mut guide);
     }
 
@@ -96,7 +175,8 @@ fn trigger_result() {
             Err(err) => err.entry.get().clone(),
         };
 
-        let mut result_guide = result_list.kll_core_guide(layouts.clone());
+        let mut result_guide =
+            result_list.kll_core_guide(layouts.clone(), &UnicodeStringLookup::new());
         // Determine if result guide has already been added
         let result_pos = match result_hash.try_insert(result_guide.clone(), result_guide.len()) {
             Ok(pos) => {
@@ -129,7 +209,7 @@ fn layer_lookup_simple() {
     let mut layers = vec![state];
     dbg!(layers.clone());
     let layouts = Layouts::from_dir(PathBuf::from("layouts"));
-    let kdata = KllCoreData::new(&mut layers, layouts);
+    let kdata = KllCoreData::new(&mut layers, layouts).unwrap();
 
     // TODO - Generate loop conditions using compiler
     let loop_condition_lookup: &[u32] = &[0];
@@ -142,7 +222,8 @@ fn layer_lookup_simple() {
         &kdata.result_guides,
         &kdata.trigger_result_map,
         &loop_condition_lookup,
-    );
+    )
+    .unwrap();
 
     // Initialize LayerState
     const STATE_SIZE: usize = 2;
@@ -231,6 +312,53 @@ fn layer_lookup_simple() {
     );
 }
 
+#[test]
+fn unicode_string_table() {
+    setup_logging_lite().ok();
+
+    let test = "S0 : u\"⌨\";\nS1 : u\"⌨\";\nS2 : u\"keyboard\";\n";
+    let result = KllFile::from_str(test);
+    let state = result.unwrap().into_struct();
+    let mut layers = vec![state];
+    let layouts = Layouts::from_dir(PathBuf::from("layouts"));
+    let kdata = KllCoreData::new(&mut layers, layouts.clone()).unwrap();
+
+    // Identical strings are only stored once
+    assert_eq!(kdata.unicode_string_lookup.len(), 2);
+    assert_eq!(kdata.unicode_string_lookup["keyboard"], 0);
+    assert_eq!(kdata.unicode_string_lookup["⌨"], 1);
+    assert_eq!(kdata.unicode_strings, "keyboard\0⌨\0".as_bytes());
+
+    // S0 and S1 share the same result guides (and unicode string index)
+    // First 4 mappings are S0 and S1 (press and release)
+    let result_guides = layers[0]
+        .trigger_result_lists()
+        .take(4)
+        .map(|(_, result_list)| {
+            result_list.kll_core_guide(layouts.clone(), &kdata.unicode_string_lookup)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(result_guides.len(), 4);
+    assert_eq!(result_guides[0..2], result_guides[2..4]);
+
+    let cap = kll_core::Capability::HidioUnicodeString {
+        state: kll_core::CapabilityState::Initial,
+        loop_condition_index: 0,
+        index: 1,
+    };
+    let mut guide = vec![1];
+    unsafe {
+        guide.extend_from_slice(cap.bytes());
+    }
+    guide.push(0);
+    assert!(
+        result_guides.contains(&guide),
+        "Missing unicode string guide {:?} in {:?}",
+        guide,
+        result_guides
+    );
+}
+
 #[test]
 fn generate_binary() {
     // todo needs an offset table for the firmware to know where the pointers
@@ -252,9 +380,40 @@ fn keystone_basemap_rust() {
     let mut layers = vec![state];
     dbg!(layers.clone());
     let layouts = Layouts::from_dir(PathBuf::from("layouts"));
-    let kdata = KllCoreData::new(&mut layers, layouts.clone());
+    let kdata = KllCoreData::new(&mut layers, layouts.clone()).unwrap();
 
     const LAYOUT_SIZE: usize = 128;
     let kval = KllCoreValidation::<LAYOUT_SIZE>::new(&kdata, layers, layouts);
     kval.validate();
 }
+
+#[test]
+fn corrupted_guide_index() {
+    setup_logging_lite().ok();
+
+    let test = "S0 : U\"A\";\nS1 : U\"B\";\n";
+    let result = KllFile::from_str(test);
+    let state = result.unwrap().into_struct();
+    let mut layers = vec![state];
+    let layouts = Layouts::from_dir(PathBuf::from("layouts"));
+    let mut kdata = KllCoreData::new(&mut layers, layouts).unwrap();
+    assert_eq!(kdata.validate(), Ok(()));
+
+    // Off-by-one trigger guide index
+    kdata.trigger_result_map[2] += 1;
+    assert_eq!(kdata.validate(), Err(Error::InvalidTriggerGuideIndex(2)));
+    kdata.trigger_result_map[2] -= 1;
+
+    // Off-by-one result guide index
+    kdata.trigger_result_map[3] += 1;
+    assert_eq!(kdata.validate(), Err(Error::InvalidResultGuideIndex(3)));
+    kdata.trigger_result_map[3] -= 1;
+
+    // Layer lookup pointing past the trigger:result mapping
+    let len = kdata.raw_layer_lookup.len();
+    kdata.raw_layer_lookup[len - 2] = 0xFF;
+    assert!(matches!(
+        kdata.validate(),
+        Err(Error::InvalidTriggerResultMapIndex(_))
+    ));
+}
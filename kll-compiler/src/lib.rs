@@ -14,6 +14,7 @@ use object_merge::Merge;
 pub use parser::parse_int;
 use parser::PestError;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
@@ -48,6 +49,81 @@ pub struct KllState<'a> {
     pub animations: HashMap<&'a str, Animation<'a>>,
 }
 
+/// A define, variable or capability redefined with a different value while merging KllStates
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    /// Type of the redefined value (define, variable or capability)
+    pub kind: &'static str,
+    pub name: String,
+    /// Value that was kept
+    pub old: String,
+    /// Value that was ignored
+    pub new: String,
+    /// File that contains the ignored value
+    pub source: PathBuf,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} redefined in {:?}: keeping {}, ignoring {}",
+            self.kind, self.name, self.source, self.old, self.new
+        )
+    }
+}
+
+/// A USB code trigger with no scancode in the base map, found while reducing a KllState
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmappedUsbCode {
+    /// USB code without a scancode binding
+    pub usb: String,
+    /// Mapping that references the USB code
+    pub mapping: String,
+}
+
+impl fmt::Display for UnmappedUsbCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "U\"{}\" has no scancode in the base map (used by: {})",
+            self.usb, self.mapping
+        )
+    }
+}
+
+/// A capability result that doesn't match its capability definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapabilityMismatch {
+    /// Capability name used by the result
+    pub name: String,
+    /// Number of arguments in the definition (None if the capability is not defined)
+    pub expected: Option<usize>,
+    /// Number of arguments used by the result
+    pub found: usize,
+    /// Keymap index of the mapping
+    pub index: usize,
+    /// Mapping that uses the capability
+    pub mapping: String,
+}
+
+impl fmt::Display for CapabilityMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.expected {
+            Some(expected) => write!(
+                f,
+                "{} expects {} argument(s), found {} (used by: {})",
+                self.name, expected, self.found, self.mapping
+            ),
+            None => write!(
+                f,
+                "{} is not a defined capability (used by: {})",
+                self.name, self.mapping
+            ),
+        }
+    }
+}
+
 impl<'a> KllFile<'a> {
     pub fn into_struct(self) -> KllState<'a> {
         let mut kll = KllState::default();
@@ -82,14 +158,16 @@ impl<'a> KllFile<'a> {
                 }
                 Statement::Position((indices, pos)) => {
                     for range in indices {
-                        for index in range {
+                        // Position ranges are inclusive (e.g. S[122-125], S122)
+                        for index in range.start..=range.end {
                             kll.positions.insert(index, pos.clone());
                         }
                     }
                 }
                 Statement::Pixelmap((indices, map)) => {
                     for range in indices {
-                        for index in range {
+                        // Pixel ranges are inclusive (e.g. P[1-3], P[1])
+                        for index in range.start..=range.end {
                             kll.pixelmap.insert(index, map.clone());
                         }
                     }
@@ -101,7 +179,8 @@ impl<'a> KllFile<'a> {
                     let animation = kll.animations.entry(name).or_default();
                     let frames = &mut animation.frames;
                     for range in indices {
-                        for index in range {
+                        // Frame ranges are inclusive (e.g. A[Name, 1-3], A[Name, 1])
+                        for index in range.start..=range.end {
                             if frames.len() <= index {
                                 frames.resize(index + 1, vec![]);
                             }
@@ -180,6 +259,64 @@ impl<'a> KllState<'a> {
             .collect()
     }
 
+    /// Returns the scancodes bound more than once by plain replace (:) mappings
+    /// The later binding silently overwrites the earlier one, which is usually a layout mistake.
+    /// Explicit replace (::, i:, i::), add (:+) and remove (:-) mappings are not duplicates.
+    /// Only single key bindings are checked (combos and sequences may share keys).
+    pub fn duplicate_scancodes(&self) -> Vec<usize> {
+        let mut bindings = HashSet::new();
+        let mut duplicates = Vec::new();
+        for Mapping(trigger_list, mode, _) in &self.keymap {
+            if *mode != TriggerMode::Replace {
+                continue;
+            }
+            if let [combo] = trigger_list.0.as_slice() {
+                if let [trigger] = combo.as_slice() {
+                    if let TriggerType::Key(Key::Scancode(scancode)) = trigger.trigger {
+                        if !bindings.insert(trigger) && !duplicates.contains(&scancode) {
+                            duplicates.push(scancode);
+                        }
+                    }
+                }
+            }
+        }
+        duplicates
+    }
+
+    /// Validates each capability result against the capability definitions (name => func(args))
+    /// Reports results using an undefined capability name or the wrong number of arguments.
+    pub fn capability_mismatches(&self) -> Vec<CapabilityMismatch> {
+        let mut mismatches = Vec::new();
+        for (index, mapping) in self.keymap.iter().enumerate() {
+            for action in mapping.2.iter() {
+                if let ResultType::Capability((cap, _)) = &action.result {
+                    let expected = self
+                        .capabilities
+                        .get(cap.function)
+                        .map(|def| def.args.len());
+                    if expected != Some(cap.args.len()) {
+                        mismatches.push(CapabilityMismatch {
+                            name: cap.function.to_string(),
+                            expected,
+                            found: cap.args.len(),
+                            index,
+                            mapping: mapping.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+        mismatches
+    }
+
+    /// Maps each keymap scancode to its physical position (if a position was defined)
+    pub fn position_map(&self) -> HashMap<usize, &Position> {
+        self.scancodes()
+            .into_iter()
+            .filter_map(|scancode| self.positions.get(&scancode).map(|pos| (scancode, pos)))
+            .collect()
+    }
+
     pub fn animations(&self) -> impl Iterator<Item = &AnimationResult> + '_ {
         self.actions().filter_map(|action| match &action.result {
             ResultType::Animation(anim) => Some(anim),
@@ -196,12 +333,16 @@ impl<'a> KllState<'a> {
             .collect()
     }
 
-    pub fn reduce(&self, base: KllState<'a>) -> Vec<Mapping<'a>> {
+    /// Maps USB code triggers back to scancodes (using the base map) and USB code results to
+    /// usbKeyOut capabilities
+    /// Fails on the first USB code trigger that has no scancode in the base map
+    pub fn reduce(&self, base: KllState<'a>) -> Result<Vec<Mapping<'a>>, UnmappedUsbCode> {
         let scancode_map = base.scancode_map();
         let mut new_keymap: Vec<Mapping> = self
             .keymap
             .iter()
-            .map(|Mapping(trigger_groups, mode, result_groups)| {
+            .map(|mapping| {
+                let Mapping(trigger_groups, mode, result_groups) = mapping;
                 let new_triggers = TriggerList(match mode {
                     TriggerMode::SoftReplace => trigger_groups.0.clone(),
                     _ => trigger_groups
@@ -211,18 +352,21 @@ impl<'a> KllState<'a> {
                             combo
                                 .iter()
                                 .map(|t| match &t.trigger {
-                                    TriggerType::Key(Key::Usb(u)) => {
-                                        let s = scancode_map.get(u).unwrap();
-                                        Trigger {
+                                    TriggerType::Key(Key::Usb(u)) => match scancode_map.get(u) {
+                                        Some(s) => Ok(Trigger {
                                             trigger: TriggerType::Key(Key::Scancode(*s)),
                                             state: t.state.clone(),
-                                        }
-                                    }
-                                    _ => t.clone(),
+                                        }),
+                                        None => Err(UnmappedUsbCode {
+                                            usb: u.to_string(),
+                                            mapping: mapping.to_string(),
+                                        }),
+                                    },
+                                    _ => Ok(t.clone()),
                                 })
-                                .collect::<Vec<_>>()
+                                .collect::<Result<Vec<_>, _>>()
                         })
-                        .collect::<Vec<_>>(),
+                        .collect::<Result<Vec<_>, _>>()?,
                 });
                 let new_results = ResultList(match mode {
                     TriggerMode::SoftReplace => result_groups.0.clone(),
@@ -247,17 +391,59 @@ impl<'a> KllState<'a> {
                         .collect::<Vec<_>>(),
                 });
 
-                Mapping(new_triggers, mode.clone(), new_results)
+                Ok(Mapping(new_triggers, mode.clone(), new_results))
             })
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, _>>()?;
+
+        new_keymap.sort_by_cached_key(|mapping| mapping.sort_key());
+
+        Ok(new_keymap)
+    }
 
-        new_keymap.sort_by(|a, b| {
-            let a = format!("{}", a);
-            let b = format!("{}", b);
-            alphanumeric_sort::compare_path(a, b)
-        });
+    /// Merges another KllState (same as merge()), recording any defines, variables or
+    /// capabilities that are redefined with a different value
+    /// As with merge(), values already in self take precedence over the redefinition
+    /// source is the file the other KllState was loaded from
+    pub fn merge_with_conflicts(
+        &mut self,
+        other: &KllState<'a>,
+        source: &Path,
+    ) -> Vec<MergeConflict> {
+        let mut conflicts = Vec::new();
+        let mut conflict = |kind, name: &str, old: String, new: String| {
+            conflicts.push(MergeConflict {
+                kind,
+                name: name.to_string(),
+                old,
+                new,
+                source: source.to_path_buf(),
+            });
+        };
+
+        for (name, new) in &other.defines {
+            if let Some(old) = self.defines.get(name) {
+                if old != new {
+                    conflict("define", name, old.to_string(), new.to_string());
+                }
+            }
+        }
+        for (name, new) in &other.variables {
+            if let Some(old) = self.variables.get(name) {
+                if old != new {
+                    conflict("variable", name, format!("{:?}", old), format!("{:?}", new));
+                }
+            }
+        }
+        for (name, new) in &other.capabilities {
+            if let Some(old) = self.capabilities.get(name) {
+                if old != new {
+                    conflict("capability", name, old.to_string(), new.to_string());
+                }
+            }
+        }
 
-        new_keymap
+        self.merge(other);
+        conflicts
     }
 
     /// Replaces any implied (KLL) state with explicit state
@@ -285,6 +471,30 @@ pub struct KllDatastore<'a> {
     pub unique_triggers: HashSet<Trigger<'a>>,
     pub unique_results: HashSet<Action<'a>>,
     pub unique_animations: HashSet<AnimationResult<'a>>,
+    pub position_map: HashMap<usize, &'a Position>,
+    pub mapping_count: usize,
+    /// Unique trigger:result mappings (each is a kll-core trigger guide:result guide pair)
+    pub trigger_results: HashSet<(TriggerList<'a>, ResultList<'a>)>,
+}
+
+/// Recommended minimum kll-core const generic sizes for a layout
+/// heapless maps require a power of two, so each size is rounded up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KllCoreSizes {
+    /// LayerLookup/LayerState LAYOUT_SIZE (unique trigger conditions of each layer)
+    pub layout_size: usize,
+    /// LayerState STATE_SIZE (trigger:result mappings tracked in lookup_state)
+    pub state_size: usize,
+    /// LayerState MAX_ACTIVE_TRIGGERS (trigger:result mappings with a partially evaluated combo)
+    pub max_active_triggers: usize,
+}
+
+impl fmt::Display for KllCoreSizes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "LAYOUT_SIZE: {}", self.layout_size)?;
+        writeln!(f, "STATE_SIZE: {}", self.state_size)?;
+        write!(f, "MAX_ACTIVE_TRIGGERS: {}", self.max_active_triggers)
+    }
 }
 
 impl<'a> KllDatastore<'a> {
@@ -318,6 +528,49 @@ impl<'a> KllDatastore<'a> {
             unique_triggers: state.triggers().cloned().collect(),
             unique_results: state.actions().cloned().collect(),
             unique_animations: state.animations().cloned().collect(),
+            position_map: state.position_map(),
+            mapping_count: state.keymap.len(),
+            trigger_results: state
+                .keymap
+                .iter()
+                .map(|Mapping(triggers, _, results)| (triggers.clone(), results.clone()))
+                .collect(),
+        }
+    }
+
+    /// Computes the recommended minimum kll-core const generic sizes for a list of layers
+    /// (see KllGroups::layers(), one KllDatastore per layer)
+    pub fn recommended_sizes(layers: &[KllDatastore]) -> KllCoreSizes {
+        // Heapless maps must be a power of two (and larger than 1)
+        let size = |count: usize| count.next_power_of_two().max(2);
+
+        // The layer lookup is keyed by (layer, ttype, index)
+        // The trigger state (e.g. Press/Release) does not change the lookup key
+        let layout_size = layers
+            .iter()
+            .map(|layer| {
+                layer
+                    .unique_triggers
+                    .iter()
+                    .map(|t| &t.trigger)
+                    .collect::<HashSet<&TriggerType>>()
+                    .len()
+            })
+            .sum();
+
+        // Guides are shared between layers, so identical mappings share the same state entry
+        // Both lookup_state and the combo evaluation state are keyed by (trigger guide, result
+        // guide), and any pair may be waiting on a combo (or a state, e.g. Hold) in the same scan
+        let trigger_results = layers
+            .iter()
+            .flat_map(|layer| layer.trigger_results.iter())
+            .collect::<HashSet<_>>()
+            .len();
+
+        KllCoreSizes {
+            layout_size: size(layout_size),
+            state_size: size(trigger_results),
+            max_active_triggers: size(trigger_results),
         }
     }
 }
@@ -344,6 +597,12 @@ impl Filestore {
         self.files.insert(path.to_path_buf(), raw_text);
     }
 
+    /// Parses a loaded file, returning any syntax errors
+    pub fn check_file(&self, path: &Path) -> Result<(), PestError> {
+        let raw_text = self.files.get(path).unwrap();
+        parse(raw_text).map(|_| ())
+    }
+
     pub fn get_file<'a>(&'a self, path: &Path) -> KllState<'a> {
         let raw_text = self.files.get(path).unwrap();
         parse(raw_text).unwrap().into_struct()
@@ -362,6 +621,10 @@ pub struct KllGroups<'a> {
     base: Vec<KllState<'a>>,
     default: Vec<KllState<'a>>,
     partials: Vec<KllState<'a>>,
+    config_paths: Vec<PathBuf>,
+    base_paths: Vec<PathBuf>,
+    default_paths: Vec<PathBuf>,
+    partial_paths: Vec<PathBuf>,
 }
 
 impl<'a> KllGroups<'a> {
@@ -377,9 +640,43 @@ impl<'a> KllGroups<'a> {
             base: base.iter().map(|p| filestore.get_file(p)).collect(),
             default: default.iter().map(|p| filestore.get_file(p)).collect(),
             partials: partials.iter().map(|p| filestore.get_file(p)).collect(),
+            config_paths: config.to_vec(),
+            base_paths: base.to_vec(),
+            default_paths: default.to_vec(),
+            partial_paths: partials.to_vec(),
         }
     }
 
+    /// Merges all the groups (config, base, default and partials), same as config(),
+    /// defaultmap() and partialmaps(), and returns any values that were redefined
+    pub fn merge_conflicts(&self) -> Vec<MergeConflict> {
+        let mut conflicts = Vec::new();
+
+        if let Some(config) = self.config.first() {
+            let mut config = config.clone();
+            for (c, path) in self.config.iter().zip(&self.config_paths).skip(1) {
+                conflicts.append(&mut config.merge_with_conflicts(c, path));
+            }
+        }
+
+        let mut base = self.base.first().cloned().unwrap_or_default();
+        for (b, path) in self.base.iter().zip(&self.base_paths).skip(1) {
+            conflicts.append(&mut base.merge_with_conflicts(b, path));
+        }
+
+        let mut layout = base.clone();
+        for (default, path) in self.default.iter().zip(&self.default_paths) {
+            conflicts.append(&mut layout.merge_with_conflicts(default, path));
+        }
+
+        for (partial, path) in self.partials.iter().zip(&self.partial_paths) {
+            let mut layout = base.clone();
+            conflicts.append(&mut layout.merge_with_conflicts(partial, path));
+        }
+
+        conflicts
+    }
+
     pub fn config(&self) -> KllState<'a> {
         let mut configs = self.config.iter();
         let mut config = configs.next().unwrap().clone();
@@ -389,9 +686,10 @@ impl<'a> KllGroups<'a> {
         config
     }
 
+    /// Merged base map, empty if no --base files were given (e.g. check mode)
     pub fn basemap(&self) -> KllState<'a> {
         let mut layouts = self.base.iter();
-        let mut layout = layouts.next().unwrap().clone();
+        let mut layout = layouts.next().cloned().unwrap_or_default();
         for base in layouts {
             layout.merge(base);
         }
@@ -1,7 +1,9 @@
+use kll_compiler::emitters::kllcore;
 use kll_compiler::{Filestore, KllDatastore, KllGroups};
 use layouts_rs::Layouts;
 use std::env;
 use std::path::PathBuf;
+use std::process;
 use std::str::FromStr;
 use structopt::StructOpt;
 
@@ -16,7 +18,7 @@ pub enum EmitterType {
     Configurator,
     /// Simple rust code generation (for basic unit tests)
     Rust,
-    /// Not set
+    /// Not set (parse and validate only, no files are written)
     None,
 }
 
@@ -28,7 +30,12 @@ struct CliOpts {
     #[structopt(short, long)]
     debug: bool,
 
+    /// Warn about defines, variables and capabilities redefined while merging .kll files
+    #[structopt(long)]
+    warn_conflicts: bool,
+
     /// Specify target emitter for the KLL compiler. Pass multiple times to use more than one.
+    /// Use "none" to only parse and validate the given .kll files.
     #[structopt(long, default_value = "kiibohd")]
     emitter: String,
 
@@ -86,17 +93,34 @@ fn main() {
         println!("=== ARGS === \n{:#?}", &args);
     }
 
+    let emitter = EmitterType::from_str(&args.emitter).unwrap();
+
     let mut filestore = Filestore::new();
-    for file in args
+    let files: Vec<&PathBuf> = args
         .config
         .iter()
         .chain(&args.base)
         .chain(&args.default)
         .chain(&args.partial)
-    {
+        .collect();
+    for file in &files {
         filestore.load_file(file);
     }
 
+    // Report all syntax errors before merging (merging panics on unparsable files)
+    if emitter == EmitterType::None {
+        let mut failed = false;
+        for file in &files {
+            if let Err(err) = filestore.check_file(file) {
+                println!("Error: {:?}\n{}", file, err);
+                failed = true;
+            }
+        }
+        if failed {
+            process::exit(1);
+        }
+    }
+
     let groups = KllGroups::new(
         &filestore,
         &args.config,
@@ -109,8 +133,27 @@ fn main() {
         println!("=== DEFAULT === \n{:#?}", groups.defaultmap());
         println!("=== PARTIAL === \n{:#?}", groups.partialmaps());
     }
+    if args.warn_conflicts {
+        for conflict in groups.merge_conflicts() {
+            println!("Warning: {}", conflict);
+        }
+    }
+
+    // Semantic validation, required by all emitters
+    if let Err(err) = kllcore::verify(&groups) {
+        println!("Error: {:?}", err);
+        process::exit(1);
+    }
+
+    // Validation only
+    if emitter == EmitterType::None {
+        if let Err(err) = check(&groups) {
+            println!("Error: {:?}", err);
+            process::exit(1);
+        }
+        println!("OK");
+    }
 
-    let emitter = EmitterType::from_str(&args.emitter).unwrap();
     // Old C-based kiibohd firmware
     if emitter == EmitterType::Kiibohd {
         let mut layouts = Layouts::from_dir(PathBuf::from("layouts"));
@@ -118,18 +161,60 @@ fn main() {
         dbg!(&layout.from_hid_keyboard);
 
         let mut defaultmap = groups.defaultmap();
-        defaultmap.keymap = defaultmap.reduce(groups.basemap());
+        defaultmap.keymap = match defaultmap.reduce(groups.basemap()) {
+            Ok(keymap) => keymap,
+            Err(err) => {
+                println!("Error: {}", err);
+                process::exit(1);
+            }
+        };
         for s in &defaultmap.keymap {
             println!("{}", s);
         }
 
+        let kll_data = KllDatastore::new(&defaultmap);
         if args.debug {
-            let kll_data = KllDatastore::new(&defaultmap);
             println!("{:?}", kll_data);
         }
 
+        let layers = groups.layers();
+        let layer_data = layers.iter().map(KllDatastore::new).collect::<Vec<_>>();
+        println!(
+            "=== kll-core sizes ===\n{}",
+            KllDatastore::recommended_sizes(&layer_data)
+        );
+
         let outfile = env::current_dir().unwrap().join("generatedKeymap.h");
         kll_compiler::emitters::kiibohd::write(&outfile, &groups);
         println!("Wrote {:?}", outfile);
+
+        let pixelfile = env::current_dir().unwrap().join(&args.kiibohd.pixel_output);
+        kll_compiler::emitters::kiibohd::write_pixelmap(&pixelfile, &groups);
+        println!("Wrote {:?}", pixelfile);
+
+        let hidfile = env::current_dir().unwrap().join(&args.kiibohd.hid_output);
+        kll_compiler::emitters::kiibohd::write_usb_ids(
+            &hidfile,
+            &groups,
+            Layouts::from_dir(PathBuf::from("layouts")),
+        );
+        println!("Wrote {:?}", hidfile);
+
+        let deffile = env::current_dir().unwrap().join(&args.kiibohd.def_output);
+        kll_compiler::emitters::kiibohd::write_defines(&deffile, &groups);
+        println!("Wrote {:?}", deffile);
+
+        let jsonfile = env::current_dir().unwrap().join(&args.kiibohd.json_output);
+        kll_compiler::emitters::kiibohd::write_json(&jsonfile, &groups);
+        println!("Wrote {:?}", jsonfile);
     }
 }
+
+/// Builds the kll-core datastructures without emitting any files
+/// Semantic validation (kllcore::verify) has already been run
+fn check(groups: &KllGroups) -> Result<(), kllcore::Error> {
+    let layouts = Layouts::from_dir(PathBuf::from("layouts"));
+    let mut layers = groups.layers();
+    kllcore::KllCoreData::new(&mut layers, layouts)?;
+    Ok(())
+}
@@ -39,6 +39,13 @@ impl KLLParser {
     fn number(input: Node) -> Result<usize> {
         Ok(parse_int(input.as_str()))
     }
+    fn signed_number(input: Node) -> Result<isize> {
+        let text = input.as_str();
+        Ok(match text.strip_prefix('-') {
+            Some(text) => -(parse_int(text) as isize),
+            None => parse_int(text) as isize,
+        })
+    }
     fn range(input: Node) -> Result<(usize, usize)> {
         Ok(match_nodes!(input.into_children();
             [number(start)] => (start, start),
@@ -233,9 +240,50 @@ impl KLLParser {
             [number(bank), number(index), number(arg)] => (bank, index, Some(arg)),
         ))
     }
+    fn analog_kind(input: Node) -> Result<AnalogKind> {
+        AnalogKind::from_str(input.as_str())
+            .map_err(|_| input.error(format!("Unknown analog kind {}", input.as_str())))
+    }
+    fn analog(input: Node) -> Result<TriggerType> {
+        Ok(match_nodes!(input.into_children();
+            [analog_kind(kind), number(index), signed_number(value)] => TriggerType::Analog {
+                kind,
+                index,
+                value,
+            },
+        ))
+    }
+    fn rotation_direction(input: Node) -> Result<isize> {
+        Ok(match input.as_str() {
+            "CW" => 1,
+            "CCW" => -1,
+            _ => match_nodes!(input.into_children();
+                [signed_number(direction)] => direction,
+            ),
+        })
+    }
+    fn rotation(input: Node) -> Result<TriggerType> {
+        let node = input.clone();
+        let (index, direction) = match_nodes!(input.into_children();
+            [number(index), rotation_direction(direction)] => (index, direction),
+        );
+        // kll-core stores rotation triggers as u8 index and i8 direction
+        if u8::try_from(index).is_err() {
+            return Err(node.error(format!("Rotation index {} is out of range (0-255)", index)));
+        }
+        if i8::try_from(direction).is_err() {
+            return Err(node.error(format!(
+                "Rotation direction {} is out of range (-128-127)",
+                direction
+            )));
+        }
+        Ok(TriggerType::Rotation { index, direction })
+    }
     fn trigger_type(input: Node) -> Result<TriggerType> {
         Ok(match_nodes!(input.into_children();
             [name(name)] => TriggerType::Animation(name),
+            [analog(trigger)] => trigger,
+            [rotation(trigger)] => trigger,
             [key(trigger)] => TriggerType::Key(trigger),
             [layer(trigger)] => TriggerType::Layer(trigger),
             [indicator(trigger)] => TriggerType::Indicator(trigger),
@@ -346,7 +394,7 @@ impl KLLParser {
                 let pixel = PixelDef::new(channelmap, Some(scancode));
                 Statement::Pixelmap((vec![ Range { start, end } ], pixel))
             },
-            [pixel(indices), kvmap(channelmap), _none] => {
+            [pixel(indices), kvmap(channelmap), none(_)] => {
                 let pixel = PixelDef::new(channelmap, None);
                 Statement::Pixelmap((indices, pixel))
             },
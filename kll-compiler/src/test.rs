@@ -1,6 +1,6 @@
 #[cfg(test)]
 mod trivial {
-    use crate::types::KllFile;
+    use crate::types::{AnalogKind, KllFile, Mapping, Statement, TriggerType};
 
     #[test]
     fn define() {
@@ -34,6 +34,50 @@ mod trivial {
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn analog() {
+        let result = dbg!(KllFile::from_str("A[Distance, 0x12, -50] : U\"A\";\n"));
+        match &result.unwrap().statements[0] {
+            Statement::Keymap(Mapping(triggers, _, _)) => {
+                assert_eq!(
+                    triggers.iter().next().unwrap().trigger,
+                    TriggerType::Analog {
+                        kind: AnalogKind::Distance,
+                        index: 0x12,
+                        value: -50,
+                    }
+                );
+            }
+            statement => panic!("Unexpected statement: {:?}", statement),
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        for (test, direction) in [("R[1, CW]", 1), ("R[1, CCW]", -1), ("R[1, -1]", -1)] {
+            let text = format!("{} : U\"VolumeUp\";\n", test);
+            let result = KllFile::from_str(&text);
+            match &result.unwrap().statements[0] {
+                Statement::Keymap(Mapping(triggers, _, _)) => {
+                    assert_eq!(
+                        triggers.iter().next().unwrap().trigger,
+                        TriggerType::Rotation {
+                            index: 1,
+                            direction,
+                        }
+                    );
+                }
+                statement => panic!("Unexpected statement: {:?}", statement),
+            }
+        }
+
+        // Index and direction must fit in kll-core's u8/i8
+        for test in ["R[256, CW]", "R[1, 128]", "R[1, -129]"] {
+            let text = format!("{} : U\"VolumeUp\";\n", test);
+            assert!(KllFile::from_str(&text).is_err(), "{}", test);
+        }
+    }
+
     #[test]
     fn pixelmap() {
         let result = dbg!(KllFile::from_str("P[5](30:8) : S13;\n"));
@@ -210,7 +254,9 @@ mod examples {
 
 #[cfg(test)]
 mod processing {
-    use crate::types::{KllFile, Statement};
+    use crate::types::{Key, KllFile, PixelColor, Statement, TriggerType};
+    use crate::KllDatastore;
+    use std::path::Path;
 
     #[test]
     fn scancode_implied_state() {
@@ -223,4 +269,182 @@ mod processing {
             _ => {}
         }
     }
+
+    #[test]
+    fn recommended_sizes() {
+        let result = KllFile::from_str("S10 : U\"A\";\nS11 : U\"B\";\nS12 : U\"C\";\n");
+        let state = result.unwrap().into_struct();
+        let kll_data = KllDatastore::new(&state);
+        let sizes = KllDatastore::recommended_sizes(&[kll_data.clone()]);
+        assert_eq!(sizes.layout_size, 4);
+        assert_eq!(sizes.state_size, 4);
+        assert_eq!(sizes.max_active_triggers, 4);
+
+        // Each layer has its own lookup entries, identical mappings share their state entry
+        let result = KllFile::from_str(
+            "S10 : U\"A\";\nS11 : U\"D\";\nS12 : U\"E\";\nS13 : U\"F\";\nS14 : U\"G\";\nS15 : U\"H\";\n",
+        );
+        let layer = result.unwrap().into_struct();
+        let layer_data = KllDatastore::new(&layer);
+        let sizes = KllDatastore::recommended_sizes(&[kll_data, layer_data]);
+        assert_eq!(sizes.layout_size, 16);
+        assert_eq!(sizes.state_size, 8);
+        assert_eq!(sizes.max_active_triggers, 8);
+    }
+
+    #[test]
+    fn duplicate_scancodes() {
+        let result = KllFile::from_str("S10 : U\"A\";\nS11 : U\"B\";\nS10 : U\"C\";\n");
+        let state = result.unwrap().into_struct();
+        assert_eq!(state.duplicate_scancodes(), vec![10]);
+
+        // Explicit replace is not a duplicate
+        let result = KllFile::from_str("S10 : U\"A\";\nS10 :: U\"C\";\n");
+        let state = result.unwrap().into_struct();
+        assert!(state.duplicate_scancodes().is_empty());
+    }
+
+    #[test]
+    fn merge_conflicts() {
+        let mut state = KllFile::from_str("myCap => myFunc(arg1:1);\nmyDefine => myCDefine;\n")
+            .unwrap()
+            .into_struct();
+        let other = KllFile::from_str("myCap => myOtherFunc(arg1:1);\nmyDefine => myCDefine;\n")
+            .unwrap()
+            .into_struct();
+
+        // Only the capability was redefined with a different value
+        let conflicts = state.merge_with_conflicts(&other, Path::new("other.kll"));
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].kind, "capability");
+        assert_eq!(conflicts[0].name, "myCap");
+        assert_eq!(conflicts[0].source, Path::new("other.kll"));
+        assert_ne!(conflicts[0].old, conflicts[0].new);
+
+        // merge() keeps the first definition
+        assert_eq!(state.capabilities["myCap"].function, "myFunc");
+    }
+
+    #[test]
+    fn reduce_unmapped_usb() {
+        let base = KllFile::from_str("S10 : U\"A\";\n").unwrap().into_struct();
+
+        // Mapped USB code triggers are reduced to scancodes
+        let partial = KllFile::from_str("U\"A\" : U\"C\";\n")
+            .unwrap()
+            .into_struct();
+        let keymap = partial.reduce(base.clone()).unwrap();
+        assert_eq!(keymap.len(), 1);
+        assert_eq!(
+            keymap[0].0.iter().next().unwrap().trigger,
+            TriggerType::Key(Key::Scancode(10))
+        );
+
+        // U"B" has no scancode in the base map
+        let partial = KllFile::from_str("U\"A\" : U\"C\";\nU\"B\" : U\"D\";\n")
+            .unwrap()
+            .into_struct();
+        let err = partial.reduce(base).unwrap_err();
+        assert_eq!(err.usb, "B");
+        assert!(err.mapping.contains("U\"D\""));
+        assert!(err.to_string().contains("U\"B\""));
+    }
+
+    #[test]
+    fn reduce_sort_order() {
+        let base = KllFile::from_str("S2 : U\"A\";\nS10 : U\"B\";\n")
+            .unwrap()
+            .into_struct();
+        let partial = KllFile::from_str(
+            "Layer[1] : U\"C\";\nU\"B\"(R) : U\"D\";\nU\"B\"(P) : U\"D\";\nU\"A\" : U\"E\";\n",
+        )
+        .unwrap()
+        .into_struct();
+
+        // Display order would put Layer[1] first
+        let keymap = partial.reduce(base).unwrap();
+        let triggers = keymap
+            .iter()
+            .map(|mapping| mapping.0.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(triggers, vec!["S2", "S10(P)", "S10(R)", "Layer[1-1]"]);
+    }
+
+    #[test]
+    fn capability_mismatches() {
+        let result = KllFile::from_str(
+            "myCap => myFunc(arg1:1, arg2:2);\nS1 : myCap(1, 2);\nS2 : myCpa(1, 2);\nS3 : myCap(1);\n",
+        );
+        let state = result.unwrap().into_struct();
+        let mismatches = state.capability_mismatches();
+        assert_eq!(mismatches.len(), 2);
+
+        // Misspelled name
+        assert_eq!(mismatches[0].name, "myCpa");
+        assert_eq!(mismatches[0].expected, None);
+        assert_eq!(mismatches[0].index, 1);
+        assert!(mismatches[0]
+            .to_string()
+            .contains("myCpa is not a defined capability"));
+
+        // Wrong number of arguments
+        assert_eq!(mismatches[1].name, "myCap");
+        assert_eq!(mismatches[1].expected, Some(2));
+        assert_eq!(mismatches[1].found, 1);
+        assert_eq!(mismatches[1].index, 2);
+    }
+
+    #[test]
+    fn position_map() {
+        let result = KllFile::from_str("S10 : U\"A\";\nS11 : U\"B\";\nS10 <= x:20, rx:15;\n");
+        let state = result.unwrap().into_struct();
+        let position_map = state.position_map();
+
+        // Only S10 has a position
+        assert_eq!(position_map.len(), 1);
+        let pos = position_map.get(&10).unwrap();
+        assert_eq!(pos.x, 20.0);
+        assert_eq!(pos.rx, 15.0);
+        assert_eq!(pos.y, 0.0);
+    }
+
+    #[test]
+    fn animation_frame_ranges() {
+        // Frame indices and ranges are inclusive
+        let test = "A[Glow, 1] <= P[0](1, 1, 1);\nA[Glow, 3-4] <= P[0](2, 2, 2);\n";
+        let state = KllFile::from_str(test).unwrap().into_struct();
+        let frames = &state.animations["Glow"].frames;
+        assert_eq!(frames.len(), 5);
+        assert!(frames[0].is_empty());
+        assert_eq!(frames[1][0].channel_values[0], PixelColor::Rgb(1));
+        assert!(frames[2].is_empty());
+        assert_eq!(frames[3][0].channel_values[0], PixelColor::Rgb(2));
+        assert_eq!(frames[4][0].channel_values[0], PixelColor::Rgb(2));
+    }
+
+    #[test]
+    fn animation_interpolation() {
+        let test = "A[Fade] <= interp:linear, fps:2;\nA[Fade, 0] <= P[0](0, 0, 0);\nA[Fade, 1] <= P[0](100, 50, 10);\n";
+        let state = KllFile::from_str(test).unwrap().into_struct();
+
+        let frames = state.animations["Fade"].interpolated_frames();
+        let channels = |frame: usize| frames[frame][0].channel_values.clone();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            channels(0),
+            vec![PixelColor::Rgb(0), PixelColor::Rgb(0), PixelColor::Rgb(0)]
+        );
+        assert_eq!(
+            channels(1),
+            vec![PixelColor::Rgb(50), PixelColor::Rgb(25), PixelColor::Rgb(5)]
+        );
+        assert_eq!(
+            channels(2),
+            vec![
+                PixelColor::Rgb(100),
+                PixelColor::Rgb(50),
+                PixelColor::Rgb(10)
+            ]
+        );
+    }
 }
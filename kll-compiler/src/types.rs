@@ -7,6 +7,8 @@ use std::str::FromStr;
 pub type Index = Range<usize>;
 pub type Indices = Vec<Index>;
 pub type Map<'a> = HashMap<&'a str, &'a str>;
+/// Unicode string -> unicode string table index
+pub type UnicodeStringLookup = HashMap<String, u16>;
 
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -61,6 +63,24 @@ impl<'a> Mapping<'a> {
             None
         }
     }
+
+    /// Structured sort key: trigger scancodes, then trigger states, then results
+    /// Scancode triggers sort numerically before any other trigger type.
+    /// Non-scancode fields use the derived Debug representation so the order does not depend
+    /// on Display formatting.
+    pub fn sort_key(&self) -> (Vec<(usize, String)>, Vec<String>, String) {
+        let triggers = self
+            .0
+            .iter()
+            .map(|t| match &t.trigger {
+                TriggerType::Key(Key::Scancode(s)) => (*s, String::new()),
+                trigger => (usize::MAX, format!("{:?}", trigger)),
+            })
+            .collect();
+        let states = self.0.iter().map(|t| format!("{:?}", t.state)).collect();
+        let results = format!("{:?}", self.2 .0);
+        (triggers, states, results)
+    }
 }
 
 impl<'a> fmt::Display for Mapping<'a> {
@@ -69,7 +89,7 @@ impl<'a> fmt::Display for Mapping<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct TriggerList<'a>(pub Vec<Vec<Trigger<'a>>>);
 
 impl<'a> TriggerList<'a> {
@@ -135,7 +155,7 @@ impl<'a> fmt::Display for TriggerList<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct ResultList<'a>(pub Vec<Vec<Action<'a>>>);
 
 impl<'a> ResultList<'a> {
@@ -144,7 +164,12 @@ impl<'a> ResultList<'a> {
     }
 
     /// Converts the ResultList into a kll-core result guide
-    pub fn kll_core_guide(&self, layouts: Layouts) -> Vec<u8> {
+    /// unicode_strings is used to lookup the unicode string table index of any unicode text
+    pub fn kll_core_guide(
+        &self,
+        layouts: Layouts,
+        unicode_strings: &UnicodeStringLookup,
+    ) -> Vec<u8> {
         let mut buf = Vec::new();
         for combo in &self.0 {
             // Push the length of the combo
@@ -152,7 +177,10 @@ impl<'a> ResultList<'a> {
             // Push each combo element
             for elem in combo {
                 unsafe {
-                    buf.extend_from_slice(elem.kll_core_condition(layouts.clone()).bytes());
+                    buf.extend_from_slice(
+                        elem.kll_core_condition(layouts.clone(), unicode_strings)
+                            .bytes(),
+                    );
                 }
             }
         }
@@ -343,6 +371,103 @@ pub struct Animation<'a> {
     pub frames: Vec<Vec<Pixel<'a>>>,
 }
 
+/// Frame interpolation mode between animation keyframes
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Interpolation {
+    /// Stepwise (keyframes only)
+    None,
+    /// Linear interpolation between keyframes
+    Linear,
+}
+
+impl<'a> Animation<'a> {
+    /// Frame interpolation mode (interp:linear)
+    pub fn interpolation(&self) -> Interpolation {
+        match self.modifiers.get("interp") {
+            Some(&"linear") => Interpolation::Linear,
+            _ => Interpolation::None,
+        }
+    }
+
+    /// Number of frames generated per keyframe step when interpolating (fps:<n>)
+    /// Defaults to 1 (keyframes only)
+    pub fn fps(&self) -> usize {
+        self.modifiers
+            .get("fps")
+            .and_then(|fps| fps.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1)
+    }
+
+    /// Animation frames to emit
+    /// With interpolation enabled (interp:linear), fps frames are generated for each keyframe step.
+    /// Keyframes are the defined frames of the animation (e.g. A[Name, 0] and A[Name, 2] are two
+    /// keyframe steps apart). Only absolute (Rgb) channel values of the same pixel are interpolated,
+    /// any other pixel or channel holds the previous keyframe value until the next keyframe.
+    pub fn interpolated_frames(&self) -> Vec<Vec<Pixel<'a>>> {
+        if self.interpolation() == Interpolation::None {
+            return self.frames.clone();
+        }
+        let fps = self.fps();
+
+        // Keyframes are the non-empty frames
+        let keyframes: Vec<(usize, &Vec<Pixel>)> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter(|(_, frame)| !frame.is_empty())
+            .collect();
+
+        let mut frames = Vec::new();
+        for pair in keyframes.windows(2) {
+            let (start_index, start) = pair[0];
+            let (end_index, end) = pair[1];
+            let steps = (end_index - start_index) * fps;
+
+            for step in 0..steps {
+                let frame = start
+                    .iter()
+                    .map(|pixel| {
+                        // Find the same pixel in the next keyframe
+                        let next = match end.iter().find(|p| p.range == pixel.range) {
+                            Some(next) => next,
+                            None => {
+                                return pixel.clone();
+                            }
+                        };
+                        let channel_values = pixel
+                            .channel_values
+                            .iter()
+                            .zip(next.channel_values.iter())
+                            .map(|(a, b)| match (a, b) {
+                                (PixelColor::Rgb(a), PixelColor::Rgb(b)) => {
+                                    let (a, b) = (*a as isize, *b as isize);
+                                    PixelColor::Rgb(
+                                        (a + (b - a) * step as isize / steps as isize) as usize,
+                                    )
+                                }
+                                _ => a.clone(),
+                            })
+                            .collect();
+                        Pixel {
+                            range: pixel.range.clone(),
+                            channel_values,
+                        }
+                    })
+                    .collect();
+                frames.push(frame);
+            }
+        }
+
+        // Final keyframe
+        if let Some((_, last)) = keyframes.last() {
+            frames.push(last.to_vec());
+        }
+
+        frames
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq, Hash, Clone)]
 pub struct Capability<'a> {
     pub function: &'a str,
@@ -406,6 +531,41 @@ impl<'a> fmt::Display for LayerMode {
     }
 }
 
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum AnalogKind {
+    Distance,
+    Velocity,
+    Acceleration,
+    Jerk,
+}
+
+impl FromStr for AnalogKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Distance" => Self::Distance,
+            "Velocity" => Self::Velocity,
+            "Acceleration" => Self::Acceleration,
+            "Jerk" => Self::Jerk,
+            _ => {
+                return Err(Error::UnknownMatch { s: s.to_string() });
+            }
+        })
+    }
+}
+
+impl fmt::Display for AnalogKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Distance => write!(f, "Distance"),
+            Self::Velocity => write!(f, "Velocity"),
+            Self::Acceleration => write!(f, "Acceleration"),
+            Self::Jerk => write!(f, "Jerk"),
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub enum TriggerType<'a> {
     Key(Key<'a>),
@@ -413,6 +573,19 @@ pub enum TriggerType<'a> {
     Indicator(Indices),
     Generic((usize, usize, Option<usize>)),
     Animation(&'a str),
+    /// Analog threshold trigger, e.g. A[Distance, 0x12, 50]
+    /// Units depend on the keyboard
+    Analog {
+        kind: AnalogKind,
+        index: usize,
+        value: isize,
+    },
+    /// Rotary encoder step, e.g. R[0, CW] or R[0, -1]
+    /// Direction is +1 (CW) or -1 (CCW)
+    Rotation {
+        index: usize,
+        direction: isize,
+    },
 }
 
 impl<'a> fmt::Display for TriggerType<'a> {
@@ -435,6 +608,10 @@ impl<'a> fmt::Display for TriggerType<'a> {
                 }
             }
             Self::Animation(name) => write!(f, "A[{}]", name),
+            Self::Analog { kind, index, value } => {
+                write!(f, "A[{}, {}, {}]", kind, index, value)
+            }
+            Self::Rotation { index, direction } => write!(f, "R[{}, {}]", index, direction),
         }
     }
 }
@@ -458,6 +635,40 @@ impl<'a> Trigger<'a> {
     /// TODO ^ Use a kll-compiler function to automatically duplicate so we don't have to do it
     /// here.
     pub fn kll_core_condition(&self) -> kll_core::TriggerCondition {
+        // Analog and rotation triggers do not use state scheduling
+        if let TriggerType::Rotation { index, direction } = &self.trigger {
+            return kll_core::TriggerCondition::Rotation {
+                index: u8::try_from(*index).expect("Rotation index out of range"),
+                loop_condition_index: 0, // TODO
+                position: i8::try_from(*direction).expect("Rotation direction out of range"),
+            };
+        }
+        if let TriggerType::Analog { kind, index, value } = &self.trigger {
+            let (index, val) = (*index as u16, *value as i16);
+            return match kind {
+                AnalogKind::Distance => kll_core::TriggerCondition::AnalogDistance {
+                    reserved: 0,
+                    index,
+                    val,
+                },
+                AnalogKind::Velocity => kll_core::TriggerCondition::AnalogVelocity {
+                    reserved: 0,
+                    index,
+                    val,
+                },
+                AnalogKind::Acceleration => kll_core::TriggerCondition::AnalogAcceleration {
+                    reserved: 0,
+                    index,
+                    val,
+                },
+                AnalogKind::Jerk => kll_core::TriggerCondition::AnalogJerk {
+                    reserved: 0,
+                    index,
+                    val,
+                },
+            };
+        }
+
         // State must be defined
         // generate_state_scheduling() function can be used to compute if
         // it's not defined.
@@ -515,6 +726,7 @@ impl<'a> Trigger<'a> {
                 }
                 */
             }
+            TriggerType::Analog { .. } | TriggerType::Rotation { .. } => unreachable!(),
         }
     }
 
@@ -524,7 +736,13 @@ impl<'a> Trigger<'a> {
     ///    S1(R) : U"A"(R);
     fn implied_state(&self) -> Option<Vec<Self>> {
         // No state (implied state), generate new triggers
-        if self.state.is_none() {
+        // Analog and rotation triggers have no press/release state
+        if self.state.is_none()
+            && !matches!(
+                self.trigger,
+                TriggerType::Analog { .. } | TriggerType::Rotation { .. }
+            )
+        {
             Some(vec![
                 Self {
                     trigger: self.trigger.clone(),
@@ -792,7 +1010,12 @@ pub struct Action<'a> {
 
 impl<'a> Action<'a> {
     /// Converts to a kll-core Capability definition
-    pub fn kll_core_condition(&self, mut layouts: Layouts) -> kll_core::Capability {
+    /// unicode_strings is used to lookup the unicode string table index of any unicode text
+    pub fn kll_core_condition(
+        &self,
+        mut layouts: Layouts,
+        unicode_strings: &UnicodeStringLookup,
+    ) -> kll_core::Capability {
         // State must be defined
         // generate_state_scheduling() function can be used to compute if
         // it's not defined.
@@ -836,8 +1059,20 @@ impl<'a> Action<'a> {
             ResultType::Text(_text) => {
                 panic!("Incomplete {:?}", &self.result);
             }
-            ResultType::UnicodeText(_text) => {
-                panic!("Incomplete {:?}", &self.result);
+            ResultType::UnicodeText(text) => {
+                let index = match unicode_strings.get(*text) {
+                    Some(index) => *index,
+                    None => {
+                        panic!("{:?} is missing from the unicode string table.", text);
+                    }
+                };
+                kll_core::Capability::HidioUnicodeString {
+                    state: self.state.as_ref().unwrap().states[0]
+                        .kind
+                        .capability_state(),
+                    loop_condition_index: 0, // TODO
+                    index,
+                }
             }
             ResultType::NOP => kll_core::Capability::NoOp {
                 state: kll_core::CapabilityState::None,
@@ -976,6 +1211,12 @@ impl<'a> PixelRange<'a> {
                 "i" => pos.index = Some(PixelAddr::from_str(v)?),
                 "r" => pos.row = Some(PixelAddr::from_str(v)?),
                 "c" => pos.col = Some(PixelAddr::from_str(v)?),
+                // Bare pixel index, e.g. P[4]
+                _ if v.is_empty() => {
+                    if let Ok(index) = k.parse::<usize>() {
+                        pos.index = Some(PixelAddr::Absolute(index));
+                    }
+                }
                 _ => {}
             }
         }
@@ -0,0 +1,70 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+// ----- Functions -----
+
+/// Runs kll-compiler in check mode (--emitter none)
+fn check(config: &Path, default: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kll-compiler"))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args(["--emitter", "none", "--config"])
+        .arg(config)
+        .arg("--default")
+        .arg(default)
+        .output()
+        .expect("failed to run kll-compiler")
+}
+
+/// Writes a temporary .kll file
+fn write_kll(name: &str, contents: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("kll-compiler-check-{}.kll", name));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+// ----- Tests -----
+
+#[test]
+fn check_good() {
+    let config = PathBuf::from("examples/kllcoretest.kll");
+    let output = check(&config, &config);
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn check_duplicate_scancode() {
+    let config = PathBuf::from("examples/kllcoretest.kll");
+    let default = write_kll("duplicate", "S0x01 : U\"A\";\nS0x01 : U\"B\";\n");
+    let output = check(&config, &default);
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("DuplicateScancode"));
+}
+
+#[test]
+fn check_unknown_capability() {
+    let config = PathBuf::from("examples/kllcoretest.kll");
+    let default = write_kll(
+        "capability",
+        "myCap => myFunc(arg1:1);\nS0x01 : myCpa(1);\n",
+    );
+    let output = check(&config, &default);
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("UnknownCapability"));
+}
+
+#[test]
+fn check_syntax_error() {
+    let config = PathBuf::from("examples/kllcoretest.kll");
+    let default = write_kll("syntax", "S0x01 : U\"A\"\n");
+    let output = check(&config, &default);
+    assert!(!output.status.success(), "{:?}", output);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Error"));
+}
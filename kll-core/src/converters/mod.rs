@@ -120,6 +120,16 @@ mod convert {
                     state: state.event(tevent),
                     id,
                 },
+                Capability::LayerTap {
+                    state,
+                    layer,
+                    tap_key,
+                    ..
+                } => CapabilityRun::LayerTap {
+                    state: state.event(tevent),
+                    layer,
+                    tap_key,
+                },
                 _ => {
                     panic!("Capability to CapabilityRun not implemented! {:?}", cap);
                 }
@@ -0,0 +1,506 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Host-side guide encoding and decoding
+//! Builds and reverses the kll_macros (and KLL compiler) guide and layer lookup layout, mainly
+//! useful for build scripts and tests
+
+use std::vec::Vec;
+
+use crate::{Capability, TriggerCondition};
+
+/// Builds a single trigger or result guide without the kll_macros proc-macros
+/// Output is byte-identical to trigger_guide! / result_guide! (e.g. for build.rs generation)
+///
+/// ```
+/// use kll_core::guide::GuideBuilder;
+/// use kll_core::{trigger, TriggerCondition};
+///
+/// let mut builder = GuideBuilder::new();
+/// builder.push_trigger_combo(&[TriggerCondition::Switch {
+///     state: trigger::Phro::Press,
+///     index: 15,
+///     loop_condition_index: 0,
+/// }]);
+/// assert_eq!(builder.finish(), [1, 1, 1, 15, 0, 0, 0, 0]);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GuideBuilder {
+    bytes: Vec<u8>,
+}
+
+impl GuideBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a combo of TriggerConditions to the sequence
+    pub fn push_trigger_combo(&mut self, combo: &[TriggerCondition]) -> &mut Self {
+        const SIZE: usize = core::mem::size_of::<TriggerCondition>();
+
+        self.bytes.push(combo.len() as u8);
+        for cond in combo {
+            let bytes = unsafe { cond.bytes() };
+            self.push_elem(&bytes[..trigger_condition_len(cond)], SIZE);
+        }
+        self
+    }
+
+    /// Appends a combo of Capabilities to the sequence
+    pub fn push_result_combo(&mut self, combo: &[Capability]) -> &mut Self {
+        const SIZE: usize = core::mem::size_of::<Capability>();
+
+        self.bytes.push(combo.len() as u8);
+        for cap in combo {
+            let bytes = unsafe { cap.bytes() };
+            self.push_elem(&bytes[..capability_len(cap)], SIZE);
+        }
+        self
+    }
+
+    /// Terminates the sequence with a 0-length combo and returns the guide
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0);
+        self.bytes
+    }
+
+    /// Only the used bytes are copied, the remainder is zero padded
+    /// This prevents undefined (padding) bytes from ending up in the guide
+    fn push_elem(&mut self, bytes: &[u8], size: usize) {
+        self.bytes.extend_from_slice(bytes);
+        self.bytes.resize(self.bytes.len() + size - bytes.len(), 0);
+    }
+}
+
+/// Number of used bytes for each TriggerCondition (must match kll_macros::trigger_guide)
+fn trigger_condition_len(cond: &TriggerCondition) -> usize {
+    match cond {
+        TriggerCondition::None => 1,
+        TriggerCondition::Sleep { .. }
+        | TriggerCondition::Resume { .. }
+        | TriggerCondition::Inactive { .. }
+        | TriggerCondition::Active { .. } => 4,
+        TriggerCondition::HidLed { .. }
+        | TriggerCondition::Layer { .. }
+        | TriggerCondition::Rotation { .. } => 5,
+        TriggerCondition::Switch { .. }
+        | TriggerCondition::AnalogDistance { .. }
+        | TriggerCondition::AnalogVelocity { .. }
+        | TriggerCondition::AnalogAcceleration { .. }
+        | TriggerCondition::AnalogJerk { .. }
+        | TriggerCondition::Animation { .. } => 6,
+    }
+}
+
+/// Number of used bytes for each Capability (must match kll_macros::result_guide)
+fn capability_len(cap: &Capability) -> usize {
+    match cap {
+        Capability::NoOp { .. }
+        | Capability::LayerClear { .. }
+        | Capability::McuFlashMode { .. } => 4,
+        Capability::HidKeyboard { .. }
+        | Capability::HidMouseButton { .. }
+        | Capability::HidProtocol { .. }
+        | Capability::HidSystemControl { .. }
+        | Capability::LayerRotate { .. }
+        | Capability::PixelAnimationControl { .. }
+        | Capability::PixelFadeLayer { .. }
+        | Capability::PixelGammaControl { .. } => 5,
+        Capability::HidioOpenUrl { .. }
+        | Capability::HidioUnicodeString { .. }
+        | Capability::HidConsumerControl { .. }
+        | Capability::HidKeyboardState { .. }
+        | Capability::HidMouseWheel { .. }
+        | Capability::LayerState { .. }
+        | Capability::LayerTap { .. }
+        | Capability::PixelAnimationIndex { .. }
+        | Capability::PixelLedControl { .. }
+        | Capability::Rotate { .. } => 6,
+        Capability::HidKeyboardOs { .. }
+        | Capability::HidKeyboardRepeat { .. }
+        | Capability::PixelFadeControl { .. }
+        | Capability::PixelFadeSet { .. }
+        | Capability::PixelTest { .. } => 7,
+        Capability::HidioUnicodeState { .. } | Capability::HidMouseMove { .. } => 8,
+    }
+}
+
+/// Decodes a single trigger guide into a sequence of TriggerCondition combos
+/// Decoding stops at the 0-length combo that ends the sequence
+///
+/// Panics if the guide is truncated
+pub fn decode_trigger_guide(bytes: &[u8]) -> Vec<Vec<TriggerCondition>> {
+    const SIZE: usize = core::mem::size_of::<TriggerCondition>();
+
+    let mut sequence = Vec::new();
+    let mut pos = 0;
+    while let Some(count) = bytes.get(pos) {
+        let count = *count as usize;
+        if count == 0 {
+            break;
+        }
+        pos += 1;
+
+        let mut combo = Vec::new();
+        for _ in 0..count {
+            let elem: [u8; SIZE] = bytes[pos..pos + SIZE].try_into().unwrap();
+            combo.push(unsafe { TriggerCondition::from_byte_array(elem) });
+            pos += SIZE;
+        }
+        sequence.push(combo);
+    }
+    sequence
+}
+
+/// Decodes a single result guide into a sequence of Capability combos
+/// Decoding stops at the 0-length combo that ends the sequence
+/// Each Capability uses a full 8-byte slot, smaller Capabilities are zero padded
+///
+/// Panics if the guide is truncated
+pub fn decode_result_guide(bytes: &[u8]) -> Vec<Vec<Capability>> {
+    const SIZE: usize = core::mem::size_of::<Capability>();
+
+    let mut sequence = Vec::new();
+    let mut pos = 0;
+    while let Some(count) = bytes.get(pos) {
+        let count = *count as usize;
+        if count == 0 {
+            break;
+        }
+        pos += 1;
+
+        let mut combo = Vec::new();
+        for _ in 0..count {
+            let elem: [u8; SIZE] = bytes[pos..pos + SIZE].try_into().unwrap();
+            combo.push(unsafe { Capability::from_byte_array(elem) });
+            pos += SIZE;
+        }
+        sequence.push(combo);
+    }
+    sequence
+}
+
+/// Decodes a layer lookup (layer_lookup!) into (layer, ttype, index, triggers) entries
+/// Entries are returned in order, including entries without any triggers
+///
+/// Panics if the layer lookup is truncated
+pub fn decode_layer_lookup(bytes: &[u8]) -> Vec<(u8, u8, u16, Vec<u16>)> {
+    let mut entries = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let layer = bytes[pos];
+        let ttype = bytes[pos + 1];
+        let index = u16::from_le_bytes([bytes[pos + 2], bytes[pos + 3]]);
+        let count = bytes[pos + 4] as usize;
+        pos += 5;
+
+        let triggers = bytes[pos..pos + count * 2]
+            .chunks_exact(2)
+            .map(|trigger| u16::from_le_bytes([trigger[0], trigger[1]]))
+            .collect();
+        pos += count * 2;
+
+        entries.push((layer, ttype, index, triggers));
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::guide::{
+        decode_layer_lookup, decode_result_guide, decode_trigger_guide, GuideBuilder,
+    };
+    use crate::layout::LayerLookup;
+    use crate::{layer, trigger, Capability, CapabilityState, TriggerCondition};
+    use std::vec;
+
+    #[test]
+    fn trigger_guide_round_trip() {
+        const TRIGGER_GUIDE: &[u8] = kll_macros::trigger_guide!([
+            [
+                TriggerCondition::Switch {
+                    state: trigger::Phro::Press,
+                    index: 6,
+                    loop_condition_index: 0,
+                },
+                TriggerCondition::Layer {
+                    state: trigger::LayerState::ShiftActivate,
+                    layer: 3,
+                    loop_condition_index: 0,
+                },
+            ],
+            [TriggerCondition::AnalogDistance {
+                reserved: 0,
+                index: 8,
+                val: 1500,
+            },]
+        ]);
+
+        assert_eq!(
+            decode_trigger_guide(TRIGGER_GUIDE),
+            [
+                vec![
+                    TriggerCondition::Switch {
+                        state: trigger::Phro::Press,
+                        index: 6,
+                        loop_condition_index: 0,
+                    },
+                    TriggerCondition::Layer {
+                        state: trigger::LayerState::ShiftActivate,
+                        layer: 3,
+                        loop_condition_index: 0,
+                    },
+                ],
+                vec![TriggerCondition::AnalogDistance {
+                    reserved: 0,
+                    index: 8,
+                    val: 1500,
+                }],
+            ]
+        );
+    }
+
+    #[test]
+    fn result_guide_round_trip() {
+        const RESULT_GUIDE: &[u8] = kll_macros::result_guide!([
+            // Press Shift + A; Release Shift; Release A
+            [
+                Capability::HidKeyboard {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    id: kll_hid::Keyboard::LeftShift,
+                },
+                Capability::HidKeyboard {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    id: kll_hid::Keyboard::A,
+                },
+            ],
+            [Capability::LayerState {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                layer: 2,
+                layer_state: layer::State::Shift,
+            },],
+            [Capability::NoOp {
+                state: CapabilityState::None,
+                loop_condition_index: 0,
+            },]
+        ]);
+
+        assert_eq!(
+            decode_result_guide(RESULT_GUIDE),
+            [
+                vec![
+                    Capability::HidKeyboard {
+                        state: CapabilityState::Initial,
+                        loop_condition_index: 0,
+                        id: kll_hid::Keyboard::LeftShift,
+                    },
+                    Capability::HidKeyboard {
+                        state: CapabilityState::Initial,
+                        loop_condition_index: 0,
+                        id: kll_hid::Keyboard::A,
+                    },
+                ],
+                vec![Capability::LayerState {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    layer: 2,
+                    layer_state: layer::State::Shift,
+                }],
+                vec![Capability::NoOp {
+                    state: CapabilityState::None,
+                    loop_condition_index: 0,
+                }],
+            ]
+        );
+    }
+
+    #[test]
+    fn trigger_guide_builder() {
+        const TRIGGER_GUIDE: &[u8] = kll_macros::trigger_guide!([
+            [
+                TriggerCondition::Switch {
+                    state: trigger::Phro::Press,
+                    index: 6,
+                    loop_condition_index: 0,
+                },
+                TriggerCondition::Layer {
+                    state: trigger::LayerState::ShiftActivate,
+                    layer: 3,
+                    loop_condition_index: 0,
+                },
+            ],
+            [TriggerCondition::AnalogDistance {
+                reserved: 0,
+                index: 8,
+                val: 1500,
+            },]
+        ]);
+
+        let mut builder = GuideBuilder::new();
+        builder
+            .push_trigger_combo(&[
+                TriggerCondition::Switch {
+                    state: trigger::Phro::Press,
+                    index: 6,
+                    loop_condition_index: 0,
+                },
+                TriggerCondition::Layer {
+                    state: trigger::LayerState::ShiftActivate,
+                    layer: 3,
+                    loop_condition_index: 0,
+                },
+            ])
+            .push_trigger_combo(&[TriggerCondition::AnalogDistance {
+                reserved: 0,
+                index: 8,
+                val: 1500,
+            }]);
+        assert_eq!(builder.finish(), TRIGGER_GUIDE);
+    }
+
+    #[test]
+    fn result_guide_builder() {
+        const RESULT_GUIDE: &[u8] = kll_macros::result_guide!([
+            [
+                Capability::HidKeyboard {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    id: kll_hid::Keyboard::LeftShift,
+                },
+                Capability::HidKeyboard {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    id: kll_hid::Keyboard::A,
+                },
+            ],
+            [Capability::LayerState {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                layer: 2,
+                layer_state: layer::State::Shift,
+            },],
+            [Capability::NoOp {
+                state: CapabilityState::None,
+                loop_condition_index: 0,
+            },]
+        ]);
+
+        let mut builder = GuideBuilder::new();
+        builder
+            .push_result_combo(&[
+                Capability::HidKeyboard {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    id: kll_hid::Keyboard::LeftShift,
+                },
+                Capability::HidKeyboard {
+                    state: CapabilityState::Initial,
+                    loop_condition_index: 0,
+                    id: kll_hid::Keyboard::A,
+                },
+            ])
+            .push_result_combo(&[Capability::LayerState {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                layer: 2,
+                layer_state: layer::State::Shift,
+            }])
+            .push_result_combo(&[Capability::NoOp {
+                state: CapabilityState::None,
+                loop_condition_index: 0,
+            }]);
+        assert_eq!(builder.finish(), RESULT_GUIDE);
+    }
+
+    #[test]
+    fn mouse_result_guide_builder() {
+        const RESULT_GUIDE: &[u8] = kll_macros::result_guide!([[
+            Capability::HidMouseButton {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                button: 1,
+            },
+            Capability::HidMouseMove {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                x: -3,
+                y: 300,
+            },
+            Capability::HidMouseWheel {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                vert: 1,
+                horz: -1,
+            },
+        ]]);
+
+        let mut builder = GuideBuilder::new();
+        builder.push_result_combo(&[
+            Capability::HidMouseButton {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                button: 1,
+            },
+            Capability::HidMouseMove {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                x: -3,
+                y: 300,
+            },
+            Capability::HidMouseWheel {
+                state: CapabilityState::Initial,
+                loop_condition_index: 0,
+                vert: 1,
+                horz: -1,
+            },
+        ]);
+        assert_eq!(builder.finish(), RESULT_GUIDE);
+    }
+
+    #[test]
+    fn layer_lookup_round_trip() {
+        #[rustfmt::skip]
+        const LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+            // Layer 0, Switch Type (1), Index 5, No Triggers
+            0, 1, 5, [],
+            // Layer 0, Switch Type (1), Index 6, 2 Triggers: 0 14
+            0, 1, 6, [0, 14],
+            // Layer 1, Layer Type (7), Layer(index) 3, 1 Trigger: A
+            1, 7, 3, [0xA],
+            // Layer 2, AnalogDistance Type (3), Index 0x1234, 1 Trigger: 0x1FF
+            2, 3, 0x1234, [0x1FF],
+        );
+
+        let entries = decode_layer_lookup(LAYER_LOOKUP);
+        assert_eq!(
+            entries,
+            [
+                (0, 1, 5, vec![]),
+                (0, 1, 6, vec![0, 14]),
+                (1, 7, 3, vec![0xA]),
+                (2, 3, 0x1234, vec![0x1FF]),
+            ]
+        );
+
+        // Cross-check against the LayerLookup parser
+        let lookup = LayerLookup::<16>::new(LAYER_LOOKUP, &[], &[], &[], &[]).unwrap();
+        assert_eq!(lookup.max_layers(), 3);
+        for (layer, ttype, index, triggers) in entries {
+            let trigger_list = lookup.trigger_list((layer, ttype, index));
+            if triggers.is_empty() {
+                assert_eq!(trigger_list, None);
+            } else {
+                let bytes: std::vec::Vec<u8> =
+                    triggers.iter().flat_map(|t| t.to_le_bytes()).collect();
+                assert_eq!(trigger_list, Some(&bytes[..]));
+            }
+        }
+    }
+}
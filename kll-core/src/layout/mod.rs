@@ -13,6 +13,11 @@ use super::*;
 use core::cmp::Ordering;
 use heapless::{FnvIndexMap, Vec};
 
+// ----- Constants -----
+
+/// Default LayerTap hold threshold (in time instances)
+pub const DEFAULT_TAP_HOLD_THRESHOLD: u32 = 200;
+
 // ----- Enums -----
 
 #[derive(Copy, Clone, Debug, PartialEq, defmt::Format)]
@@ -42,6 +47,8 @@ enum StateStatus {
         /// This value is set on increment and is set for the first combo eval
         time_instance: u32,
         /// TriggerEvent that initiated the Result Capability
+        /// This is the event that completed the final trigger combo, it is sent to Passthrough
+        /// Capabilities
         event: TriggerEvent,
         /// Next offset in the ResultGuide
         /// This is the offset inside the datastructure so it can be any number
@@ -58,8 +65,27 @@ pub enum ProcessError {
     FailedOffStatePush,
     /// STATE_SIZE is too small
     FailedLookupStateInsert,
+    /// MAX_OVERRIDES is too small
+    FailedOverrideInsert,
     /// MAX_ACTIVE_TRIGGERS is too small
-    FailedTriggerComboEvalStateInsert,
+    /// Includes the guide and event that could not be tracked
+    FailedTriggerComboEvalStateInsert {
+        guide: (u16, u16),
+        event: TriggerEvent,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, defmt::Format)]
+pub enum LayerLookupError {
+    /// LAYOUT_SIZE is too small
+    /// Includes the (layer, ttype, index) key that could not be added
+    LayoutSizeTooSmall { layer: u8, ttype: u8, index: u16 },
+    /// (layer, ttype, index) key is defined more than once
+    DuplicateKey { layer: u8, ttype: u8, index: u16 },
+    /// raw_layer_lookup ends in the middle of an entry
+    Truncated { layer: u8, ttype: u8, index: u16 },
+    /// LayerLookup already has an overlay, only a single overlay is supported
+    OverlayAlreadySet,
 }
 
 // ----- Structs -----
@@ -80,6 +106,11 @@ pub struct LayerState<
     const MAX_ACTIVE_TRIGGERS: usize,
     const MAX_LAYER_STACK_CACHE: usize,
     const MAX_OFF_STATE_LOOKUP: usize,
+    const MAX_OVERRIDES: usize = 2,
+    const MAX_ONE_SHOT: usize = 8,
+    const MAX_LAYER_TAPS: usize = 4,
+    const MAX_AUTOREPEAT: usize = 8,
+    const MAX_COALESCED: usize = 8,
 > {
     /// KLL guide lookup
     layer_lookup: LayerLookup<'a, LAYOUT_SIZE>,
@@ -101,9 +132,13 @@ pub struct LayerState<
     /// (ttype, index) -> (layer index, Layer {layer state, time instance})
     layer_stack_cache: FnvIndexMap<(u8, u16), (u8, Layer), MAX_LAYER_STACK_CACHE>,
     /// Maintains the combo state when evaluating a list of TriggerEvents
-    /// This hash table is cleared when finalizing a scan loop
-    /// Maps (trigger_guide, result_guide) -> (combo evaluations remaining)
-    trigger_combo_eval_state: FnvIndexMap<(u16, u16), u8, MAX_ACTIVE_TRIGGERS>,
+    /// Entries are cleared when finalizing a scan loop once the combo window has passed
+    /// Maps (trigger_guide, result_guide) -> (combo evaluations remaining, starting time instance)
+    trigger_combo_eval_state: FnvIndexMap<(u16, u16), (u8, u32), MAX_ACTIVE_TRIGGERS>,
+    /// Combo simultaneity window (in time instances)
+    /// Trigger conditions satisfied within this window count towards the same combo
+    /// 0 requires all combo conditions to be satisfied in the same scan loop
+    combo_window: u32,
     /// time_instance is a dumb counter used to keep track of processing instances.
     /// Yes, the counter will rollover but generally this shouldn't matter
     /// Used to calculate produced Layer TriggerEvents, is generally set once per processing loop
@@ -113,6 +148,50 @@ pub struct LayerState<
     /// Cleared each processing loop.
     /// ((trigger_guide, result_guide), ttype, index)
     off_state_lookups: Vec<((u16, u16), u8, u16), MAX_OFF_STATE_LOOKUP>,
+    /// Dynamic keymap overrides
+    /// Checked before the static layer_lookup, used to remap keys at runtime
+    /// (layer, ttype, index) -> (trigger_guide, result_guide)
+    /// MAX_OVERRIDES defaults to 2 (the smallest FnvIndexMap) so LayerState types declared
+    /// without it keep building
+    overrides: FnvIndexMap<(u8, u8, u16), (u16, u16), MAX_OVERRIDES>,
+    /// One-shot modifier timeout (in time instances)
+    /// None if one-shot modifiers are disabled
+    one_shot_timeout: Option<u32>,
+    /// Armed one-shot modifiers
+    /// (modifier, time instance when armed)
+    /// MAX_ONE_SHOT also limits the number of candidates
+    one_shot: Vec<(kll_hid::Keyboard, u32), MAX_ONE_SHOT>,
+    /// Modifiers that are pressed but have not been used with another key yet
+    one_shot_candidates: Vec<kll_hid::Keyboard, MAX_ONE_SHOT>,
+    /// Set when the armed one-shot modifiers have been applied to a key
+    /// They are released on the next call to finalize_triggers()
+    one_shot_consumed: bool,
+    /// Layer tap hold threshold (in time instances)
+    /// Held longer than this (or interrupted by another key) activates the layer
+    /// Defaults to DEFAULT_TAP_HOLD_THRESHOLD, see set_tap_hold_threshold()
+    tap_hold_threshold: u32,
+    /// Layer taps that are currently pressed
+    /// (layer, tap_key, time instance when pressed, tap-hold resolution)
+    layer_tap: Vec<(u8, kll_hid::Keyboard, u32, trigger::TapHold), MAX_LAYER_TAPS>,
+    /// Tapped keys that need to be released on the next call to finalize_triggers()
+    layer_tap_release: Vec<kll_hid::Keyboard, MAX_LAYER_TAPS>,
+    /// Held autorepeat keys
+    /// (key, time instance when pressed, next repeat (time instances since the press), interval,
+    /// released for repeat)
+    autorepeat: Vec<(kll_hid::Keyboard, u32, u32, u32, bool), MAX_AUTOREPEAT>,
+    /// Host OS, used for OS dependent capabilities
+    os: hid::OsType,
+    /// Current LayerRotate layer (0 is the default layer)
+    layer_rotate: u8,
+    /// Skip held (e.g. Hold) TriggerEvents for keys whose guide is already resolved
+    coalesce_held: bool,
+    /// Held keys that can be coalesced
+    /// Cleared whenever a TriggerEvent advances any guide
+    /// (ttype, index) -> resolved (trigger_guide, result_guide)
+    /// Coalescing is only an optimization, held keys beyond MAX_COALESCED are processed normally
+    coalesced: FnvIndexMap<(u8, u16), (u16, u16), MAX_COALESCED>,
+    /// Number of guide lookups (wrapping), useful for profiling
+    lookups: u32,
 }
 
 impl<
@@ -124,6 +203,11 @@ impl<
         const MAX_ACTIVE_TRIGGERS: usize,
         const MAX_LAYER_STACK_CACHE: usize,
         const MAX_OFF_STATE_LOOKUP: usize,
+        const MAX_OVERRIDES: usize,
+        const MAX_ONE_SHOT: usize,
+        const MAX_LAYER_TAPS: usize,
+        const MAX_AUTOREPEAT: usize,
+        const MAX_COALESCED: usize,
     >
     LayerState<
         'a,
@@ -134,6 +218,11 @@ impl<
         MAX_ACTIVE_TRIGGERS,
         MAX_LAYER_STACK_CACHE,
         MAX_OFF_STATE_LOOKUP,
+        MAX_OVERRIDES,
+        MAX_ONE_SHOT,
+        MAX_LAYER_TAPS,
+        MAX_AUTOREPEAT,
+        MAX_COALESCED,
     >
 {
     pub fn new(layer_lookup: LayerLookup<'a, LAYOUT_SIZE>, time_instance: u32) -> Self {
@@ -159,8 +248,10 @@ impl<
 
         let layer_stack = Vec::new();
         let layer_stack_cache = FnvIndexMap::<(u8, u16), (u8, Layer), MAX_LAYER_STACK_CACHE>::new();
-        let trigger_combo_eval_state = FnvIndexMap::<(u16, u16), u8, MAX_ACTIVE_TRIGGERS>::new();
+        let trigger_combo_eval_state =
+            FnvIndexMap::<(u16, u16), (u8, u32), MAX_ACTIVE_TRIGGERS>::new();
         let off_state_lookups = Vec::new();
+        let overrides = FnvIndexMap::<(u8, u8, u16), (u16, u16), MAX_OVERRIDES>::new();
 
         Self {
             layer_lookup,
@@ -169,8 +260,23 @@ impl<
             layer_stack,
             layer_stack_cache,
             trigger_combo_eval_state,
+            combo_window: 0,
             time_instance,
             off_state_lookups,
+            overrides,
+            one_shot_timeout: None,
+            one_shot: Vec::new(),
+            one_shot_candidates: Vec::new(),
+            one_shot_consumed: false,
+            tap_hold_threshold: DEFAULT_TAP_HOLD_THRESHOLD,
+            layer_tap: Vec::new(),
+            layer_tap_release: Vec::new(),
+            autorepeat: Vec::new(),
+            os: hid::OsType::Unknown,
+            layer_rotate: 0,
+            coalesce_held: false,
+            coalesced: FnvIndexMap::new(),
+            lookups: 0,
         }
     }
 
@@ -186,17 +292,19 @@ impl<
 
     /// Set layer state
     /// If layer already has the state enable, disable and vice versa
-    pub fn set_layer(&mut self, layer: u8, state: layer::State) -> TriggerEvent {
+    /// Returns None (and is ignored) if the layer is invalid or layer 0 (always enabled)
+    pub fn set_layer(&mut self, layer: u8, state: layer::State) -> Option<TriggerEvent> {
         // Make sure the layer is valid
-        assert!(
-            layer as usize >= self.layer.len(),
-            "Invalid layer set: {} {:?}",
-            layer,
-            state,
-        );
+        if layer as usize >= self.layer.len() {
+            warn!("Invalid layer set: {} {:?}", layer, state);
+            return None;
+        }
 
         // Cannot set layer 0
-        assert!(layer != 0, "Cannot change layer 0 state");
+        if layer == 0 {
+            warn!("Cannot change layer 0 state: {:?}", state);
+            return None;
+        }
 
         // Check to see if the layer is already in the stack, add it if not
         let layer_in_stack = self.is_layer_in_stack(layer);
@@ -243,33 +351,214 @@ impl<
         }
 
         // Build layer trigger event
-        let state = trigger::LayerState::from_layer(cur_state, activity_state);
+        // Use the previous state when deactivating (to indicate which state was removed)
+        let event_state = if cur_state == layer::State::Off {
+            prev_state
+        } else {
+            cur_state
+        };
+        let state = trigger::LayerState::from_layer(event_state, activity_state);
 
         // Send signal for layer state change
-        TriggerEvent::Layer {
+        Some(TriggerEvent::Layer {
             state,
             layer,
             last_state: 0u32, // Initial events always start at 0
+        })
+    }
+
+    /// Apply a layer CapabilityRun (e.g. from finalize_triggers) to the layer state
+    /// Returns the generated layer TriggerEvents, these must be processed (process_trigger) at the
+    /// start of the next processing loop (after increment_time, before any new input events) so
+    /// that triggers bound to layer state changes (e.g. layer activation macros) fire.
+    /// Non-layer CapabilityRuns are ignored.
+    pub fn apply_capability(&mut self, cap: CapabilityRun) -> Vec<TriggerEvent, MAX_LAYERS> {
+        let mut events = Vec::new();
+        match cap {
+            // Momentary layer, Initial (e.g. press) adds Shift and Last (e.g. release) removes it
+            // Ignored if Shift is already in the requested state (e.g. cleared while held)
+            CapabilityRun::LayerState {
+                state: state @ (CapabilityEvent::Initial | CapabilityEvent::Last),
+                layer,
+                layer_state: layer::State::Shift,
+            } => {
+                let shifted = self
+                    .layer
+                    .get(layer as usize)
+                    .is_some_and(|entry| entry.state.is_set(layer::State::Shift));
+                if shifted != (state == CapabilityEvent::Initial) {
+                    events.extend(self.set_layer(layer, layer::State::Shift));
+                }
+            }
+            // Other layer states (e.g. Lock) toggle on Initial (e.g. press)
+            CapabilityRun::LayerState {
+                state: CapabilityEvent::Initial,
+                layer,
+                layer_state,
+            } => {
+                events.extend(self.set_layer(layer, layer_state));
+            }
+            CapabilityRun::LayerClear {
+                state: CapabilityEvent::Initial,
+            } => {
+                // Deactivate every active layer, most recently activated first
+                for layer in self.layer_stack.clone().iter().rev() {
+                    let state = self.layer[*layer as usize].state;
+                    events.extend(self.set_layer(*layer, state));
+                }
+            }
+            CapabilityRun::LayerRotate {
+                state: CapabilityEvent::Initial,
+                direction,
+            } => {
+                // usize so the wrap-around can't overflow with many layers
+                let layers = self.layer.len();
+                let prev = self.layer_rotate;
+                let next = match direction {
+                    layer::Direction::Next => (prev as usize + 1) % layers,
+                    layer::Direction::Previous => (prev as usize + layers - 1) % layers,
+                } as u8;
+                self.layer_rotate = next;
+
+                // Rotated layers are locked, layer 0 is always enabled
+                if prev != 0 && self.layer[prev as usize].state.is_set(layer::State::Lock) {
+                    events.extend(self.set_layer(prev, layer::State::Lock));
+                }
+                if next != 0 && !self.layer[next as usize].state.is_set(layer::State::Lock) {
+                    events.extend(self.set_layer(next, layer::State::Lock));
+                }
+            }
+            _ => {}
+        }
+        events
+    }
+
+    /// Current LayerRotate layer
+    pub fn rotate_layer(&self) -> u8 {
+        self.layer_rotate
+    }
+
+    /// Active layers in evaluation order (lowest to highest priority)
+    /// Layer 0 is always active and is not included
+    pub fn active_layers(&self) -> &[u8] {
+        &self.layer_stack
+    }
+
+    /// Current state of a layer
+    /// None if the layer is invalid
+    pub fn layer_state(&self, layer: u8) -> Option<layer::State> {
+        self.layer.get(layer as usize).map(|layer| layer.state)
+    }
+
+    /// Set the combo (chord) simultaneity window
+    /// Trigger conditions satisfied within window time instances of the first condition
+    /// count towards the same combo, even across scan loops.
+    /// Defaults to 0 (all conditions must be satisfied in the same scan loop).
+    pub fn set_combo_window(&mut self, window: u32) {
+        self.combo_window = window;
+    }
+
+    /// Enable/disable one-shot (sticky) modifiers
+    /// When enabled, tapping a keyboard modifier (press and release without using it) keeps the
+    /// modifier pressed for the next non-modifier key. The modifier is released afterwards.
+    ///
+    /// timeout - Number of time instances before an unused one-shot modifier is released
+    ///           None disables one-shot modifiers
+    pub fn set_one_shot(&mut self, timeout: Option<u32>) {
+        self.one_shot_timeout = timeout;
+
+        // Reset state
+        self.one_shot.clear();
+        self.one_shot_candidates.clear();
+        self.one_shot_consumed = false;
+    }
+
+    /// Set the layer tap hold threshold (in time instances)
+    /// A LayerTap pressed for at least this long activates the layer instead of sending tap_key
+    /// Defaults to DEFAULT_TAP_HOLD_THRESHOLD
+    pub fn set_tap_hold_threshold(&mut self, threshold: u32) {
+        self.tap_hold_threshold = threshold;
+    }
+
+    /// Enable/disable held TriggerEvent coalescing
+    /// When enabled, held TriggerEvents (e.g. Phro::Hold) skip the guide lookup entirely if the
+    /// previous held event for the same key did not advance any guide and the key's only
+    /// in-progress guide is already past the trigger phase (ResultPos).
+    /// Only applies to already-resolved guides, any event that advances a guide resets coalescing.
+    pub fn set_coalesce_held(&mut self, enable: bool) {
+        self.coalesce_held = enable;
+        self.coalesced.clear();
+    }
+
+    /// Number of guide lookups done so far (wrapping)
+    pub fn lookup_count(&self) -> u32 {
+        self.lookups
+    }
+
+    /// Set the host OS
+    /// Usually retrieved using HID-IO (HidIoHostInfo)
+    pub fn set_os(&mut self, os: hid::OsType) {
+        self.os = os;
+    }
+
+    /// Override the trigger:result guide for a given (layer, ttype, index)
+    /// Used for dynamic keymaps (remapping keys at runtime without reflashing)
+    /// The guide must be a valid (trigger_guide, result_guide) pair from the LayerLookup.
+    ///
+    /// Returns the previous override if one was set
+    pub fn set_override(
+        &mut self,
+        key: (u8, u8, u16),
+        guide: (u16, u16),
+    ) -> Result<Option<(u16, u16)>, ProcessError> {
+        self.coalesced.clear();
+        self.overrides
+            .insert(key, guide)
+            .map_err(|_| ProcessError::FailedOverrideInsert)
+    }
+
+    /// Clear an override for the given (layer, ttype, index)
+    ///
+    /// Returns the override if one was set
+    pub fn clear_override(&mut self, key: (u8, u8, u16)) -> Option<(u16, u16)> {
+        self.coalesced.clear();
+        self.overrides.remove(&key)
+    }
+
+    /// Lookup the trigger:result guides for a given (layer, ttype, index)
+    /// Overrides take priority over the static layer_lookup
+    fn lookup_guides<const LSIZE: usize>(
+        &self,
+        key: (u8, u8, u16),
+    ) -> heapless::Vec<(u16, u16), LSIZE> {
+        if let Some(guide) = self.overrides.get(&key) {
+            let mut guides = heapless::Vec::new();
+            guides.push(*guide).unwrap();
+            guides
+        } else {
+            self.layer_lookup.lookup_guides::<LSIZE>(key)
         }
     }
 
     /// Attempts to lookup a trigger list given a layer and given state
+    ///
+    /// Layer priority is determined by the layer stack: the most recently activated effective
+    /// layer wins. Changing the state of an already active layer (e.g. Shift -> Shift+Lock) does
+    /// not change its priority. Layer 0 (default layer) always has the lowest priority.
     fn layer_lookup_search<const LSIZE: usize>(
         &self,
         ttype: u8,
         index: u16,
     ) -> Option<(u8, heapless::Vec<(u16, u16), LSIZE>)> {
-        // Start from the top of the stack
-        for (layer, state) in self.layer.iter().rev().enumerate() {
-            let layer = layer as u8;
+        // Start from the top of the stack (most recently activated layer)
+        // Layer 0 is never in the stack and is always evaluated last
+        for layer in self.layer_stack.iter().rev().chain(core::iter::once(&0)) {
             // Check if effective state is valid
-            if state.state.effective() {
-                let guides = self
-                    .layer_lookup
-                    .lookup_guides::<LSIZE>((layer, ttype, index));
+            if self.layer[*layer as usize].state.effective() {
+                let guides = self.lookup_guides::<LSIZE>((*layer, ttype, index));
                 // If guides were found, we can stop here
                 if !guides.is_empty() {
-                    return Some((layer, guides));
+                    return Some((*layer, guides));
                 }
             }
         }
@@ -287,6 +576,7 @@ impl<
         &mut self,
         event: TriggerEvent,
     ) -> Option<(u8, heapless::Vec<(u16, u16), LSIZE>)> {
+        self.lookups = self.lookups.wrapping_add(1);
         let cache_lookup = (u8::from(event), event.index());
         let cache_hit = self.layer_stack_cache.get(&cache_lookup);
         trace!("Lookup cache hit: {:?}", cache_hit);
@@ -297,18 +587,18 @@ impl<
         trace!("Converted capability_state: {:?}", capability_state);
 
         // Do cached lookup if not the initial event for the trigger and present in the cache
-        let layer_guides = if capability_state != CapabilityEvent::Initial && let Some((layer, _layer_state)) = cache_hit {
-            // Retrieve layer, and build guide lookup
-            let guide_lookup = (*layer, cache_lookup.0, cache_lookup.1);
+        let layer_guides = match cache_hit {
+            Some((layer, _layer_state)) if capability_state != CapabilityEvent::Initial => {
+                // Retrieve layer, and build guide lookup
+                let guide_lookup = (*layer, cache_lookup.0, cache_lookup.1);
 
-            // We can do a direct lookup as we're hitting a cache
-            let guides = self.layer_lookup.lookup_guides::<LSIZE>(guide_lookup);
+                // We can do a direct lookup as we're hitting a cache
+                let guides = self.lookup_guides::<LSIZE>(guide_lookup);
 
-            Some((*layer, guides))
-
-        // Do full lookup if this is the initial event for the trigger or was not in the cache
-        } else {
-            self.layer_lookup_search::<LSIZE>(cache_lookup.0, cache_lookup.1)
+                Some((*layer, guides))
+            }
+            // Do full lookup if this is the initial event for the trigger or was not in the cache
+            _ => self.layer_lookup_search::<LSIZE>(cache_lookup.0, cache_lookup.1),
         };
         trace!("layer_guides: {:?}", layer_guides);
 
@@ -352,9 +642,37 @@ impl<
         event: TriggerEvent,
     ) -> Result<(), ProcessError> {
         trace!("Event: {:?}", event);
+
+        // Skip held events if the resolved guide is still processing results
+        let key = (u8::from(event), event.index());
+        // Only steady state events (e.g. a key that is still pressed) are coalesced
+        let held = self.coalesce_held
+            && match event {
+                TriggerEvent::Switch { state, .. } => state == trigger::Phro::Hold,
+                TriggerEvent::HidLed { state, .. } => state == trigger::Aodo::On,
+                _ => false,
+            };
+        if let Some(guide) = self.coalesced.get(&key) {
+            if held
+                && matches!(
+                    self.lookup_state.get(guide),
+                    Some(StateStatus::ResultPos { .. })
+                )
+            {
+                trace!("Coalesced event: {:?}", event);
+                return Ok(());
+            }
+            self.coalesced.remove(&key);
+        }
+
         // Lookup guide
         if let Some((_layer, guides)) = self.lookup::<LSIZE>(event) {
             trace!("Event guides: {:?}", guides);
+            // Held events can only be coalesced if no guides are advanced
+            // and a single guide is already resolved
+            let mut coalesce = held;
+            let mut resolved = None;
+
             // Process each of the guides
             for guide in guides {
                 // Lookup the state of each of the guides
@@ -372,22 +690,30 @@ impl<
                 // event. We don't ignore result events, they are just queued up.
                 let pos = match state {
                     StateStatus::TriggerPos { offset, .. } => offset,
+                    StateStatus::ResultPos { .. } if resolved.is_none() => {
+                        resolved = Some(guide);
+                        continue;
+                    }
                     _ => {
+                        coalesce = false;
                         continue;
                     }
                 };
 
                 // Lookup trigger guide
                 if let Some(trigger_guide) = self.layer_lookup.trigger_guide(guide, pos) {
-                    // Check for already evaluated trigger state for this processing loop
-                    let mut remaining =
-                        if let Some(remaining) = self.trigger_combo_eval_state.get(&guide) {
-                            *remaining
+                    // Check for already evaluated trigger state (within the combo window)
+                    let (mut remaining, combo_start) =
+                        if let Some(eval_state) = self.trigger_combo_eval_state.get(&guide) {
+                            *eval_state
                         } else {
                             // Lookup size of this trigger list combo
-                            trigger_guide.len() as u8
+                            (trigger_guide.len() as u8, self.time_instance)
                         };
 
+                    // Used to roll back any off state lookups queued by this guide on failure
+                    let off_state_lookups_len = self.off_state_lookups.len();
+
                     // Verify that we actually match the condition
                     // e.g. Press vs. Release
                     let mut removed_lookup_state = false;
@@ -395,20 +721,24 @@ impl<
                         match cond.evaluate(event, self.layer_lookup.loop_condition_lookup) {
                             Vote::Positive => {
                                 remaining -= 1;
+                                coalesce = false;
                             }
                             Vote::Negative => {
                                 // Remove lookup state entry, continue to next guide
-                                self.lookup_state.remove(&guide);
+                                if self.lookup_state.remove(&guide).is_some() {
+                                    coalesce = false;
+                                }
                                 removed_lookup_state = true;
                                 break;
                             }
                             Vote::Insufficient => {} // Do nothing
                             Vote::OffState => {
+                                coalesce = false;
                                 // Attempt to push a reverse lookup query
                                 // The results of the query will be another set of TriggerEvents
                                 if self
                                     .off_state_lookups
-                                    .push((guide, u8::from(*cond), cond.index()))
+                                    .push((guide, u8::from(cond), cond.index()))
                                     .is_err()
                                 {
                                     return Err(ProcessError::FailedOffStatePush);
@@ -424,6 +754,9 @@ impl<
 
                     // Check if there are no remaining evaluations
                     if remaining == 0 {
+                        // Combo has been satisfied
+                        self.trigger_combo_eval_state.remove(&guide);
+
                         // Determine the next offset
                         let next_status = if let Some(next_offset) =
                             self.layer_lookup.next_trigger_combo(guide, pos)
@@ -444,18 +777,30 @@ impl<
                         if self.lookup_state.insert(guide, next_status).is_err() {
                             return Err(ProcessError::FailedLookupStateInsert);
                         }
+                        self.coalesced.clear();
                     } else {
                         // Update trigger_combo_eval_state
+                        // On failure the previous remaining count is left untouched, but any
+                        // off state lookups queued for this guide must be discarded as well
                         if self
                             .trigger_combo_eval_state
-                            .insert(guide, remaining)
+                            .insert(guide, (remaining, combo_start))
                             .is_err()
                         {
-                            return Err(ProcessError::FailedTriggerComboEvalStateInsert);
+                            self.off_state_lookups.truncate(off_state_lookups_len);
+                            return Err(ProcessError::FailedTriggerComboEvalStateInsert {
+                                guide,
+                                event,
+                            });
                         }
                     }
                 }
             }
+
+            if let Some(guide) = resolved.filter(|_| coalesce) {
+                // Coalescing is only an optimization, ignore a full map
+                self.coalesced.insert(key, guide).ok();
+            }
         } else {
             trace!("No event mapping for: {:?}", event);
         }
@@ -473,18 +818,14 @@ impl<
 
     /// Process off state lookups
     /// To maintain state use a callback function to evaluate input off states
+    /// generate_event is called with the ttype and index of each lookup
     pub fn process_off_state_lookups<const MAX_LAYER_LOOKUP_SIZE: usize>(
         &mut self,
-        generate_event: &dyn Fn(usize) -> TriggerEvent,
+        generate_event: impl Fn(u8, u16) -> TriggerEvent,
     ) {
         let mut events: heapless::Vec<TriggerEvent, MAX_LAYER_LOOKUP_SIZE> = heapless::Vec::new();
         for lookup in &self.off_state_lookups {
-            // TODO support non-keyboard TriggerConditions
-            assert!(
-                lookup.1 == 1,
-                "Currently only keyboard TriggerConditions are supported"
-            );
-            events.push(generate_event(lookup.2.into())).unwrap();
+            events.push(generate_event(lookup.1, lookup.2)).unwrap();
         }
 
         for event in events {
@@ -520,7 +861,7 @@ impl<
                     let mut completed_cond = 0;
 
                     // For each element in the combo
-                    for cap in result_guide {
+                    for cap in result_guide.clone() {
                         let time_cond = self.layer_lookup.loop_condition_lookup
                             [cap.loop_condition_index() as usize];
                         match time_offset.cmp(&time_cond) {
@@ -586,14 +927,370 @@ impl<
             }
         }
 
-        // Clear the trigger_combo_eval_state for the next scan iteration
-        self.trigger_combo_eval_state.clear();
+        // Clear the trigger_combo_eval_state entries that are outside of the combo window
+        // for the next scan iteration
+        if self.combo_window == 0 {
+            self.trigger_combo_eval_state.clear();
+        } else {
+            let time_instance = self.time_instance;
+            let combo_window = self.combo_window;
+            let expired: Vec<(u16, u16), MAX_ACTIVE_TRIGGERS> = self
+                .trigger_combo_eval_state
+                .iter()
+                .filter(|(_, (_, combo_start))| {
+                    time_instance.wrapping_sub(*combo_start) >= combo_window
+                })
+                .map(|(guide, _)| *guide)
+                .collect();
+            for guide in expired.iter() {
+                self.trigger_combo_eval_state.remove(guide);
+            }
+        }
 
         // Clear the off_state_lookups for the next scan iteration
         self.off_state_lookups.clear();
 
+        // Resolve layer taps
+        results = self.process_layer_tap::<LSIZE>(results);
+
+        // Resolve OS dependent keys
+        for result in results.iter_mut() {
+            if let CapabilityRun::HidKeyboardOs {
+                state,
+                id,
+                os,
+                os_id,
+            } = *result
+            {
+                *result = CapabilityRun::HidKeyboard {
+                    state,
+                    id: if os == self.os { os_id } else { id },
+                };
+            }
+        }
+
+        // Generate autorepeat key events
+        results = self.process_autorepeat::<LSIZE>(results);
+
+        // Apply one-shot modifiers
+        if let Some(timeout) = self.one_shot_timeout {
+            results = self.process_one_shot::<LSIZE>(results, timeout);
+        }
+
         results
     }
+
+    /// Resolve LayerTap results into either layer activation (hold) or a tap_key press (tap)
+    /// Hold generates LayerState Shift events, tap generates a HidKeyboard press and release
+    fn process_layer_tap<const LSIZE: usize>(
+        &mut self,
+        results: heapless::Vec<CapabilityRun, LSIZE>,
+    ) -> heapless::Vec<CapabilityRun, LSIZE> {
+        let mut new_results = heapless::Vec::<_, LSIZE>::new();
+        let mut push = |result| {
+            if new_results.push(result).is_err() {
+                panic!("finalize_triggers LSIZE is too small!");
+            }
+        };
+
+        // Release keys tapped during the previous scan loop
+        for tap_key in self.layer_tap_release.iter() {
+            push(CapabilityRun::HidKeyboard {
+                state: CapabilityEvent::Last,
+                id: *tap_key,
+            });
+        }
+        self.layer_tap_release.clear();
+
+        // Any other key press interrupts pending layer taps
+        let interrupted = results.iter().any(|result| {
+            matches!(
+                result,
+                CapabilityRun::HidKeyboard {
+                    state: CapabilityEvent::Initial,
+                    ..
+                }
+            )
+        });
+
+        // Check pending layer taps for hold
+        for (layer, _, press_time, resolution) in self.layer_tap.iter_mut() {
+            if *resolution == trigger::TapHold::Pending
+                && trigger::TapHold::classify(
+                    *press_time,
+                    self.time_instance,
+                    self.tap_hold_threshold,
+                    interrupted,
+                    false,
+                ) == trigger::TapHold::Hold
+            {
+                *resolution = trigger::TapHold::Hold;
+                push(CapabilityRun::LayerState {
+                    state: CapabilityEvent::Initial,
+                    layer: *layer,
+                    layer_state: layer::State::Shift,
+                });
+            }
+        }
+
+        for result in results {
+            match result {
+                CapabilityRun::LayerTap {
+                    state: CapabilityEvent::Initial,
+                    layer,
+                    tap_key,
+                } => {
+                    if self
+                        .layer_tap
+                        .push((
+                            layer,
+                            tap_key,
+                            self.time_instance,
+                            trigger::TapHold::Pending,
+                        ))
+                        .is_err()
+                    {
+                        warn!("Too many layer taps pressed, ignoring: {}", layer);
+                    }
+                }
+                CapabilityRun::LayerTap {
+                    state: CapabilityEvent::Last,
+                    layer,
+                    tap_key,
+                } => {
+                    let pos = self
+                        .layer_tap
+                        .iter()
+                        .position(|(l, k, _, _)| *l == layer && *k == tap_key);
+                    if let Some(pos) = pos {
+                        let (_, _, _, resolution) = self.layer_tap.swap_remove(pos);
+                        if resolution == trigger::TapHold::Hold {
+                            push(CapabilityRun::LayerState {
+                                state: CapabilityEvent::Last,
+                                layer,
+                                layer_state: layer::State::Shift,
+                            });
+                        } else {
+                            push(CapabilityRun::HidKeyboard {
+                                state: CapabilityEvent::Initial,
+                                id: tap_key,
+                            });
+                            self.layer_tap_release.push(tap_key).ok();
+                        }
+                    }
+                }
+                CapabilityRun::LayerTap { .. } => {}
+                _ => push(result),
+            }
+        }
+
+        new_results
+    }
+
+    /// Convert HidKeyboardRepeat results into HidKeyboard events
+    /// Held keys are released then pressed again on the next scan loop for each repeat
+    fn process_autorepeat<const LSIZE: usize>(
+        &mut self,
+        results: heapless::Vec<CapabilityRun, LSIZE>,
+    ) -> heapless::Vec<CapabilityRun, LSIZE> {
+        let mut new_results = heapless::Vec::<_, LSIZE>::new();
+        let mut push = |result| {
+            if new_results.push(result).is_err() {
+                panic!("finalize_triggers LSIZE is too small!");
+            }
+        };
+
+        // Repeat held keys
+        for (id, press_time, next, interval, released) in self.autorepeat.iter_mut() {
+            if *released {
+                // Press the key again after the repeat release
+                *released = false;
+                push(CapabilityRun::HidKeyboard {
+                    state: CapabilityEvent::Initial,
+                    id: *id,
+                });
+                continue;
+            }
+
+            // An interval of 0 disables repeating
+            // Repeats are due once the deadline has passed, time instances may be skipped
+            let elapsed = self.time_instance.wrapping_sub(*press_time);
+            if *interval > 0 && elapsed >= *next {
+                // Schedule the next repeat after the current time instance
+                let intervals = (elapsed - *next) / *interval + 1;
+                *next = next.saturating_add(intervals.saturating_mul(*interval));
+                *released = true;
+                push(CapabilityRun::HidKeyboard {
+                    state: CapabilityEvent::Last,
+                    id: *id,
+                });
+            }
+        }
+
+        for result in results {
+            match result {
+                CapabilityRun::HidKeyboardRepeat {
+                    state: CapabilityEvent::Initial,
+                    id,
+                    delay,
+                    interval,
+                } => {
+                    if self
+                        .autorepeat
+                        .push((id, self.time_instance, delay, interval, false))
+                        .is_err()
+                    {
+                        warn!("Too many autorepeat keys held, not repeating: {:?}", id);
+                    }
+                    push(CapabilityRun::HidKeyboard {
+                        state: CapabilityEvent::Initial,
+                        id,
+                    });
+                }
+                CapabilityRun::HidKeyboardRepeat {
+                    state: CapabilityEvent::Last,
+                    id,
+                    ..
+                } => {
+                    if let Some(pos) = self.autorepeat.iter().position(|(k, ..)| *k == id) {
+                        let (_, _, _, _, released) = self.autorepeat.swap_remove(pos);
+                        // Key is already released if in the middle of a repeat
+                        if released {
+                            continue;
+                        }
+                    }
+                    push(CapabilityRun::HidKeyboard {
+                        state: CapabilityEvent::Last,
+                        id,
+                    });
+                }
+                CapabilityRun::HidKeyboardRepeat { .. } => {}
+                _ => push(result),
+            }
+        }
+
+        new_results
+    }
+
+    /// Apply one-shot modifier state to the generated results
+    fn process_one_shot<const LSIZE: usize>(
+        &mut self,
+        results: heapless::Vec<CapabilityRun, LSIZE>,
+        timeout: u32,
+    ) -> heapless::Vec<CapabilityRun, LSIZE> {
+        let mut new_results = heapless::Vec::<_, LSIZE>::new();
+
+        // Release consumed or expired one-shot modifiers
+        let mut armed = Vec::<_, MAX_ONE_SHOT>::new();
+        for (id, time_instance) in self.one_shot.iter() {
+            let expired = self.time_instance.wrapping_sub(*time_instance) >= timeout;
+            if self.one_shot_consumed || expired {
+                if new_results
+                    .push(CapabilityRun::HidKeyboard {
+                        state: CapabilityEvent::Last,
+                        id: *id,
+                    })
+                    .is_err()
+                {
+                    panic!("finalize_triggers LSIZE is too small!");
+                }
+            } else {
+                armed.push((*id, *time_instance)).ok();
+            }
+        }
+        self.one_shot = armed;
+        self.one_shot_consumed = false;
+
+        for result in results {
+            if let CapabilityRun::HidKeyboard { state, id } = result {
+                let modifier = is_modifier(id);
+                match state {
+                    CapabilityEvent::Initial if modifier => {
+                        if !self.one_shot_candidates.contains(&id) {
+                            self.one_shot_candidates.push(id).ok();
+                        }
+                    }
+                    CapabilityEvent::Initial => {
+                        // Pressed modifiers have been used normally
+                        self.one_shot_candidates.clear();
+
+                        // Armed one-shot modifiers apply to this key only
+                        if !self.one_shot.is_empty() {
+                            self.one_shot_consumed = true;
+                        }
+                    }
+                    CapabilityEvent::Last if modifier => {
+                        // Modifier was tapped, arm it instead of releasing it
+                        if let Some(pos) = self.one_shot_candidates.iter().position(|c| *c == id) {
+                            self.one_shot_candidates.swap_remove(pos);
+                            if self.one_shot.push((id, self.time_instance)).is_ok() {
+                                continue;
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            if new_results.push(result).is_err() {
+                panic!("finalize_triggers LSIZE is too small!");
+            }
+        }
+
+        new_results
+    }
+}
+
+/// Determine if the HID keyboard code is a modifier (e.g. Shift)
+fn is_modifier(id: kll_hid::Keyboard) -> bool {
+    (u16::from(kll_hid::Keyboard::LeftControl)..=u16::from(kll_hid::Keyboard::RightGUI))
+        .contains(&u16::from(id))
+}
+
+/// Elements of a single TriggerGuide or ResultGuide combo
+/// Guides are u8 arrays without any alignment guarantees, so each element is copied out
+/// (unaligned read) instead of casting the guide to a slice of elements.
+#[derive(Clone)]
+pub struct GuideCombo<'a, T> {
+    bytes: &'a [u8],
+    elem: core::marker::PhantomData<T>,
+}
+
+impl<'a, T: Copy> GuideCombo<'a, T> {
+    /// bytes must contain only complete, valid elements of T
+    unsafe fn new(bytes: &'a [u8]) -> Self {
+        Self {
+            bytes,
+            elem: core::marker::PhantomData,
+        }
+    }
+}
+
+impl<'a, T: Copy> Iterator for GuideCombo<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.bytes.len() < core::mem::size_of::<T>() {
+            return None;
+        }
+        let (elem, bytes) = self.bytes.split_at(core::mem::size_of::<T>());
+        self.bytes = bytes;
+        // Safe as long as the guide was generated from T (see GuideCombo::new)
+        Some(unsafe { core::ptr::read_unaligned(elem.as_ptr() as *const T) })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.bytes.len() / core::mem::size_of::<T>();
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: Copy> ExactSizeIterator for GuideCombo<'a, T> {}
+
+impl<'a, T: Copy + core::fmt::Debug> core::fmt::Debug for GuideCombo<'a, T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_list().entries(self.clone()).finish()
+    }
 }
 
 /// The LayerLookup struct is used as a guide for the KLL state machine
@@ -608,6 +1305,9 @@ impl<
 pub struct LayerLookup<'a, const LAYOUT_SIZE: usize> {
     layer_lookup: FnvIndexMap<(u8, u8, u16), usize, LAYOUT_SIZE>,
     raw_layer_lookup: &'a [u8],
+    /// Overlay raw layer lookup (see with_overlay)
+    /// Overlay positions in layer_lookup start after the end of raw_layer_lookup
+    overlay_layer_lookup: &'a [u8],
     trigger_guides: &'a [u8],
     result_guides: &'a [u8],
     trigger_result_mapping: &'a [u16],
@@ -616,13 +1316,95 @@ pub struct LayerLookup<'a, const LAYOUT_SIZE: usize> {
 }
 
 impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
+    /// Builds the layer lookup from the raw (KLL compiler generated) layer lookup
+    /// Fails if LAYOUT_SIZE is too small or raw_layer_lookup is malformed
     pub fn new(
         raw_layer_lookup: &'a [u8],
         trigger_guides: &'a [u8],
         result_guides: &'a [u8],
         trigger_result_mapping: &'a [u16],
         loop_condition_lookup: &'a [u32],
+    ) -> Result<Self, LayerLookupError> {
+        let (layer_lookup, max_layer) = Self::build(raw_layer_lookup, Err)?;
+        trace!("trigger_guides: {:?}", trigger_guides);
+        trace!("trigger_result_mapping: {:?}", trigger_result_mapping);
+        Ok(Self {
+            layer_lookup,
+            raw_layer_lookup,
+            overlay_layer_lookup: &[],
+            trigger_guides,
+            result_guides,
+            trigger_result_mapping,
+            loop_condition_lookup,
+            max_layer,
+        })
+    }
+
+    /// Builds the layer lookup, logging and skipping any entries that could not be added
+    /// NOTE: The resulting lookup may be incomplete, prefer new()
+    pub fn new_lossy(
+        raw_layer_lookup: &'a [u8],
+        trigger_guides: &'a [u8],
+        result_guides: &'a [u8],
+        trigger_result_mapping: &'a [u16],
+        loop_condition_lookup: &'a [u32],
     ) -> Self {
+        let Ok((layer_lookup, max_layer)) =
+            Self::build(raw_layer_lookup, |_| Ok::<_, core::convert::Infallible>(()));
+        trace!("trigger_guides: {:?}", trigger_guides);
+        trace!("trigger_result_mapping: {:?}", trigger_result_mapping);
+        Self {
+            layer_lookup,
+            raw_layer_lookup,
+            overlay_layer_lookup: &[],
+            trigger_guides,
+            result_guides,
+            trigger_result_mapping,
+            loop_condition_lookup,
+            max_layer,
+        }
+    }
+
+    /// Combines the layer lookup with an overlay raw layer lookup (e.g. a plugin layer)
+    /// Overlay entries take precedence over base entries with the same (layer, ttype, index),
+    /// overlay entries without any triggers are ignored (they do not unbind base entries).
+    ///
+    /// The overlay trigger lists index into the same trigger_result_mapping (and guides) as the
+    /// base, so the overlay must be generated against the same guide tables.
+    /// The overlay must live as long as the base guides ('a), it is not copied.
+    /// Only a single overlay is supported.
+    ///
+    /// Fails if LAYOUT_SIZE is too small, the overlay is malformed or an overlay is already set
+    pub fn with_overlay(mut base: Self, overlay: &'a [u8]) -> Result<Self, LayerLookupError> {
+        if !base.overlay_layer_lookup.is_empty() {
+            return Err(LayerLookupError::OverlayAlreadySet);
+        }
+        let (overlay_lookup, overlay_max_layer) = Self::build(overlay, Err)?;
+
+        // Overlay positions follow the base positions (logical concatenation)
+        let offset = base.raw_layer_lookup.len();
+        for (&(layer, ttype, index), lookup) in overlay_lookup.iter() {
+            base.layer_lookup
+                .insert((layer, ttype, index), offset + lookup)
+                .map_err(|_| LayerLookupError::LayoutSizeTooSmall {
+                    layer,
+                    ttype,
+                    index,
+                })?;
+        }
+
+        base.overlay_layer_lookup = overlay;
+        base.max_layer = base.max_layer.max(overlay_max_layer);
+        Ok(base)
+    }
+
+    /// Parses raw_layer_lookup into the layer lookup hash table
+    /// Errors are logged and passed to on_error, parsing only stops if on_error fails
+    #[allow(clippy::type_complexity)]
+    fn build<E>(
+        raw_layer_lookup: &[u8],
+        mut on_error: impl FnMut(LayerLookupError) -> Result<(), E>,
+    ) -> Result<(FnvIndexMap<(u8, u8, u16), usize, LAYOUT_SIZE>, u8), E> {
         // Build layer lookup from array
         // The purpose of this hash table is to quickly find the trigger list in LAYER_LOOKUP
         // Mapping
@@ -669,12 +1451,22 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
                             // Success, replace existing key (this is bad, warn)
                             Ok(Some(old_lookup)) => {
                                 warn!("Duplicate layer lookup key! ({}, {}). {} has been replaced by {}", layer, index, old_lookup, lookup);
+                                on_error(LayerLookupError::DuplicateKey {
+                                    layer,
+                                    ttype,
+                                    index,
+                                })?;
                             }
                             Err(e) => {
                                 error!(
                                     "Failed to add lookup key ({}, {}) -> {}: {:?}; Size:{:?} Capacity:{:?}",
                                     layer, index, lookup, e, layer_lookup.len(), LAYOUT_SIZE,
                                 );
+                                on_error(LayerLookupError::LayoutSizeTooSmall {
+                                    layer,
+                                    ttype,
+                                    index,
+                                })?;
                             }
                         }
                         // Triggers are u16, so multiple by 2
@@ -692,17 +1484,21 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
                 }
             }
         }
-        trace!("trigger_guides: {:?}", trigger_guides);
-        trace!("trigger_result_mapping: {:?}", trigger_result_mapping);
-        Self {
-            layer_lookup,
-            raw_layer_lookup,
-            trigger_guides,
-            result_guides,
-            trigger_result_mapping,
-            loop_condition_lookup,
-            max_layer,
+
+        // Raw layer lookup must end on a complete entry
+        if mode != LayerProcessMode::Layer {
+            error!(
+                "Truncated layer lookup entry ({}, {}, {})",
+                layer, ttype, index
+            );
+            on_error(LayerLookupError::Truncated {
+                layer,
+                ttype,
+                index,
+            })?;
         }
+
+        Ok((layer_lookup, max_layer))
     }
 
     /// Retrieves a TriggerList
@@ -712,9 +1508,19 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
         trace!("layer_lookup: {:?}", self.layer_lookup);
         match self.layer_lookup.get(&(layer, ttype, index)) {
             Some(lookup) => {
+                // Overlay positions start after the end of the base raw layer lookup
+                let (raw_layer_lookup, lookup) = if *lookup < self.raw_layer_lookup.len() {
+                    (self.raw_layer_lookup, *lookup)
+                } else {
+                    (
+                        self.overlay_layer_lookup,
+                        *lookup - self.raw_layer_lookup.len(),
+                    )
+                };
+
                 // Determine size of trigger list
-                trace!("raw_layer_lookup: {:?}", self.raw_layer_lookup);
-                let size: usize = self.raw_layer_lookup[*lookup].into();
+                trace!("raw_layer_lookup: {:?}", raw_layer_lookup);
+                let size: usize = raw_layer_lookup[lookup].into();
 
                 // If the size is 0, just return None
                 if size == 0 {
@@ -725,8 +1531,21 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
                 let size = size * 2;
 
                 // Build TriggerList slice
+                // A malformed raw_layer_lookup may not contain the entire list
                 let initial: usize = lookup + 1;
-                Some(&self.raw_layer_lookup[initial..initial + size])
+                let trigger_list = raw_layer_lookup.get(initial..initial + size);
+                if trigger_list.is_none() {
+                    error!(
+                        "Truncated trigger list ({}, {}, {}): {}..{} Size:{}",
+                        layer,
+                        ttype,
+                        index,
+                        initial,
+                        initial + size,
+                        raw_layer_lookup.len()
+                    );
+                }
+                trigger_list
             }
             None => None,
         }
@@ -777,7 +1596,7 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
         &self,
         (trigger, _result): (u16, u16),
         offset: u16,
-    ) -> Option<&[TriggerCondition]> {
+    ) -> Option<GuideCombo<'a, TriggerCondition>> {
         // Determine size of offset combo in the sequence
         let count = self.trigger_guides[trigger as usize + offset as usize] as usize;
         if count == 0 {
@@ -788,10 +1607,8 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
         let start = trigger as usize + offset as usize + 1;
 
         // Convert u8 combo list to TriggerCondition list
-        let ptr: *const u8 =
-            self.trigger_guides[start..start + core::mem::size_of::<TriggerCondition>()].as_ptr();
-        let cond = unsafe { core::slice::from_raw_parts(ptr as *const TriggerCondition, count) };
-        Some(cond)
+        let end = start + count * core::mem::size_of::<TriggerCondition>();
+        Some(unsafe { GuideCombo::new(&self.trigger_guides[start..end]) })
     }
 
     /// Retrieves the ResultGuide for a given TriggerGuide:ResultGuide pair
@@ -802,7 +1619,7 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
         &self,
         (_trigger, result): (u16, u16),
         offset: u16,
-    ) -> Option<&[Capability]> {
+    ) -> Option<GuideCombo<'a, Capability>> {
         // Determine size of offset combo in the sequence
         let count = self.result_guides[result as usize + offset as usize] as usize;
         if count == 0 {
@@ -813,10 +1630,8 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
         let start = result as usize + offset as usize + 1;
 
         // Convert u8 combo list to Capability list
-        let ptr: *const u8 =
-            self.result_guides[start..start + core::mem::size_of::<Capability>()].as_ptr();
-        let cond = unsafe { core::slice::from_raw_parts(ptr as *const Capability, count) };
-        Some(cond)
+        let end = start + count * core::mem::size_of::<Capability>();
+        Some(unsafe { GuideCombo::new(&self.result_guides[start..end]) })
     }
 
     /// Determines the next trigger guide combo offset
@@ -867,10 +1682,19 @@ impl<'a, const LAYOUT_SIZE: usize> LayerLookup<'a, LAYOUT_SIZE> {
 
     /// Convience access for layer_lookup
     /// Useful when trying to get a list of all possible triggers
+    /// Overlay positions start after the end of the base raw layer lookup (see with_overlay)
     pub fn layer_lookup(&self) -> &FnvIndexMap<(u8, u8, u16), usize, LAYOUT_SIZE> {
         &self.layer_lookup
     }
 
+    /// Enumerates each (ttype, index) trigger bound on the given layer
+    pub fn triggers_for_layer(&self, layer: u8) -> impl Iterator<Item = (u8, u16)> + '_ {
+        self.layer_lookup
+            .keys()
+            .filter(move |(klayer, _, _)| *klayer == layer)
+            .map(|(_, ttype, index)| (*ttype, *index))
+    }
+
     /// Determine the max number of layers
     pub fn max_layers(&self) -> u8 {
         self.max_layer + 1
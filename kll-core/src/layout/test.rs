@@ -34,6 +34,223 @@ fn setup_logging_lite() -> Result<(), LogError> {
     }
 }
 
+/// Deterministic replay of a scripted scenario
+/// Each outer slice is a single scan loop (increment_time, process_trigger, finalize_triggers)
+/// Returns every generated CapabilityRun in order, for golden comparisons
+fn run_scenario<
+    'a,
+    const LAYOUT_SIZE: usize,
+    const STATE_SIZE: usize,
+    const MAX_LAYERS: usize,
+    const MAX_ACTIVE_LAYERS: usize,
+    const MAX_ACTIVE_TRIGGERS: usize,
+    const MAX_LAYER_STACK_CACHE: usize,
+    const MAX_OFF_STATE_LOOKUP: usize,
+    const MAX_OVERRIDES: usize,
+>(
+    layer_state: &mut LayerState<
+        'a,
+        LAYOUT_SIZE,
+        STATE_SIZE,
+        MAX_LAYERS,
+        MAX_ACTIVE_LAYERS,
+        MAX_ACTIVE_TRIGGERS,
+        MAX_LAYER_STACK_CACHE,
+        MAX_OFF_STATE_LOOKUP,
+        MAX_OVERRIDES,
+    >,
+    scan_loops: &[&[TriggerEvent]],
+) -> std::vec::Vec<CapabilityRun> {
+    let mut results = std::vec::Vec::new();
+    for events in scan_loops {
+        layer_state.increment_time();
+        for event in events.iter() {
+            let ret = layer_state.process_trigger::<8>(*event);
+            assert!(ret.is_ok(), "Failed to process {:?} - {:?}", event, ret);
+        }
+        results.extend(layer_state.finalize_triggers::<8>());
+    }
+    results
+}
+
+/// Switch event with last_state 0
+fn switch_event(state: trigger::Phro, index: u16) -> TriggerEvent {
+    TriggerEvent::Switch {
+        state,
+        index,
+        last_state: 0,
+    }
+}
+
+/// Switch TriggerCondition (loop condition 0)
+const fn switch_condition(state: trigger::Phro, index: u16) -> TriggerCondition {
+    TriggerCondition::Switch {
+        state,
+        index,
+        loop_condition_index: 0,
+    }
+}
+
+/// HidKeyboard Capability (loop condition 0)
+const fn keyboard_capability(state: CapabilityState, id: kll_hid::Keyboard) -> Capability {
+    Capability::HidKeyboard {
+        state,
+        loop_condition_index: 0,
+        id,
+    }
+}
+
+/// LayerState capacities used by most of the fixtures
+type TestLayerState<'a> = LayerState<'a, 256, 64, 2, 2, 2, 4, 2, 2>;
+
+/// (layer, ttype, index, trigger combo, result combo)
+/// Empty combos only add the (layer, ttype, index) entry to the layer lookup
+type FixtureMapping<'b> = (u8, u8, u16, &'b [TriggerCondition], &'b [Capability]);
+
+/// Layout built from single combo trigger:result mappings
+/// Owns the generated buffers, LayerStates borrow them (see layer_state())
+/// Each guide is encoded (GuideBuilder) as its own terminated sequence, identical guides and
+/// trigger:result pairs are only stored once. Guide offsets are computed, not hand counted.
+struct Fixture {
+    layer_lookup: std::vec::Vec<u8>,
+    trigger_guides: std::vec::Vec<u8>,
+    result_guides: std::vec::Vec<u8>,
+    trigger_result_mapping: std::vec::Vec<u16>,
+    loop_condition_lookup: std::vec::Vec<u32>,
+}
+
+impl Fixture {
+    fn new(mappings: &[FixtureMapping], loop_condition_lookup: &[u32]) -> Self {
+        use crate::guide::GuideBuilder;
+        use std::vec::Vec;
+
+        let mut trigger_guides = Vec::new();
+        let mut trigger_starts = Vec::new();
+        let mut result_guides = Vec::new();
+        let mut result_starts = Vec::new();
+        let mut trigger_result_mapping: Vec<u16> = Vec::new();
+        let mut entries: Vec<(u8, u8, u16, Vec<u16>)> = Vec::new();
+
+        for &(layer, ttype, index, trigger, result) in mappings {
+            let entry = match entries
+                .iter()
+                .position(|entry| (entry.0, entry.1, entry.2) == (layer, ttype, index))
+            {
+                Some(entry) => entry,
+                None => {
+                    entries.push((layer, ttype, index, Vec::new()));
+                    entries.len() - 1
+                }
+            };
+            if trigger.is_empty() {
+                continue;
+            }
+
+            let mut builder = GuideBuilder::new();
+            builder.push_trigger_combo(trigger);
+            let trigger = push_guide(&mut trigger_guides, &mut trigger_starts, builder.finish());
+            let mut builder = GuideBuilder::new();
+            builder.push_result_combo(result);
+            let result = push_guide(&mut result_guides, &mut result_starts, builder.finish());
+
+            let mapping = match trigger_result_mapping
+                .chunks_exact(2)
+                .position(|mapping| mapping == [trigger, result])
+            {
+                Some(mapping) => mapping * 2,
+                None => {
+                    trigger_result_mapping.extend_from_slice(&[trigger, result]);
+                    trigger_result_mapping.len() - 2
+                }
+            };
+            entries[entry].3.push(mapping as u16);
+        }
+
+        // Same layout as kll_macros::layer_lookup!
+        let mut layer_lookup = Vec::new();
+        for (layer, ttype, index, triggers) in entries {
+            layer_lookup.extend_from_slice(&[layer, ttype]);
+            layer_lookup.extend_from_slice(&index.to_le_bytes());
+            layer_lookup.push(triggers.len() as u8);
+            for trigger in triggers {
+                layer_lookup.extend_from_slice(&trigger.to_le_bytes());
+            }
+        }
+
+        Self {
+            layer_lookup,
+            trigger_guides,
+            result_guides,
+            trigger_result_mapping,
+            loop_condition_lookup: loop_condition_lookup.to_vec(),
+        }
+    }
+
+    /// New LayerState (layer 0 only) using the fixture layout
+    fn layer_state<
+        const MAX_LAYERS: usize,
+        const MAX_ACTIVE_LAYERS: usize,
+        const MAX_ACTIVE_TRIGGERS: usize,
+        const MAX_LAYER_STACK_CACHE: usize,
+        const MAX_OFF_STATE_LOOKUP: usize,
+        const MAX_OVERRIDES: usize,
+    >(
+        &self,
+    ) -> LayerState<
+        '_,
+        256,
+        64,
+        MAX_LAYERS,
+        MAX_ACTIVE_LAYERS,
+        MAX_ACTIVE_TRIGGERS,
+        MAX_LAYER_STACK_CACHE,
+        MAX_OFF_STATE_LOOKUP,
+        MAX_OVERRIDES,
+    > {
+        let lookup = LayerLookup::<256>::new(
+            &self.layer_lookup,
+            &self.trigger_guides,
+            &self.result_guides,
+            &self.trigger_result_mapping,
+            &self.loop_condition_lookup,
+        )
+        .unwrap();
+        LayerState::new(lookup, 0)
+    }
+}
+
+/// Single scan loop (see run_scenario), layer capabilities are applied to the layer state
+fn scan(
+    layer_state: &mut TestLayerState,
+    events: &[TriggerEvent],
+) -> heapless::Vec<CapabilityRun, 4> {
+    let results = run_scenario(layer_state, &[events]);
+    for cap_run in &results {
+        layer_state.apply_capability(*cap_run);
+    }
+    heapless::Vec::from_slice(&results).unwrap()
+}
+
+/// Appends an encoded guide and returns its offset, reuses the offset of an identical guide
+fn push_guide(
+    guides: &mut std::vec::Vec<u8>,
+    starts: &mut std::vec::Vec<usize>,
+    guide: std::vec::Vec<u8>,
+) -> u16 {
+    let start = match starts
+        .iter()
+        .find(|&&start| guides[start..].starts_with(&guide))
+    {
+        Some(start) => *start,
+        None => {
+            starts.push(guides.len());
+            guides.extend_from_slice(&guide);
+            guides.len() - guide.len()
+        }
+    };
+    start as u16
+}
+
 // ----- Macros -----
 
 /// Convenience macro to generate TriggerGuides using TriggerConditions
@@ -375,7 +592,8 @@ fn basic_init_test() {
         RESULT_GUIDES,
         TRIGGER_RESULT_MAPPING,
         LOOP_CONDITION_LOOKUP,
-    );
+    )
+    .unwrap();
 
     // Print out valid lookups
     trace!("layer_lookup: {:?}", LAYER_LOOKUP);
@@ -474,23 +692,1679 @@ fn basic_init_test() {
                 trigger,
                 result
             );
-            assert_eq!(
-                trigger.unwrap(),
-                &triggers[index].unwrap()[0..trigger.unwrap().len()],
+            let trigger = trigger.unwrap();
+            assert!(
+                trigger
+                    .clone()
+                    .eq(triggers[index].unwrap()[0..trigger.len()].iter().copied()),
                 "TriggerGuide did not match"
             );
-            assert_eq!(
-                result.unwrap(),
-                &results[index].unwrap()[0..result.unwrap().len()],
+            let result = result.unwrap();
+            assert!(
+                result
+                    .clone()
+                    .eq(results[index].unwrap()[0..result.len()].iter().copied()),
                 "ResultGuide did not match"
             );
         }
     }
 }
 
-// TODO Tests
-// - Basic trigger -> result capability validation test
-// - Import KLL file and do a handful of manual validation (positive test cases)
-// - Import KLL file and do a handful of manual validation (negative test cases)
-// - Import KLL, retrieve json (or similar datastructure) and automate all triggers and make sure
-// all results are reached
+#[test]
+fn trigger_combo_eval_state_overflow() {
+    setup_logging_lite().ok();
+
+    let press = |index| TriggerCondition::Switch {
+        state: trigger::Phro::Press,
+        index,
+        loop_condition_index: 0,
+    };
+    let b = Capability::HidKeyboard {
+        state: CapabilityState::Initial,
+        loop_condition_index: 0,
+        id: kll_hid::Keyboard::B,
+    };
+
+    // Each guide is a 2 key combo, so a single press leaves each combo partially evaluated
+    // Trigger guides: 0, 14, 28 (1 byte count + 2 * 6 byte conditions + 1 byte terminator)
+    // Only room for 2 partially evaluated combos
+    let fixture = Fixture::new(
+        &[
+            (0, 1, 6, &[press(6), press(7)], &[b]),
+            (0, 1, 6, &[press(6), press(8)], &[b]),
+            (0, 1, 6, &[press(6), press(9)], &[b]),
+        ],
+        &[0],
+    );
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let event = TriggerEvent::Switch {
+        state: trigger::Phro::Press,
+        index: 6,
+        last_state: 0,
+    };
+    let ret = layer_state.process_trigger::<4>(event);
+    assert_eq!(
+        ret,
+        Err(ProcessError::FailedTriggerComboEvalStateInsert {
+            guide: (28, 0),
+            event,
+        }),
+        "Unexpected result: {:?}",
+        ret
+    );
+
+    // The first two combos are tracked, the overflowing combo is untouched
+    assert_eq!(
+        layer_state.trigger_combo_eval_state.get(&(0, 0)),
+        Some(&(1, 0))
+    );
+    assert_eq!(
+        layer_state.trigger_combo_eval_state.get(&(14, 0)),
+        Some(&(1, 0))
+    );
+    assert_eq!(layer_state.trigger_combo_eval_state.get(&(28, 0)), None);
+    assert!(layer_state.lookup_state.get(&(28, 0)).is_none());
+    assert!(layer_state.off_state_lookups().is_empty());
+}
+
+#[test]
+fn dynamic_keymap_override() {
+    setup_logging_lite().ok();
+
+    let press = |index| TriggerCondition::Switch {
+        state: trigger::Phro::Press,
+        index,
+        loop_condition_index: 0,
+    };
+    let key = |id| Capability::HidKeyboard {
+        state: CapabilityState::Initial,
+        loop_condition_index: 0,
+        id,
+    };
+
+    // Index 6 -> B (0, 0), Index 7 -> C (8, 10)
+    let fixture = Fixture::new(
+        &[
+            (0, 1, 6, &[press(6)], &[key(kll_hid::Keyboard::B)]),
+            (0, 1, 7, &[press(7)], &[key(kll_hid::Keyboard::C)]),
+        ],
+        &[0],
+    );
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let event = TriggerEvent::Switch {
+        state: trigger::Phro::Press,
+        index: 6,
+        last_state: 0,
+    };
+
+    // Static lookup
+    let (layer, guides) = layer_state.lookup::<4>(event).unwrap();
+    assert_eq!(layer, 0);
+    assert_eq!(guides, [(0, 0)]);
+
+    // Remap index 6 to the Press C result
+    assert_eq!(layer_state.set_override((0, 1, 6), (0, 10)), Ok(None));
+    let (layer, guides) = layer_state.lookup::<4>(event).unwrap();
+    assert_eq!(layer, 0);
+    assert_eq!(guides, [(0, 10)]);
+
+    // Overridden guide generates the remapped capability
+    assert!(layer_state.process_trigger::<4>(event).is_ok());
+    let cap_runs = layer_state.finalize_triggers::<4>();
+    assert_eq!(
+        cap_runs,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::C,
+        }],
+        "Unexpected override result {:?}",
+        cap_runs
+    );
+
+    // Restore static lookup
+    assert_eq!(layer_state.clear_override((0, 1, 6)), Some((0, 10)));
+    let (_layer, guides) = layer_state.lookup::<4>(event).unwrap();
+    assert_eq!(guides, [(0, 0)]);
+}
+
+/// Shift on index 1 and A on index 2 (press and release)
+const ONE_SHOT_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::LeftShift,
+        )],
+    ),
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Release, 1)],
+        &[keyboard_capability(
+            CapabilityState::Last,
+            kll_hid::Keyboard::LeftShift,
+        )],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Press, 2)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Release, 2)],
+        &[keyboard_capability(
+            CapabilityState::Last,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+];
+
+/// Process a single switch event as a full scan loop
+#[test]
+fn one_shot_modifier() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(ONE_SHOT_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_one_shot(Some(100));
+
+    let shift = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::LeftShift,
+    };
+    let a = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::A,
+    };
+
+    // Tap shift, release is held back
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(runs, [shift(CapabilityEvent::Initial)]);
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert!(runs.is_empty());
+
+    // Press A, shift is still applied
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 2)]);
+    assert_eq!(runs, [a(CapabilityEvent::Initial)]);
+
+    // Shift is automatically released after A
+    let runs = scan(&mut layer_state, &[]);
+    assert_eq!(runs, [shift(CapabilityEvent::Last)]);
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 2)]);
+    assert_eq!(runs, [a(CapabilityEvent::Last)]);
+    assert!(scan(&mut layer_state, &[]).is_empty());
+}
+
+#[test]
+fn one_shot_modifier_timeout() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(ONE_SHOT_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_one_shot(Some(3));
+
+    // Tap shift
+    scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert!(runs.is_empty());
+
+    // Shift is released after the timeout
+    assert!(scan(&mut layer_state, &[]).is_empty());
+    assert!(scan(&mut layer_state, &[]).is_empty());
+    assert_eq!(
+        scan(&mut layer_state, &[]),
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Last,
+            id: kll_hid::Keyboard::LeftShift,
+        }]
+    );
+}
+
+#[test]
+fn one_shot_modifier_held() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(ONE_SHOT_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_one_shot(Some(100));
+
+    // Holding shift while pressing A is not a one-shot
+    scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 2)]);
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert_eq!(
+        runs,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Last,
+            id: kll_hid::Keyboard::LeftShift,
+        }]
+    );
+}
+
+/// 2 key combo (index 1 + index 2) -> B
+const COMBO_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[
+            switch_condition(trigger::Phro::Press, 1),
+            switch_condition(trigger::Phro::Press, 2),
+        ],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::B,
+        )],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[
+            switch_condition(trigger::Phro::Press, 1),
+            switch_condition(trigger::Phro::Press, 2),
+        ],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::B,
+        )],
+    ),
+];
+
+/// Press index 1, then index 2 on the next scan loop
+fn combo_one_scan_apart(layer_state: &mut TestLayerState) -> heapless::Vec<CapabilityRun, 4> {
+    let cap_runs = scan(layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert!(cap_runs.is_empty(), "Unexpected result {:?}", cap_runs);
+    scan(layer_state, &[switch_event(trigger::Phro::Press, 2)])
+}
+
+#[test]
+fn combo_inside_window() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(COMBO_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_combo_window(2);
+
+    let cap_runs = combo_one_scan_apart(&mut layer_state);
+    assert_eq!(
+        cap_runs,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::B,
+        }],
+        "Unexpected combo result {:?}",
+        cap_runs
+    );
+    assert!(layer_state.trigger_combo_eval_state.is_empty());
+}
+
+#[test]
+fn combo_outside_window() {
+    setup_logging_lite().ok();
+
+    // Default window requires the same scan loop
+    let fixture = Fixture::new(COMBO_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let cap_runs = combo_one_scan_apart(&mut layer_state);
+    assert!(
+        cap_runs.is_empty(),
+        "Unexpected combo result {:?}",
+        cap_runs
+    );
+}
+
+#[test]
+fn combo_scenario() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(COMBO_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let results = run_scenario(
+        &mut layer_state,
+        &[
+            // Keys pressed in different scan loops do not complete the combo
+            &[switch_event(trigger::Phro::Press, 1)],
+            &[switch_event(trigger::Phro::Press, 2)],
+            &[
+                switch_event(trigger::Phro::Release, 1),
+                switch_event(trigger::Phro::Release, 2),
+            ],
+            // Both keys pressed in the same scan loop
+            &[
+                switch_event(trigger::Phro::Press, 2),
+                switch_event(trigger::Phro::Press, 1),
+            ],
+            &[],
+        ],
+    );
+    assert_eq!(
+        results,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::B,
+        }],
+        "Unexpected scenario result {:?}",
+        results
+    );
+}
+
+/// Index 1 + Index 2 combo -> Passthrough A
+const PASSTHROUGH_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[
+            switch_condition(trigger::Phro::Press, 1),
+            switch_condition(trigger::Phro::Press, 2),
+        ],
+        &[keyboard_capability(
+            CapabilityState::Passthrough,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[
+            switch_condition(trigger::Phro::Press, 1),
+            switch_condition(trigger::Phro::Press, 2),
+        ],
+        &[keyboard_capability(
+            CapabilityState::Passthrough,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+];
+
+#[test]
+fn passthrough_last_combo_event() {
+    setup_logging_lite().ok();
+
+    // The final event of the combo is passed through, regardless of order
+    for (first, last) in [(1, 2), (2, 1)] {
+        let fixture = Fixture::new(PASSTHROUGH_MAPPINGS, &[0]);
+        let mut layer_state: TestLayerState = fixture.layer_state();
+        layer_state.set_combo_window(2);
+
+        layer_state.increment_time();
+        let event = TriggerEvent::Switch {
+            state: trigger::Phro::Press,
+            index: first,
+            last_state: 0,
+        };
+        assert!(layer_state.process_trigger::<4>(event).is_ok());
+        let cap_runs = layer_state.finalize_triggers::<4>();
+        assert!(cap_runs.is_empty(), "Unexpected result {:?}", cap_runs);
+
+        layer_state.increment_time();
+        let last_event = TriggerEvent::Switch {
+            state: trigger::Phro::Press,
+            index: last,
+            last_state: 0,
+        };
+        assert!(layer_state.process_trigger::<4>(last_event).is_ok());
+        let cap_runs = layer_state.finalize_triggers::<4>();
+        assert_eq!(
+            cap_runs,
+            [CapabilityRun::HidKeyboard {
+                state: CapabilityEvent::Passthrough(last_event),
+                id: kll_hid::Keyboard::A,
+            }],
+            "Unexpected passthrough result {:?}",
+            cap_runs
+        );
+    }
+}
+
+/// Index 1 -> LayerTap (layer 1, Esc), Index 2 -> A
+/// Tests use a 3 time instance hold threshold
+const LAYER_TAP_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[Capability::LayerTap {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            layer: 1,
+            tap_key: kll_hid::Keyboard::Esc,
+        }],
+    ),
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Release, 1)],
+        &[Capability::LayerTap {
+            state: CapabilityState::Last,
+            loop_condition_index: 0,
+            layer: 1,
+            tap_key: kll_hid::Keyboard::Esc,
+        }],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Press, 2)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Release, 2)],
+        &[keyboard_capability(
+            CapabilityState::Last,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+];
+
+#[test]
+fn layer_tap_tap() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_TAP_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_tap_hold_threshold(3);
+
+    let esc = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::Esc,
+    };
+
+    // Press is held back until resolved
+    assert!(scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]).is_empty());
+    assert!(scan(&mut layer_state, &[]).is_empty());
+
+    // Quick release sends the tap key
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert_eq!(runs, [esc(CapabilityEvent::Initial)]);
+    let runs = scan(&mut layer_state, &[]);
+    assert_eq!(runs, [esc(CapabilityEvent::Last)]);
+    assert!(scan(&mut layer_state, &[]).is_empty());
+}
+
+#[test]
+fn layer_tap_hold() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_TAP_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_tap_hold_threshold(3);
+
+    let layer = |state| CapabilityRun::LayerState {
+        state,
+        layer: 1,
+        layer_state: layer::State::Shift,
+    };
+
+    // Layer is activated once the hold threshold has passed
+    assert!(scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]).is_empty());
+    assert!(scan(&mut layer_state, &[]).is_empty());
+    assert!(scan(&mut layer_state, &[]).is_empty());
+    let runs = scan(&mut layer_state, &[]);
+    assert_eq!(runs, [layer(CapabilityEvent::Initial)]);
+
+    // Release deactivates the layer, tap key is not sent
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert_eq!(runs, [layer(CapabilityEvent::Last)]);
+    assert!(scan(&mut layer_state, &[]).is_empty());
+}
+
+#[test]
+fn layer_tap_interrupt() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_TAP_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_tap_hold_threshold(3);
+
+    // Pressing another key before the threshold activates the layer
+    assert!(scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]).is_empty());
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 2)]);
+    assert_eq!(
+        runs,
+        [
+            CapabilityRun::LayerState {
+                state: CapabilityEvent::Initial,
+                layer: 1,
+                layer_state: layer::State::Shift,
+            },
+            CapabilityRun::HidKeyboard {
+                state: CapabilityEvent::Initial,
+                id: kll_hid::Keyboard::A,
+            },
+        ]
+    );
+}
+
+/// Index 1 -> A (autorepeat, 3 delay, 2 interval)
+/// Loop condition lookup: [0, 3, 2]
+const AUTOREPEAT_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[Capability::HidKeyboardRepeat {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            id: kll_hid::Keyboard::A,
+            delay_index: 1,
+            interval_index: 2,
+        }],
+    ),
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Release, 1)],
+        &[Capability::HidKeyboardRepeat {
+            state: CapabilityState::Last,
+            loop_condition_index: 0,
+            id: kll_hid::Keyboard::A,
+            delay_index: 1,
+            interval_index: 2,
+        }],
+    ),
+];
+
+#[test]
+fn autorepeat_after_delay() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(AUTOREPEAT_MAPPINGS, &[0, 3, 2]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let a = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::A,
+    };
+
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(runs, [a(CapabilityEvent::Initial)]);
+
+    // No repeats before the delay
+    assert!(scan(&mut layer_state, &[]).is_empty());
+    assert!(scan(&mut layer_state, &[]).is_empty());
+
+    // First repeat
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Last)]);
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Initial)]);
+
+    // Second repeat (after interval)
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Last)]);
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Initial)]);
+
+    // Release stops repeating
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert_eq!(runs, [a(CapabilityEvent::Last)]);
+    for _ in 0..4 {
+        assert!(scan(&mut layer_state, &[]).is_empty());
+    }
+}
+
+#[test]
+fn autorepeat_skipped_time_instances() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(AUTOREPEAT_MAPPINGS, &[0, 3, 2]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let a = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::A,
+    };
+
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(runs, [a(CapabilityEvent::Initial)]);
+
+    // Scan loop runs late, the delay (3) passed without landing on a repeat time instance (4)
+    for _ in 0..3 {
+        layer_state.increment_time();
+    }
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Last)]);
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Initial)]);
+
+    // Next repeat is still scheduled by the interval (2)
+    assert_eq!(scan(&mut layer_state, &[]), [a(CapabilityEvent::Last)]);
+}
+
+#[test]
+fn autorepeat_release_before_delay() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(AUTOREPEAT_MAPPINGS, &[0, 3, 2]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    let a = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::A,
+    };
+
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(runs, [a(CapabilityEvent::Initial)]);
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert_eq!(runs, [a(CapabilityEvent::Last)]);
+    for _ in 0..4 {
+        assert!(scan(&mut layer_state, &[]).is_empty());
+    }
+}
+
+/// Index 1 -> LeftControl (LeftGUI on macOS)
+const OS_MAPPINGS: &[FixtureMapping] = &[(
+    0,
+    1,
+    1,
+    &[switch_condition(trigger::Phro::Press, 1)],
+    &[Capability::HidKeyboardOs {
+        state: CapabilityState::Initial,
+        loop_condition_index: 0,
+        id: kll_hid::Keyboard::LeftControl,
+        os: hid::OsType::MacOs,
+        os_id: kll_hid::Keyboard::LeftGUI,
+    }],
+)];
+
+#[test]
+fn os_dependent_keyboard() {
+    setup_logging_lite().ok();
+
+    // Default (unknown) OS
+    let fixture = Fixture::new(OS_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(
+        runs,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::LeftControl,
+        }]
+    );
+
+    // macOS
+    let fixture = Fixture::new(OS_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_os(hid::OsType::MacOs);
+    let runs = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(
+        runs,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::LeftGUI,
+        }]
+    );
+}
+
+/// Index 1 -> A (layer 0), B (layer 1), C (layer 2)
+/// The trigger guide is shared, guides: (0, 0), (0, 10), (0, 20)
+const LAYER_PRIORITY_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+    (
+        1,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::B,
+        )],
+    ),
+    (
+        2,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::C,
+        )],
+    ),
+];
+
+#[test]
+fn apply_layer_capability() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    let cap = CapabilityRun::LayerState {
+        state: CapabilityEvent::Initial,
+        layer: 1,
+        layer_state: layer::State::Lock,
+    };
+
+    // Toggle on
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(
+        events,
+        [TriggerEvent::Layer {
+            state: trigger::LayerState::LockActivate,
+            layer: 1,
+            last_state: 0,
+        }]
+    );
+    assert_eq!(layer_state.layer[1].state, layer::State::Lock);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (1, (0, 10)));
+
+    // Releasing (Last) does not toggle the lock
+    let events = layer_state.apply_capability(CapabilityRun::LayerState {
+        state: CapabilityEvent::Last,
+        layer: 1,
+        layer_state: layer::State::Lock,
+    });
+    assert!(events.is_empty());
+    assert_eq!(layer_state.layer[1].state, layer::State::Lock);
+
+    // Toggle off
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(
+        events,
+        [TriggerEvent::Layer {
+            state: trigger::LayerState::LockDeactivate,
+            layer: 1,
+            last_state: 0,
+        }]
+    );
+    assert_eq!(layer_state.layer[1].state, layer::State::Off);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (0, (0, 0)));
+
+    // Clear all layers
+    layer_state.apply_capability(cap);
+    layer_state.set_layer(2, layer::State::Shift);
+    let events = layer_state.apply_capability(CapabilityRun::LayerClear {
+        state: CapabilityEvent::Initial,
+    });
+    assert_eq!(
+        events,
+        [
+            TriggerEvent::Layer {
+                state: trigger::LayerState::ShiftDeactivate,
+                layer: 2,
+                last_state: 0,
+            },
+            TriggerEvent::Layer {
+                state: trigger::LayerState::LockDeactivate,
+                layer: 1,
+                last_state: 0,
+            },
+        ]
+    );
+    assert_eq!(layer_state.layer[1].state, layer::State::Off);
+    assert_eq!(layer_state.layer[2].state, layer::State::Off);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (0, (0, 0)));
+
+    // Non-layer capabilities are ignored
+    let events = layer_state.apply_capability(CapabilityRun::NoOp {
+        state: CapabilityEvent::Initial,
+    });
+    assert!(events.is_empty());
+
+    // Invalid layers and layer 0 are ignored
+    for layer in [3, 0] {
+        let events = layer_state.apply_capability(CapabilityRun::LayerState {
+            state: CapabilityEvent::Initial,
+            layer,
+            layer_state: layer::State::Lock,
+        });
+        assert!(events.is_empty());
+        assert!(layer_state.active_layers().is_empty());
+    }
+    assert_eq!(layer_state.layer[0].state, layer::State::Shift);
+
+    // Clearing layers leaves layer 0 enabled
+    let events = layer_state.apply_capability(CapabilityRun::LayerClear {
+        state: CapabilityEvent::Initial,
+    });
+    assert!(events.is_empty());
+    assert_eq!(layer_state.layer[0].state, layer::State::Shift);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (0, (0, 0)));
+}
+
+/// Index 1 -> Momentary layer 1 (Shift), Index 2 -> A (Layer 0) / B (Layer 1)
+const MOMENTARY_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[Capability::LayerState {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            layer: 1,
+            layer_state: layer::State::Shift,
+        }],
+    ),
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Release, 1)],
+        &[Capability::LayerState {
+            state: CapabilityState::Last,
+            loop_condition_index: 0,
+            layer: 1,
+            layer_state: layer::State::Shift,
+        }],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Press, 2)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+    (
+        0,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Release, 2)],
+        &[keyboard_capability(
+            CapabilityState::Last,
+            kll_hid::Keyboard::A,
+        )],
+    ),
+    (
+        1,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Press, 2)],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::B,
+        )],
+    ),
+    (
+        1,
+        1,
+        2,
+        &[switch_condition(trigger::Phro::Release, 2)],
+        &[keyboard_capability(
+            CapabilityState::Last,
+            kll_hid::Keyboard::B,
+        )],
+    ),
+];
+
+#[test]
+fn momentary_layer() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(MOMENTARY_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    // Hold layer key
+    let results = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 1)]);
+    assert_eq!(
+        results,
+        [CapabilityRun::LayerState {
+            state: CapabilityEvent::Initial,
+            layer: 1,
+            layer_state: layer::State::Shift,
+        }]
+    );
+    assert_eq!(layer_state.layer_state(1), Some(layer::State::Shift));
+
+    // Press key on the layer
+    let results = scan(&mut layer_state, &[switch_event(trigger::Phro::Press, 2)]);
+    assert_eq!(
+        results,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::B,
+        }]
+    );
+
+    // Release layer key, the layer is deactivated
+    let results = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 1)]);
+    assert_eq!(
+        results,
+        [CapabilityRun::LayerState {
+            state: CapabilityEvent::Last,
+            layer: 1,
+            layer_state: layer::State::Shift,
+        }]
+    );
+    assert_eq!(layer_state.layer_state(1), Some(layer::State::Off));
+    assert!(layer_state.active_layers().is_empty());
+
+    // Release key, the layer stack cache releases the key from the (now inactive) layer
+    let results = scan(&mut layer_state, &[switch_event(trigger::Phro::Release, 2)]);
+    assert_eq!(
+        results,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Last,
+            id: kll_hid::Keyboard::B,
+        }]
+    );
+}
+
+#[test]
+fn momentary_layer_cleared_while_held() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(MOMENTARY_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    let shift = |state| CapabilityRun::LayerState {
+        state,
+        layer: 1,
+        layer_state: layer::State::Shift,
+    };
+
+    // Activating an already active momentary layer is ignored
+    assert_eq!(
+        layer_state
+            .apply_capability(shift(CapabilityEvent::Initial))
+            .len(),
+        1
+    );
+    assert!(layer_state
+        .apply_capability(shift(CapabilityEvent::Initial))
+        .is_empty());
+    assert_eq!(layer_state.layer_state(1), Some(layer::State::Shift));
+
+    // Layer is cleared while held, release must not re-activate it
+    layer_state.apply_capability(CapabilityRun::LayerClear {
+        state: CapabilityEvent::Initial,
+    });
+    assert!(layer_state
+        .apply_capability(shift(CapabilityEvent::Last))
+        .is_empty());
+    assert_eq!(layer_state.layer_state(1), Some(layer::State::Off));
+}
+
+fn rotate_layer_event(layer: u8, activate: bool) -> TriggerEvent {
+    TriggerEvent::Layer {
+        state: if activate {
+            trigger::LayerState::LockActivate
+        } else {
+            trigger::LayerState::LockDeactivate
+        },
+        layer,
+        last_state: 0,
+    }
+}
+
+#[test]
+fn layer_rotate_next() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    let cap = CapabilityRun::LayerRotate {
+        state: CapabilityEvent::Initial,
+        direction: layer::Direction::Next,
+    };
+
+    // 0 -> 1
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(events, [rotate_layer_event(1, true)]);
+    assert_eq!(layer_state.rotate_layer(), 1);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (1, (0, 10)));
+
+    // 1 -> 2
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(
+        events,
+        [rotate_layer_event(1, false), rotate_layer_event(2, true)]
+    );
+    assert_eq!(layer_state.rotate_layer(), 2);
+    assert_eq!(layer_state.layer[1].state, layer::State::Off);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (2, (0, 20)));
+
+    // 2 -> 0 (wrap-around)
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(events, [rotate_layer_event(2, false)]);
+    assert_eq!(layer_state.rotate_layer(), 0);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (0, (0, 0)));
+
+    // Release does nothing
+    let events = layer_state.apply_capability(CapabilityRun::LayerRotate {
+        state: CapabilityEvent::Last,
+        direction: layer::Direction::Next,
+    });
+    assert!(events.is_empty());
+    assert_eq!(layer_state.rotate_layer(), 0);
+}
+
+#[test]
+fn layer_rotate_previous() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    let cap = CapabilityRun::LayerRotate {
+        state: CapabilityEvent::Initial,
+        direction: layer::Direction::Previous,
+    };
+
+    // 0 -> 2 (wrap-around)
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(events, [rotate_layer_event(2, true)]);
+    assert_eq!(layer_state.rotate_layer(), 2);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (2, (0, 20)));
+
+    // 2 -> 1
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(
+        events,
+        [rotate_layer_event(2, false), rotate_layer_event(1, true)]
+    );
+    assert_eq!(layer_state.rotate_layer(), 1);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (1, (0, 10)));
+
+    // 1 -> 0
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(events, [rotate_layer_event(1, false)]);
+    assert_eq!(layer_state.rotate_layer(), 0);
+    assert_eq!(layer_state.layer[1].state, layer::State::Off);
+    assert_eq!(layer_state.layer[2].state, layer::State::Off);
+}
+
+#[test]
+fn layer_rotate_many_layers() {
+    setup_logging_lite().ok();
+
+    // Declares layers 0 to 199
+    let fixture = Fixture::new(&[(199, 1, 1, &[], &[])], &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 256, 4, 2, 4, 2, 2> = fixture.layer_state();
+    let cap = CapabilityRun::LayerRotate {
+        state: CapabilityEvent::Initial,
+        direction: layer::Direction::Previous,
+    };
+
+    // 0 -> 199 (wrap-around)
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(events, [rotate_layer_event(199, true)]);
+
+    // 199 -> 198
+    let events = layer_state.apply_capability(cap);
+    assert_eq!(
+        events,
+        [
+            rotate_layer_event(199, false),
+            rotate_layer_event(198, true)
+        ]
+    );
+    assert_eq!(layer_state.rotate_layer(), 198);
+
+    // 198 -> 199 -> 0 (wrap-around)
+    layer_state.apply_capability(CapabilityRun::LayerRotate {
+        state: CapabilityEvent::Initial,
+        direction: layer::Direction::Next,
+    });
+    let events = layer_state.apply_capability(CapabilityRun::LayerRotate {
+        state: CapabilityEvent::Initial,
+        direction: layer::Direction::Next,
+    });
+    assert_eq!(events, [rotate_layer_event(199, false)]);
+    assert_eq!(layer_state.rotate_layer(), 0);
+}
+
+#[test]
+fn off_state_non_keyboard() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    layer_state.increment_time();
+
+    // HidLed Type (2), Index 1 (NumLock)
+    layer_state.off_state_lookups.push(((0, 0), 2, 1)).unwrap();
+
+    let generated = core::cell::Cell::new(None);
+    layer_state.process_off_state_lookups::<4>(|ttype, index| {
+        generated.set(Some((ttype, index)));
+        TriggerEvent::HidLed {
+            state: trigger::Aodo::Off,
+            index: index as u8,
+            last_state: 1,
+        }
+    });
+    assert_eq!(generated.get(), Some((2, 1)));
+
+    // No mapping for the LED trigger, nothing is generated
+    let result = layer_state.finalize_triggers::<4>();
+    assert!(result.is_empty());
+    assert!(layer_state.off_state_lookups().is_empty());
+}
+
+#[test]
+fn set_layer_valid() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+
+    // Every layer after the default layer can be set, including the last one
+    for layer in 1..3 {
+        assert_eq!(
+            layer_state.set_layer(layer, layer::State::Lock),
+            Some(TriggerEvent::Layer {
+                state: trigger::LayerState::LockActivate,
+                layer,
+                last_state: 0,
+            })
+        );
+        assert_eq!(layer_state.layer_state(layer), Some(layer::State::Lock));
+    }
+
+    // Layers past the last layer are ignored
+    assert_eq!(layer_state.set_layer(3, layer::State::Lock), None);
+    assert_eq!(layer_state.active_layers(), [1, 2]);
+}
+
+#[test]
+fn set_layer_deactivate() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+
+    // Removing one of two states keeps the layer active, the event uses the remaining state
+    layer_state.set_layer(1, layer::State::Shift);
+    layer_state.set_layer(1, layer::State::Lock);
+    assert_eq!(
+        layer_state.set_layer(1, layer::State::Lock),
+        Some(TriggerEvent::Layer {
+            state: trigger::LayerState::ShiftOn,
+            layer: 1,
+            last_state: 0,
+        })
+    );
+
+    // Deactivating reports the state that was removed
+    assert_eq!(
+        layer_state.set_layer(1, layer::State::Shift),
+        Some(TriggerEvent::Layer {
+            state: trigger::LayerState::ShiftDeactivate,
+            layer: 1,
+            last_state: 0,
+        })
+    );
+    assert_eq!(layer_state.layer_state(1), Some(layer::State::Off));
+}
+
+#[test]
+fn active_layers() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    assert!(layer_state.active_layers().is_empty());
+
+    layer_state.set_layer(2, layer::State::Lock);
+    layer_state.set_layer(1, layer::State::Shift);
+    assert_eq!(layer_state.active_layers(), [2, 1]);
+    assert_eq!(layer_state.layer_state(0), Some(layer::State::Shift));
+    assert_eq!(layer_state.layer_state(1), Some(layer::State::Shift));
+    assert_eq!(layer_state.layer_state(2), Some(layer::State::Lock));
+    assert_eq!(layer_state.layer_state(3), None);
+}
+
+/// Index 1 -> Lock layer 1, Layer 1 lock activation -> B
+const LAYER_ACTIVATION_MAPPINGS: &[FixtureMapping] = &[
+    (
+        0,
+        1,
+        1,
+        &[switch_condition(trigger::Phro::Press, 1)],
+        &[Capability::LayerState {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            layer: 1,
+            layer_state: layer::State::Lock,
+        }],
+    ),
+    (
+        0,
+        7,
+        1,
+        &[TriggerCondition::Layer {
+            state: trigger::LayerState::LockActivate,
+            loop_condition_index: 0,
+            layer: 1,
+        }],
+        &[keyboard_capability(
+            CapabilityState::Initial,
+            kll_hid::Keyboard::B,
+        )],
+    ),
+    // Declares layer 1
+    (1, 1, 2, &[], &[]),
+];
+
+#[test]
+fn layer_activation_trigger() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_ACTIVATION_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+
+    // Lock layer 1
+    let results = run_scenario(
+        &mut layer_state,
+        &[&[switch_event(trigger::Phro::Press, 1)]],
+    );
+    assert_eq!(
+        results,
+        [CapabilityRun::LayerState {
+            state: CapabilityEvent::Initial,
+            layer: 1,
+            layer_state: layer::State::Lock,
+        }]
+    );
+    let events = layer_state.apply_capability(results[0]);
+    assert_eq!(
+        events,
+        [TriggerEvent::Layer {
+            state: trigger::LayerState::LockActivate,
+            layer: 1,
+            last_state: 0,
+        }]
+    );
+
+    // The layer event is processed at the start of the next scan loop
+    let results = run_scenario(&mut layer_state, &[&events]);
+    assert_eq!(
+        results,
+        [CapabilityRun::HidKeyboard {
+            state: CapabilityEvent::Initial,
+            id: kll_hid::Keyboard::B,
+        }],
+        "Unexpected layer activation result {:?}",
+        results
+    );
+
+    // Activation of another layer does not match
+    let condition = TriggerCondition::Layer {
+        state: trigger::LayerState::LockActivate,
+        loop_condition_index: 0,
+        layer: 1,
+    };
+    let event = TriggerEvent::Layer {
+        state: trigger::LayerState::LockActivate,
+        layer: 2,
+        last_state: 0,
+    };
+    assert!(matches!(
+        condition.evaluate(event, &[0]),
+        Vote::Insufficient
+    ));
+}
+
+/// Lookup the winning (layer, guide) for Index 1
+fn layer_priority_lookup(
+    layer_state: &mut LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2>,
+) -> (u8, (u16, u16)) {
+    let event = TriggerEvent::Switch {
+        state: trigger::Phro::Press,
+        index: 1,
+        last_state: 0,
+    };
+    let (layer, guides) = layer_state.lookup::<4>(event).unwrap();
+    assert_eq!(guides.len(), 1);
+    (layer, guides[0])
+}
+
+#[test]
+fn layer_priority_activation_order() {
+    setup_logging_lite().ok();
+
+    // No layers active, default layer
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    assert_eq!(layer_priority_lookup(&mut layer_state), (0, (0, 0)));
+
+    // Activate 1 then 2, layer 2 wins
+    layer_state.set_layer(1, layer::State::Shift);
+    layer_state.set_layer(2, layer::State::Shift);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (2, (0, 20)));
+
+    // Activate 2 then 1, layer 1 wins
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    layer_state.set_layer(2, layer::State::Shift);
+    layer_state.set_layer(1, layer::State::Shift);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (1, (0, 10)));
+}
+
+#[test]
+fn layer_priority_reactivation() {
+    setup_logging_lite().ok();
+
+    let fixture = Fixture::new(LAYER_PRIORITY_MAPPINGS, &[0]);
+    let mut layer_state: LayerState<'_, 256, 64, 4, 4, 2, 4, 2, 2> = fixture.layer_state();
+    layer_state.set_layer(1, layer::State::Shift);
+    layer_state.set_layer(2, layer::State::Shift);
+
+    assert_eq!(layer_priority_lookup(&mut layer_state), (2, (0, 20)));
+
+    // Deactivate and reactivate layer 1, it is now the most recent
+    layer_state.set_layer(1, layer::State::Shift);
+    layer_state.set_layer(1, layer::State::Shift);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (1, (0, 10)));
+
+    // Deactivate layer 1, falls back to layer 2
+    layer_state.set_layer(1, layer::State::Shift);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (2, (0, 20)));
+
+    // Deactivate layer 2, falls back to the default layer
+    layer_state.set_layer(2, layer::State::Shift);
+    assert_eq!(layer_priority_lookup(&mut layer_state), (0, (0, 0)));
+}
+
+// TODO Tests
+// - Basic trigger -> result capability validation test
+// - Import KLL file and do a handful of manual validation (positive test cases)
+// - Import KLL file and do a handful of manual validation (negative test cases)
+// - Import KLL, retrieve json (or similar datastructure) and automate all triggers and make sure
+// all results are reached
+
+#[test]
+fn layer_lookup_overflow() {
+    setup_logging_lite().ok();
+
+    // 3 keys do not fit in a LAYOUT_SIZE of 2
+    const LAYER_LOOKUP: &[u8] =
+        kll_macros::layer_lookup!(0, 1, 1, [0], 0, 1, 2, [0], 0, 1, 3, [0],);
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[0, 0];
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    let lookup = LayerLookup::<2>::new(
+        LAYER_LOOKUP,
+        &[],
+        &[],
+        TRIGGER_RESULT_MAPPING,
+        LOOP_CONDITION_LOOKUP,
+    );
+    assert_eq!(
+        lookup,
+        Err(LayerLookupError::LayoutSizeTooSmall {
+            layer: 0,
+            ttype: 1,
+            index: 3,
+        })
+    );
+
+    // Lossy lookup skips the key that did not fit
+    let lookup = LayerLookup::<2>::new_lossy(
+        LAYER_LOOKUP,
+        &[],
+        &[],
+        TRIGGER_RESULT_MAPPING,
+        LOOP_CONDITION_LOOKUP,
+    );
+    assert_eq!(lookup.layer_lookup().len(), 2);
+}
+
+#[test]
+fn layer_lookup_truncated() {
+    setup_logging_lite().ok();
+
+    // Layer 0, Switch Type (1), Index 1, 2 triggers, but only 1 trigger index is present
+    const LAYER_LOOKUP: &[u8] = &[0, 1, 1, 0, 2, 0, 0];
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[0, 0];
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    let lookup = LayerLookup::<4>::new(
+        LAYER_LOOKUP,
+        &[],
+        &[],
+        TRIGGER_RESULT_MAPPING,
+        LOOP_CONDITION_LOOKUP,
+    );
+    assert_eq!(
+        lookup,
+        Err(LayerLookupError::Truncated {
+            layer: 0,
+            ttype: 1,
+            index: 1,
+        })
+    );
+}
+
+#[test]
+fn trigger_list_truncated() {
+    setup_logging_lite().ok();
+
+    // Layer 0, Switch Type (1), Index 1, 2 triggers, but only 1 trigger index is present
+    const LAYER_LOOKUP: &[u8] = &[0, 1, 1, 0, 2, 0, 0];
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[0, 0];
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    let lookup = LayerLookup::<4>::new_lossy(
+        LAYER_LOOKUP,
+        &[],
+        &[],
+        TRIGGER_RESULT_MAPPING,
+        LOOP_CONDITION_LOOKUP,
+    );
+    assert_eq!(lookup.trigger_list((0, 1, 1)), None);
+}
+
+#[test]
+fn triggers_for_layer() {
+    setup_logging_lite().ok();
+
+    #[rustfmt::skip]
+    const LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+        // Layer 0, Switch Type (1), Index 1, 1 trigger index: 0
+        0, 1, 1, [0],
+        // Layer 0, Switch Type (1), Index 2, 1 trigger index: 0
+        0, 1, 2, [0],
+        // Layer 0, Switch Type (1), Index 3, No Triggers
+        0, 1, 3, [],
+        // Layer 1, Switch Type (1), Index 2, 1 trigger index: 0
+        1, 1, 2, [0],
+        // Layer 1, AnalogDistance Type (3), Index 8, 1 trigger index: 0
+        1, 3, 8, [0],
+    );
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[0, 0];
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    let lookup = LayerLookup::<16>::new(
+        LAYER_LOOKUP,
+        &[],
+        &[],
+        TRIGGER_RESULT_MAPPING,
+        LOOP_CONDITION_LOOKUP,
+    )
+    .unwrap();
+
+    let mut layer0: heapless::Vec<(u8, u16), 4> = lookup.triggers_for_layer(0).collect();
+    layer0.sort_unstable();
+    assert_eq!(layer0, [(1, 1), (1, 2)]);
+
+    let mut layer1: heapless::Vec<(u8, u16), 4> = lookup.triggers_for_layer(1).collect();
+    layer1.sort_unstable();
+    assert_eq!(layer1, [(1, 2), (3, 8)]);
+
+    assert_eq!(lookup.triggers_for_layer(2).count(), 0);
+}
+
+#[test]
+fn coalesce_held() {
+    setup_logging_lite().ok();
+
+    // Press, hold for 100 scan loops, release
+    let press = [switch_event(trigger::Phro::Press, 1)];
+    let hold = [switch_event(trigger::Phro::Hold, 1)];
+    let release = [switch_event(trigger::Phro::Release, 1)];
+    let mut scenario: std::vec::Vec<&[TriggerEvent]> = std::vec::Vec::new();
+    scenario.push(&press);
+    scenario.extend(core::iter::repeat_n(&hold[..], 100));
+    scenario.push(&release);
+
+    let shift = |state| CapabilityRun::HidKeyboard {
+        state,
+        id: kll_hid::Keyboard::LeftShift,
+    };
+
+    // Every event is looked up
+    let fixture = Fixture::new(ONE_SHOT_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    let expected = run_scenario(&mut layer_state, &scenario);
+    assert_eq!(
+        expected,
+        [
+            shift(CapabilityEvent::Initial),
+            shift(CapabilityEvent::Last)
+        ]
+    );
+    assert_eq!(layer_state.lookup_count(), 102);
+
+    // Only the press, first hold (resolves the guide) and release are looked up
+    let fixture = Fixture::new(ONE_SHOT_MAPPINGS, &[0]);
+    let mut layer_state: TestLayerState = fixture.layer_state();
+    layer_state.set_coalesce_held(true);
+    assert_eq!(run_scenario(&mut layer_state, &scenario), expected);
+    assert_eq!(layer_state.lookup_count(), 3);
+}
+
+#[test]
+fn unaligned_guide_lookup() {
+    setup_logging_lite().ok();
+
+    // Layer 0, Switch Type (1), Index 1, 1 trigger index: 0
+    const LAYER_LOOKUP: &[u8] = &[0, 1, 1, 0, 1, 0, 0];
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[0, 0];
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    const TRIGGER: TriggerCondition = TriggerCondition::Switch {
+        state: trigger::Phro::Press,
+        index: 1,
+        loop_condition_index: 0,
+    };
+    const RESULT: Capability = Capability::HidKeyboard {
+        state: CapabilityState::Initial,
+        loop_condition_index: 0,
+        id: kll_hid::Keyboard::A,
+    };
+    const TRIGGER_GUIDES: &[u8] = kll_macros::trigger_guide!([[TriggerCondition::Switch {
+        state: trigger::Phro::Press,
+        index: 1,
+        loop_condition_index: 0,
+    },]]);
+    const RESULT_GUIDES: &[u8] = kll_macros::result_guide!([[Capability::HidKeyboard {
+        state: CapabilityState::Initial,
+        loop_condition_index: 0,
+        id: kll_hid::Keyboard::A,
+    },]]);
+
+    // Shift the guides by 0 and 1 bytes so at least one lookup reads from a misaligned
+    // address (guides are plain u8 arrays and carry no alignment guarantee)
+    for shift in 0..2 {
+        let mut trigger_guides = [0u8; 32];
+        trigger_guides[shift..shift + TRIGGER_GUIDES.len()].copy_from_slice(TRIGGER_GUIDES);
+        let mut result_guides = [0u8; 32];
+        result_guides[shift..shift + RESULT_GUIDES.len()].copy_from_slice(RESULT_GUIDES);
+
+        let lookup = LayerLookup::<4>::new(
+            LAYER_LOOKUP,
+            &trigger_guides[shift..],
+            &result_guides[shift..],
+            TRIGGER_RESULT_MAPPING,
+            LOOP_CONDITION_LOOKUP,
+        )
+        .unwrap();
+
+        let trigger = lookup.trigger_guide((0, 0), 0).unwrap();
+        assert_eq!(trigger.len(), 1);
+        assert!(trigger.eq([TRIGGER]));
+        let result = lookup.result_guide((0, 0), 0).unwrap();
+        assert_eq!(result.len(), 1);
+        assert!(result.eq([RESULT]));
+    }
+}
+
+#[test]
+fn layer_lookup_overlay() {
+    setup_logging_lite().ok();
+
+    #[rustfmt::skip]
+    const BASE_LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+        // Layer 0, Switch Type (1), Index 1, 1 trigger index: 0
+        0, 1, 1, [0],
+        // Layer 0, Switch Type (1), Index 2, 1 trigger index: 2
+        0, 1, 2, [2],
+    );
+    #[rustfmt::skip]
+    const OVERLAY_LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+        // Layer 0, Switch Type (1), Index 2, 1 trigger index: 4
+        0, 1, 2, [4],
+        // Layer 1, Switch Type (1), Index 3, 1 trigger index: 4
+        1, 1, 3, [4],
+    );
+    const TRIGGER_RESULT_MAPPING: &[u16] = &[
+        0, 0, // 0: 0 => 0
+        8, 10, // 2: 8 => 10
+        16, 20, // 4: 16 => 20
+    ];
+    const LOOP_CONDITION_LOOKUP: &[u32] = &[0];
+
+    let base = LayerLookup::<16>::new(
+        BASE_LAYER_LOOKUP,
+        &[],
+        &[],
+        TRIGGER_RESULT_MAPPING,
+        LOOP_CONDITION_LOOKUP,
+    )
+    .unwrap();
+    assert_eq!(base.max_layers(), 1);
+    assert_eq!(base.lookup_guides::<4>((0, 1, 2)), [(8, 10)]);
+
+    let lookup = LayerLookup::with_overlay(base, OVERLAY_LAYER_LOOKUP).unwrap();
+    assert_eq!(lookup.max_layers(), 2);
+
+    // Rebound by the overlay
+    assert_eq!(lookup.lookup_guides::<4>((0, 1, 2)), [(16, 20)]);
+    // New overlay entry
+    assert_eq!(lookup.lookup_guides::<4>((1, 1, 3)), [(16, 20)]);
+    // Unaffected base entry
+    assert_eq!(lookup.lookup_guides::<4>((0, 1, 1)), [(0, 0)]);
+    assert!(lookup.lookup_guides::<4>((0, 1, 3)).is_empty());
+
+    // Only a single overlay is supported
+    assert_eq!(
+        LayerLookup::with_overlay(lookup, OVERLAY_LAYER_LOOKUP).err(),
+        Some(LayerLookupError::OverlayAlreadySet)
+    );
+}
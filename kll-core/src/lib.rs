@@ -6,10 +6,10 @@
 // copied, modified, or distributed except according to those terms.
 
 #![no_std]
-#![feature(arbitrary_enum_discriminant)]
-#![feature(const_ptr_read)]
-#![feature(const_slice_from_raw_parts)]
-#![feature(let_chains)]
+// Nightly features, the stable feature uses the stabilized (or non-const) equivalents
+#![cfg_attr(not(feature = "stable"), feature(arbitrary_enum_discriminant))]
+#![cfg_attr(not(feature = "stable"), feature(const_ptr_read))]
+#![cfg_attr(not(feature = "stable"), feature(const_slice_from_raw_parts))]
 
 #[macro_use]
 extern crate static_assertions;
@@ -17,9 +17,15 @@ extern crate static_assertions;
 extern crate enum_primitive_derive;
 extern crate num_traits;
 
+#[cfg(feature = "std")]
+extern crate std;
+
 mod converters;
+#[cfg(feature = "std")]
+pub mod guide;
 pub mod layout;
 pub mod macros;
+pub mod unicode;
 pub use kll_hid;
 
 #[cfg(any(
@@ -61,6 +67,23 @@ pub mod hid {
         /// Control is disabled / released
         Inactive = 1,
     }
+
+    /// Host OS type
+    /// Matches the HID-IO h0001 OsType values (see HidIoHostInfo)
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, defmt::Format, Primitive)]
+    #[repr(u8)]
+    pub enum OsType {
+        Unknown = 0,
+        Windows = 1,
+        Linux = 2,
+        Android = 3,
+        MacOs = 4,
+        Ios = 5,
+        ChromeOs = 6,
+        FreeBsd = 7,
+        OpenBsd = 8,
+        NetBsd = 9,
+    }
 }
 
 pub mod layer {
@@ -100,14 +123,14 @@ pub mod layer {
     impl State {
         /// Adds the given state to this state
         /// This is a bitwise or operation
-        pub fn add(mut self, state: State) {
-            self |= state;
+        pub fn add(&mut self, state: State) {
+            *self |= state;
         }
 
         /// Removes the given state from this state
         /// This is a bitwise nand operation
-        pub fn remove(mut self, state: State) {
-            self &= !(state);
+        pub fn remove(&mut self, state: State) {
+            *self &= !(state);
         }
 
         /// Determine if the given state is present in this state
@@ -165,7 +188,7 @@ pub mod layer {
         type Output = Self;
 
         fn not(self) -> Self::Output {
-            State::from_u32(!(self as u32)).unwrap()
+            State::from_u32(!(self as u32) & State::ShiftLatchLock as u32).unwrap()
         }
     }
 }
@@ -401,7 +424,6 @@ pub enum Capability {
         id: kll_hid::SystemControl,
     } = 9,
 
-    // TODO Mouse Control
     // TODO Joystick Control
     /// Enter Flash Mode
     /// Usually jumps to the bootloader
@@ -531,12 +553,98 @@ pub enum Capability {
         loop_condition_index: u16,
         unicode: char,
     },
+
+    /// Layer tap
+    /// Activates layer (Shift) while held, sends tap_key when tapped
+    /// 6 bytes
+    LayerTap {
+        /// Capability state
+        state: CapabilityState,
+        /// Scanning loop condition (number of scanning loops attached to state condition)
+        /// Lookup index
+        loop_condition_index: u16,
+        layer: u8,
+        tap_key: kll_hid::Keyboard,
+    },
+
+    /// USB HID keyboard event with typematic autorepeat
+    /// Handles press/released based on incoming state
+    /// While held, the key is released and pressed again after the initial delay and then every
+    /// repeat interval.
+    /// delay_index and interval_index are loop condition lookup indices.
+    ///
+    /// NOTE: The host OS also applies its own typematic repeat to held keys (both 6KRO and NKRO).
+    ///       Each repeat releases the key for a single scan loop, which restarts the host repeat
+    ///       timer. Use this for keys that should repeat regardless of the host settings.
+    /// 7 bytes
+    HidKeyboardRepeat {
+        /// Capability state
+        state: CapabilityState,
+        /// Scanning loop condition (number of scanning loops attached to state condition)
+        /// Lookup index
+        loop_condition_index: u16,
+        id: kll_hid::Keyboard,
+        delay_index: u8,
+        interval_index: u8,
+    },
+
+    /// USB HID keyboard event, OS dependent
+    /// Sends os_id instead of id when the host OS matches os (e.g. swap Ctrl/GUI on macOS)
+    /// Handles press/released based on incoming state
+    /// 7 bytes
+    HidKeyboardOs {
+        /// Capability state
+        state: CapabilityState,
+        /// Scanning loop condition (number of scanning loops attached to state condition)
+        /// Lookup index
+        loop_condition_index: u16,
+        id: kll_hid::Keyboard,
+        os: hid::OsType,
+        os_id: kll_hid::Keyboard,
+    },
+
+    /// USB HID mouse button event
+    /// Handles press/released based on incoming state
+    /// 5 bytes
+    HidMouseButton {
+        /// Capability state
+        state: CapabilityState,
+        /// Scanning loop condition (number of scanning loops attached to state condition)
+        /// Lookup index
+        loop_condition_index: u16,
+        /// Mouse button (1->8)
+        button: u8,
+    },
+    /// USB HID mouse position event
+    /// Relative movement, sent on press
+    /// 8 bytes
+    HidMouseMove {
+        /// Capability state
+        state: CapabilityState,
+        /// Scanning loop condition (number of scanning loops attached to state condition)
+        /// Lookup index
+        loop_condition_index: u16,
+        x: i16,
+        y: i16,
+    },
+    /// USB HID mouse wheel event
+    /// Relative movement, sent on press
+    /// 6 bytes
+    HidMouseWheel {
+        /// Capability state
+        state: CapabilityState,
+        /// Scanning loop condition (number of scanning loops attached to state condition)
+        /// Lookup index
+        loop_condition_index: u16,
+        vert: i8,
+        horz: i8,
+    },
 }
 
 impl Capability {
     /// Generate a CapabilityRun using a Capability + TriggerEvent
     /// The TriggerEvent is only important when CapabilityState::Passthrough is set.
-    pub fn generate(&self, event: TriggerEvent, _loop_condition_lookup: &[u32]) -> CapabilityRun {
+    pub fn generate(&self, event: TriggerEvent, loop_condition_lookup: &[u32]) -> CapabilityRun {
         // TODO: Handle loop_condition_index
         match self {
             Capability::NoOp { state, .. } => CapabilityRun::NoOp {
@@ -546,6 +654,101 @@ impl Capability {
                 state: state.event(event),
                 id: *id,
             },
+            Capability::LayerState {
+                state,
+                layer,
+                layer_state,
+                ..
+            } => CapabilityRun::LayerState {
+                state: state.event(event),
+                layer: *layer,
+                layer_state: *layer_state,
+            },
+            Capability::Rotate {
+                state,
+                index,
+                increment,
+                ..
+            } => CapabilityRun::Rotate {
+                state: state.event(event),
+                index: *index,
+                increment: *increment,
+            },
+            Capability::HidioOpenUrl { state, index, .. } => CapabilityRun::HidioOpenUrl {
+                state: state.event(event),
+                index: *index,
+            },
+            Capability::HidioUnicodeString { state, index, .. } => {
+                CapabilityRun::HidioUnicodeString {
+                    state: state.event(event),
+                    index: *index,
+                }
+            }
+            Capability::LayerTap {
+                state,
+                layer,
+                tap_key,
+                ..
+            } => CapabilityRun::LayerTap {
+                state: state.event(event),
+                layer: *layer,
+                tap_key: *tap_key,
+            },
+            Capability::HidKeyboardRepeat {
+                state,
+                id,
+                delay_index,
+                interval_index,
+                ..
+            } => match (
+                loop_condition_lookup.get(*delay_index as usize),
+                loop_condition_lookup.get(*interval_index as usize),
+            ) {
+                (Some(delay), Some(interval)) => CapabilityRun::HidKeyboardRepeat {
+                    state: state.event(event),
+                    id: *id,
+                    delay: *delay,
+                    interval: *interval,
+                },
+                // Invalid loop condition lookup, ignore the capability
+                _ => {
+                    warn!(
+                        "Invalid HidKeyboardRepeat loop condition index: {} {}",
+                        delay_index, interval_index
+                    );
+                    CapabilityRun::NoOp {
+                        state: state.event(event),
+                    }
+                }
+            },
+            Capability::HidKeyboardOs {
+                state,
+                id,
+                os,
+                os_id,
+                ..
+            } => CapabilityRun::HidKeyboardOs {
+                state: state.event(event),
+                id: *id,
+                os: *os,
+                os_id: *os_id,
+            },
+            Capability::HidMouseButton { state, button, .. } => CapabilityRun::HidMouseButton {
+                state: state.event(event),
+                button: *button,
+            },
+            Capability::HidMouseMove { state, x, y, .. } => CapabilityRun::HidMouseMove {
+                state: state.event(event),
+                x: *x,
+                y: *y,
+            },
+            Capability::HidMouseWheel {
+                state, vert, horz, ..
+            } => CapabilityRun::HidMouseWheel {
+                state: state.event(event),
+                vert: *vert,
+                horz: *horz,
+            },
             _ => {
                 panic!(
                     "Missing implementation for Capability::generate: {:?}",
@@ -646,6 +849,30 @@ impl Capability {
                 loop_condition_index,
                 ..
             } => *loop_condition_index,
+            Capability::LayerTap {
+                loop_condition_index,
+                ..
+            } => *loop_condition_index,
+            Capability::HidKeyboardRepeat {
+                loop_condition_index,
+                ..
+            } => *loop_condition_index,
+            Capability::HidKeyboardOs {
+                loop_condition_index,
+                ..
+            } => *loop_condition_index,
+            Capability::HidMouseButton {
+                loop_condition_index,
+                ..
+            } => *loop_condition_index,
+            Capability::HidMouseMove {
+                loop_condition_index,
+                ..
+            } => *loop_condition_index,
+            Capability::HidMouseWheel {
+                loop_condition_index,
+                ..
+            } => *loop_condition_index,
         }
     }
 }
@@ -721,7 +948,6 @@ pub enum CapabilityRun {
         id: kll_hid::SystemControl,
     } = 9,
 
-    // TODO Mouse Control
     // TODO Joystick Control
     /// Enter Flash Mode
     /// Usually jumps to the bootloader
@@ -797,6 +1023,55 @@ pub enum CapabilityRun {
         state: CapabilityEvent,
         unicode: char,
     },
+
+    /// Layer tap
+    /// Activates layer (Shift) while held, sends tap_key when tapped
+    /// 6 bytes
+    LayerTap {
+        state: CapabilityEvent,
+        layer: u8,
+        tap_key: kll_hid::Keyboard,
+    },
+
+    /// USB HID keyboard event with typematic autorepeat
+    /// Converted into HidKeyboard events by LayerState::finalize_triggers()
+    /// delay and interval are in time instances (scan loops)
+    /// 12 bytes
+    HidKeyboardRepeat {
+        state: CapabilityEvent,
+        id: kll_hid::Keyboard,
+        delay: u32,
+        interval: u32,
+    },
+
+    /// USB HID keyboard event, OS dependent
+    /// Converted into HidKeyboard events by LayerState::finalize_triggers()
+    /// 6 bytes
+    HidKeyboardOs {
+        state: CapabilityEvent,
+        id: kll_hid::Keyboard,
+        os: hid::OsType,
+        os_id: kll_hid::Keyboard,
+    },
+
+    /// USB HID mouse button event
+    /// Handles press/released based on incoming state
+    /// 3 bytes
+    HidMouseButton { state: CapabilityEvent, button: u8 },
+    /// USB HID mouse position event
+    /// 6 bytes
+    HidMouseMove {
+        state: CapabilityEvent,
+        x: i16,
+        y: i16,
+    },
+    /// USB HID mouse wheel event
+    /// 4 bytes
+    HidMouseWheel {
+        state: CapabilityEvent,
+        vert: i8,
+        horz: i8,
+    },
 }
 
 impl CapabilityRun {
@@ -824,6 +1099,12 @@ impl CapabilityRun {
             CapabilityRun::HidioOpenUrl { state, .. } => *state,
             CapabilityRun::HidioUnicodeString { state, .. } => *state,
             CapabilityRun::HidioUnicodeState { state, .. } => *state,
+            CapabilityRun::LayerTap { state, .. } => *state,
+            CapabilityRun::HidKeyboardRepeat { state, .. } => *state,
+            CapabilityRun::HidKeyboardOs { state, .. } => *state,
+            CapabilityRun::HidMouseButton { state, .. } => *state,
+            CapabilityRun::HidMouseMove { state, .. } => *state,
+            CapabilityRun::HidMouseWheel { state, .. } => *state,
             _ => {
                 panic!("CapabilityRun type not handled for state({:?})", self)
             }
@@ -857,9 +1138,19 @@ impl Capability {
     /// Convert slice of bytes to enum
     /// Aggressively casts the provide u8 slice to retrieve a Capability
     /// # Safety
+    #[cfg(not(feature = "stable"))]
     pub const unsafe fn from_bytes(bytes: &[u8]) -> Capability {
         core::ptr::read(bytes.as_ptr() as *const &[u8] as *const Capability)
     }
+
+    /// Convert slice of bytes to enum
+    /// Aggressively casts the provide u8 slice to retrieve a Capability
+    /// Non-const on stable, the slice does not need to be aligned
+    /// # Safety
+    #[cfg(feature = "stable")]
+    pub unsafe fn from_bytes(bytes: &[u8]) -> Capability {
+        core::ptr::read_unaligned(bytes.as_ptr() as *const Capability)
+    }
 }
 
 pub enum Vote {
@@ -1010,6 +1301,46 @@ pub mod trigger {
         Passthrough = 8,
     }
 
+    /// TapHold - Tap-hold resolution
+    /// Used to decide whether a single switch should trigger its tap or its hold action
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, defmt::Format)]
+    #[repr(u8)]
+    pub enum TapHold {
+        /// Not enough information to decide yet (still held, threshold not reached)
+        Pending = 0,
+        /// Released before the hold threshold (with no interrupting keys)
+        Tap = 1,
+        /// Held past the hold threshold or another key was pressed during the hold
+        Hold = 2,
+    }
+
+    impl TapHold {
+        /// Classify a switch as a tap or a hold
+        /// press_time, cur_time and hold_threshold must use the same time base
+        /// (e.g. scan loops or the LayerState time instance).
+        ///
+        /// interrupted - Another key was pressed while this switch was held
+        /// released - The switch has been released
+        pub fn classify(
+            press_time: u32,
+            cur_time: u32,
+            hold_threshold: u32,
+            interrupted: bool,
+            released: bool,
+        ) -> Self {
+            // Time instances may rollover
+            let elapsed = cur_time.wrapping_sub(press_time);
+
+            if interrupted || elapsed >= hold_threshold {
+                TapHold::Hold
+            } else if released {
+                TapHold::Tap
+            } else {
+                TapHold::Pending
+            }
+        }
+    }
+
     /// LayerState - AODO + Layer Info
     #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, defmt::Format, Primitive)]
     #[repr(u8)]
@@ -1054,7 +1385,7 @@ pub mod trigger {
     impl LayerState {
         /// Mergers layer::State and Aodo for TriggerEvent::LayerState
         pub fn from_layer(layer_state: layer::State, activity_state: Aodo) -> Self {
-            LayerState::from_u32(((layer_state as u32) << 1) | activity_state as u32).unwrap()
+            LayerState::from_u32(((layer_state as u32) << 4) | activity_state as u32).unwrap()
         }
     }
 }
@@ -1160,6 +1491,138 @@ impl TriggerEvent {
             TriggerEvent::Rotation { index, .. } => (*index).into(),
         }
     }
+
+    /// Sleep event
+    pub fn sleep(state: trigger::Aodo, last_state: u32) -> Self {
+        TriggerEvent::Sleep { state, last_state }
+    }
+
+    /// Resume event
+    pub fn resume(state: trigger::Aodo, last_state: u32) -> Self {
+        TriggerEvent::Resume { state, last_state }
+    }
+
+    /// Inactive event
+    pub fn inactive(state: trigger::Aodo, last_state: u32) -> Self {
+        TriggerEvent::Inactive { state, last_state }
+    }
+
+    /// Active event
+    pub fn active(state: trigger::Aodo, last_state: u32) -> Self {
+        TriggerEvent::Active { state, last_state }
+    }
+}
+
+/// Tracks power management state changes and generates the matching TriggerEvents
+/// Call once per scanning loop (similar to generating switch events from the matrix)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct PowerState {
+    /// Currently sleeping
+    sleeping: bool,
+    /// Scanning loops since the sleep state changed
+    sleep_last_state: u32,
+    /// Currently inactive
+    inactive: bool,
+    /// Scanning loops since the activity state changed
+    activity_last_state: u32,
+}
+
+impl PowerState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the sleep state
+    /// Returns the (Sleep, Resume) events for the current scanning loop
+    pub fn update_sleep(&mut self, sleeping: bool) -> (TriggerEvent, TriggerEvent) {
+        let prev = self.sleeping;
+        Self::update_last_state(&mut self.sleep_last_state, prev != sleeping);
+        self.sleeping = sleeping;
+
+        (
+            TriggerEvent::sleep(
+                trigger::Aodo::from_state(prev, sleeping),
+                self.sleep_last_state,
+            ),
+            TriggerEvent::resume(
+                trigger::Aodo::from_state(!prev, !sleeping),
+                self.sleep_last_state,
+            ),
+        )
+    }
+
+    /// Update the activity state
+    /// Returns the (Inactive, Active) events for the current scanning loop
+    pub fn update_activity(&mut self, active: bool) -> (TriggerEvent, TriggerEvent) {
+        let prev = self.inactive;
+        let inactive = !active;
+        Self::update_last_state(&mut self.activity_last_state, prev != inactive);
+        self.inactive = inactive;
+
+        (
+            TriggerEvent::inactive(
+                trigger::Aodo::from_state(prev, inactive),
+                self.activity_last_state,
+            ),
+            TriggerEvent::active(
+                trigger::Aodo::from_state(!prev, active),
+                self.activity_last_state,
+            ),
+        )
+    }
+
+    /// Currently sleeping
+    pub fn sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    /// Currently inactive
+    pub fn inactive(&self) -> bool {
+        self.inactive
+    }
+
+    fn update_last_state(last_state: &mut u32, changed: bool) {
+        if changed {
+            *last_state = 0;
+        } else {
+            *last_state = last_state.saturating_add(1);
+        }
+    }
+}
+
+/// Tracks device activity and generates the Inactive/Active TriggerEvents
+/// The device becomes inactive once there has been no input for the inactivity threshold
+/// (in scanning loops) and active again on the next input
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct ActivityTracker {
+    /// Scanning loops since the last input
+    idle_loops: u32,
+    power: PowerState,
+}
+
+impl ActivityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update with the current scanning loop
+    /// any_input: true if there was any input event during the scanning loop
+    /// threshold: number of scanning loops without input before the device is inactive
+    /// Returns the (Inactive, Active) events for the current scanning loop
+    pub fn update(&mut self, any_input: bool, threshold: u32) -> (TriggerEvent, TriggerEvent) {
+        if any_input {
+            self.idle_loops = 0;
+        } else {
+            self.idle_loops = self.idle_loops.saturating_add(1);
+        }
+
+        self.power.update_activity(self.idle_loops < threshold)
+    }
+
+    /// Currently inactive
+    pub fn inactive(&self) -> bool {
+        self.power.inactive()
+    }
 }
 
 // Size validation for TriggerEvent
@@ -1326,10 +1789,20 @@ impl TriggerCondition {
     /// Convert slice of bytes to enum
     /// Aggressively casts the provide u8 slice to retrieve a TriggerCondition
     /// # Safety
+    #[cfg(not(feature = "stable"))]
     pub const unsafe fn from_bytes(bytes: &[u8]) -> TriggerCondition {
         core::ptr::read(bytes.as_ptr() as *const &[u8] as *const TriggerCondition)
     }
 
+    /// Convert slice of bytes to enum
+    /// Aggressively casts the provide u8 slice to retrieve a TriggerCondition
+    /// Non-const on stable, the slice does not need to be aligned
+    /// # Safety
+    #[cfg(feature = "stable")]
+    pub unsafe fn from_bytes(bytes: &[u8]) -> TriggerCondition {
+        core::ptr::read_unaligned(bytes.as_ptr() as *const TriggerCondition)
+    }
+
     /// Attempts to determine the index value of the condition
     /// If an index is not valid, return 0 instead (index may not have any meaning)
     pub fn index(&self) -> u16 {
@@ -1389,6 +1862,24 @@ impl TriggerCondition {
                     Vote::Insufficient
                 }
             }
+            TriggerCondition::Layer { state, layer, .. } => {
+                if let TriggerEvent::Layer {
+                    state: e_state,
+                    layer: e_layer,
+                    ..
+                } = event
+                {
+                    if *layer != e_layer {
+                        Vote::Insufficient
+                    } else if *state == trigger::LayerState::Passthrough || *state == e_state {
+                        Vote::Positive
+                    } else {
+                        Vote::Insufficient
+                    }
+                } else {
+                    Vote::Insufficient
+                }
+            }
             _ => {
                 panic!("Unknown condition! Please fix.");
             }
@@ -1451,6 +1942,26 @@ pub enum CapabilityEvent {
     Passthrough(TriggerEvent) = 4,
 }
 
+// CapabilityEvent discriminants must mirror CapabilityState
+const_assert_eq!(CapabilityState::None as u8, 0);
+const_assert_eq!(CapabilityState::Initial as u8, 1);
+const_assert_eq!(CapabilityState::Last as u8, 2);
+const_assert_eq!(CapabilityState::Any as u8, 3);
+const_assert_eq!(CapabilityState::Passthrough as u8, 4);
+
+impl CapabilityEvent {
+    /// CapabilityState corresponding to this CapabilityEvent (reverse of CapabilityState::event)
+    pub fn state(&self) -> CapabilityState {
+        match self {
+            CapabilityEvent::None => CapabilityState::None,
+            CapabilityEvent::Initial => CapabilityState::Initial,
+            CapabilityEvent::Last => CapabilityState::Last,
+            CapabilityEvent::Any => CapabilityState::Any,
+            CapabilityEvent::Passthrough(_) => CapabilityState::Passthrough,
+        }
+    }
+}
+
 /*
 /// Position
 /// Each position has 6 dimensions
@@ -1470,3 +1981,303 @@ pub struct Position {
     rz: f32,
 }
 */
+
+#[cfg(test)]
+mod tests {
+    use crate::trigger::{Aodo, LayerState, Phro, TapHold};
+    use crate::{
+        layer, ActivityTracker, Capability, CapabilityEvent, CapabilityRun, CapabilityState,
+        PowerState, TriggerCondition, TriggerEvent,
+    };
+
+    #[test]
+    fn const_bytes() {
+        // bytes() must stay const (also with the stable feature), kll_macros uses it in consts
+        const CAPABILITY: Capability = Capability::HidKeyboard {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            id: kll_hid::Keyboard::A,
+        };
+        const CAPABILITY_ID: u8 = unsafe { CAPABILITY.bytes()[0] };
+        const CONDITION: TriggerCondition = TriggerCondition::Switch {
+            state: Phro::Press,
+            index: 1,
+            loop_condition_index: 0,
+        };
+        const CONDITION_ID: u8 = unsafe { CONDITION.bytes()[0] };
+
+        assert_eq!(CAPABILITY_ID, unsafe { CAPABILITY.bytes()[0] });
+        assert_eq!(CONDITION_ID, u8::from(CONDITION));
+    }
+
+    #[test]
+    #[cfg(feature = "stable")]
+    fn stable_from_bytes_unaligned() {
+        let cond = TriggerCondition::Switch {
+            state: Phro::Hold,
+            index: 0x1234,
+            loop_condition_index: 2,
+        };
+        let mut buf = [0u8; 7];
+        buf[1..].copy_from_slice(unsafe { cond.bytes() });
+        assert_eq!(unsafe { TriggerCondition::from_bytes(&buf[1..]) }, cond);
+
+        let cap = Capability::HidKeyboard {
+            state: CapabilityState::Last,
+            loop_condition_index: 3,
+            id: kll_hid::Keyboard::Z,
+        };
+        let mut buf = [0u8; 9];
+        buf[1..].copy_from_slice(unsafe { cap.bytes() });
+        assert_eq!(unsafe { Capability::from_bytes(&buf[1..]) }, cap);
+    }
+
+    #[test]
+    fn capability_state_event_sync() {
+        let trigger = TriggerEvent::Switch {
+            state: Phro::Press,
+            index: 5,
+            last_state: 0,
+        };
+
+        for (state, event) in [
+            (CapabilityState::None, CapabilityEvent::None),
+            (CapabilityState::Initial, CapabilityEvent::Initial),
+            (CapabilityState::Last, CapabilityEvent::Last),
+            (CapabilityState::Any, CapabilityEvent::Any),
+            (
+                CapabilityState::Passthrough,
+                CapabilityEvent::Passthrough(trigger),
+            ),
+        ] {
+            assert_eq!(state.event(trigger), event);
+            assert_eq!(event.state(), state);
+
+            // repr(u8) places the discriminant in the first byte
+            let discriminant = unsafe { *(&event as *const CapabilityEvent as *const u8) };
+            assert_eq!(discriminant, state as u8, "{:?} vs {:?}", state, event);
+        }
+    }
+
+    #[test]
+    fn keyboard_repeat_invalid_loop_condition() {
+        let trigger = TriggerEvent::Switch {
+            state: Phro::Press,
+            index: 1,
+            last_state: 0,
+        };
+        let cap = |delay_index, interval_index| Capability::HidKeyboardRepeat {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            id: kll_hid::Keyboard::A,
+            delay_index,
+            interval_index,
+        };
+
+        assert_eq!(
+            cap(1, 2).generate(trigger, &[0, 3, 2]),
+            CapabilityRun::HidKeyboardRepeat {
+                state: CapabilityEvent::Initial,
+                id: kll_hid::Keyboard::A,
+                delay: 3,
+                interval: 2,
+            }
+        );
+
+        // Out of range indices are ignored
+        for (delay_index, interval_index) in [(3, 2), (1, 3)] {
+            assert_eq!(
+                cap(delay_index, interval_index).generate(trigger, &[0, 3, 2]),
+                CapabilityRun::NoOp {
+                    state: CapabilityEvent::Initial,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn layer_state_add_remove() {
+        // add/remove modify the state in place
+        let mut state = layer::State::Off;
+        state.add(layer::State::Shift);
+        state.add(layer::State::Lock);
+        assert_eq!(state, layer::State::ShiftLock);
+        state.remove(layer::State::Shift);
+        assert_eq!(state, layer::State::Lock);
+        state.remove(layer::State::Lock);
+        assert_eq!(state, layer::State::Off);
+    }
+
+    #[test]
+    fn layer_state_not() {
+        // Only the Shift, Latch and Lock bits are inverted
+        assert_eq!(!layer::State::Off, layer::State::ShiftLatchLock);
+        assert_eq!(!layer::State::Shift, layer::State::LatchLock);
+        assert_eq!(!layer::State::LatchLock, layer::State::Shift);
+        assert_eq!(!layer::State::ShiftLatchLock, layer::State::Off);
+    }
+
+    #[test]
+    fn layer_state_from_layer() {
+        // layer::State is the upper nibble, Aodo the lower nibble
+        assert_eq!(
+            LayerState::from_layer(layer::State::Shift, Aodo::Activate),
+            LayerState::ShiftActivate
+        );
+        assert_eq!(
+            LayerState::from_layer(layer::State::Lock, Aodo::On),
+            LayerState::LockOn
+        );
+        assert_eq!(
+            LayerState::from_layer(layer::State::LatchLock, Aodo::Deactivate),
+            LayerState::LatchLockDeactivate
+        );
+        assert_eq!(
+            LayerState::from_layer(layer::State::ShiftLatchLock, Aodo::Off),
+            LayerState::ShiftLatchLockOff
+        );
+    }
+
+    #[test]
+    fn tap_hold_tap() {
+        // Still held, threshold not reached
+        assert_eq!(TapHold::classify(10, 12, 5, false, false), TapHold::Pending);
+        // Quick release
+        assert_eq!(TapHold::classify(10, 12, 5, false, true), TapHold::Tap);
+    }
+
+    #[test]
+    fn tap_hold_hold() {
+        // Timeout
+        assert_eq!(TapHold::classify(10, 15, 5, false, false), TapHold::Hold);
+        assert_eq!(TapHold::classify(10, 20, 5, false, true), TapHold::Hold);
+        // Time instance rollover
+        assert_eq!(TapHold::classify(u32::MAX, 1, 5, false, true), TapHold::Tap);
+        assert_eq!(
+            TapHold::classify(u32::MAX, 4, 5, false, false),
+            TapHold::Hold
+        );
+    }
+
+    #[test]
+    fn tap_hold_interrupt() {
+        // Another key pressed during the hold
+        assert_eq!(TapHold::classify(10, 11, 5, true, false), TapHold::Hold);
+        assert_eq!(TapHold::classify(10, 11, 5, true, true), TapHold::Hold);
+    }
+
+    #[test]
+    fn power_sleep_resume() {
+        let mut power = PowerState::new();
+
+        // Enter sleep
+        assert_eq!(
+            power.update_sleep(true),
+            (
+                TriggerEvent::sleep(Aodo::Activate, 0),
+                TriggerEvent::resume(Aodo::Deactivate, 0)
+            )
+        );
+        assert!(power.sleeping());
+
+        // Still sleeping
+        assert_eq!(
+            power.update_sleep(true),
+            (
+                TriggerEvent::sleep(Aodo::On, 1),
+                TriggerEvent::resume(Aodo::Off, 1)
+            )
+        );
+
+        // Resume
+        assert_eq!(
+            power.update_sleep(false),
+            (
+                TriggerEvent::sleep(Aodo::Deactivate, 0),
+                TriggerEvent::resume(Aodo::Activate, 0)
+            )
+        );
+        assert_eq!(
+            power.update_sleep(false),
+            (
+                TriggerEvent::sleep(Aodo::Off, 1),
+                TriggerEvent::resume(Aodo::On, 1)
+            )
+        );
+        assert!(!power.sleeping());
+    }
+
+    #[test]
+    fn power_activity() {
+        let mut power = PowerState::new();
+
+        // Inactive -> active
+        assert_eq!(
+            power.update_activity(false),
+            (
+                TriggerEvent::inactive(Aodo::Activate, 0),
+                TriggerEvent::active(Aodo::Deactivate, 0)
+            )
+        );
+        assert!(power.inactive());
+        assert_eq!(
+            power.update_activity(true),
+            (
+                TriggerEvent::inactive(Aodo::Deactivate, 0),
+                TriggerEvent::active(Aodo::Activate, 0)
+            )
+        );
+        assert!(!power.inactive());
+    }
+
+    #[test]
+    fn activity_tracker_threshold() {
+        let mut activity = ActivityTracker::new();
+        let active = (
+            TriggerEvent::inactive(Aodo::Off, 1),
+            TriggerEvent::active(Aodo::On, 1),
+        );
+
+        // Input keeps the device active
+        assert_eq!(activity.update(true, 3), active);
+
+        // Below the threshold
+        for last_state in 2..4 {
+            assert_eq!(
+                activity.update(false, 3),
+                (
+                    TriggerEvent::inactive(Aodo::Off, last_state),
+                    TriggerEvent::active(Aodo::On, last_state)
+                )
+            );
+        }
+        assert!(!activity.inactive());
+
+        // Threshold reached
+        assert_eq!(
+            activity.update(false, 3),
+            (
+                TriggerEvent::inactive(Aodo::Activate, 0),
+                TriggerEvent::active(Aodo::Deactivate, 0)
+            )
+        );
+        assert_eq!(
+            activity.update(false, 3),
+            (
+                TriggerEvent::inactive(Aodo::On, 1),
+                TriggerEvent::active(Aodo::Off, 1)
+            )
+        );
+        assert!(activity.inactive());
+
+        // Input makes the device active again
+        assert_eq!(
+            activity.update(true, 3),
+            (
+                TriggerEvent::inactive(Aodo::Deactivate, 0),
+                TriggerEvent::active(Aodo::Activate, 0)
+            )
+        );
+        assert!(!activity.inactive());
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+/// Unicode string table lookup
+/// Wraps the unicode string table generated by kll-compiler
+///
+/// The table is a list of NULL terminated UTF-8 strings
+/// The index of each string is the order in the table (not the byte offset)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct UnicodeTable<'a> {
+    raw_table: &'a [u8],
+}
+
+impl<'a> UnicodeTable<'a> {
+    pub fn new(raw_table: &'a [u8]) -> Self {
+        Self { raw_table }
+    }
+
+    /// Lookup unicode string using the given index
+    /// Returns None if the index is out of range or the string is not valid UTF-8
+    pub fn get(&self, index: u16) -> Option<&'a str> {
+        let mut strings = self.raw_table.split(|b| *b == 0);
+        let string = strings.nth(index as usize)?;
+
+        // Make sure the string was NULL terminated (last split is after the final NULL)
+        strings.next()?;
+
+        core::str::from_utf8(string).ok()
+    }
+
+    /// Number of strings in the table
+    pub fn len(&self) -> usize {
+        self.raw_table.iter().filter(|b| **b == 0).count()
+    }
+
+    /// True if there are no strings in the table
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::unicode::UnicodeTable;
+    use crate::{Capability, CapabilityEvent, CapabilityRun, CapabilityState, TriggerEvent};
+
+    const UNICODE_STRINGS: &[u8] = "keyboard\0⌨\0キーボード\0".as_bytes();
+
+    #[test]
+    fn lookup_valid_index() {
+        let table = UnicodeTable::new(UNICODE_STRINGS);
+        assert_eq!(table.len(), 3);
+        assert_eq!(table.get(0), Some("keyboard"));
+        assert_eq!(table.get(1), Some("⌨"));
+        assert_eq!(table.get(2), Some("キーボード"));
+    }
+
+    #[test]
+    fn lookup_out_of_range_index() {
+        let table = UnicodeTable::new(UNICODE_STRINGS);
+        assert_eq!(table.get(3), None);
+        assert_eq!(table.get(u16::MAX), None);
+
+        let table = UnicodeTable::new(&[]);
+        assert!(table.is_empty());
+        assert_eq!(table.get(0), None);
+    }
+
+    #[test]
+    fn generate_unicode_string() {
+        let table = UnicodeTable::new(UNICODE_STRINGS);
+        let cap = Capability::HidioUnicodeString {
+            state: CapabilityState::Initial,
+            loop_condition_index: 0,
+            index: 1,
+        };
+
+        let run = cap.generate(TriggerEvent::None, &[0]);
+        assert_eq!(
+            run,
+            CapabilityRun::HidioUnicodeString {
+                state: CapabilityEvent::Initial,
+                index: 1,
+            }
+        );
+
+        if let CapabilityRun::HidioUnicodeString { index, .. } = run {
+            assert_eq!(table.get(index), Some("⌨"));
+        }
+    }
+}
@@ -283,6 +283,233 @@ pub enum Keyboard {
     RightGUI = 0xE7,
 }
 
+impl Keyboard {
+    /// Symbolic name of the keyboard code (same as the enum variant)
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Keyboard::NoEvent => "NoEvent",
+            Keyboard::ErrorRollOver => "ErrorRollOver",
+            Keyboard::PostFail => "PostFail",
+            Keyboard::ErrorUndefined => "ErrorUndefined",
+            Keyboard::A => "A",
+            Keyboard::B => "B",
+            Keyboard::C => "C",
+            Keyboard::D => "D",
+            Keyboard::E => "E",
+            Keyboard::F => "F",
+            Keyboard::G => "G",
+            Keyboard::H => "H",
+            Keyboard::I => "I",
+            Keyboard::J => "J",
+            Keyboard::K => "K",
+            Keyboard::L => "L",
+            Keyboard::M => "M",
+            Keyboard::N => "N",
+            Keyboard::O => "O",
+            Keyboard::P => "P",
+            Keyboard::Q => "Q",
+            Keyboard::R => "R",
+            Keyboard::S => "S",
+            Keyboard::T => "T",
+            Keyboard::U => "U",
+            Keyboard::V => "V",
+            Keyboard::W => "W",
+            Keyboard::X => "X",
+            Keyboard::Y => "Y",
+            Keyboard::Z => "Z",
+            Keyboard::_1 => "_1",
+            Keyboard::_2 => "_2",
+            Keyboard::_3 => "_3",
+            Keyboard::_4 => "_4",
+            Keyboard::_5 => "_5",
+            Keyboard::_6 => "_6",
+            Keyboard::_7 => "_7",
+            Keyboard::_8 => "_8",
+            Keyboard::_9 => "_9",
+            Keyboard::_0 => "_0",
+            Keyboard::Enter => "Enter",
+            Keyboard::Esc => "Esc",
+            Keyboard::Backspace => "Backspace",
+            Keyboard::Tab => "Tab",
+            Keyboard::Space => "Space",
+            Keyboard::Minus => "Minus",
+            Keyboard::Equal => "Equal",
+            Keyboard::LeftBracket => "LeftBracket",
+            Keyboard::RightBracket => "RightBracket",
+            Keyboard::Backslash => "Backslash",
+            Keyboard::Number => "Number",
+            Keyboard::Semicolon => "Semicolon",
+            Keyboard::Quote => "Quote",
+            Keyboard::Backtick => "Backtick",
+            Keyboard::Comma => "Comma",
+            Keyboard::Period => "Period",
+            Keyboard::Slash => "Slash",
+            Keyboard::CapsLock => "CapsLock",
+            Keyboard::F1 => "F1",
+            Keyboard::F2 => "F2",
+            Keyboard::F3 => "F3",
+            Keyboard::F4 => "F4",
+            Keyboard::F5 => "F5",
+            Keyboard::F6 => "F6",
+            Keyboard::F7 => "F7",
+            Keyboard::F8 => "F8",
+            Keyboard::F9 => "F9",
+            Keyboard::F10 => "F10",
+            Keyboard::F11 => "F11",
+            Keyboard::F12 => "F12",
+            Keyboard::PrintScreen => "PrintScreen",
+            Keyboard::ScrollLock => "ScrollLock",
+            Keyboard::Pause => "Pause",
+            Keyboard::Insert => "Insert",
+            Keyboard::Home => "Home",
+            Keyboard::PageUp => "PageUp",
+            Keyboard::Delete => "Delete",
+            Keyboard::End => "End",
+            Keyboard::PageDown => "PageDown",
+            Keyboard::Right => "Right",
+            Keyboard::Left => "Left",
+            Keyboard::Down => "Down",
+            Keyboard::Up => "Up",
+            Keyboard::NumLock => "NumLock",
+            Keyboard::KeypadSlash => "KeypadSlash",
+            Keyboard::KeypadAsterisk => "KeypadAsterisk",
+            Keyboard::KeypadMinus => "KeypadMinus",
+            Keyboard::KeypadPlus => "KeypadPlus",
+            Keyboard::KeypadEnter => "KeypadEnter",
+            Keyboard::Keypad1 => "Keypad1",
+            Keyboard::Keypad2 => "Keypad2",
+            Keyboard::Keypad3 => "Keypad3",
+            Keyboard::Keypad4 => "Keypad4",
+            Keyboard::Keypad5 => "Keypad5",
+            Keyboard::Keypad6 => "Keypad6",
+            Keyboard::Keypad7 => "Keypad7",
+            Keyboard::Keypad8 => "Keypad8",
+            Keyboard::Keypad9 => "Keypad9",
+            Keyboard::Keypad0 => "Keypad0",
+            Keyboard::KeypadPeriod => "KeypadPeriod",
+            Keyboard::ISOSlash => "ISOSlash",
+            Keyboard::App => "App",
+            Keyboard::KeyboardStatus => "KeyboardStatus",
+            Keyboard::KeypadEqual => "KeypadEqual",
+            Keyboard::F13 => "F13",
+            Keyboard::F14 => "F14",
+            Keyboard::F15 => "F15",
+            Keyboard::F16 => "F16",
+            Keyboard::F17 => "F17",
+            Keyboard::F18 => "F18",
+            Keyboard::F19 => "F19",
+            Keyboard::F20 => "F20",
+            Keyboard::F21 => "F21",
+            Keyboard::F22 => "F22",
+            Keyboard::F23 => "F23",
+            Keyboard::F24 => "F24",
+            Keyboard::Exec => "Exec",
+            Keyboard::Help => "Help",
+            Keyboard::Menu => "Menu",
+            Keyboard::Select => "Select",
+            Keyboard::Stop => "Stop",
+            Keyboard::Again => "Again",
+            Keyboard::Undo => "Undo",
+            Keyboard::Cut => "Cut",
+            Keyboard::Copy => "Copy",
+            Keyboard::Paste => "Paste",
+            Keyboard::Find => "Find",
+            Keyboard::Mute => "Mute",
+            Keyboard::VolumeUp => "VolumeUp",
+            Keyboard::VolumeDown => "VolumeDown",
+            Keyboard::LockingCapsLock => "LockingCapsLock",
+            Keyboard::LockingNumLock => "LockingNumLock",
+            Keyboard::LockingScrollLock => "LockingScrollLock",
+            Keyboard::KeypadComma => "KeypadComma",
+            Keyboard::KeypadEqualAS400 => "KeypadEqualAS400",
+            Keyboard::International1 => "International1",
+            Keyboard::International2 => "International2",
+            Keyboard::International3 => "International3",
+            Keyboard::International4 => "International4",
+            Keyboard::International5 => "International5",
+            Keyboard::International6 => "International6",
+            Keyboard::International7 => "International7",
+            Keyboard::International8 => "International8",
+            Keyboard::International9 => "International9",
+            Keyboard::LANG1 => "LANG1",
+            Keyboard::LANG2 => "LANG2",
+            Keyboard::LANG3 => "LANG3",
+            Keyboard::LANG4 => "LANG4",
+            Keyboard::LANG5 => "LANG5",
+            Keyboard::LANG6 => "LANG6",
+            Keyboard::LANG7 => "LANG7",
+            Keyboard::LANG8 => "LANG8",
+            Keyboard::LANG9 => "LANG9",
+            Keyboard::AlternateErase => "AlternateErase",
+            Keyboard::SysReq => "SysReq",
+            Keyboard::Cancel => "Cancel",
+            Keyboard::Clear => "Clear",
+            Keyboard::Prior => "Prior",
+            Keyboard::Return => "Return",
+            Keyboard::Separator => "Separator",
+            Keyboard::Out => "Out",
+            Keyboard::Oper => "Oper",
+            Keyboard::ClearAgain => "ClearAgain",
+            Keyboard::CrSelProps => "CrSelProps",
+            Keyboard::ExSel => "ExSel",
+            Keyboard::Keypad00 => "Keypad00",
+            Keyboard::Keypad000 => "Keypad000",
+            Keyboard::ThousandSeparator => "ThousandSeparator",
+            Keyboard::DecimalSeparator => "DecimalSeparator",
+            Keyboard::CurrencyUnit => "CurrencyUnit",
+            Keyboard::CurrencySubUnit => "CurrencySubUnit",
+            Keyboard::KeypadLeftParenthesis => "KeypadLeftParenthesis",
+            Keyboard::KeypadRightParenthesis => "KeypadRightParenthesis",
+            Keyboard::KeypadLeftBrace => "KeypadLeftBrace",
+            Keyboard::KeypadRightBrace => "KeypadRightBrace",
+            Keyboard::KeypadTab => "KeypadTab",
+            Keyboard::KeypadBackspace => "KeypadBackspace",
+            Keyboard::KeypadA => "KeypadA",
+            Keyboard::KeypadB => "KeypadB",
+            Keyboard::KeypadC => "KeypadC",
+            Keyboard::KeypadD => "KeypadD",
+            Keyboard::KeypadE => "KeypadE",
+            Keyboard::KeypadF => "KeypadF",
+            Keyboard::KeypadXOR => "KeypadXOR",
+            Keyboard::KeypadChevron => "KeypadChevron",
+            Keyboard::KeypadPercent => "KeypadPercent",
+            Keyboard::KeypadLessThan => "KeypadLessThan",
+            Keyboard::KeypadGreaterThan => "KeypadGreaterThan",
+            Keyboard::KeypadBITAND => "KeypadBITAND",
+            Keyboard::KeypadAND => "KeypadAND",
+            Keyboard::KeypadBITOR => "KeypadBITOR",
+            Keyboard::KeypadOR => "KeypadOR",
+            Keyboard::KeypadColon => "KeypadColon",
+            Keyboard::KeypadNumber => "KeypadNumber",
+            Keyboard::KeypadSpace => "KeypadSpace",
+            Keyboard::KeypadAt => "KeypadAt",
+            Keyboard::KeypadExclamation => "KeypadExclamation",
+            Keyboard::KeypadMemoryStore => "KeypadMemoryStore",
+            Keyboard::KeypadMemoryRecall => "KeypadMemoryRecall",
+            Keyboard::KeypadMemoryClear => "KeypadMemoryClear",
+            Keyboard::KeypadMemoryAdd => "KeypadMemoryAdd",
+            Keyboard::KeypadMemorySubtract => "KeypadMemorySubtract",
+            Keyboard::KeypadMemoryMultiply => "KeypadMemoryMultiply",
+            Keyboard::KeypadMemoryDivide => "KeypadMemoryDivide",
+            Keyboard::KeypadPlusMinus => "KeypadPlusMinus",
+            Keyboard::KeypadClear => "KeypadClear",
+            Keyboard::KeypadClearEntry => "KeypadClearEntry",
+            Keyboard::KeypadBinary => "KeypadBinary",
+            Keyboard::KeypadOctal => "KeypadOctal",
+            Keyboard::KeypadDecimal => "KeypadDecimal",
+            Keyboard::KeypadHexidecimal => "KeypadHexidecimal",
+            Keyboard::LeftControl => "LeftControl",
+            Keyboard::LeftShift => "LeftShift",
+            Keyboard::LeftAlt => "LeftAlt",
+            Keyboard::LeftGUI => "LeftGUI",
+            Keyboard::RightControl => "RightControl",
+            Keyboard::RightShift => "RightShift",
+            Keyboard::RightAlt => "RightAlt",
+            Keyboard::RightGUI => "RightGUI",
+        }
+    }
+}
+
 /// Conversion from u16 indexes to Keyboard enum
 /// # Safety
 impl From<u16> for Keyboard {
@@ -5,9 +5,49 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
-use proc_macro::{TokenStream, TokenTree};
+use proc_macro::{Delimiter, Group, Ident, Literal, Punct, Spacing, Span, TokenStream, TokenTree};
 use std::iter::FromIterator;
 
+/// Generates a compile_error! pointing at the given span
+fn compile_error(span: Span, message: &str) -> TokenStream {
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut args = Group::new(
+        Delimiter::Parenthesis,
+        TokenStream::from(TokenTree::Literal(message)),
+    );
+    args.set_span(span);
+    TokenStream::from_iter([
+        TokenTree::Ident(Ident::new("compile_error", span)),
+        TokenTree::Punct(bang),
+        TokenTree::Group(args),
+    ])
+}
+
+/// Parses a u16 literal (decimal or 0x hex)
+fn parse_u16(literal: &Literal) -> Result<u16, TokenStream> {
+    let text = literal.to_string();
+    let val = if let Some(hex) = text.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        text.parse::<u64>()
+    };
+    match val {
+        Ok(val) => u16::try_from(val).map_err(|_| {
+            compile_error(
+                literal.span(),
+                &format!("`{}` does not fit in a u16 (max {})", text, u16::MAX),
+            )
+        }),
+        Err(_) => Err(compile_error(
+            literal.span(),
+            &format!("`{}` is not a valid integer literal", text),
+        )),
+    }
+}
+
 /// Takes a list of sequences of combos and turns it into a u8 array
 /// that can be stored in memory as a contiguous piece of data.
 /// This is necessary to store the trigger guide independently of rust compilation.
@@ -233,6 +273,7 @@ pub fn result_guide(input: TokenStream) -> TokenStream {
                                                 byte_count = 4;
                                             }
                                             "HidKeyboard"
+                                            | "HidMouseButton"
                                             | "HidProtocol"
                                             | "HidLed"
                                             | "HidSystemControl"
@@ -246,16 +287,19 @@ pub fn result_guide(input: TokenStream) -> TokenStream {
                                             | "HidioUnicodeString"
                                             | "HidConsumerControl"
                                             | "HidKeyboardState"
+                                            | "HidMouseWheel"
                                             | "LayerState"
+                                            | "LayerTap"
                                             | "PixelAnimationIndex"
                                             | "PixelLedControl"
                                             | "Rotate" => {
                                                 byte_count = 6;
                                             }
-                                            "PixelFadeIndex" | "PixelFadeSet" | "PixelTest" => {
+                                            "HidKeyboardOs" | "HidKeyboardRepeat"
+                                            | "PixelFadeIndex" | "PixelFadeSet" | "PixelTest" => {
                                                 byte_count = 7;
                                             }
-                                            "HidioUnicodeState" => {
+                                            "HidioUnicodeState" | "HidMouseMove" => {
                                                 byte_count = 8;
                                             }
                                             _ => {
@@ -322,6 +366,7 @@ enum LayerLookupState {
 /// Takes data in the following format and turns it into a byte array.
 /// The trigger count is automatically calculated.
 /// Triggers are u16, all the other fields are u8.
+/// Invalid indices or triggers (non-literal or larger than a u16) are compile errors.
 ///
 /// ```
 /// const LAYER_LOOKUP: &'static [u8] = kll_macros::layer_lookup!(
@@ -372,12 +417,11 @@ pub fn layer_lookup(input: TokenStream) -> TokenStream {
             LayerLookupState::Index => {
                 match token {
                     TokenTree::Literal(literal) => {
-                        // Check if this is a hex value
-                        let val = if literal.to_string().contains("0x") {
-                            u16::from_str_radix(literal.to_string().trim_start_matches("0x"), 16)
-                                .unwrap()
-                        } else {
-                            literal.to_string().parse::<u16>().unwrap()
+                        let val = match parse_u16(&literal) {
+                            Ok(val) => val,
+                            Err(err) => {
+                                return err;
+                            }
                         };
 
                         // Push index as two bytes
@@ -386,7 +430,10 @@ pub fn layer_lookup(input: TokenStream) -> TokenStream {
                         }
                     }
                     _ => {
-                        panic!("Invalid token, expected index token: {:?}", token);
+                        return compile_error(
+                            token.span(),
+                            &format!("Invalid index `{}`, expected a u16 literal", token),
+                        );
                     }
                 }
                 state = LayerLookupState::IndexComma;
@@ -401,26 +448,34 @@ pub fn layer_lookup(input: TokenStream) -> TokenStream {
                         for subtoken in group.stream() {
                             match subtoken.clone() {
                                 TokenTree::Punct(_) => {}
-                                TokenTree::Literal(literal) => {
-                                    // Check if this is a hex value
-                                    if literal.to_string().contains("0x") {
-                                        triggers.push(
-                                            u16::from_str_radix(
-                                                literal.to_string().trim_start_matches("0x"),
-                                                16,
-                                            )
-                                            .unwrap(),
-                                        );
-                                    } else {
-                                        triggers.push(literal.to_string().parse::<u16>().unwrap());
+                                TokenTree::Literal(literal) => match parse_u16(&literal) {
+                                    Ok(val) => {
+                                        triggers.push(val);
                                     }
-                                }
+                                    Err(err) => {
+                                        return err;
+                                    }
+                                },
                                 _ => {
-                                    panic!("Invalid trigger list token: {:?}", subtoken);
+                                    return compile_error(
+                                        subtoken.span(),
+                                        &format!(
+                                            "Invalid trigger `{}`, expected a u16 literal",
+                                            subtoken
+                                        ),
+                                    );
                                 }
                             }
                         }
 
+                        // The trigger count is a u8
+                        if triggers.len() > u8::MAX as usize {
+                            return compile_error(
+                                group.span(),
+                                &format!("Too many triggers ({}), max {}", triggers.len(), u8::MAX),
+                            );
+                        }
+
                         // Finished gathering triggers
                         // 1. Add the count
                         output.push(format!("{},", triggers.len()));
@@ -437,7 +492,10 @@ pub fn layer_lookup(input: TokenStream) -> TokenStream {
                         state = LayerLookupState::TriggersComma;
                     }
                     _ => {
-                        panic!("Invalid trigger token group: {:?}", token);
+                        return compile_error(
+                            token.span(),
+                            &format!("Invalid trigger list `{}`, expected [..]", token),
+                        );
                     }
                 }
             }
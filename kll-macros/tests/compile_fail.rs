@@ -0,0 +1,12 @@
+// Copyright 2022 Jacob Alexander
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// http://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// http://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+#[test]
+fn layer_lookup_errors() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/layer_lookup_*.rs");
+}
@@ -0,0 +1,5 @@
+const LAYER_LOOKUP: &[u8] = kll_macros::layer_lookup!(
+    0, 1, 5, [0, trigger],
+);
+
+fn main() {}